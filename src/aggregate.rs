@@ -0,0 +1,791 @@
+// Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::cmp::Ordering;
+use std::vec::IntoIter;
+
+use sqlparser::ast::Expr;
+
+use crate::binder::{Aggregate, AggregateFunction, Field};
+use crate::database::Database;
+use crate::executor::{close_on_error, row_size, Operator, PlanRow, WORK_MEM_PAGES};
+use crate::extensible_hash::HashTable;
+use crate::hash_join::{fanout, partition_of, MAX_DEPTH};
+use crate::page::PAGE_SIZE;
+use crate::spill_file::SpillFile;
+use crate::tuple::Tuple;
+use crate::value::Value;
+
+/// The state of an aggregate function for one group.
+struct Accumulator {
+    /// Number of aggregated arguments, NULLs are skipped except by `COUNT(*)`.
+    count: i64,
+    /// The sum, minimum or maximum so far, NULL before the first argument.
+    value: Value,
+    /// The arguments seen so far, for aggregates over distinct arguments.
+    seen: Option<HashTable<Value, ()>>,
+}
+
+impl Accumulator {
+    fn new(aggregate: &Aggregate) -> Self {
+        Self {
+            count: 0,
+            value: Value::Null,
+            seen: if aggregate.distinct {
+                Some(HashTable::new())
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Estimated bytes of memory used by the state.
+    fn size(&self) -> usize {
+        let seen = self.seen.iter().flat_map(HashTable::keys);
+        std::mem::size_of::<Self>()
+            + seen
+                .map(|arg| row_size(std::slice::from_ref(arg)))
+                .sum::<usize>()
+    }
+
+    /// Adds the argument of a row and returns the number of bytes the state grew by.
+    fn add(&mut self, aggregate: &Aggregate, arg: &Value) -> Result<usize, String> {
+        if aggregate.arg.is_none() {
+            self.count += 1;
+            return Ok(0);
+        }
+        if arg.is_null() {
+            return Ok(0);
+        }
+        let mut size = 0;
+        if let Some(seen) = &mut self.seen {
+            if seen.insert(arg.clone(), ()).is_some() {
+                return Ok(0);
+            }
+            size = row_size(std::slice::from_ref(arg));
+        }
+        self.count += 1;
+        self.accumulate(aggregate, arg)?;
+        Ok(size)
+    }
+
+    /// Adds the state of another part of the same group, like `add`.
+    fn merge(&mut self, aggregate: &Aggregate, other: Accumulator) -> Result<usize, String> {
+        if let Some(seen) = other.seen {
+            let mut size = 0;
+            for (arg, ()) in seen {
+                size += self.add(aggregate, &arg)?;
+            }
+            return Ok(size);
+        }
+        self.count += other.count;
+        if !other.value.is_null() {
+            self.accumulate(aggregate, &other.value)?;
+        }
+        Ok(0)
+    }
+
+    /// Combines the sum, minimum or maximum with a value.
+    fn accumulate(&mut self, aggregate: &Aggregate, arg: &Value) -> Result<(), String> {
+        match aggregate.function {
+            AggregateFunction::Count => {}
+            AggregateFunction::Sum | AggregateFunction::Avg => {
+                // integers are summed as BIGINT, so that sums of INT columns do not overflow
+                let arg = match arg {
+                    Value::Int(v) => Value::BigInt(*v as i64),
+                    Value::BigInt(_) | Value::Float(_) => arg.clone(),
+                    _ => {
+                        return Err(format!(
+                            "function {}({}) does not exist",
+                            aggregate.function,
+                            arg.data_type().unwrap()
+                        ))
+                    }
+                };
+                self.value = match self.value {
+                    Value::Null => arg,
+                    _ => self.value.add(&arg)?,
+                };
+            }
+            AggregateFunction::Min | AggregateFunction::Max => {
+                let replace = self.value.is_null()
+                    || match arg.compare(&self.value)? {
+                        Some(Ordering::Less) => aggregate.function == AggregateFunction::Min,
+                        Some(Ordering::Greater) => aggregate.function == AggregateFunction::Max,
+                        _ => false,
+                    };
+                if replace {
+                    self.value = arg.clone();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends the state to a row of a spill file.
+    fn encode(&self, row: &mut Tuple) {
+        row.push(Value::BigInt(self.count));
+        row.push(self.value.clone());
+        if let Some(seen) = &self.seen {
+            row.push(Value::BigInt(seen.len() as i64));
+            row.extend(seen.keys().cloned());
+        }
+    }
+
+    /// Reads a state written by `encode`.
+    fn decode(aggregate: &Aggregate, row: &mut IntoIter<Value>) -> Result<Self, String> {
+        let mut next = || {
+            row.next()
+                .ok_or_else(|| "spilled aggregate state is truncated".to_owned())
+        };
+        let count = match next()? {
+            Value::BigInt(count) => count,
+            _ => return Err("spilled aggregate state is invalid".to_owned()),
+        };
+        let value = next()?;
+        let seen = match aggregate.distinct {
+            true => {
+                let len = match next()? {
+                    Value::BigInt(len) => len,
+                    _ => return Err("spilled aggregate state is invalid".to_owned()),
+                };
+                let mut seen = HashTable::with_capacity(len as usize);
+                for _ in 0..len {
+                    seen.insert(next()?, ());
+                }
+                Some(seen)
+            }
+            false => None,
+        };
+        Ok(Self { count, value, seen })
+    }
+
+    fn finish(&self, aggregate: &Aggregate) -> Value {
+        match aggregate.function {
+            AggregateFunction::Count => Value::BigInt(self.count),
+            AggregateFunction::Avg => match self.value {
+                Value::BigInt(sum) => Value::Float(sum as f64 / self.count as f64),
+                Value::Float(sum) => Value::Float(sum / self.count as f64),
+                _ => Value::Null,
+            },
+            _ => self.value.clone(),
+        }
+    }
+}
+
+/// Evaluates the group key of a row and the arguments of the aggregates,
+/// the argument of `COUNT(*)` is NULL.
+fn evaluate(
+    db: &mut Database,
+    group_by: &[Expr],
+    aggregates: &[Aggregate],
+    fields: &[Field],
+    values: &[Value],
+) -> Result<(Vec<Value>, Vec<Value>), String> {
+    let row = PlanRow { fields, values };
+    let key = group_by
+        .iter()
+        .map(|expr| db.eval(expr, &row))
+        .collect::<Result<_, _>>()?;
+    let args = aggregates
+        .iter()
+        .map(|aggregate| match &aggregate.arg {
+            Some(arg) => db.eval(arg, &row),
+            None => Ok(Value::Null),
+        })
+        .collect::<Result<_, _>>()?;
+    Ok((key, args))
+}
+
+/// The output row of a group, its key followed by the results of the aggregates.
+fn output(aggregates: &[Aggregate], key: Vec<Value>, states: &[Accumulator]) -> Tuple {
+    let mut row = key;
+    row.extend(
+        aggregates
+            .iter()
+            .zip(states)
+            .map(|(aggregate, state)| state.finish(aggregate)),
+    );
+    row
+}
+
+/// Estimated bytes of memory used by a group.
+fn group_size(key: &[Value], states: &[Accumulator]) -> usize {
+    row_size(key) + states.iter().map(Accumulator::size).sum::<usize>()
+}
+
+/// What is added to a group: the arguments of an input row,
+/// or the states of a part of the group that was spilled from memory.
+enum Update {
+    Row(Vec<Value>),
+    State(Vec<Accumulator>),
+}
+
+impl Update {
+    /// Adds the update to the states of its group, returns the number of bytes they grew by.
+    fn apply(self, aggregates: &[Aggregate], states: &mut [Accumulator]) -> Result<usize, String> {
+        let mut size = 0;
+        match self {
+            Update::Row(args) => {
+                for ((aggregate, state), arg) in aggregates.iter().zip(states).zip(&args) {
+                    size += state.add(aggregate, arg)?;
+                }
+            }
+            Update::State(others) => {
+                for ((aggregate, state), other) in aggregates.iter().zip(states).zip(others) {
+                    size += state.merge(aggregate, other)?;
+                }
+            }
+        }
+        Ok(size)
+    }
+
+    /// The row of a spill file with the update of the group with the given key,
+    /// the key is followed by whether the update is a state.
+    fn encode(self, key: Vec<Value>) -> Tuple {
+        let mut row = key;
+        match self {
+            Update::Row(args) => {
+                row.push(Value::Boolean(false));
+                row.extend(args);
+            }
+            Update::State(states) => {
+                row.push(Value::Boolean(true));
+                for state in &states {
+                    state.encode(&mut row);
+                }
+            }
+        }
+        row
+    }
+
+    /// Reads an update written by `encode`, without the key.
+    fn decode(aggregates: &[Aggregate], row: Tuple) -> Result<Self, String> {
+        let mut row = row.into_iter();
+        match row.next() {
+            Some(Value::Boolean(false)) => Ok(Update::Row(row.collect())),
+            Some(Value::Boolean(true)) => aggregates
+                .iter()
+                .map(|aggregate| Accumulator::decode(aggregate, &mut row))
+                .collect::<Result<_, _>>()
+                .map(Update::State),
+            _ => Err("spilled aggregate row is invalid".to_owned()),
+        }
+    }
+}
+
+/// Aggregates groups in a hash table, returning them in no particular order.
+///
+/// Once the groups do not fit into memory anymore, rows of new groups are partitioned
+/// by the hash of their key and written to spill files with their evaluated arguments,
+/// while the groups in memory are still updated. A group in memory whose distinct arguments
+/// outgrow the memory is written to its partition with the states of its aggregates.
+/// The partitions are aggregated one after another once the groups in memory have been returned,
+/// and partitioned again if they are still too large.
+pub struct HashAggregate {
+    input: Box<dyn Operator>,
+    group_by: Vec<Expr>,
+    aggregates: Vec<Aggregate>,
+    fields: Vec<Field>,
+    /// Bytes of groups kept in memory.
+    memory: usize,
+    groups: HashTable<Vec<Value>, Vec<Accumulator>>,
+    size: usize,
+    /// The partitions rows are spilled to during the current pass, created when needed.
+    spilling: Vec<Option<SpillFile>>,
+    /// The partitions still to be aggregated and the number of times their rows were partitioned.
+    partitions: Vec<(SpillFile, usize)>,
+    output: IntoIter<Tuple>,
+    /// Number of partitions written, including those of partitions that were split again.
+    spilled: usize,
+}
+
+impl HashAggregate {
+    pub fn new(
+        input: Box<dyn Operator>,
+        group_by: Vec<Expr>,
+        aggregates: Vec<Aggregate>,
+        fields: Vec<Field>,
+    ) -> Self {
+        Self {
+            input,
+            group_by,
+            aggregates,
+            fields,
+            memory: WORK_MEM_PAGES * PAGE_SIZE,
+            groups: HashTable::new(),
+            size: 0,
+            spilling: Vec::new(),
+            partitions: Vec::new(),
+            output: Vec::new().into_iter(),
+            spilled: 0,
+        }
+    }
+
+    /// Limits the groups held in memory to about the given number of pages.
+    #[cfg(test)]
+    pub fn with_buffer_pages(mut self, pages: usize) -> Self {
+        self.memory = pages.max(1) * PAGE_SIZE;
+        self
+    }
+
+    fn add(&mut self, key: Vec<Value>, update: Update, depth: usize) -> Result<(), String> {
+        // groups that are partitioned too often mostly have the same key and stay in memory
+        let spill = depth < MAX_DEPTH;
+        if let Some(states) = self.groups.get_mut(&key) {
+            let grown = update.apply(&self.aggregates, states)?;
+            self.size += grown;
+            // a group whose distinct arguments outgrow the memory is spilled with its states
+            if grown > 0 && self.size > self.memory && spill {
+                let states = self.groups.remove(&key).unwrap();
+                self.size = self.size.saturating_sub(group_size(&key, &states));
+                return self.spill(key, Update::State(states), depth);
+            }
+            return Ok(());
+        }
+        let mut states: Vec<_> = self.aggregates.iter().map(Accumulator::new).collect();
+        let base = group_size(&key, &states);
+        let size = match &update {
+            Update::Row(_) => base,
+            Update::State(others) => group_size(&key, others),
+        };
+        // once groups have been spilled, rows of their keys must not start new groups in memory
+        if (!self.spilling.is_empty() || self.size + size > self.memory) && spill {
+            return self.spill(key, update, depth);
+        }
+        self.size += base + update.apply(&self.aggregates, &mut states)?;
+        self.groups.insert(key, states);
+        Ok(())
+    }
+
+    /// Writes the update of a group that is not kept in memory to the group's partition.
+    fn spill(&mut self, key: Vec<Value>, update: Update, depth: usize) -> Result<(), String> {
+        if self.spilling.is_empty() {
+            self.spilling = (0..fanout(self.memory)).map(|_| None).collect();
+        }
+        let p = partition_of(&key, depth, self.spilling.len());
+        let file = match &mut self.spilling[p] {
+            Some(file) => file,
+            file => {
+                self.spilled += 1;
+                file.insert(SpillFile::create()?)
+            }
+        };
+        file.write(&update.encode(key))
+    }
+
+    /// Aggregates the rows of the opened input in the first pass.
+    fn read_input(&mut self, db: &mut Database) -> Result<(), String> {
+        while let Some(row) = self.input.next(db)? {
+            let (key, args) = evaluate(
+                db,
+                &self.group_by,
+                &self.aggregates,
+                self.input.schema(),
+                &row,
+            )?;
+            self.add(key, Update::Row(args), 0)?;
+        }
+        self.finish_pass(0);
+        Ok(())
+    }
+
+    /// Returns the groups in memory and queues the partitions spilled during the pass.
+    fn finish_pass(&mut self, depth: usize) {
+        let aggregates = &self.aggregates;
+        let mut rows: Vec<_> = std::mem::take(&mut self.groups)
+            .into_iter()
+            .map(|(key, states)| output(aggregates, key, &states))
+            .collect();
+        if rows.is_empty() && self.group_by.is_empty() && depth == 0 {
+            let states: Vec<_> = aggregates.iter().map(Accumulator::new).collect();
+            rows.push(output(aggregates, Vec::new(), &states));
+        }
+        self.size = 0;
+        for file in self.spilling.drain(..).flatten() {
+            self.partitions.push((file, depth + 1));
+        }
+        self.output = rows.into_iter();
+    }
+}
+
+impl Operator for HashAggregate {
+    fn open(&mut self, db: &mut Database) -> Result<(), String> {
+        self.groups.clear();
+        self.size = 0;
+        self.spilling.clear();
+        self.partitions.clear();
+        self.spilled = 0;
+        self.input.open(db)?;
+        let result = self.read_input(db);
+        close_on_error(result, db, &mut [self.input.as_mut()])
+    }
+
+    fn next(&mut self, _db: &mut Database) -> Result<Option<Tuple>, String> {
+        loop {
+            if let Some(row) = self.output.next() {
+                return Ok(Some(row));
+            }
+            let (mut file, depth) = match self.partitions.pop() {
+                Some(partition) => partition,
+                None => return Ok(None),
+            };
+            let mut reader = file.read()?;
+            while let Some(mut key) = reader.next()? {
+                let update = Update::decode(&self.aggregates, key.split_off(self.group_by.len()))?;
+                self.add(key, update, depth)?;
+            }
+            self.finish_pass(depth);
+        }
+    }
+
+    fn close(&mut self, db: &mut Database) -> Result<(), String> {
+        self.groups.clear();
+        self.spilling.clear();
+        self.partitions.clear();
+        self.output = Vec::new().into_iter();
+        self.input.close(db)
+    }
+
+    fn schema(&self) -> &[Field] {
+        &self.fields
+    }
+}
+
+/// Aggregates an input whose rows are sorted on the group expressions, or at least grouped,
+/// so that only the current group has to be kept. Groups are returned in the order of the input.
+pub struct StreamAggregate {
+    input: Box<dyn Operator>,
+    group_by: Vec<Expr>,
+    aggregates: Vec<Aggregate>,
+    fields: Vec<Field>,
+    /// The key and states of the current group.
+    group: Option<(Vec<Value>, Vec<Accumulator>)>,
+    done: bool,
+}
+
+impl StreamAggregate {
+    pub fn new(
+        input: Box<dyn Operator>,
+        group_by: Vec<Expr>,
+        aggregates: Vec<Aggregate>,
+        fields: Vec<Field>,
+    ) -> Self {
+        Self {
+            input,
+            group_by,
+            aggregates,
+            fields,
+            group: None,
+            done: false,
+        }
+    }
+}
+
+impl Operator for StreamAggregate {
+    fn open(&mut self, db: &mut Database) -> Result<(), String> {
+        self.group = None;
+        self.done = false;
+        self.input.open(db)
+    }
+
+    fn next(&mut self, db: &mut Database) -> Result<Option<Tuple>, String> {
+        if self.done {
+            return Ok(None);
+        }
+        while let Some(row) = self.input.next(db)? {
+            let (key, args) = evaluate(
+                db,
+                &self.group_by,
+                &self.aggregates,
+                self.input.schema(),
+                &row,
+            )?;
+            let finished = match &self.group {
+                Some((current, _)) if *current == key => None,
+                _ => self
+                    .group
+                    .replace((key, self.aggregates.iter().map(Accumulator::new).collect())),
+            };
+            let (_, states) = self.group.as_mut().unwrap();
+            for ((aggregate, state), arg) in self.aggregates.iter().zip(states).zip(&args) {
+                state.add(aggregate, arg)?;
+            }
+            if let Some((key, states)) = finished {
+                return Ok(Some(output(&self.aggregates, key, &states)));
+            }
+        }
+        self.done = true;
+        let aggregates = &self.aggregates;
+        Ok(match self.group.take() {
+            Some((key, states)) => Some(output(aggregates, key, &states)),
+            // without groups there is a single row even if the input is empty
+            None if self.group_by.is_empty() => {
+                let states: Vec<_> = aggregates.iter().map(Accumulator::new).collect();
+                Some(output(aggregates, Vec::new(), &states))
+            }
+            None => None,
+        })
+    }
+
+    fn close(&mut self, db: &mut Database) -> Result<(), String> {
+        self.group = None;
+        self.input.close(db)
+    }
+
+    fn schema(&self) -> &[Field] {
+        &self.fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use crate::binder::SortKey;
+    use crate::executor::{collect, TempDatabase, Values};
+    use crate::external_sort::Sort;
+    use crate::sql::{parse_expr, parse_query, parse_sql};
+
+    fn aggregate(function: AggregateFunction, arg: Option<&str>, distinct: bool) -> Aggregate {
+        Aggregate {
+            function,
+            arg: arg.map(|arg| parse_expr(arg).unwrap()),
+            distinct,
+        }
+    }
+
+    #[test]
+    fn aggregates() {
+        let mut db = TempDatabase::open("aggregate");
+        let rows: Vec<(Option<i64>, Option<i64>)> = (0..5000)
+            .map(|i| {
+                let g = if i % 97 == 0 { None } else { Some(i % 1000) };
+                let x = if i % 5 == 0 { None } else { Some(i % 7) };
+                (g, x)
+            })
+            .collect();
+        let values = |rows: &[(Option<i64>, Option<i64>)]| {
+            let literal = |v: Option<i64>| {
+                parse_expr(&v.map_or("NULL".to_owned(), |v| v.to_string())).unwrap()
+            };
+            let rows = rows
+                .iter()
+                .map(|&(g, x)| vec![literal(g), literal(x)])
+                .collect();
+            Box::new(Values::new(
+                rows,
+                vec![Field::new(None, "g"), Field::new(None, "x")],
+            ))
+        };
+        let aggregates = vec![
+            aggregate(AggregateFunction::Count, None, false),
+            aggregate(AggregateFunction::Count, Some("x"), false),
+            aggregate(AggregateFunction::Sum, Some("x"), false),
+            aggregate(AggregateFunction::Avg, Some("x"), false),
+            aggregate(AggregateFunction::Min, Some("x"), false),
+            aggregate(AggregateFunction::Max, Some("x"), false),
+            aggregate(AggregateFunction::Count, Some("x"), true),
+        ];
+        let fields: Vec<_> = ["g", "count", "count", "sum", "avg", "min", "max", "count"]
+            .iter()
+            .map(|name| Field::new(None, name))
+            .collect();
+
+        let mut groups: BTreeMap<Option<i64>, Vec<i64>> = BTreeMap::new();
+        for &(g, x) in &rows {
+            let group = groups.entry(g).or_default();
+            group.extend(x);
+            // rows with a NULL argument only count for COUNT(*)
+            group.push(-1);
+        }
+        let mut expected: Vec<Tuple> = groups
+            .into_iter()
+            .map(|(g, group)| {
+                let count = group.iter().filter(|&&x| x == -1).count() as i64;
+                let mut xs: Vec<i64> = group.into_iter().filter(|&x| x >= 0).collect();
+                let sum: i64 = xs.iter().sum();
+                let n = xs.len() as i64;
+                let (min, max) = (xs.iter().min().copied(), xs.iter().max().copied());
+                xs.sort_unstable();
+                xs.dedup();
+                let optional = |v: Option<i64>| v.map_or(Value::Null, Value::BigInt);
+                vec![
+                    optional(g),
+                    Value::BigInt(count),
+                    Value::BigInt(n),
+                    if n == 0 {
+                        Value::Null
+                    } else {
+                        Value::BigInt(sum)
+                    },
+                    if n == 0 {
+                        Value::Null
+                    } else {
+                        Value::Float(sum as f64 / n as f64)
+                    },
+                    optional(min),
+                    optional(max),
+                    Value::BigInt(xs.len() as i64),
+                ]
+            })
+            .collect();
+        expected.sort();
+
+        let group_by = vec![parse_expr("g").unwrap()];
+        let mut hash = HashAggregate::new(
+            values(&rows),
+            group_by.clone(),
+            aggregates.clone(),
+            fields.clone(),
+        )
+        .with_buffer_pages(2);
+        let mut result = collect(&mut hash, &mut db).unwrap();
+        result.sort();
+        assert_eq!(result, expected);
+        assert!(hash.spilled > 2, "{}", hash.spilled);
+
+        let sorted = Sort::new(
+            values(&rows),
+            vec![SortKey {
+                expr: parse_expr("g").unwrap(),
+                asc: true,
+                nulls_first: false,
+            }],
+        );
+        let mut stream = StreamAggregate::new(
+            Box::new(sorted),
+            group_by,
+            aggregates.clone(),
+            fields.clone(),
+        );
+        // sorted groups come out in order, NULL last
+        assert_eq!(collect(&mut stream, &mut db).unwrap(), expected);
+
+        // without groups there is a single row, even for an empty input
+        for rows in [&rows[..0], &rows[..]] {
+            let mut hash = HashAggregate::new(
+                values(rows),
+                Vec::new(),
+                aggregates.clone(),
+                fields[1..].to_vec(),
+            );
+            let mut stream = StreamAggregate::new(
+                values(rows),
+                Vec::new(),
+                aggregates.clone(),
+                fields[1..].to_vec(),
+            );
+            let result = collect(&mut hash, &mut db).unwrap();
+            assert_eq!(result.len(), 1);
+            assert_eq!(result, collect(&mut stream, &mut db).unwrap());
+        }
+        let mut empty = HashAggregate::new(
+            values(&[]),
+            Vec::new(),
+            aggregates.clone(),
+            fields[1..].to_vec(),
+        );
+        assert_eq!(
+            collect(&mut empty, &mut db).unwrap()[0],
+            vec![
+                Value::BigInt(0),
+                Value::BigInt(0),
+                Value::Null,
+                Value::Null,
+                Value::Null,
+                Value::Null,
+                Value::BigInt(0),
+            ]
+        );
+
+        let mut text = HashAggregate::new(
+            values(&rows[..1]),
+            Vec::new(),
+            vec![aggregate(AggregateFunction::Sum, Some("'x'"), false)],
+            fields[..1].to_vec(),
+        );
+        assert!(collect(&mut text, &mut db).is_err());
+    }
+
+    #[test]
+    fn distinct_spilling() {
+        let mut db = TempDatabase::open("aggregate_distinct");
+        // few groups, each with more distinct arguments than fit into memory
+        let rows: Vec<_> = (0..6000).map(|i| (i % 3, i / 2)).collect();
+        let values = rows
+            .iter()
+            .map(|(g, x)| {
+                vec![
+                    parse_expr(&g.to_string()).unwrap(),
+                    parse_expr(&x.to_string()).unwrap(),
+                ]
+            })
+            .collect();
+        let mut hash = HashAggregate::new(
+            Box::new(Values::new(
+                values,
+                vec![Field::new(None, "g"), Field::new(None, "x")],
+            )),
+            vec![parse_expr("g").unwrap()],
+            vec![
+                aggregate(AggregateFunction::Count, None, false),
+                aggregate(AggregateFunction::Count, Some("x"), true),
+                aggregate(AggregateFunction::Sum, Some("x"), true),
+                aggregate(AggregateFunction::Max, Some("x"), false),
+            ],
+            ["g", "count", "count", "sum", "max"]
+                .iter()
+                .map(|name| Field::new(None, name))
+                .collect(),
+        )
+        .with_buffer_pages(2);
+        let mut result = collect(&mut hash, &mut db).unwrap();
+        result.sort();
+        let expected: Vec<Tuple> = (0..3)
+            .map(|g| {
+                let mut xs: Vec<i64> = rows.iter().filter(|r| r.0 == g).map(|r| r.1).collect();
+                let (count, max) = (xs.len() as i64, *xs.iter().max().unwrap());
+                xs.dedup();
+                vec![
+                    Value::BigInt(g),
+                    Value::BigInt(count),
+                    Value::BigInt(xs.len() as i64),
+                    Value::BigInt(xs.iter().sum()),
+                    Value::BigInt(max),
+                ]
+            })
+            .collect();
+        assert_eq!(result, expected);
+        assert!(hash.spilled > 0);
+    }
+
+    #[test]
+    fn having() {
+        let mut db = TempDatabase::open("aggregate_having");
+        for sql in [
+            "CREATE TABLE t (g INT, x INT)",
+            "INSERT INTO t VALUES (1, 1), (1, 1), (1, 2), (2, 5), (3, 1), (3, NULL), (NULL, 4)",
+        ] {
+            db.execute(&parse_sql(sql).unwrap()[0]).unwrap();
+        }
+        let query = parse_query(
+            "SELECT g, count(DISTINCT x), sum(x) FROM t GROUP BY g \
+             HAVING count(*) > 1 AND count(DISTINCT x) < 2 OR sum(DISTINCT x) > 3",
+        )
+        .unwrap();
+        let mut rows = db.query(&query).unwrap().rows;
+        rows.sort();
+        let int = |v: i64| Value::BigInt(v);
+        assert_eq!(
+            rows,
+            vec![
+                vec![Value::Int(2), int(1), int(5)],
+                vec![Value::Int(3), int(1), int(1)],
+                vec![Value::Null, int(1), int(4)],
+            ]
+        );
+    }
+}
@@ -0,0 +1,1914 @@
+// Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use sqlparser::ast::{
+    BinaryOperator, Expr, Function, FunctionArg, Ident, Join, JoinConstraint, JoinOperator,
+    ObjectName, Offset, OrderByExpr, Query, Select, SelectItem, SetExpr, TableFactor,
+    TableWithJoins, UnaryOperator, Value as Literal, WindowFrameBound, WindowFrameUnits,
+};
+
+use crate::catalog::Catalog;
+use crate::expression::{column_references, conjuncts, display_name, evaluate_constant, replace};
+use crate::sql::ident_name;
+use crate::value::Value;
+
+/// A column of a plan's output.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    /// Name of the table, view or alias the column belongs to, used to qualify references.
+    pub table: Option<String>,
+    pub name: String,
+    /// Hidden fields can only be referenced with a qualifier and are not part of `*`,
+    /// like the columns merged by `JOIN ... USING`.
+    pub hidden: bool,
+}
+
+impl Field {
+    pub fn new(table: Option<&str>, name: &str) -> Self {
+        Self {
+            table: table.map(str::to_owned),
+            name: name.to_owned(),
+            hidden: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JoinKind {
+    Inner,
+    Left,
+    Right,
+    Full,
+    /// Each left row that has a matching right row, once and without the right columns.
+    Semi,
+    /// Each left row that has no matching right row, without the right columns.
+    Anti,
+}
+
+/// An `ORDER BY` key, evaluated on the input of the sort.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SortKey {
+    pub expr: Expr,
+    pub asc: bool,
+    pub nulls_first: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl fmt::Display for AggregateFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            AggregateFunction::Count => "count",
+            AggregateFunction::Sum => "sum",
+            AggregateFunction::Avg => "avg",
+            AggregateFunction::Min => "min",
+            AggregateFunction::Max => "max",
+        })
+    }
+}
+
+/// A call of an aggregate function, its argument is evaluated on the input of the aggregation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Aggregate {
+    pub function: AggregateFunction,
+    /// `None` for `COUNT(*)`, which counts all rows.
+    pub arg: Option<Expr>,
+    /// Whether each distinct argument is only aggregated once.
+    pub distinct: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowFunction {
+    RowNumber,
+    Rank,
+    DenseRank,
+    Lag,
+    Lead,
+    /// An aggregate function computed over the frame of each row.
+    Aggregate(AggregateFunction),
+}
+
+/// The rows of its partition a window function aggregates for a row.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WindowFrame {
+    /// Whether the bounds count rows (`ROWS`),
+    /// otherwise `CURRENT ROW` stands for all peers of the row (`RANGE`).
+    pub rows: bool,
+    pub start: WindowFrameBound,
+    pub end: WindowFrameBound,
+}
+
+/// A call of a window function, its arguments are evaluated on the input of the window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WindowCall {
+    pub function: WindowFunction,
+    /// The arguments, none for `COUNT(*)`.
+    pub args: Vec<Expr>,
+    pub frame: WindowFrame,
+}
+
+/// A logical query plan, the result of binding a query against the catalog.
+/// Expressions are kept as SQL ASTs,
+/// every column reference in them resolves to exactly one field of the node's input.
+#[derive(Clone, Debug)]
+pub enum Plan {
+    /// All rows of a table.
+    Scan { table: usize, fields: Vec<Field> },
+    /// Rows of constant expressions, e.g. the single empty row a `SELECT` without `FROM` reads.
+    Values {
+        rows: Vec<Vec<Expr>>,
+        fields: Vec<Field>,
+    },
+    /// The rows of the input for which the predicate is true.
+    Filter { input: Box<Plan>, predicate: Expr },
+    Project {
+        input: Box<Plan>,
+        exprs: Vec<Expr>,
+        fields: Vec<Field>,
+    },
+    /// Pairs of rows satisfying the condition, with the fields of both sides.
+    /// Outer joins pad unmatched rows with NULLs, semi and anti joins only have the left fields.
+    Join {
+        left: Box<Plan>,
+        right: Box<Plan>,
+        kind: JoinKind,
+        condition: Option<Expr>,
+        fields: Vec<Field>,
+    },
+    /// Renames the output of a subquery, view or common table expression.
+    /// Only the first columns are kept if there are fewer fields than the input has.
+    Alias {
+        input: Box<Plan>,
+        fields: Vec<Field>,
+    },
+    Sort {
+        input: Box<Plan>,
+        keys: Vec<SortKey>,
+    },
+    /// Skips `offset` rows and returns at most `limit` of the following ones.
+    Limit {
+        input: Box<Plan>,
+        limit: Option<usize>,
+        offset: usize,
+    },
+    /// Removes duplicate rows, keeping the first of each.
+    Distinct { input: Box<Plan> },
+    /// One row per group of input rows with equal values of the group expressions,
+    /// with the group values followed by the results of the aggregates.
+    /// There is exactly one row if there are no group expressions, even for an empty input.
+    Aggregate {
+        input: Box<Plan>,
+        group_by: Vec<Expr>,
+        aggregates: Vec<Aggregate>,
+        fields: Vec<Field>,
+    },
+    /// The rows of the input followed by the results of window functions, which are computed
+    /// over the rows with the same values of the partition expressions, in the window's order.
+    /// The rows are returned sorted by partition and in the window's order.
+    Window {
+        input: Box<Plan>,
+        partition_by: Vec<Expr>,
+        order_by: Vec<SortKey>,
+        functions: Vec<WindowCall>,
+        fields: Vec<Field>,
+    },
+}
+
+impl Plan {
+    /// The columns of the rows this plan produces.
+    pub fn fields(&self) -> &[Field] {
+        match self {
+            Plan::Scan { fields, .. }
+            | Plan::Values { fields, .. }
+            | Plan::Project { fields, .. }
+            | Plan::Join { fields, .. }
+            | Plan::Alias { fields, .. }
+            | Plan::Aggregate { fields, .. }
+            | Plan::Window { fields, .. } => fields,
+            Plan::Filter { input, .. }
+            | Plan::Sort { input, .. }
+            | Plan::Limit { input, .. }
+            | Plan::Distinct { input } => input.fields(),
+        }
+    }
+}
+
+/// Finds the field a possibly qualified column reference refers to.
+pub fn resolve(fields: &[Field], name: &[Ident]) -> Result<usize, String> {
+    let (table, column) = match name {
+        [column] => (None, ident_name(column)),
+        [table, column] => (Some(ident_name(table)), ident_name(column)),
+        _ => {
+            return Err(format!(
+                "improper qualified name (too many dotted names): {}",
+                display_name(name)
+            ))
+        }
+    };
+    let mut matches = fields.iter().enumerate().filter(|(_, f)| {
+        f.name == column
+            && match &table {
+                Some(table) => f.table.as_ref() == Some(table),
+                None => !f.hidden,
+            }
+    });
+    match (matches.next(), matches.next()) {
+        (Some((i, _)), None) => Ok(i),
+        (Some(_), Some(_)) => Err(format!(
+            "column reference \"{}\" is ambiguous",
+            display_name(name)
+        )),
+        (None, _) => match table {
+            Some(table) if !fields.iter().any(|f| f.table.as_ref() == Some(&table)) => {
+                Err(format!("missing FROM-clause entry for table \"{}\"", table))
+            }
+            _ => Err(format!("column \"{}\" does not exist", display_name(name))),
+        },
+    }
+}
+
+/// Resolves the relations of queries against the catalog and checks their column references.
+/// Views and common table expressions are expanded into the plan of their query.
+pub struct Binder<'a> {
+    catalog: &'a Catalog,
+    /// Common table expressions in scope, innermost last.
+    ctes: Vec<(String, Plan)>,
+    /// Names of the views being expanded.
+    views: Vec<String>,
+    dependencies: Vec<usize>,
+}
+
+impl<'a> Binder<'a> {
+    pub fn new(catalog: &'a Catalog) -> Self {
+        Self {
+            catalog,
+            ctes: Vec::new(),
+            views: Vec::new(),
+            dependencies: Vec::new(),
+        }
+    }
+
+    /// IDs of the tables and views referenced by the bound queries, not counting those
+    /// only referenced inside views.
+    pub fn dependencies(&self) -> Vec<usize> {
+        let mut dependencies = self.dependencies.clone();
+        dependencies.sort_unstable();
+        dependencies.dedup();
+        dependencies
+    }
+
+    pub fn bind_query(&mut self, query: &Query) -> Result<Plan, String> {
+        let scope = self.ctes.len();
+        let result = self.bind_query_in_scope(query);
+        self.ctes.truncate(scope);
+        result
+    }
+
+    fn bind_query_in_scope(&mut self, query: &Query) -> Result<Plan, String> {
+        if let Some(with) = &query.with {
+            if with.recursive {
+                return Err("WITH RECURSIVE is not supported".to_owned());
+            }
+            for cte in &with.cte_tables {
+                let name = ident_name(&cte.alias.name);
+                let plan = self.bind_query(&cte.query)?;
+                let plan = alias(plan, &name, &cte.alias.columns)?;
+                self.ctes.push((name, plan));
+            }
+        }
+
+        let mut plan = match &query.body {
+            SetExpr::Select(select) => self.bind_select(select, &query.order_by)?,
+            body => {
+                let plan = self.bind_set_expr(body)?;
+                let fields = plan.fields().to_vec();
+                let keys = query
+                    .order_by
+                    .iter()
+                    .map(|o| {
+                        let expr = output_column(&o.expr, &fields, &[])?
+                            .map_or_else(|| o.expr.clone(), |i| field_ref(&fields, i));
+                        check_columns(&expr, &fields)?;
+                        Ok(sort_key(o, expr))
+                    })
+                    .collect::<Result<Vec<_>, String>>()?;
+                sort(plan, keys)
+            }
+        };
+
+        let mut limit = match &query.limit {
+            Some(expr) => Some(row_count(expr, "LIMIT")?),
+            None => None,
+        };
+        if let Some(fetch) = &query.fetch {
+            if fetch.percent || fetch.with_ties {
+                return Err("FETCH ... PERCENT and WITH TIES are not supported".to_owned());
+            }
+            let count = match &fetch.quantity {
+                Some(expr) => row_count(expr, "FETCH")?,
+                None => 1,
+            };
+            limit = Some(limit.map_or(count, |l| l.min(count)));
+        }
+        let offset = match &query.offset {
+            Some(Offset { value, .. }) => row_count(value, "OFFSET")?,
+            None => 0,
+        };
+        if limit.is_some() || offset > 0 {
+            plan = Plan::Limit {
+                input: Box::new(plan),
+                limit,
+                offset,
+            };
+        }
+        Ok(plan)
+    }
+
+    fn bind_set_expr(&mut self, body: &SetExpr) -> Result<Plan, String> {
+        match body {
+            SetExpr::Select(select) => self.bind_select(select, &[]),
+            SetExpr::Query(query) => self.bind_query(query),
+            SetExpr::Values(values) => {
+                let width = values.0.first().map_or(0, Vec::len);
+                if values.0.iter().any(|row| row.len() != width) {
+                    return Err("VALUES lists must all be the same length".to_owned());
+                }
+                for expr in values.0.iter().flatten() {
+                    check_columns(expr, &[])?;
+                }
+                let fields = (1..=width)
+                    .map(|i| Field::new(None, &format!("column{}", i)))
+                    .collect();
+                Ok(Plan::Values {
+                    rows: values.0.clone(),
+                    fields,
+                })
+            }
+            SetExpr::SetOperation { op, .. } => Err(format!("{} is not supported", op)),
+            SetExpr::Insert(_) => Err("INSERT is not supported in a query".to_owned()),
+        }
+    }
+
+    /// Binds a `SELECT`, sorting its rows before the projection,
+    /// so `ORDER BY` can use both output columns and columns of the `FROM` clause.
+    fn bind_select(&mut self, select: &Select, order_by: &[OrderByExpr]) -> Result<Plan, String> {
+        if select.top.is_some() || !select.lateral_views.is_empty() {
+            return Err("TOP and LATERAL VIEW are not supported".to_owned());
+        }
+
+        let mut plan = self.bind_from(&select.from)?;
+        if let Some(predicate) = &select.selection {
+            plan = self.bind_where(plan, predicate)?;
+        }
+
+        let input = plan.fields().to_vec();
+        let mut exprs = Vec::new();
+        let mut fields = Vec::new();
+        for item in &select.projection {
+            match item {
+                SelectItem::UnnamedExpr(expr) => {
+                    check_columns(expr, &input)?;
+                    exprs.push(expr.clone());
+                    fields.push(Field::new(None, &output_name(expr)));
+                }
+                SelectItem::ExprWithAlias { expr, alias } => {
+                    check_columns(expr, &input)?;
+                    exprs.push(expr.clone());
+                    fields.push(Field::new(None, &ident_name(alias)));
+                }
+                SelectItem::Wildcard => {
+                    if select.from.is_empty() {
+                        return Err("SELECT * with no tables specified is not valid".to_owned());
+                    }
+                    for (i, field) in input.iter().enumerate().filter(|(_, f)| !f.hidden) {
+                        exprs.push(checked_field_ref(&input, i)?);
+                        fields.push(Field::new(None, &field.name));
+                    }
+                }
+                SelectItem::QualifiedWildcard(name) => {
+                    let table = object_name(name)?;
+                    let matching: Vec<_> = (0..input.len())
+                        .filter(|&i| input[i].table.as_ref() == Some(&table))
+                        .collect();
+                    if matching.is_empty() {
+                        return Err(format!("missing FROM-clause entry for table \"{}\"", table));
+                    }
+                    for i in matching {
+                        exprs.push(checked_field_ref(&input, i)?);
+                        fields.push(Field::new(None, &input[i].name));
+                    }
+                }
+            }
+        }
+
+        let mut keys = Vec::new();
+        for o in order_by {
+            let expr = match output_column(&o.expr, &fields, &input)? {
+                Some(i) => exprs[i].clone(),
+                None if select.distinct => {
+                    return Err(
+                        "for SELECT DISTINCT, ORDER BY expressions must appear in select list"
+                            .to_owned(),
+                    )
+                }
+                None => {
+                    check_columns(&o.expr, &input)?;
+                    o.expr.clone()
+                }
+            };
+            keys.push(sort_key(o, expr));
+        }
+
+        let mut windows = Vec::new();
+        for expr in &mut exprs {
+            *expr = bind_windows(expr, &input, &mut windows)?;
+        }
+        for key in &mut keys {
+            key.expr = bind_windows(&key.expr, &input, &mut windows)?;
+        }
+
+        if !select.group_by.is_empty()
+            || select.having.is_some()
+            || exprs.iter().any(contains_aggregate)
+            || keys.iter().any(|key| contains_aggregate(&key.expr))
+        {
+            if !windows.is_empty() {
+                return Err(
+                    "window functions in queries with GROUP BY or aggregates are not supported"
+                        .to_owned(),
+                );
+            }
+            let mut aggregation = Aggregation::new(&select.group_by, &exprs, &fields, &input)?;
+            for expr in &mut exprs {
+                *expr = aggregation.rewrite(expr)?;
+            }
+            for key in &mut keys {
+                key.expr = aggregation.rewrite(&key.expr)?;
+            }
+            let having = match &select.having {
+                Some(having) => {
+                    check_columns(having, &input)?;
+                    Some(aggregation.rewrite(having)?)
+                }
+                None => None,
+            };
+            plan = Plan::Aggregate {
+                input: Box::new(plan),
+                group_by: aggregation.group_by,
+                aggregates: aggregation.aggregates,
+                fields: aggregation.fields,
+            };
+            if let Some(predicate) = having {
+                plan = Plan::Filter {
+                    input: Box::new(plan),
+                    predicate,
+                };
+            }
+        }
+        for window in windows {
+            let mut fields = plan.fields().to_vec();
+            fields.extend(window.names.iter().map(|name| Field::new(None, name)));
+            plan = Plan::Window {
+                input: Box::new(plan),
+                partition_by: window.partition_by,
+                order_by: window.order_by,
+                functions: window.functions,
+                fields,
+            };
+        }
+        plan = sort(plan, keys);
+
+        plan = Plan::Project {
+            input: Box::new(plan),
+            exprs,
+            fields,
+        };
+        if select.distinct {
+            plan = Plan::Distinct {
+                input: Box::new(plan),
+            };
+        }
+        Ok(plan)
+    }
+
+    /// Binds the `FROM` clause of a `SELECT`, the cross join of its items.
+    fn bind_from(&mut self, from: &[TableWithJoins]) -> Result<Plan, String> {
+        let mut plan = Plan::Values {
+            rows: vec![Vec::new()],
+            fields: Vec::new(),
+        };
+        for (i, from) in from.iter().enumerate() {
+            let right = self.bind_table_with_joins(from)?;
+            plan = if i == 0 {
+                right
+            } else {
+                join(plan, right, JoinKind::Inner, None)?
+            };
+        }
+        Ok(plan)
+    }
+
+    /// Filters a plan by the predicate of a `WHERE` clause.
+    /// Its `[NOT] EXISTS` and `[NOT] IN` conditions with a subquery become semi and anti joins
+    /// with the subquery, above a filter by the other conditions.
+    fn bind_where(&mut self, mut plan: Plan, predicate: &Expr) -> Result<Plan, String> {
+        let (subqueries, filters): (Vec<_>, Vec<_>) = conjuncts(predicate)
+            .into_iter()
+            .partition(|e| subquery_condition(e).is_some());
+        let predicate = match subqueries.is_empty() {
+            true => Some(predicate.clone()),
+            false => filters.into_iter().cloned().reduce(and),
+        };
+        if let Some(predicate) = predicate {
+            check_columns(&predicate, plan.fields())?;
+            if contains_aggregate(&predicate) {
+                return Err("aggregate functions are not allowed in WHERE".to_owned());
+            }
+            if contains_window(&predicate) {
+                return Err("window functions are not allowed in WHERE".to_owned());
+            }
+            if contains_subquery(&predicate) {
+                return Err(
+                    "subqueries are only supported by EXISTS and IN conditions of WHERE combined with AND"
+                        .to_owned(),
+                );
+            }
+            plan = Plan::Filter {
+                input: Box::new(plan),
+                predicate,
+            };
+        }
+        for condition in subqueries.into_iter().filter_map(subquery_condition) {
+            plan = self.bind_subquery(plan, condition)?;
+        }
+        Ok(plan)
+    }
+
+    /// Joins a plan with the subquery of a `[NOT] EXISTS` or `[NOT] IN` condition,
+    /// keeping the rows the condition is true for.
+    ///
+    /// The `WHERE` conditions of a simple `SELECT` that refer to columns of the plan
+    /// (correlated subqueries) become the condition of the join.
+    /// The subquery's columns are renamed to hidden fields of the join,
+    /// so they do not clash with the plan's.
+    fn bind_subquery(&mut self, plan: Plan, condition: SubqueryCondition) -> Result<Plan, String> {
+        let kind = match condition.negated {
+            true => JoinKind::Anti,
+            false => JoinKind::Semi,
+        };
+        let (subquery, output, mut conditions) = match correlatable(condition.query) {
+            Some(select) if condition.expr.is_none() || single_expr(select).is_some() => {
+                let mut subquery = self.bind_from(&select.from)?;
+                let fields = subquery.fields().to_vec();
+                // conditions only on the subquery's columns filter it before the join
+                let (inner, correlated): (Vec<_>, Vec<_>) = select
+                    .selection
+                    .iter()
+                    .flat_map(conjuncts)
+                    .partition(|e| check_columns(e, &fields).is_ok());
+                if let Some(predicate) = inner.into_iter().cloned().reduce(and) {
+                    subquery = self.bind_where(subquery, &predicate)?;
+                }
+                let conditions = correlated
+                    .into_iter()
+                    .map(|e| subquery_columns(e, &fields))
+                    .collect::<Result<Vec<_>, String>>()?;
+                let output = match single_expr(select) {
+                    Some(expr) if condition.expr.is_some() => {
+                        check_columns(expr, &fields)?;
+                        Some(subquery_columns(expr, &fields)?)
+                    }
+                    _ => None,
+                };
+                (subquery, output, conditions)
+            }
+            _ => {
+                let subquery = self.bind_query(condition.query)?;
+                let output = match (condition.expr, subquery.fields().len()) {
+                    (None, _) => None,
+                    (Some(_), 1) => Some(subquery_field(0)),
+                    (Some(_), _) => return Err("subquery has too many columns".to_owned()),
+                };
+                (subquery, output, Vec::new())
+            }
+        };
+        if let (Some(expr), Some(output)) = (condition.expr, output) {
+            let eq = Expr::BinaryOp {
+                left: Box::new(expr.clone()),
+                op: BinaryOperator::Eq,
+                right: Box::new(output.clone()),
+            };
+            conditions.push(match kind {
+                // `NOT IN` is not true if either side is NULL, unless the subquery is empty
+                JoinKind::Anti => {
+                    let or = |left, right| Expr::BinaryOp {
+                        left: Box::new(left),
+                        op: BinaryOperator::Or,
+                        right: Box::new(right),
+                    };
+                    let is_null = |expr| Expr::IsNull(Box::new(expr));
+                    Expr::Nested(Box::new(or(or(eq, is_null(expr.clone())), is_null(output))))
+                }
+                _ => eq,
+            });
+        }
+
+        let fields = (0..subquery.fields().len())
+            .map(|i| Field {
+                table: Some(SUBQUERY.to_owned()),
+                name: i.to_string(),
+                hidden: true,
+            })
+            .collect();
+        let subquery = Plan::Alias {
+            input: Box::new(subquery),
+            fields,
+        };
+        let condition = conditions.into_iter().reduce(and);
+        if let Some(condition) = &condition {
+            check_columns(condition, &[plan.fields(), subquery.fields()].concat())?;
+        }
+        Ok(Plan::Join {
+            fields: plan.fields().to_vec(),
+            left: Box::new(plan),
+            right: Box::new(subquery),
+            kind,
+            condition,
+        })
+    }
+
+    fn bind_table_with_joins(&mut self, from: &TableWithJoins) -> Result<Plan, String> {
+        let mut plan = self.bind_table_factor(&from.relation)?;
+        for Join {
+            relation,
+            join_operator,
+        } in &from.joins
+        {
+            let right = self.bind_table_factor(relation)?;
+            let (kind, constraint) = match join_operator {
+                JoinOperator::Inner(c) => (JoinKind::Inner, c),
+                JoinOperator::LeftOuter(c) => (JoinKind::Left, c),
+                JoinOperator::RightOuter(c) => (JoinKind::Right, c),
+                JoinOperator::FullOuter(c) => (JoinKind::Full, c),
+                JoinOperator::CrossJoin => (JoinKind::Inner, &JoinConstraint::None),
+                _ => return Err("CROSS APPLY and OUTER APPLY are not supported".to_owned()),
+            };
+            plan = match constraint {
+                JoinConstraint::On(condition) => {
+                    let plan = join(plan, right, kind, None)?;
+                    check_columns(condition, plan.fields())?;
+                    match plan {
+                        Plan::Join {
+                            left,
+                            right,
+                            fields,
+                            ..
+                        } => Plan::Join {
+                            left,
+                            right,
+                            kind,
+                            condition: Some(condition.clone()),
+                            fields,
+                        },
+                        _ => unreachable!(),
+                    }
+                }
+                JoinConstraint::None => join(plan, right, kind, None)?,
+                JoinConstraint::Using(columns) => {
+                    let columns: Vec<_> = columns.iter().map(ident_name).collect();
+                    using_join(plan, right, kind, &columns)?
+                }
+                JoinConstraint::Natural => {
+                    let visible = |plan: &Plan| -> Vec<String> {
+                        let fields = plan.fields().iter().filter(|f| !f.hidden);
+                        fields.map(|f| f.name.clone()).collect()
+                    };
+                    let right_names = visible(&right);
+                    let mut columns = visible(&plan);
+                    columns.retain(|c| right_names.contains(c));
+                    columns.dedup();
+                    using_join(plan, right, kind, &columns)?
+                }
+            };
+        }
+        Ok(plan)
+    }
+
+    fn bind_table_factor(&mut self, factor: &TableFactor) -> Result<Plan, String> {
+        match factor {
+            TableFactor::Table {
+                name,
+                alias: table_alias,
+                args,
+                ..
+            } => {
+                if !args.is_empty() {
+                    return Err("table functions are not supported".to_owned());
+                }
+                let name = object_name(name)?;
+                let (qualifier, columns) = match table_alias {
+                    Some(a) => (ident_name(&a.name), a.columns.as_slice()),
+                    None => (name.clone(), &[][..]),
+                };
+                if let Some((_, plan)) = self.ctes.iter().rev().find(|(cte, _)| *cte == name) {
+                    return alias(plan.clone(), &qualifier, columns);
+                }
+                if let Some(view) = self.catalog.get_view(&name) {
+                    if self.views.is_empty() {
+                        self.dependencies.push(view.id);
+                    }
+                    if self.views.contains(&name) {
+                        return Err(format!("infinite recursion detected in view \"{}\"", name));
+                    }
+                    // a view does not see the common table expressions of the query using it
+                    let ctes = std::mem::take(&mut self.ctes);
+                    self.views.push(name.clone());
+                    let result = self.bind_query(&view.query);
+                    self.views.pop();
+                    self.ctes = ctes;
+                    // a query using `*` also returns columns added to its tables later
+                    let plan = result?;
+                    if plan.fields().len() < view.columns.len() {
+                        return Err(format!("definition of view \"{}\" is invalid", name));
+                    }
+                    let names: Vec<_> = view.columns.iter().collect();
+                    return Ok(Plan::Alias {
+                        input: Box::new(plan),
+                        fields: renamed(&names, &qualifier, columns)?,
+                    });
+                }
+                let table = match self.catalog.get_table(&name) {
+                    Some(table) => table,
+                    None => return Err(format!("relation \"{}\" does not exist", name)),
+                };
+                if self.views.is_empty() {
+                    self.dependencies.push(table.id);
+                }
+                let names: Vec<_> = table.schema.columns().iter().map(|c| &c.name).collect();
+                let fields = renamed(&names, &qualifier, columns)?;
+                Ok(Plan::Scan {
+                    table: table.id,
+                    fields,
+                })
+            }
+            TableFactor::Derived {
+                lateral,
+                subquery,
+                alias: table_alias,
+            } => {
+                if *lateral {
+                    return Err("LATERAL subqueries are not supported".to_owned());
+                }
+                let table_alias = match table_alias {
+                    Some(a) => a,
+                    None => return Err("subquery in FROM must have an alias".to_owned()),
+                };
+                let plan = self.bind_query(subquery)?;
+                alias(plan, &ident_name(&table_alias.name), &table_alias.columns)
+            }
+            TableFactor::NestedJoin(from) => self.bind_table_with_joins(from),
+            TableFactor::TableFunction { .. } => {
+                Err("table functions are not supported".to_owned())
+            }
+        }
+    }
+}
+
+/// Qualifier of the hidden fields a subquery's columns are renamed to, see `bind_subquery`.
+const SUBQUERY: &str = "?subquery?";
+
+/// A `[NOT] EXISTS` or `[NOT] IN` condition with a subquery.
+struct SubqueryCondition<'a> {
+    /// The expression compared with the subquery's rows by `IN`.
+    expr: Option<&'a Expr>,
+    query: &'a Query,
+    negated: bool,
+}
+
+fn subquery_condition(expr: &Expr) -> Option<SubqueryCondition<'_>> {
+    match expr {
+        Expr::Exists(query) => Some(SubqueryCondition {
+            expr: None,
+            query,
+            negated: false,
+        }),
+        Expr::InSubquery {
+            expr,
+            subquery,
+            negated,
+        } => Some(SubqueryCondition {
+            expr: Some(expr),
+            query: subquery,
+            negated: *negated,
+        }),
+        Expr::UnaryOp {
+            op: UnaryOperator::Not,
+            expr,
+        } => subquery_condition(expr).map(|c| SubqueryCondition {
+            negated: !c.negated,
+            ..c
+        }),
+        Expr::Nested(expr) => subquery_condition(expr),
+        _ => None,
+    }
+}
+
+/// The `SELECT` of a subquery whose conditions can be joined with the outer query's rows,
+/// one without grouping, limits or common table expressions.
+fn correlatable(query: &Query) -> Option<&Select> {
+    match &query.body {
+        SetExpr::Select(select)
+            if query.with.is_none()
+                && query.limit.is_none()
+                && query.offset.is_none()
+                && query.fetch.is_none()
+                && select.top.is_none()
+                && select.lateral_views.is_empty()
+                && select.group_by.is_empty()
+                && select.having.is_none()
+                && !select.projection.iter().any(|item| match item {
+                    SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => {
+                        contains_aggregate(expr) || contains_window(expr)
+                    }
+                    _ => false,
+                }) =>
+        {
+            Some(select)
+        }
+        _ => None,
+    }
+}
+
+/// The expression of a select list with a single one.
+fn single_expr(select: &Select) -> Option<&Expr> {
+    match select.projection.as_slice() {
+        [SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. }] => Some(expr),
+        _ => None,
+    }
+}
+
+/// A reference to the hidden field of the i-th column of a subquery.
+fn subquery_field(i: usize) -> Expr {
+    Expr::CompoundIdentifier(vec![
+        Ident::with_quote('"', SUBQUERY),
+        Ident::with_quote('"', i.to_string()),
+    ])
+}
+
+/// Replaces the references to the subquery's columns in an expression
+/// by references to their hidden fields, the other references are to the outer query.
+fn subquery_columns(expr: &Expr, fields: &[Field]) -> Result<Expr, String> {
+    let mut result = Ok(());
+    let mut expr = expr.clone();
+    replace(&mut expr, &mut |e| {
+        let name = match e {
+            Expr::Identifier(ident) => std::slice::from_ref(ident),
+            Expr::CompoundIdentifier(idents) => idents.as_slice(),
+            _ => return None,
+        };
+        // columns of the subquery hide those of the outer query
+        if !fields
+            .iter()
+            .any(|f| resolve(std::slice::from_ref(f), name).is_ok())
+        {
+            return None;
+        }
+        match resolve(fields, name) {
+            Ok(i) => Some(subquery_field(i)),
+            Err(e) => {
+                result = Err(e);
+                None
+            }
+        }
+    });
+    result.map(|_| expr)
+}
+
+/// The conjunction `left AND right`.
+pub(crate) fn and(left: Expr, right: Expr) -> Expr {
+    Expr::BinaryOp {
+        left: Box::new(left),
+        op: BinaryOperator::And,
+        right: Box::new(right),
+    }
+}
+
+/// Checks that all column references of an expression resolve to one of the fields.
+fn check_columns(expr: &Expr, fields: &[Field]) -> Result<(), String> {
+    for name in column_references(expr) {
+        resolve(fields, &name)?;
+    }
+    Ok(())
+}
+
+/// Builds a reference to the given field that resolves to it.
+fn field_ref(fields: &[Field], i: usize) -> Expr {
+    let column = Ident::with_quote('"', &fields[i].name);
+    match &fields[i].table {
+        Some(table) => Expr::CompoundIdentifier(vec![Ident::with_quote('"', table), column]),
+        None => Expr::Identifier(column),
+    }
+}
+
+/// Like `field_ref`, but fails if the reference would be ambiguous.
+fn checked_field_ref(fields: &[Field], i: usize) -> Result<Expr, String> {
+    let expr = field_ref(fields, i);
+    match &expr {
+        Expr::Identifier(ident) if resolve(fields, std::slice::from_ref(ident)) != Ok(i) => Err(
+            format!("column reference \"{}\" is ambiguous", fields[i].name),
+        ),
+        Expr::CompoundIdentifier(idents) if resolve(fields, idents) != Ok(i) => Err(format!(
+            "column reference \"{}\" is ambiguous",
+            display_name(idents)
+        )),
+        _ => Ok(expr),
+    }
+}
+
+/// Finds the output column an `ORDER BY` expression refers to, by position or name.
+/// Names of input columns take precedence over output names in expressions, like in PostgreSQL,
+/// but a bare name refers to the output column.
+fn output_column(expr: &Expr, output: &[Field], input: &[Field]) -> Result<Option<usize>, String> {
+    match expr {
+        Expr::Value(Literal::Number(n, _)) => match n.parse::<usize>() {
+            Ok(i) if (1..=output.len()).contains(&i) => Ok(Some(i - 1)),
+            _ => Err(format!("ORDER BY position {} is not in select list", n)),
+        },
+        Expr::Identifier(ident) => {
+            let name = ident_name(ident);
+            let mut matches = (0..output.len()).filter(|&i| output[i].name == name);
+            match (matches.next(), matches.next()) {
+                (Some(i), None) => Ok(Some(i)),
+                (Some(_), Some(_)) if resolve(input, std::slice::from_ref(ident)).is_err() => {
+                    Err(format!("ORDER BY \"{}\" is ambiguous", name))
+                }
+                _ => Ok(None),
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Collects the groups and aggregates of a `SELECT` and rewrites its expressions
+/// to refer to the columns of the aggregation's output instead.
+struct Aggregation {
+    group_by: Vec<Expr>,
+    aggregates: Vec<Aggregate>,
+    /// The output of the aggregation, grouped columns keep their input field
+    /// so that references to them still resolve.
+    fields: Vec<Field>,
+}
+
+impl Aggregation {
+    /// Binds the `GROUP BY` expressions, which can also be positions and names of output columns.
+    fn new(
+        group_by: &[Expr],
+        exprs: &[Expr],
+        output: &[Field],
+        input: &[Field],
+    ) -> Result<Self, String> {
+        let mut aggregation = Self {
+            group_by: Vec::new(),
+            aggregates: Vec::new(),
+            fields: Vec::new(),
+        };
+        for expr in group_by {
+            // names of input columns take precedence over output names, like in PostgreSQL
+            let expr = match expr {
+                Expr::Value(Literal::Number(n, _)) => match n.parse::<usize>() {
+                    Ok(i) if (1..=exprs.len()).contains(&i) => exprs[i - 1].clone(),
+                    _ => return Err(format!("GROUP BY position {} is not in select list", n)),
+                },
+                Expr::Identifier(ident) if resolve(input, std::slice::from_ref(ident)).is_err() => {
+                    let name = ident_name(ident);
+                    match output.iter().position(|f| f.name == name) {
+                        Some(i) => exprs[i].clone(),
+                        None => expr.clone(),
+                    }
+                }
+                expr => expr.clone(),
+            };
+            check_columns(&expr, input)?;
+            if contains_aggregate(&expr) {
+                return Err("aggregate functions are not allowed in GROUP BY".to_owned());
+            }
+            let field = match &expr {
+                Expr::Identifier(ident) => {
+                    input[resolve(input, std::slice::from_ref(ident))?].clone()
+                }
+                Expr::CompoundIdentifier(idents) => input[resolve(input, idents)?].clone(),
+                _ => Field::new(None, &format!("?group{}?", aggregation.group_by.len() + 1)),
+            };
+            if aggregation.group_by.contains(&expr) || aggregation.fields.contains(&field) {
+                continue;
+            }
+            aggregation.group_by.push(expr);
+            aggregation.fields.push(field);
+        }
+        Ok(aggregation)
+    }
+
+    /// Replaces the aggregate calls and group expressions in an expression
+    /// by references to the output of the aggregation.
+    fn rewrite(&mut self, expr: &Expr) -> Result<Expr, String> {
+        let mut expr = expr.clone();
+        let mut error = None;
+        let group_by = &self.group_by;
+        let aggregates = &mut self.aggregates;
+        let fields = &mut self.fields;
+        replace(&mut expr, &mut |e| match aggregate_call(e) {
+            Ok(Some(aggregate)) => {
+                if aggregate.arg.as_ref().is_some_and(contains_aggregate) {
+                    error = Some("aggregate function calls cannot be nested".to_owned());
+                    return None;
+                }
+                let i = match aggregates.iter().position(|a| *a == aggregate) {
+                    Some(i) => i,
+                    None => {
+                        aggregates.push(aggregate);
+                        let name = format!("?aggregate{}?", aggregates.len());
+                        fields.push(Field::new(None, &name));
+                        aggregates.len() - 1
+                    }
+                };
+                Some(field_ref(fields, group_by.len() + i))
+            }
+            Ok(None) => group_by
+                .iter()
+                .position(|g| g == e)
+                .map(|i| field_ref(fields, i)),
+            Err(err) => {
+                error = Some(err);
+                None
+            }
+        });
+        if let Some(err) = error {
+            return Err(err);
+        }
+        for name in column_references(&expr) {
+            if resolve(&self.fields, &name).is_err() {
+                return Err(format!(
+                    "column \"{}\" must appear in the GROUP BY clause or be used in an aggregate function",
+                    display_name(&name)
+                ));
+            }
+        }
+        Ok(expr)
+    }
+}
+
+/// Binds a call of an aggregate function, `None` if the expression is not one.
+fn aggregate_call(expr: &Expr) -> Result<Option<Aggregate>, String> {
+    let function = match expr {
+        Expr::Function(function) if function.over.is_none() => function,
+        _ => return Ok(None),
+    };
+    let name = function.name.to_string().to_lowercase();
+    let kind = match name.as_str() {
+        "count" => AggregateFunction::Count,
+        "sum" => AggregateFunction::Sum,
+        "avg" => AggregateFunction::Avg,
+        "min" => AggregateFunction::Min,
+        "max" => AggregateFunction::Max,
+        _ => return Ok(None),
+    };
+    let arg = match function.args.as_slice() {
+        [FunctionArg::Unnamed(Expr::Wildcard)]
+            if kind == AggregateFunction::Count && !function.distinct =>
+        {
+            None
+        }
+        [FunctionArg::Unnamed(Expr::Wildcard)] => {
+            return Err(format!("{}(*) is not supported", name))
+        }
+        [FunctionArg::Unnamed(arg)] => Some(arg.clone()),
+        _ => return Err(format!("function {} takes 1 argument", name)),
+    };
+    Ok(Some(Aggregate {
+        function: kind,
+        arg,
+        distinct: function.distinct,
+    }))
+}
+
+/// Whether an expression calls an aggregate function.
+fn contains_aggregate(expr: &Expr) -> bool {
+    let mut found = false;
+    replace(&mut expr.clone(), &mut |e| {
+        found |= !matches!(aggregate_call(e), Ok(None));
+        None
+    });
+    found
+}
+
+/// Whether an expression contains a subquery.
+fn contains_subquery(expr: &Expr) -> bool {
+    let mut found = false;
+    replace(&mut expr.clone(), &mut |e| {
+        found |= matches!(
+            e,
+            Expr::Exists(_) | Expr::InSubquery { .. } | Expr::Subquery(_)
+        );
+        None
+    });
+    found
+}
+
+/// Window function calls of a `SELECT` with the same partitions and order,
+/// which are computed by the same window.
+struct Window {
+    partition_by: Vec<Expr>,
+    order_by: Vec<SortKey>,
+    functions: Vec<WindowCall>,
+    /// Names of the fields of the functions' results.
+    names: Vec<String>,
+}
+
+/// Replaces the window function calls in an expression by references to their results,
+/// adding the calls to the windows computing them.
+fn bind_windows(expr: &Expr, input: &[Field], windows: &mut Vec<Window>) -> Result<Expr, String> {
+    let mut expr = expr.clone();
+    let mut error = None;
+    replace(&mut expr, &mut |e| {
+        let (partition_by, order_by, call) = match window_call(e, input) {
+            Ok(Some(call)) => call,
+            Ok(None) => return None,
+            Err(err) => {
+                error = Some(err);
+                return None;
+            }
+        };
+        let count = windows.iter().map(|w| w.names.len()).sum::<usize>();
+        let i = match windows
+            .iter()
+            .position(|w| w.partition_by == partition_by && w.order_by == order_by)
+        {
+            Some(i) => i,
+            None => {
+                windows.push(Window {
+                    partition_by,
+                    order_by,
+                    functions: Vec::new(),
+                    names: Vec::new(),
+                });
+                windows.len() - 1
+            }
+        };
+        let window = &mut windows[i];
+        let name = match window.functions.iter().position(|f| *f == call) {
+            Some(j) => window.names[j].clone(),
+            None => {
+                let name = format!("?window{}?", count + 1);
+                window.functions.push(call);
+                window.names.push(name.clone());
+                name
+            }
+        };
+        Some(Expr::Identifier(Ident::with_quote('"', name)))
+    });
+    match error {
+        Some(err) => Err(err),
+        None => Ok(expr),
+    }
+}
+
+/// Binds a call of a window function with its partitions and order,
+/// `None` if the expression is not one.
+#[allow(clippy::type_complexity)]
+fn window_call(
+    expr: &Expr,
+    input: &[Field],
+) -> Result<Option<(Vec<Expr>, Vec<SortKey>, WindowCall)>, String> {
+    let (function, over) = match expr {
+        Expr::Function(function) => match &function.over {
+            Some(over) => (function, over),
+            None => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    let name = function.name.to_string().to_lowercase();
+    let kind = match name.as_str() {
+        "row_number" => WindowFunction::RowNumber,
+        "rank" => WindowFunction::Rank,
+        "dense_rank" => WindowFunction::DenseRank,
+        "lag" => WindowFunction::Lag,
+        "lead" => WindowFunction::Lead,
+        "count" => WindowFunction::Aggregate(AggregateFunction::Count),
+        "sum" => WindowFunction::Aggregate(AggregateFunction::Sum),
+        "avg" => WindowFunction::Aggregate(AggregateFunction::Avg),
+        "min" => WindowFunction::Aggregate(AggregateFunction::Min),
+        "max" => WindowFunction::Aggregate(AggregateFunction::Max),
+        _ => return Err(format!("window function {} does not exist", name)),
+    };
+    if function.distinct {
+        return Err("DISTINCT is not implemented for window functions".to_owned());
+    }
+    let args = function
+        .args
+        .iter()
+        .map(|arg| match arg {
+            FunctionArg::Unnamed(Expr::Wildcard)
+                if kind == WindowFunction::Aggregate(AggregateFunction::Count) =>
+            {
+                Ok(None)
+            }
+            FunctionArg::Unnamed(Expr::Wildcard) => Err(format!("{}(*) is not supported", name)),
+            FunctionArg::Unnamed(arg) => Ok(Some(arg.clone())),
+            FunctionArg::Named { name, .. } => {
+                Err(format!("named arguments are not supported: {}", name))
+            }
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let args = match (kind, args.as_slice()) {
+        (WindowFunction::Aggregate(AggregateFunction::Count), [None]) => Vec::new(),
+        (WindowFunction::RowNumber | WindowFunction::Rank | WindowFunction::DenseRank, [])
+        | (WindowFunction::Lag | WindowFunction::Lead, [Some(_)])
+        | (WindowFunction::Lag | WindowFunction::Lead, [Some(_), Some(_)])
+        | (WindowFunction::Lag | WindowFunction::Lead, [Some(_), Some(_), Some(_)])
+        | (WindowFunction::Aggregate(_), [Some(_)]) => args.into_iter().flatten().collect(),
+        _ => {
+            return Err(format!(
+                "function {} does not take {} arguments",
+                name,
+                args.len()
+            ))
+        }
+    };
+    let order_by: Vec<_> = over
+        .order_by
+        .iter()
+        .map(|o| sort_key(o, o.expr.clone()))
+        .collect();
+    for expr in args
+        .iter()
+        .chain(&over.partition_by)
+        .chain(order_by.iter().map(|key| &key.expr))
+    {
+        check_columns(expr, input)?;
+        if contains_window(expr) {
+            return Err("window function calls cannot be nested".to_owned());
+        }
+        if contains_aggregate(expr) {
+            return Err("aggregate functions are not allowed in window definitions".to_owned());
+        }
+    }
+
+    // without a frame, aggregates cover the partition up to the last peer of the row
+    let frame = match &over.window_frame {
+        None => WindowFrame {
+            rows: false,
+            start: WindowFrameBound::Preceding(None),
+            end: WindowFrameBound::CurrentRow,
+        },
+        Some(frame) => WindowFrame {
+            rows: match frame.units {
+                WindowFrameUnits::Rows => true,
+                WindowFrameUnits::Range => false,
+                WindowFrameUnits::Groups => {
+                    return Err("GROUPS frames are not supported".to_owned())
+                }
+            },
+            start: frame.start_bound.clone(),
+            end: frame
+                .end_bound
+                .clone()
+                .unwrap_or(WindowFrameBound::CurrentRow),
+        },
+    };
+    match (&frame.start, &frame.end) {
+        (WindowFrameBound::Following(None), _) => {
+            return Err("frame start cannot be UNBOUNDED FOLLOWING".to_owned())
+        }
+        (_, WindowFrameBound::Preceding(None)) => {
+            return Err("frame end cannot be UNBOUNDED PRECEDING".to_owned())
+        }
+        (WindowFrameBound::Preceding(Some(_)) | WindowFrameBound::Following(Some(_)), _)
+        | (_, WindowFrameBound::Preceding(Some(_)) | WindowFrameBound::Following(Some(_)))
+            if !frame.rows =>
+        {
+            return Err("RANGE with offset PRECEDING or FOLLOWING is not supported".to_owned())
+        }
+        _ => {}
+    }
+    Ok(Some((
+        over.partition_by.clone(),
+        order_by,
+        WindowCall {
+            function: kind,
+            args,
+            frame,
+        },
+    )))
+}
+
+/// Whether an expression calls a window function.
+fn contains_window(expr: &Expr) -> bool {
+    let mut found = false;
+    replace(&mut expr.clone(), &mut |e| {
+        found |= matches!(e, Expr::Function(function) if function.over.is_some());
+        None
+    });
+    found
+}
+
+fn sort_key(order_by: &OrderByExpr, expr: Expr) -> SortKey {
+    let asc = order_by.asc.unwrap_or(true);
+    SortKey {
+        expr,
+        asc,
+        // NULLs are larger than all other values
+        nulls_first: order_by.nulls_first.unwrap_or(!asc),
+    }
+}
+
+fn sort(plan: Plan, keys: Vec<SortKey>) -> Plan {
+    if keys.is_empty() {
+        return plan;
+    }
+    Plan::Sort {
+        input: Box::new(plan),
+        keys,
+    }
+}
+
+/// Evaluates the row count of a `LIMIT`, `OFFSET` or `FETCH` clause.
+fn row_count(expr: &Expr, clause: &str) -> Result<usize, String> {
+    match evaluate_constant(expr)?.cast(crate::catalog::DataType::BigInt)? {
+        Value::BigInt(n) => {
+            usize::try_from(n).map_err(|_| format!("{} must not be negative", clause))
+        }
+        _ => Err(format!("{} must not be NULL", clause)),
+    }
+}
+
+/// Joins two plans, which must not use the same table names.
+fn join(left: Plan, right: Plan, kind: JoinKind, condition: Option<Expr>) -> Result<Plan, String> {
+    let mut fields = left.fields().to_vec();
+    for field in right.fields() {
+        if let Some(table) = &field.table {
+            if left
+                .fields()
+                .iter()
+                .any(|f| f.table.as_ref() == Some(table))
+            {
+                return Err(format!("table name \"{}\" specified more than once", table));
+            }
+        }
+        fields.push(field.clone());
+    }
+    Ok(Plan::Join {
+        left: Box::new(left),
+        right: Box::new(right),
+        kind,
+        condition,
+        fields,
+    })
+}
+
+/// Joins two plans on equality of the given columns, which are merged into one output column
+/// in front of the other columns, like `JOIN ... USING` and `NATURAL JOIN`.
+fn using_join(left: Plan, right: Plan, kind: JoinKind, columns: &[String]) -> Result<Plan, String> {
+    let width = left.fields().len();
+    let mut pairs = Vec::new();
+    for (i, column) in columns.iter().enumerate() {
+        if columns[..i].contains(column) {
+            return Err(format!(
+                "column name \"{}\" appears more than once in USING clause",
+                column
+            ));
+        }
+        let name = [Ident::with_quote('"', column)];
+        let find = |plan: &Plan, side: &str| {
+            resolve(plan.fields(), &name).map_err(|_| {
+                format!(
+                    "column \"{}\" specified in USING clause does not exist in {} table",
+                    column, side
+                )
+            })
+        };
+        pairs.push((find(&left, "left")?, width + find(&right, "right")?));
+    }
+
+    let mut plan = join(left, right, kind, None)?;
+    let mut fields = plan.fields().to_vec();
+    let mut condition = None;
+    let mut exprs = Vec::new();
+    for (l, r) in &pairs {
+        let (l, r) = (
+            checked_field_ref(&fields, *l)?,
+            checked_field_ref(&fields, *r)?,
+        );
+        let eq = Expr::BinaryOp {
+            left: Box::new(l.clone()),
+            op: BinaryOperator::Eq,
+            right: Box::new(r.clone()),
+        };
+        condition = Some(match condition {
+            Some(c) => Expr::BinaryOp {
+                left: Box::new(c),
+                op: BinaryOperator::And,
+                right: Box::new(eq),
+            },
+            None => eq,
+        });
+        exprs.push(match kind {
+            JoinKind::Inner | JoinKind::Left | JoinKind::Semi | JoinKind::Anti => l,
+            JoinKind::Right => r,
+            JoinKind::Full => Expr::Function(Function {
+                name: ObjectName(vec![Ident::new("coalesce")]),
+                args: vec![FunctionArg::Unnamed(l), FunctionArg::Unnamed(r)],
+                over: None,
+                distinct: false,
+            }),
+        });
+    }
+    if let Plan::Join { condition: c, .. } = &mut plan {
+        *c = condition;
+    }
+
+    let mut output: Vec<_> = columns.iter().map(|c| Field::new(None, c)).collect();
+    for (l, r) in &pairs {
+        fields[*l].hidden = true;
+        fields[*r].hidden = true;
+    }
+    for (i, field) in fields.iter().enumerate() {
+        // fields that are hidden and unqualified cannot be referenced anymore
+        if field.hidden && field.table.is_none() {
+            continue;
+        }
+        exprs.push(checked_field_ref(plan.fields(), i)?);
+        output.push(field.clone());
+    }
+    Ok(Plan::Project {
+        input: Box::new(plan),
+        exprs,
+        fields: output,
+    })
+}
+
+/// Qualifies the output of a plan with a new table name,
+/// renaming as many of its columns as names are given.
+fn alias(plan: Plan, table: &str, columns: &[Ident]) -> Result<Plan, String> {
+    let names: Vec<_> = plan.fields().iter().map(|f| &f.name).collect();
+    let fields = renamed(&names, table, columns)?;
+    Ok(Plan::Alias {
+        input: Box::new(plan),
+        fields,
+    })
+}
+
+fn renamed(names: &[&String], table: &str, columns: &[Ident]) -> Result<Vec<Field>, String> {
+    if columns.len() > names.len() {
+        return Err(format!(
+            "table \"{}\" has {} columns available but {} columns specified",
+            table,
+            names.len(),
+            columns.len()
+        ));
+    }
+    Ok(names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| match columns.get(i) {
+            Some(column) => Field::new(Some(table), &ident_name(column)),
+            None => Field::new(Some(table), name),
+        })
+        .collect())
+}
+
+/// The column name PostgreSQL gives to a select list item without alias.
+fn output_name(expr: &Expr) -> String {
+    match expr {
+        Expr::Identifier(ident) => ident_name(ident),
+        Expr::CompoundIdentifier(idents) => idents.last().map_or_else(String::new, ident_name),
+        Expr::Function(f) => f.name.0.last().map_or_else(String::new, ident_name),
+        Expr::Cast { expr, .. } | Expr::Nested(expr) => output_name(expr),
+        _ => "?column?".to_owned(),
+    }
+}
+
+fn object_name(name: &ObjectName) -> Result<String, String> {
+    match name.0.as_slice() {
+        [ident] => Ok(ident_name(ident)),
+        _ => Err(format!("invalid relation name: {}", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::buffer_manager::BufferManager;
+    use crate::catalog::{Column, DataType, Schema, ViewMetadata};
+    use crate::disk_manager::TempFile;
+    use crate::sql::parse_query;
+
+    fn catalog(name: &str) -> (TempFile, BufferManager, Catalog) {
+        let file = TempFile::new(&format!("binder_{}", name));
+        let mut bm = BufferManager::with_file(16, file.path());
+        let mut catalog = Catalog::open(&mut bm).unwrap();
+        let t = Schema::new(vec![
+            Column::new("a", DataType::Int),
+            Column::new("b", DataType::Text),
+        ])
+        .unwrap();
+        let u = Schema::new(vec![
+            Column::new("a", DataType::Int),
+            Column::new("c", DataType::Int),
+        ])
+        .unwrap();
+        catalog.create_table(&mut bm, "t", &t).unwrap();
+        catalog.create_table(&mut bm, "u", &u).unwrap();
+        (file, bm, catalog)
+    }
+
+    fn bind(catalog: &Catalog, sql: &str) -> Result<Plan, String> {
+        Binder::new(catalog).bind_query(&parse_query(sql)?)
+    }
+
+    fn names(catalog: &Catalog, sql: &str) -> Result<Vec<String>, String> {
+        let plan = bind(catalog, sql)?;
+        Ok(plan.fields().iter().map(|f| f.name.clone()).collect())
+    }
+
+    /// IDs of the tables scanned by a plan.
+    fn scans(plan: &Plan) -> Vec<usize> {
+        match plan {
+            Plan::Scan { table, .. } => vec![*table],
+            Plan::Values { .. } => Vec::new(),
+            Plan::Join { left, right, .. } => [scans(left), scans(right)].concat(),
+            Plan::Filter { input, .. }
+            | Plan::Project { input, .. }
+            | Plan::Alias { input, .. }
+            | Plan::Sort { input, .. }
+            | Plan::Limit { input, .. }
+            | Plan::Distinct { input }
+            | Plan::Aggregate { input, .. }
+            | Plan::Window { input, .. } => scans(input),
+        }
+    }
+
+    #[test]
+    fn select_list() {
+        let (_file, _bm, catalog) = catalog("select_list");
+        assert_eq!(
+            names(&catalog, "SELECT *, a + 1, b AS x, upper(b) FROM t").unwrap(),
+            vec!["a", "b", "?column?", "x", "upper"]
+        );
+        assert_eq!(
+            names(&catalog, "SELECT u.* FROM t, u").unwrap(),
+            vec!["a", "c"]
+        );
+        assert_eq!(names(&catalog, "SELECT 1").unwrap(), vec!["?column?"]);
+        assert!(matches!(
+            bind(&catalog, "SELECT 1").unwrap(),
+            Plan::Project { input, .. } if matches!(*input, Plan::Values { .. })
+        ));
+        assert_eq!(
+            bind(&catalog, "SELECT c FROM t").unwrap_err(),
+            "column \"c\" does not exist"
+        );
+        assert_eq!(
+            bind(&catalog, "SELECT a FROM t, u").unwrap_err(),
+            "column reference \"a\" is ambiguous"
+        );
+        assert!(bind(&catalog, "SELECT t.a, u.a FROM t, u WHERE c > 0").is_ok());
+        assert_eq!(
+            bind(&catalog, "SELECT v.a FROM t").unwrap_err(),
+            "missing FROM-clause entry for table \"v\""
+        );
+        assert!(bind(&catalog, "SELECT * FROM t, t").is_err());
+        assert!(bind(&catalog, "SELECT x.a, y.a FROM t AS x, t AS y").is_ok());
+        assert!(bind(&catalog, "SELECT t.a FROM t AS x").is_err());
+        assert!(bind(&catalog, "SELECT * FROM nope").is_err());
+        assert!(bind(&catalog, "SELECT * FROM t WHERE d = 1").is_err());
+    }
+
+    #[test]
+    fn group_by() {
+        let (_file, _bm, catalog) = catalog("group_by");
+        let plan = bind(
+            &catalog,
+            "SELECT b, count(*), sum(a) + 1 AS s FROM t GROUP BY 1 HAVING max(a) > 1 ORDER BY count(*)",
+        )
+        .unwrap();
+        assert_eq!(
+            plan.fields()
+                .iter()
+                .map(|f| &f.name[..])
+                .collect::<Vec<_>>(),
+            vec!["b", "count", "s"]
+        );
+        let (group_by, aggregates) = match plan {
+            Plan::Project { input, .. } => match *input {
+                Plan::Sort { input, .. } => match *input {
+                    Plan::Filter { input, .. } => match *input {
+                        Plan::Aggregate {
+                            group_by,
+                            aggregates,
+                            ..
+                        } => (group_by, aggregates),
+                        other => panic!("unexpected plan: {:?}", other),
+                    },
+                    other => panic!("unexpected plan: {:?}", other),
+                },
+                other => panic!("unexpected plan: {:?}", other),
+            },
+            other => panic!("unexpected plan: {:?}", other),
+        };
+        assert_eq!(group_by.len(), 1);
+        assert_eq!(group_by[0].to_string(), "b");
+        // count(*) is computed once for the select list and ORDER BY
+        let functions: Vec<_> = aggregates.iter().map(|a| a.function).collect();
+        assert_eq!(
+            functions,
+            vec![
+                AggregateFunction::Count,
+                AggregateFunction::Sum,
+                AggregateFunction::Max
+            ]
+        );
+        assert_eq!(aggregates[0].arg, None);
+
+        assert!(bind(&catalog, "SELECT count(DISTINCT a), min(b) FROM t").is_ok());
+        assert!(bind(&catalog, "SELECT a + 1 FROM t GROUP BY a + 1").is_ok());
+        assert!(bind(&catalog, "SELECT t.a, sum(a) FROM t GROUP BY a").is_ok());
+        assert!(bind(&catalog, "SELECT a AS k FROM t GROUP BY k").is_ok());
+        assert_eq!(
+            bind(&catalog, "SELECT a, b FROM t GROUP BY a").unwrap_err(),
+            "column \"b\" must appear in the GROUP BY clause or be used in an aggregate function"
+        );
+        assert!(bind(&catalog, "SELECT a FROM t GROUP BY a ORDER BY b").is_err());
+        assert!(bind(&catalog, "SELECT a FROM t HAVING count(*) > 1").is_err());
+        assert!(bind(&catalog, "SELECT a FROM t WHERE count(*) > 1").is_err());
+        assert!(bind(&catalog, "SELECT sum(count(*)) FROM t").is_err());
+        assert!(bind(&catalog, "SELECT count(*) FROM t GROUP BY count(*)").is_err());
+        assert!(bind(&catalog, "SELECT a FROM t GROUP BY 2").is_err());
+        assert!(bind(&catalog, "SELECT sum(a, a) FROM t").is_err());
+    }
+
+    #[test]
+    fn window_functions() {
+        let (_file, _bm, catalog) = catalog("window_functions");
+        let plan = bind(
+            &catalog,
+            "SELECT a, row_number() OVER (ORDER BY a) + 1 AS n, \
+             sum(a) OVER (PARTITION BY b ORDER BY a ROWS 2 PRECEDING) \
+             FROM t ORDER BY row_number() OVER (ORDER BY a) DESC",
+        )
+        .unwrap();
+        let windows = match plan {
+            Plan::Project { input, .. } => match *input {
+                Plan::Sort { input, .. } => match *input {
+                    Plan::Window {
+                        input,
+                        partition_by,
+                        functions,
+                        fields,
+                        ..
+                    } => match *input {
+                        Plan::Window {
+                            order_by,
+                            functions: inner,
+                            ..
+                        } => (order_by, inner, partition_by, functions, fields),
+                        other => panic!("unexpected plan: {:?}", other),
+                    },
+                    other => panic!("unexpected plan: {:?}", other),
+                },
+                other => panic!("unexpected plan: {:?}", other),
+            },
+            other => panic!("unexpected plan: {:?}", other),
+        };
+        let (order_by, inner, partition_by, functions, fields) = windows;
+        // row_number() is computed once for the select list and ORDER BY
+        assert_eq!(order_by.len(), 1);
+        assert_eq!(inner.len(), 1);
+        assert_eq!(inner[0].function, WindowFunction::RowNumber);
+        assert_eq!(partition_by[0].to_string(), "b");
+        assert_eq!(
+            functions[0].function,
+            WindowFunction::Aggregate(AggregateFunction::Sum)
+        );
+        assert_eq!(
+            functions[0].frame,
+            WindowFrame {
+                rows: true,
+                start: WindowFrameBound::Preceding(Some(2)),
+                end: WindowFrameBound::CurrentRow,
+            }
+        );
+        assert_eq!(fields.last().unwrap().name, "?window2?");
+
+        assert!(bind(
+            &catalog,
+            "SELECT count(*) OVER (), lag(b, 2, 'x') OVER () FROM t"
+        )
+        .is_ok());
+        assert_eq!(
+            bind(&catalog, "SELECT a FROM t WHERE rank() OVER () > 1").unwrap_err(),
+            "window functions are not allowed in WHERE"
+        );
+        assert!(bind(&catalog, "SELECT sum(a), rank() OVER () FROM t").is_err());
+        assert!(bind(&catalog, "SELECT rank(a) OVER () FROM t").is_err());
+        assert!(bind(&catalog, "SELECT lag() OVER () FROM t").is_err());
+        assert!(bind(&catalog, "SELECT sum(*) OVER () FROM t").is_err());
+        assert!(bind(&catalog, "SELECT foo() OVER () FROM t").is_err());
+        assert!(bind(&catalog, "SELECT sum(DISTINCT a) OVER () FROM t").is_err());
+        assert!(bind(&catalog, "SELECT sum(rank() OVER ()) OVER () FROM t").is_err());
+        assert!(bind(&catalog, "SELECT rank() OVER (ORDER BY x) FROM t").is_err());
+        assert!(bind(
+            &catalog,
+            "SELECT sum(a) OVER (ORDER BY a RANGE 1 PRECEDING) FROM t"
+        )
+        .is_err());
+        assert!(bind(
+            &catalog,
+            "SELECT sum(a) OVER (ORDER BY a ROWS UNBOUNDED FOLLOWING) FROM t"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn order_by_and_limit() {
+        let (_file, _bm, catalog) = catalog("order_by");
+        let plan = bind(
+            &catalog,
+            "SELECT a AS k FROM t ORDER BY k DESC, b LIMIT 10 OFFSET 5",
+        )
+        .unwrap();
+        let keys = match plan {
+            Plan::Limit {
+                input,
+                limit: Some(10),
+                offset: 5,
+            } => match *input {
+                Plan::Project { input, .. } => match *input {
+                    Plan::Sort { keys, .. } => keys,
+                    other => panic!("unexpected plan: {:?}", other),
+                },
+                other => panic!("unexpected plan: {:?}", other),
+            },
+            other => panic!("unexpected plan: {:?}", other),
+        };
+        assert_eq!(keys[0].expr.to_string(), "a");
+        assert!(!keys[0].asc && keys[0].nulls_first);
+        assert_eq!(keys[1].expr.to_string(), "b");
+        assert!(keys[1].asc && !keys[1].nulls_first);
+
+        assert!(bind(&catalog, "SELECT a FROM t ORDER BY 1").is_ok());
+        assert!(bind(&catalog, "SELECT a FROM t ORDER BY 2").is_err());
+        assert!(bind(&catalog, "SELECT DISTINCT a FROM t ORDER BY b").is_err());
+        assert!(bind(&catalog, "SELECT DISTINCT a FROM t ORDER BY a").is_ok());
+        assert!(bind(&catalog, "SELECT a FROM t LIMIT -1").is_err());
+        assert!(bind(&catalog, "VALUES (1, 'x'), (2, 'y') ORDER BY column2").is_ok());
+        assert!(bind(&catalog, "VALUES (1), (2, 3)").is_err());
+    }
+
+    #[test]
+    fn joins() {
+        let (_file, _bm, catalog) = catalog("joins");
+        assert_eq!(
+            names(&catalog, "SELECT * FROM t JOIN u USING (a)").unwrap(),
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(
+            names(&catalog, "SELECT * FROM t NATURAL FULL JOIN u").unwrap(),
+            vec!["a", "b", "c"]
+        );
+        assert!(bind(&catalog, "SELECT a, t.a, u.a FROM t JOIN u USING (a)").is_ok());
+        assert!(bind(&catalog, "SELECT * FROM t JOIN u USING (b)").is_err());
+        match bind(&catalog, "SELECT * FROM t LEFT JOIN u ON t.a = u.c").unwrap() {
+            Plan::Project { input, .. } => match *input {
+                Plan::Join {
+                    kind, condition, ..
+                } => {
+                    assert_eq!(kind, JoinKind::Left);
+                    assert_eq!(condition.unwrap().to_string(), "t.a = u.c");
+                }
+                other => panic!("unexpected plan: {:?}", other),
+            },
+            other => panic!("unexpected plan: {:?}", other),
+        }
+        assert!(bind(&catalog, "SELECT * FROM t JOIN u ON t.a = x.a").is_err());
+        assert!(bind(&catalog, "SELECT * FROM (SELECT a FROM t)").is_err());
+        assert_eq!(
+            names(&catalog, "SELECT * FROM (SELECT a, b FROM t) AS s (x)").unwrap(),
+            vec!["x", "b"]
+        );
+    }
+
+    #[test]
+    fn subqueries() {
+        let (_file, _bm, catalog) = catalog("subqueries");
+        let join = |sql: &str| match bind(&catalog, sql).unwrap() {
+            Plan::Project { input, .. } => match *input {
+                Plan::Join {
+                    kind,
+                    condition,
+                    fields,
+                    ..
+                } => (kind, condition.map(|c| c.to_string()), fields.len()),
+                other => panic!("unexpected plan: {:?}", other),
+            },
+            other => panic!("unexpected plan: {:?}", other),
+        };
+        assert_eq!(
+            join("SELECT * FROM t WHERE EXISTS (SELECT 1 FROM u WHERE u.a = t.a AND c > 0)"),
+            (
+                JoinKind::Semi,
+                Some("\"?subquery?\".\"0\" = t.a".to_owned()),
+                2
+            )
+        );
+        // the subquery's columns hide those of the outer query
+        assert_eq!(
+            join("SELECT * FROM t WHERE NOT EXISTS (SELECT * FROM u WHERE a = b)"),
+            (
+                JoinKind::Anti,
+                Some("\"?subquery?\".\"0\" = b".to_owned()),
+                2
+            )
+        );
+        assert_eq!(
+            join("SELECT * FROM t WHERE a IN (SELECT count(*) FROM u)"),
+            (
+                JoinKind::Semi,
+                Some("a = \"?subquery?\".\"0\"".to_owned()),
+                2
+            )
+        );
+        assert_eq!(
+            join("SELECT * FROM t WHERE NOT a IN (SELECT c FROM u)").0,
+            JoinKind::Anti
+        );
+        assert!(bind(&catalog, "SELECT * FROM t WHERE a IN (SELECT a, c FROM u)").is_err());
+        assert!(bind(
+            &catalog,
+            "SELECT * FROM t WHERE EXISTS (SELECT 1 FROM u WHERE x = 1)"
+        )
+        .is_err());
+        assert!(bind(
+            &catalog,
+            "SELECT * FROM t WHERE EXISTS (SELECT 1 FROM u) AND a = u.a"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn views_and_ctes() {
+        let (_file, mut bm, mut catalog) = catalog("views");
+        let t = catalog.get_table("t").unwrap().id;
+        let query = parse_query("SELECT a, b FROM t WHERE a > 0").unwrap();
+        let mut binder = Binder::new(&catalog);
+        binder.bind_query(&query).unwrap();
+        assert_eq!(binder.dependencies(), vec![t]);
+        let view = ViewMetadata {
+            id: 0,
+            name: "v".to_owned(),
+            columns: vec!["x".to_owned(), "b".to_owned()],
+            query,
+            dependencies: vec![t],
+        };
+        let v = catalog.create_view(&mut bm, view, false).unwrap();
+
+        let plan = bind(&catalog, "SELECT v.x FROM v WHERE b = 'y'").unwrap();
+        assert_eq!(scans(&plan), vec![t]);
+        assert!(bind(&catalog, "SELECT a FROM v").is_err());
+        // the view reads the table even if a common table expression has the same name
+        let plan = bind(&catalog, "WITH t AS (SELECT 1 AS a) SELECT * FROM v, t").unwrap();
+        assert_eq!(scans(&plan), vec![t]);
+        assert_eq!(
+            plan.fields().iter().map(|f| &f.name).collect::<Vec<_>>(),
+            vec!["x", "b", "a"]
+        );
+
+        let mut binder = Binder::new(&catalog);
+        binder
+            .bind_query(&parse_query("SELECT * FROM v JOIN u ON x = c").unwrap())
+            .unwrap();
+        let u = catalog.get_table("u").unwrap().id;
+        assert_eq!(binder.dependencies(), vec![u, v]);
+
+        assert_eq!(
+            names(&catalog, "WITH w (n) AS (SELECT a FROM t) SELECT n FROM w").unwrap(),
+            vec!["n"]
+        );
+        assert!(bind(&catalog, "WITH RECURSIVE w AS (SELECT 1) SELECT * FROM w").is_err());
+        assert!(bind(
+            &catalog,
+            "SELECT * FROM (WITH w AS (SELECT 1) SELECT 2) AS s, w"
+        )
+        .is_err());
+    }
+}
@@ -1,17 +1,618 @@
 // Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
 // Distributed under terms of the MIT license.
 
-const BTREE_ORDER: usize = 64;
+use std::convert::TryInto;
+use std::ops::Bound;
 
+use crate::buffer_manager::{fetch_page, new_page, BufferManager};
+use crate::page::{read_u64, write_u64, PageID, RecordId, PAGE_SIZE};
+
+/// "QDBTREE1"
+const MAGIC: u64 = 0x5144_4254_5245_4531;
+
+// Layout of the meta page.
+const META_MAGIC: usize = 0;
+const META_ROOT: usize = 8;
+const META_LEN: usize = 16;
+
+/// Node pages start with a leaf flag and the number of entries,
+/// inner nodes continue with the page IDs of their children.
+/// Every entry consists of key length, key, page ID and slot of the record.
+const NODE_HEADER: usize = 1 + 2;
+const ENTRY_OVERHEAD: usize = 2 + 8 + 2;
+
+/// Nodes that shrink below this many bytes are merged with or refilled from a sibling.
+const MIN_NODE_SIZE: usize = PAGE_SIZE / 4;
+
+/// Largest key that can be stored in the tree.
+/// Small enough that both halves of a split node stay above `MIN_NODE_SIZE`.
+pub const MAX_KEY_SIZE: usize = PAGE_SIZE / 5 - ENTRY_OVERHEAD - 8;
+
+/// Index entries are ordered by key first and record ID second,
+/// so that duplicate keys are supported and every entry is unique.
+type Entry = (Vec<u8>, RecordId);
+
+/// Disk-resident B+-Tree, mapping byte-string keys to record IDs.
+/// Every node is a page, nodes are split and merged by the number of bytes they occupy.
+/// The root and the number of entries live in the meta page,
+/// so the tree can be reopened given only the ID of its meta page.
+/// Duplicate keys are allowed, uniqueness has to be enforced by the caller.
+pub struct BTree {
+    meta_page: PageID,
+    root: PageID,
+    len: usize,
+}
+
+impl BTree {
+    /// Creates a new, empty tree.
+    pub fn create(bm: &mut BufferManager) -> Result<Self, String> {
+        let meta_page = new_page(bm)?;
+        let root = new_page(bm)?;
+        write_node(bm, root, &Node::leaf())?;
+        let tree = Self {
+            meta_page,
+            root,
+            len: 0,
+        };
+        tree.write_meta(bm)?;
+        Ok(tree)
+    }
+
+    /// Opens an existing tree.
+    pub fn open(bm: &mut BufferManager, meta_page: PageID) -> Result<Self, String> {
+        let page = fetch_page(bm, meta_page)?;
+        let meta = page.read().unwrap().data;
+        bm.unpin_page(meta_page, false);
+
+        if read_u64(&meta, META_MAGIC) != MAGIC {
+            return Err(format!("page {} is not a B+-tree", meta_page));
+        }
+        Ok(Self {
+            meta_page,
+            root: read_u64(&meta, META_ROOT) as PageID,
+            len: read_u64(&meta, META_LEN) as usize,
+        })
+    }
+
+    /// The page ID needed to reopen this tree.
+    pub fn meta_page(&self) -> PageID {
+        self.meta_page
+    }
+
+    /// Number of entries in the tree.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Deletes all pages of the tree.
+    pub fn destroy(self, bm: &mut BufferManager) -> Result<(), String> {
+        let mut pages = vec![self.meta_page];
+        let mut next = vec![self.root];
+        while let Some(page) = next.pop() {
+            next.extend(read_node(bm, page)?.children);
+            pages.push(page);
+        }
+        for page in pages {
+            bm.delete_page(page);
+        }
+        Ok(())
+    }
+
+    /// Adds an entry, returns false if exactly this entry already existed.
+    pub fn insert(
+        &mut self,
+        bm: &mut BufferManager,
+        key: &[u8],
+        rid: RecordId,
+    ) -> Result<bool, String> {
+        if key.len() > MAX_KEY_SIZE {
+            return Err(format!(
+                "B+-tree key of {} bytes exceeds maximum of {} bytes",
+                key.len(),
+                MAX_KEY_SIZE
+            ));
+        }
+
+        match insert(bm, self.root, (key.to_vec(), rid))? {
+            Insert::Duplicate => return Ok(false),
+            Insert::Done => {}
+            Insert::Split(sep, right) => {
+                let root = new_page(bm)?;
+                let node = Node {
+                    keys: vec![sep],
+                    children: vec![self.root, right],
+                };
+                write_node(bm, root, &node)?;
+                self.root = root;
+            }
+        }
+        self.len += 1;
+        self.write_meta(bm)?;
+        Ok(true)
+    }
+
+    /// Removes an entry, returns whether it existed.
+    pub fn remove(
+        &mut self,
+        bm: &mut BufferManager,
+        key: &[u8],
+        rid: RecordId,
+    ) -> Result<bool, String> {
+        if !remove(bm, self.root, &(key.to_vec(), rid))? {
+            return Ok(false);
+        }
+        self.len -= 1;
+        let root = read_node(bm, self.root)?;
+        if !root.is_leaf() && root.keys.is_empty() {
+            bm.delete_page(self.root);
+            self.root = root.children[0];
+        }
+        self.write_meta(bm)?;
+        Ok(true)
+    }
+
+    /// Returns the record IDs of all entries with the given key.
+    pub fn get(&self, bm: &mut BufferManager, key: &[u8]) -> Result<Vec<RecordId>, String> {
+        self.range(bm, Bound::Included(key), Bound::Included(key))
+    }
+
+    /// Returns the record IDs of all entries with keys in the given range, in key order.
+    pub fn range(
+        &self,
+        bm: &mut BufferManager,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<Vec<RecordId>, String> {
+        let mut rids = Vec::new();
+        range(bm, self.root, lower, upper, &mut rids)?;
+        Ok(rids)
+    }
+
+    fn write_meta(&self, bm: &mut BufferManager) -> Result<(), String> {
+        let page = fetch_page(bm, self.meta_page)?;
+        {
+            let data = &mut page.write().unwrap().data;
+            write_u64(data, META_MAGIC, MAGIC);
+            write_u64(data, META_ROOT, self.root as u64);
+            write_u64(data, META_LEN, self.len as u64);
+        }
+        bm.unpin_page(self.meta_page, true);
+        Ok(())
+    }
+}
+
+enum Insert {
+    Duplicate,
+    Done,
+    Split(Entry, PageID),
+}
+
+fn insert(bm: &mut BufferManager, page: PageID, entry: Entry) -> Result<Insert, String> {
+    let mut node = read_node(bm, page)?;
+    if node.is_leaf() {
+        match node.keys.binary_search(&entry) {
+            Ok(_) => return Ok(Insert::Duplicate),
+            Err(i) => node.keys.insert(i, entry),
+        }
+    } else {
+        let i = node.child_index(&entry);
+        match insert(bm, node.children[i], entry)? {
+            Insert::Split(sep, right) => {
+                node.keys.insert(i, sep);
+                node.children.insert(i + 1, right);
+            }
+            result => return Ok(result),
+        }
+    }
+
+    if node.size() <= PAGE_SIZE {
+        write_node(bm, page, &node)?;
+        return Ok(Insert::Done);
+    }
+    let (sep, right) = node.split();
+    let right_page = new_page(bm)?;
+    write_node(bm, right_page, &right)?;
+    write_node(bm, page, &node)?;
+    Ok(Insert::Split(sep, right_page))
+}
+
+fn remove(bm: &mut BufferManager, page: PageID, entry: &Entry) -> Result<bool, String> {
+    let mut node = read_node(bm, page)?;
+    if node.is_leaf() {
+        return match node.keys.binary_search(entry) {
+            Ok(i) => {
+                node.keys.remove(i);
+                write_node(bm, page, &node)?;
+                Ok(true)
+            }
+            Err(_) => Ok(false),
+        };
+    }
+
+    let i = node.child_index(entry);
+    if !remove(bm, node.children[i], entry)? {
+        return Ok(false);
+    }
+    if read_node(bm, node.children[i])?.size() < MIN_NODE_SIZE {
+        rebalance(bm, &mut node, i)?;
+        write_node(bm, page, &node)?;
+    }
+    Ok(true)
+}
+
+/// Fixes the underfull child `i` of `node` by merging it with a sibling,
+/// or by distributing their entries evenly if they do not fit into one page.
+fn rebalance(bm: &mut BufferManager, node: &mut Node, i: usize) -> Result<(), String> {
+    let l = if i > 0 { i - 1 } else { i };
+    let (left_page, right_page) = (node.children[l], node.children[l + 1]);
+    let mut left = read_node(bm, left_page)?;
+    let right = read_node(bm, right_page)?;
+    let sep = node.keys.remove(l);
+    if !left.is_leaf() {
+        left.keys.push(sep);
+    }
+    left.keys.extend(right.keys);
+    left.children.extend(right.children);
+
+    if left.size() <= PAGE_SIZE {
+        node.children.remove(l + 1);
+        write_node(bm, left_page, &left)?;
+        bm.delete_page(right_page);
+    } else {
+        let (sep, right) = left.split();
+        node.keys.insert(l, sep);
+        write_node(bm, left_page, &left)?;
+        write_node(bm, right_page, &right)?;
+    }
+    Ok(())
+}
+
+fn range(
+    bm: &mut BufferManager,
+    page: PageID,
+    lower: Bound<&[u8]>,
+    upper: Bound<&[u8]>,
+    rids: &mut Vec<RecordId>,
+) -> Result<(), String> {
+    let node = read_node(bm, page)?;
+    if node.is_leaf() {
+        rids.extend(
+            node.keys
+                .iter()
+                .filter(|(k, _)| above(k, lower) && below(k, upper))
+                .map(|(_, rid)| *rid),
+        );
+        return Ok(());
+    }
+
+    for (i, &child) in node.children.iter().enumerate() {
+        // child i only contains keys in [keys[i - 1].0, keys[i].0]
+        if i > 0 && !below(&node.keys[i - 1].0, upper) {
+            break;
+        }
+        if i < node.keys.len() && !above(&node.keys[i].0, lower) {
+            continue;
+        }
+        range(bm, child, lower, upper, rids)?;
+    }
+    Ok(())
+}
+
+/// In-memory representation of a node.
+/// Leaves store the entries, inner nodes store separators:
+/// all entries in `children[i]` are smaller than `keys[i]`, which is not larger than any in `children[i + 1]`.
 struct Node {
-    keys: Vec<Vec<u8>>,
-    children: Vec<Node>,
+    /// Memcomparable keys (see `key_encoding`), compared as plain byte strings.
+    keys: Vec<Entry>,
+    children: Vec<PageID>,
+}
+
+impl Node {
+    fn leaf() -> Self {
+        Self {
+            keys: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    fn child_index(&self, entry: &Entry) -> usize {
+        self.keys.partition_point(|k| k <= entry)
+    }
+
+    /// Moves about half of the bytes into a new right sibling, returns it with its separator.
+    fn split(&mut self) -> (Entry, Node) {
+        // find the entry that crosses the middle of the node
+        let child = 8 * !self.is_leaf() as usize;
+        let half = self.size() / 2;
+        let mut size = NODE_HEADER + child;
+        let mut mid = 0;
+        while mid < self.keys.len() {
+            size += entry_size(&self.keys[mid].0) + child;
+            if size >= half {
+                break;
+            }
+            mid += 1;
+        }
+        if self.is_leaf() {
+            let keys = self.keys.split_off((mid + 1).clamp(1, self.keys.len() - 1));
+            let sep = keys[0].clone();
+            return (
+                sep,
+                Node {
+                    keys,
+                    children: Vec::new(),
+                },
+            );
+        }
+        // the crossing entry moves up, so both halves keep at least half a node minus an entry
+        let mid = mid.clamp(1, self.keys.len() - 2);
+        let keys = self.keys.split_off(mid + 1);
+        let sep = self.keys.pop().unwrap();
+        let children = self.children.split_off(mid + 1);
+        (sep, Node { keys, children })
+    }
+
+    fn read(data: &[u8; PAGE_SIZE]) -> Self {
+        let leaf = data[0] != 0;
+        let n = u16::from_le_bytes(data[1..3].try_into().unwrap()) as usize;
+        let mut pos = NODE_HEADER;
+        let mut children = Vec::new();
+        if !leaf {
+            for _ in 0..=n {
+                children.push(read_u64(data, pos) as PageID);
+                pos += 8;
+            }
+        }
+        let mut keys = Vec::with_capacity(n);
+        for _ in 0..n {
+            let len = u16::from_le_bytes(data[pos..pos + 2].try_into().unwrap()) as usize;
+            pos += 2;
+            let key = data[pos..pos + len].to_vec();
+            pos += len;
+            let page = read_u64(data, pos) as PageID;
+            let slot = u16::from_le_bytes(data[pos + 8..pos + 10].try_into().unwrap());
+            pos += 10;
+            keys.push((key, RecordId { page, slot }));
+        }
+        Self { keys, children }
+    }
+
+    fn write(&self, data: &mut [u8; PAGE_SIZE]) {
+        data[0] = self.is_leaf() as u8;
+        data[1..3].copy_from_slice(&(self.keys.len() as u16).to_le_bytes());
+        let mut pos = NODE_HEADER;
+        for &child in &self.children {
+            write_u64(data, pos, child as u64);
+            pos += 8;
+        }
+        for (key, rid) in &self.keys {
+            data[pos..pos + 2].copy_from_slice(&(key.len() as u16).to_le_bytes());
+            pos += 2;
+            data[pos..pos + key.len()].copy_from_slice(key);
+            pos += key.len();
+            write_u64(data, pos, rid.page as u64);
+            data[pos + 8..pos + 10].copy_from_slice(&rid.slot.to_le_bytes());
+            pos += 10;
+        }
+    }
+
+    /// Number of bytes this node occupies on its page.
+    fn size(&self) -> usize {
+        NODE_HEADER
+            + 8 * self.children.len()
+            + self.keys.iter().map(|(k, _)| entry_size(k)).sum::<usize>()
+    }
+}
+
+fn entry_size(key: &[u8]) -> usize {
+    ENTRY_OVERHEAD + key.len()
+}
+
+fn read_node(bm: &mut BufferManager, page: PageID) -> Result<Node, String> {
+    let p = fetch_page(bm, page)?;
+    let node = Node::read(&p.read().unwrap().data);
+    bm.unpin_page(page, false);
+    Ok(node)
+}
+
+fn write_node(bm: &mut BufferManager, page: PageID, node: &Node) -> Result<(), String> {
+    let p = fetch_page(bm, page)?;
+    node.write(&mut p.write().unwrap().data);
+    bm.unpin_page(page, true);
+    Ok(())
+}
+
+fn above(key: &[u8], lower: Bound<&[u8]>) -> bool {
+    match lower {
+        Bound::Included(l) => key >= l,
+        Bound::Excluded(l) => key > l,
+        Bound::Unbounded => true,
+    }
+}
+
+fn below(key: &[u8], upper: Bound<&[u8]>) -> bool {
+    match upper {
+        Bound::Included(u) => key <= u,
+        Bound::Excluded(u) => key < u,
+        Bound::Unbounded => true,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use std::collections::BTreeSet;
+
+    use crate::disk_manager::TempFile;
+
+    fn buffer_manager(name: &str) -> (TempFile, BufferManager) {
+        let file = TempFile::new(&format!("btree_{}", name));
+        let bm = BufferManager::with_file(16, file.path());
+        (file, bm)
+    }
+
+    fn rid(i: usize) -> RecordId {
+        RecordId { page: i, slot: 0 }
+    }
+
+    fn key(i: usize) -> Vec<u8> {
+        (i as u64).to_be_bytes().to_vec()
+    }
+
+    /// Checks ordering, fill degree and uniform depth of all nodes.
+    fn check(bm: &mut BufferManager, page: PageID, is_root: bool) -> usize {
+        let node = read_node(bm, page).unwrap();
+        assert!(node.keys.windows(2).all(|w| w[0] < w[1]));
+        assert!(node.size() <= PAGE_SIZE);
+        assert!(is_root || node.size() >= MIN_NODE_SIZE);
+        if node.is_leaf() {
+            return 1;
+        }
+        assert_eq!(node.children.len(), node.keys.len() + 1);
+        let depths: Vec<_> = node.children.iter().map(|&c| check(bm, c, false)).collect();
+        assert!(depths.iter().all(|&d| d == depths[0]));
+        depths[0] + 1
+    }
+
+    #[test]
+    fn insert_get() {
+        let (_file, mut bm) = buffer_manager("insert_get");
+        let mut tree = BTree::create(&mut bm).unwrap();
+        for i in (0..10_000).rev() {
+            assert!(tree.insert(&mut bm, &key(i), rid(i)).unwrap());
+        }
+        assert!(!tree.insert(&mut bm, &key(42), rid(42)).unwrap());
+        assert!(tree.insert(&mut bm, &key(42), rid(43)).unwrap());
+        assert!(check(&mut bm, tree.root, true) > 1);
+        assert_eq!(tree.len(), 10_001);
+        assert_eq!(tree.get(&mut bm, &key(42)).unwrap(), vec![rid(42), rid(43)]);
+        assert_eq!(tree.get(&mut bm, &key(9_999)).unwrap(), vec![rid(9_999)]);
+        assert_eq!(tree.get(&mut bm, &key(10_000)).unwrap(), vec![]);
+        assert!(tree
+            .insert(&mut bm, &[0u8; MAX_KEY_SIZE + 1], rid(0))
+            .is_err());
+    }
+
     #[test]
-    fn it_works() {}
+    fn range() {
+        let (_file, mut bm) = buffer_manager("range");
+        let mut tree = BTree::create(&mut bm).unwrap();
+        for i in 0..1000 {
+            tree.insert(&mut bm, &key(i * 2), rid(i * 2)).unwrap();
+        }
+        let lo = key(100);
+        let hi = key(200);
+        let expected: Vec<_> = (50..=100).map(|i| rid(i * 2)).collect();
+        assert_eq!(
+            tree.range(&mut bm, Bound::Included(&lo), Bound::Included(&hi))
+                .unwrap(),
+            expected
+        );
+        let expected: Vec<_> = (51..100).map(|i| rid(i * 2)).collect();
+        assert_eq!(
+            tree.range(&mut bm, Bound::Excluded(&lo), Bound::Excluded(&hi))
+                .unwrap(),
+            expected
+        );
+        assert_eq!(
+            tree.range(&mut bm, Bound::Unbounded, Bound::Excluded(&key(4)))
+                .unwrap(),
+            vec![rid(0), rid(2)]
+        );
+        assert_eq!(
+            tree.range(&mut bm, Bound::Unbounded, Bound::Unbounded)
+                .unwrap()
+                .len(),
+            1000
+        );
+    }
+
+    #[test]
+    fn remove() {
+        let (_file, mut bm) = buffer_manager("remove");
+        let mut tree = BTree::create(&mut bm).unwrap();
+        let mut reference = BTreeSet::new();
+        for i in 0..20_000 {
+            let k = (i * 7919) % 5000;
+            tree.insert(&mut bm, &key(k), rid(i)).unwrap();
+            reference.insert((k, i));
+        }
+        for i in (0..20_000).filter(|i| i % 3 != 0) {
+            let k = (i * 7919) % 5000;
+            assert!(tree.remove(&mut bm, &key(k), rid(i)).unwrap());
+            reference.remove(&(k, i));
+        }
+        assert!(!tree.remove(&mut bm, &key(0), rid(1)).unwrap());
+        check(&mut bm, tree.root, true);
+        assert_eq!(tree.len(), reference.len());
+
+        let all = tree
+            .range(&mut bm, Bound::Unbounded, Bound::Unbounded)
+            .unwrap();
+        let expected: Vec<_> = reference.iter().map(|&(_, i)| rid(i)).collect();
+        assert_eq!(all, expected);
+
+        for &(k, i) in &reference {
+            assert!(tree.remove(&mut bm, &key(k), rid(i)).unwrap());
+        }
+        assert!(tree.is_empty());
+        assert!(read_node(&mut bm, tree.root).unwrap().is_leaf());
+    }
+
+    #[test]
+    fn large_keys() {
+        let (_file, mut bm) = buffer_manager("large_keys");
+        let mut tree = BTree::create(&mut bm).unwrap();
+        let key = |i: usize| {
+            let mut k = (i as u64).to_be_bytes().to_vec();
+            k.resize(8 + (i * 37) % (MAX_KEY_SIZE - 8), b'x');
+            k
+        };
+        for i in 0..2000 {
+            tree.insert(&mut bm, &key(i), rid(i)).unwrap();
+        }
+        check(&mut bm, tree.root, true);
+        for i in (0..2000).step_by(2) {
+            assert!(tree.remove(&mut bm, &key(i), rid(i)).unwrap());
+        }
+        check(&mut bm, tree.root, true);
+        for i in 0..2000 {
+            let expected = if i % 2 == 0 { vec![] } else { vec![rid(i)] };
+            assert_eq!(tree.get(&mut bm, &key(i)).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn reopen_destroy() {
+        let (_file, mut bm) = buffer_manager("reopen_destroy");
+        let mut tree = BTree::create(&mut bm).unwrap();
+        for i in 0..5000 {
+            tree.insert(&mut bm, &key(i), rid(i)).unwrap();
+        }
+        let tree = BTree::open(&mut bm, tree.meta_page()).unwrap();
+        assert_eq!(tree.len(), 5000);
+        for i in 0..5000 {
+            assert_eq!(tree.get(&mut bm, &key(i)).unwrap(), vec![rid(i)]);
+        }
+        assert!(BTree::open(&mut bm, tree.root).is_err());
+
+        let pages = bm.num_pages();
+        tree.destroy(&mut bm).unwrap();
+        let mut tree = BTree::create(&mut bm).unwrap();
+        for i in 0..5000 {
+            tree.insert(&mut bm, &key(i), rid(i)).unwrap();
+        }
+        assert_eq!(bm.num_pages(), pages);
+    }
 }
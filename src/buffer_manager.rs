@@ -2,6 +2,7 @@
 // Distributed under terms of the MIT license.
 
 use std::collections::{HashMap, VecDeque};
+use std::io;
 use std::sync::{Arc, RwLock};
 
 use crate::disk_manager::DiskManager;
@@ -26,13 +27,30 @@ where
 impl<R: Replacer> BufferManager<R> {
     /// Initiate a new Buffer Manager.
     pub fn new(capacity: usize) -> BufferManager<R> {
+        Self::with_file(capacity, "xxxx.tmp")
+    }
+
+    /// Initiate a new Buffer Manager on top of a new database file.
+    pub fn with_file(capacity: usize, db_file_name: &str) -> BufferManager<R> {
+        Self::with_disk_manager(capacity, DiskManager::new(db_file_name))
+    }
+
+    /// Initiate a new Buffer Manager on top of an existing database file, creating it if necessary.
+    pub fn open(capacity: usize, db_file_name: &str) -> io::Result<BufferManager<R>> {
+        Ok(Self::with_disk_manager(
+            capacity,
+            DiskManager::open(db_file_name)?,
+        ))
+    }
+
+    fn with_disk_manager(capacity: usize, disk_manager: DiskManager) -> BufferManager<R> {
         let mut bm = BufferManager {
             max_pages: capacity,
             pages: vec![Arc::new(RwLock::new(Page::default())); capacity],
             page_table: HashMap::with_capacity(capacity),
             free_list: VecDeque::with_capacity(capacity),
             replacer: R::new(capacity),
-            disk_manager: DiskManager::new("xxxx.tmp"),
+            disk_manager,
         };
         for i in 0..capacity {
             bm.free_list.push_back(i);
@@ -41,12 +59,20 @@ impl<R: Replacer> BufferManager<R> {
     }
 
     /// Fetch the requested page, loading it form disk if necessary.
+    /// The page is pinned until a matching call to `unpin_page`.
     /// Returns `None` if we failed to allocate the page, i.e. all pages are pinned.
     // TODO don't panic
     pub fn fetch_page(&mut self, page: PageID) -> Option<Arc<RwLock<Page>>> {
         // Check if requested page is already cached
         if let Some(&frame) = self.page_table.get(&page) {
-            return Some(self.pages[frame].clone());
+            let p = self.pages[frame].clone();
+            let mut guard = p.write().unwrap();
+            if guard.pin_count == 0 {
+                self.replacer.pin(frame);
+            }
+            guard.pin_count += 1;
+            drop(guard);
+            return Some(p);
         }
 
         match self.find_free_page() {
@@ -84,7 +110,7 @@ impl<R: Replacer> BufferManager<R> {
         }
     }
 
-    ///
+    /// Unpins the given page, marking it dirty if it was modified.
     // TODO don't panic
     pub fn unpin_page(&mut self, page: PageID, dirty: bool) {
         let frame = self.page_table[&page];
@@ -99,7 +125,9 @@ impl<R: Replacer> BufferManager<R> {
             p.dirty = true;
         }
         p.pin_count -= 1;
-        self.replacer.unpin(frame);
+        if p.pin_count == 0 {
+            self.replacer.unpin(frame);
+        }
     }
 
     /// Flushes the given page to disk.
@@ -116,10 +144,63 @@ impl<R: Replacer> BufferManager<R> {
         }
     }
 
-    ///
-    // TODO
+    /// Writes the given page to disk if it is cached and dirty, keeping it cached.
+    // TODO don't panic
+    pub fn write_back(&mut self, page: PageID) {
+        if let Some(&frame) = self.page_table.get(&page) {
+            let mut p = self.pages[frame].write().unwrap();
+            if p.dirty {
+                if self.disk_manager.write_page(page, &p.data).is_err() {
+                    panic!("failed to write page to disk");
+                }
+                p.dirty = false;
+            }
+        }
+    }
+
+    /// Writes all dirty pages to disk, keeping them cached.
+    // TODO don't panic
+    pub fn flush_all(&mut self) {
+        for &frame in self.page_table.values() {
+            let mut p = self.pages[frame].write().unwrap();
+            if p.dirty {
+                if self.disk_manager.write_page(p.id, &p.data).is_err() {
+                    panic!("failed to write page to disk");
+                }
+                p.dirty = false;
+            }
+        }
+    }
+
+    /// Drops the given page without writing it back, its ID may be reused by `new_page`.
+    // TODO don't panic
     pub fn delete_page(&mut self, page: PageID) {
-        let frame = self.page_table[&page];
+        if let Some(frame) = self.page_table.remove(&page) {
+            if self.pages[frame].read().unwrap().pin_count > 0 {
+                panic!("tried to delete page that is pinned");
+            }
+            self.free_list.push_back(frame);
+        }
+        self.disk_manager.deallocate_page(page);
+    }
+
+    /// Writes the list of deleted pages to disk, returns its first page.
+    pub fn write_free_list(&mut self) -> Result<Option<PageID>, String> {
+        self.disk_manager
+            .write_free_list()
+            .map_err(|err| format!("failed to write free list: {}", err))
+    }
+
+    /// Reads the list of deleted pages starting at `head`, so that they are reused again.
+    pub fn read_free_list(&mut self, head: Option<PageID>) -> Result<(), String> {
+        self.disk_manager
+            .read_free_list(head)
+            .map_err(|err| format!("failed to read free list: {}", err))
+    }
+
+    /// Number of pages in the database file, including deleted ones.
+    pub fn num_pages(&self) -> usize {
+        self.disk_manager.num_pages()
     }
 
     /// Number of pages currently free (i.e. not used at all, pinned or unpinned).
@@ -127,7 +208,12 @@ impl<R: Replacer> BufferManager<R> {
         self.free_list.len()
     }
 
-    /// Finds a free page from the free list.
+    /// Number of pages currently pinned.
+    pub fn pages_pinned(&self) -> usize {
+        self.max_pages - self.replacer.len()
+    }
+
+    /// Finds a free frame from the free list.
     /// Frees an unpinned page first if necessary.
     fn find_free_page(&mut self) -> Option<PageID> {
         if self.pages_free() == 0 {
@@ -144,10 +230,32 @@ impl<R: Replacer> BufferManager<R> {
     }
 }
 
+/// Like `BufferManager::fetch_page`, but failing with an error message.
+pub fn fetch_page(bm: &mut BufferManager, page: PageID) -> Result<Arc<RwLock<Page>>, String> {
+    bm.fetch_page(page).ok_or_else(|| {
+        format!(
+            "failed to fetch page {}: all buffer frames are pinned",
+            page
+        )
+    })
+}
+
+/// Allocates a new page and returns its ID, leaving it unpinned.
+pub fn new_page(bm: &mut BufferManager) -> Result<PageID, String> {
+    let page = bm
+        .new_page()
+        .ok_or_else(|| "failed to allocate page: all buffer frames are pinned".to_owned())?;
+    let id = page.read().unwrap().id;
+    bm.unpin_page(id, true);
+    Ok(id)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use crate::disk_manager::TempFile;
+
     const CAPACITY: usize = 10;
 
     #[test]
@@ -209,4 +317,32 @@ mod tests {
         let p = p_opt.unwrap();
         assert_eq!(p.read().unwrap().data[..5], *"Hello".as_bytes());
     }
+
+    #[test]
+    fn reopen_and_delete() {
+        let file = TempFile::new("buffer_manager_reopen");
+        let path = file.path();
+        let mut mm = BufferManager::<ClockReplacer>::with_file(CAPACITY, path);
+        for i in 0..3 {
+            let p = mm.new_page().unwrap();
+            p.write().unwrap().data[0] = i as u8 + 1;
+            mm.unpin_page(i, true);
+        }
+        mm.flush_all();
+        // flushed pages stay cached
+        assert_eq!(mm.pages_free(), CAPACITY - 3);
+
+        let mut mm = BufferManager::<ClockReplacer>::open(CAPACITY, path).unwrap();
+        assert_eq!(mm.num_pages(), 3);
+        let p = mm.fetch_page(2).unwrap();
+        assert_eq!(p.read().unwrap().data[0], 3);
+        mm.unpin_page(2, false);
+
+        mm.delete_page(1);
+        let p = mm.new_page().unwrap();
+        assert_eq!(p.read().unwrap().id, 1);
+        assert_eq!(p.read().unwrap().data[0], 0);
+        mm.unpin_page(1, true);
+        assert_eq!(mm.new_page().unwrap().read().unwrap().id, 3);
+    }
 }
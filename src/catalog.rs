@@ -2,57 +2,2674 @@
 // Distributed under terms of the MIT license.
 
 use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::sync::Arc;
 //use std::sync::atomic::AtomicUsize;
 
+use sqlparser::ast::{
+    self, ColumnDef, ColumnOption, Expr, Query, ReferentialAction, TableConstraint,
+};
+
+use crate::buffer_manager::{fetch_page, new_page, BufferManager};
+use crate::expression::{referenced_columns, referenced_sequences, rename_column};
+use crate::heap_file::HeapFile;
+use crate::page::{read_page_id, read_u64, write_u64, PageID, RecordId, NO_PAGE};
+use crate::sql::{ident_name, parse_expr, parse_query, SequenceOptions};
+use crate::statistics::{ColumnStatistics, TableStatistics};
+use crate::tuple::{RowFormat, TupleLayout, TupleRef};
+use crate::value::Value;
+
+/// "QDBFILE1"
+const MAGIC: u64 = 0x5144_4246_494c_4531;
+
+// Layout of the file header in page 0, which points to the system tables.
+const HEADER_PAGE: PageID = 0;
+const HEADER_MAGIC: usize = 0;
+const HEADER_TABLES: usize = 8;
+const HEADER_COLUMNS: usize = 16;
+const HEADER_INDEXES: usize = 24;
+const HEADER_NEXT_TABLE_ID: usize = 32;
+const HEADER_NEXT_INDEX_ID: usize = 40;
+const HEADER_CONSTRAINTS: usize = 48;
+const HEADER_VIEWS: usize = 56;
+const HEADER_SEQUENCES: usize = 64;
+const HEADER_STATISTICS: usize = 72;
+const HEADER_FREE_LIST: usize = 80;
+
+/// Stored in the system tables for columns that have not been dropped.
+const NO_VERSION: u64 = u16::MAX as u64;
+
+/// How many values a sequence reserves at once if it does not specify `CACHE`.
+const DEFAULT_SEQUENCE_CACHE: i64 = 32;
+
+/// Kinds of records in `qdb_statistics`.
+/// Each value has its own record, so wide values cannot make a record exceed a page.
+const STATISTICS_TABLE: u8 = 0;
+const STATISTICS_COLUMN: u8 = 1;
+const STATISTICS_MOST_COMMON: u8 = 2;
+const STATISTICS_HISTOGRAM: u8 = 3;
+
+/// The catalog keeps the metadata of all tables, views, sequences, indexes and constraints.
+/// It is persisted in the system tables `qdb_tables`, `qdb_columns`, `qdb_indexes`, `qdb_constraints`,
+/// `qdb_views`, `qdb_sequences` and `qdb_statistics`.
+/// Every DDL change writes fresh copies of the system tables and then atomically switches
+/// the root pointers in the file header, so a change is either completely visible or not at all.
+#[derive(Clone)]
 pub struct Catalog {
-    tables: Vec<TableMetadata>,
+    tables: HashMap<usize, TableMetadata>,
     table_names: HashMap<String, usize>,
-    //next_table_id: AtomicUsize,
+    indexes: Vec<IndexMetadata>,
+    constraints: Vec<ConstraintMetadata>,
+    views: Vec<ViewMetadata>,
+    sequences: Vec<SequenceMetadata>,
+    /// Where each sequence is stored in `qdb_sequences`, by sequence ID,
+    /// so reserving values can update the record in place.
+    sequence_records: HashMap<usize, RecordId>,
+    /// Statistics collected by `ANALYZE`, by table ID.
+    statistics: HashMap<usize, TableStatistics>,
+    /// Tables, views and sequences share the ID space, so view dependencies can refer to both.
+    next_table_id: usize,
+    next_index_id: usize,
+    system_tables: SystemTables,
 }
 
 impl Catalog {
-    pub fn create_table(&mut self, name: &str, schema: &Schema) -> Result<usize, &str> {
-        if self.table_names.get(name).is_some() {
-            Err("")
-        } else {
-            let id = self.tables.len() - 1;
-            self.tables.push(TableMetadata {
-                schema: schema.clone(),
-                name: name.to_owned(),
-                id,
-            });
+    /// Loads the catalog of the database, bootstrapping the system tables in a new database file.
+    pub fn open(bm: &mut BufferManager) -> Result<Self, String> {
+        if bm.num_pages() == 0 {
+            return Self::bootstrap(bm);
+        }
+
+        let p = fetch_page(bm, HEADER_PAGE)?;
+        let header = p.read().unwrap();
+        let magic = read_u64(&header.data, HEADER_MAGIC);
+        let roots = [
+            HEADER_TABLES,
+            HEADER_COLUMNS,
+            HEADER_INDEXES,
+            HEADER_CONSTRAINTS,
+            HEADER_VIEWS,
+            HEADER_SEQUENCES,
+            HEADER_STATISTICS,
+        ]
+        .map(|offset| read_u64(&header.data, offset) as PageID);
+        let next_table_id = read_u64(&header.data, HEADER_NEXT_TABLE_ID) as usize;
+        let next_index_id = read_u64(&header.data, HEADER_NEXT_INDEX_ID) as usize;
+        let free_list = read_page_id(&header.data, HEADER_FREE_LIST);
+        drop(header);
+        bm.unpin_page(HEADER_PAGE, false);
+        if magic != MAGIC {
+            return Err("not a qdb database file".to_owned());
+        }
+        bm.read_free_list(free_list)?;
+
+        let system_tables = SystemTables {
+            tables: HeapFile::open(bm, roots[0])?,
+            columns: HeapFile::open(bm, roots[1])?,
+            indexes: HeapFile::open(bm, roots[2])?,
+            constraints: HeapFile::open(bm, roots[3])?,
+            views: HeapFile::open(bm, roots[4])?,
+            sequences: HeapFile::open(bm, roots[5])?,
+            statistics: HeapFile::open(bm, roots[6])?,
+        };
+        let mut catalog = Self {
+            tables: HashMap::new(),
+            table_names: HashMap::new(),
+            indexes: Vec::new(),
+            constraints: Vec::new(),
+            views: Vec::new(),
+            sequences: Vec::new(),
+            sequence_records: HashMap::new(),
+            statistics: HashMap::new(),
+            next_table_id,
+            next_index_id,
+            system_tables,
+        };
+        catalog.load(bm)?;
+        Ok(catalog)
+    }
+
+    /// Creates a new table with an empty heap file, returns its ID.
+    pub fn create_table(
+        &mut self,
+        bm: &mut BufferManager,
+        name: &str,
+        schema: &Schema,
+    ) -> Result<usize, String> {
+        if self.relation_exists(name) {
+            return Err(format!("relation \"{}\" already exists", name));
+        }
+        let heap = HeapFile::create(bm)?;
+        let result = self.transaction(bm, |catalog| {
+            let id = catalog.next_table_id;
+            catalog.next_table_id += 1;
+            catalog.table_names.insert(name.to_owned(), id);
+            let history = schema
+                .columns()
+                .iter()
+                .map(|c| StoredColumn::new(c.clone(), 0, Value::Null))
+                .collect();
+            let table = TableMetadata::new(name.to_owned(), id, heap.first_page(), 0, history)?;
+            catalog.tables.insert(id, table);
             Ok(id)
+        });
+        if result.is_err() {
+            heap.destroy(bm)?;
+        }
+        result
+    }
+
+    /// Drops a table together with its indexes, constraints and owned sequences and deletes its data.
+    /// The structures of the table's indexes have to be dropped by the caller.
+    /// Foreign keys of other tables and views referencing it are dropped if `cascade` is set,
+    /// otherwise they are an error.
+    pub fn drop_table(
+        &mut self,
+        bm: &mut BufferManager,
+        name: &str,
+        cascade: bool,
+    ) -> Result<(), String> {
+        let id = self.table_id(name)?;
+        let dependent = self
+            .referencing_constraints(id)
+            .find(|c| c.table != id)
+            .map(|c| c.name.clone());
+        if let (Some(dependent), false) = (dependent, cascade) {
+            return Err(format!(
+                "cannot drop table {} because constraint {} depends on it",
+                name, dependent
+            ));
+        }
+        let views = self.dependent_views(id);
+        if let (Some(view), false) = (views.first(), cascade) {
+            return Err(format!(
+                "cannot drop table {} because view {} depends on it",
+                name, self.views[*view].name
+            ));
+        }
+        let table = self.transaction(bm, |catalog| {
+            catalog.table_names.remove(name);
+            catalog.remove_views(&views);
+            catalog
+                .sequences
+                .retain(|s| s.owner.as_ref().is_none_or(|(table, _)| *table != id));
+            catalog.statistics.remove(&id);
+            catalog.indexes.retain(|i| i.table != id);
+            catalog.constraints.retain(|c| {
+                c.table != id && !matches!(c.kind, ConstraintKind::ForeignKey { referenced_table, .. } if referenced_table == id)
+            });
+            Ok(catalog.tables.remove(&id).unwrap())
+        })?;
+        HeapFile::open(bm, table.first_page)?.destroy(bm)?;
+        self.save_free_list(bm)
+    }
+
+    /// Renames a table, its ID and data stay the same.
+    pub fn rename_table(
+        &mut self,
+        bm: &mut BufferManager,
+        name: &str,
+        new_name: &str,
+    ) -> Result<(), String> {
+        let id = self.table_id(name)?;
+        self.check_dependent_views(id, name)?;
+        if self.relation_exists(new_name) {
+            return Err(format!("relation \"{}\" already exists", new_name));
         }
+        self.transaction(bm, |catalog| {
+            catalog.table_names.remove(name);
+            catalog.table_names.insert(new_name.to_owned(), id);
+            catalog.tables.get_mut(&id).unwrap().name = new_name.to_owned();
+            Ok(())
+        })
     }
 
-    pub fn get_table(&self, name: &str) -> Option<TableMetadata> {
+    /// Appends a column to a table.
+    /// Rows stored before are not rewritten, they read `missing` as the column's value.
+    pub fn add_column(
+        &mut self,
+        bm: &mut BufferManager,
+        table: &str,
+        column: Column,
+        missing: Value,
+    ) -> Result<(), String> {
+        let id = self.table_id(table)?;
+        if self.tables[&id].schema.column(&column.name).is_some() {
+            return Err(format!(
+                "column \"{}\" of relation \"{}\" already exists",
+                column.name, table
+            ));
+        }
+        self.transaction(bm, |catalog| {
+            let table = catalog.tables.get_mut(&id).unwrap();
+            let version = table.next_version()?;
+            table
+                .history
+                .push(StoredColumn::new(column, version, missing));
+            table.update_schema(version)
+        })
+    }
+
+    /// Removes a column from a table, together with all indexes and constraints containing it
+    /// and the sequence it owns.
+    /// Foreign keys of other tables referencing the column are dropped if `cascade` is set,
+    /// otherwise they are an error.
+    /// Returns the metadata of the dropped indexes, the caller has to drop the index structures.
+    /// Stored rows are not rewritten, the column's values are only skipped when reading them.
+    pub fn drop_column(
+        &mut self,
+        bm: &mut BufferManager,
+        table: &str,
+        column: &str,
+        cascade: bool,
+    ) -> Result<Vec<IndexMetadata>, String> {
+        let id = self.table_id(table)?;
+        self.check_dependent_views(id, table)?;
+        let position = match self.tables[&id].schema.index_of(column) {
+            Some(position) => position,
+            None => {
+                return Err(format!(
+                    "column \"{}\" of relation \"{}\" does not exist",
+                    column, table
+                ))
+            }
+        };
+        let referenced = |c: &ConstraintMetadata| match &c.kind {
+            ConstraintKind::ForeignKey {
+                referenced_table,
+                referenced_columns,
+                ..
+            } => *referenced_table == id && referenced_columns.contains(&position),
+            _ => false,
+        };
+        if let Some(dependent) = self
+            .constraints
+            .iter()
+            .find(|c| referenced(c) && c.table != id)
+        {
+            if !cascade {
+                return Err(format!(
+                    "cannot drop column {} of table {} because constraint {} depends on it",
+                    column, table, dependent.name
+                ));
+            }
+        }
+        self.transaction(bm, |catalog| {
+            let table = catalog.tables.get_mut(&id).unwrap();
+            let version = table.next_version()?;
+            let stored = table.live_columns_mut().nth(position).unwrap();
+            stored.dropped = Some(version);
+            table.update_schema(version)?;
+            let owner = Some((id, column.to_owned()));
+            catalog.sequences.retain(|s| s.owner != owner);
+            if let Some(statistics) = catalog.statistics.get_mut(&id) {
+                statistics.columns.retain(|c| c.column != column);
+            }
+
+            catalog.constraints.retain(|c| {
+                let own = c.table == id
+                    && match &c.kind {
+                        ConstraintKind::Unique { columns, .. }
+                        | ConstraintKind::ForeignKey { columns, .. } => columns.contains(&position),
+                        ConstraintKind::Check(expr) => {
+                            referenced_columns(expr).iter().any(|c| c == column)
+                        }
+                    };
+                !own && !referenced(c)
+            });
+            let shift = |columns: &mut Vec<usize>| {
+                for c in columns.iter_mut().filter(|c| **c > position) {
+                    *c -= 1;
+                }
+            };
+            for constraint in &mut catalog.constraints {
+                match &mut constraint.kind {
+                    ConstraintKind::Unique { columns, .. } if constraint.table == id => {
+                        shift(columns)
+                    }
+                    ConstraintKind::ForeignKey {
+                        columns,
+                        referenced_table,
+                        referenced_columns,
+                        ..
+                    } => {
+                        if constraint.table == id {
+                            shift(columns);
+                        }
+                        if *referenced_table == id {
+                            shift(referenced_columns);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            let (dropped, kept) = std::mem::take(&mut catalog.indexes)
+                .into_iter()
+                .partition(|i| i.table == id && i.columns.contains(&position));
+            catalog.indexes = kept;
+            for index in catalog.indexes.iter_mut().filter(|i| i.table == id) {
+                for c in index.columns.iter_mut().filter(|c| **c > position) {
+                    *c -= 1;
+                }
+            }
+            Ok(dropped)
+        })
+    }
+
+    pub fn rename_column(
+        &mut self,
+        bm: &mut BufferManager,
+        table: &str,
+        column: &str,
+        new_name: &str,
+    ) -> Result<(), String> {
+        let id = self.table_id(table)?;
+        self.check_dependent_views(id, table)?;
+        let schema = &self.tables[&id].schema;
+        let position = match schema.index_of(column) {
+            Some(position) => position,
+            None => return Err(format!("column \"{}\" does not exist", column)),
+        };
+        if schema.column(new_name).is_some() {
+            return Err(format!(
+                "column \"{}\" of relation \"{}\" already exists",
+                new_name, table
+            ));
+        }
+        self.transaction(bm, |catalog| {
+            let table = catalog.tables.get_mut(&id).unwrap();
+            table.live_columns_mut().nth(position).unwrap().column.name = new_name.to_owned();
+            table.update_schema(table.version)?;
+            let owner = Some((id, column.to_owned()));
+            for sequence in catalog.sequences.iter_mut().filter(|s| s.owner == owner) {
+                sequence.owner = Some((id, new_name.to_owned()));
+            }
+            if let Some(statistics) = catalog.statistics.get_mut(&id) {
+                for c in statistics.columns.iter_mut().filter(|c| c.column == column) {
+                    c.column = new_name.to_owned();
+                }
+            }
+            for constraint in catalog.constraints.iter_mut().filter(|c| c.table == id) {
+                if let ConstraintKind::Check(expr) = &mut constraint.kind {
+                    rename_column(expr, column, new_name);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    pub fn get_table(&self, name: &str) -> Option<&TableMetadata> {
+        self.table_names.get(name).map(|id| &self.tables[id])
+    }
+
+    pub fn get_table_by_id(&self, id: usize) -> Option<&TableMetadata> {
+        self.tables.get(&id)
+    }
+
+    /// IDs of all tables, in the order they were created.
+    pub fn table_ids(&self) -> Vec<usize> {
+        let mut ids: Vec<_> = self.tables.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    fn table_id(&self, name: &str) -> Result<usize, String> {
         match self.table_names.get(name) {
-            Some(&id) => Some(self.tables[id].clone()),
-            None => None,
+            Some(&id) => Ok(id),
+            None if self.relation_exists(name) => Err(format!("\"{}\" is not a table", name)),
+            None => Err(format!("relation \"{}\" does not exist", name)),
+        }
+    }
+
+    /// Records a view, returns its ID. The ID in `view` is ignored.
+    /// With `replace`, an existing view of the same name gets the new definition and keeps its ID.
+    /// Its columns have to stay the same, new ones can only be appended,
+    /// so views depending on it remain valid.
+    pub fn create_view(
+        &mut self,
+        bm: &mut BufferManager,
+        mut view: ViewMetadata,
+        replace: bool,
+    ) -> Result<usize, String> {
+        let existing = self.views.iter().position(|v| v.name == view.name);
+        match existing {
+            Some(pos) if replace => {
+                let old = &self.views[pos];
+                if !view.columns.starts_with(&old.columns) {
+                    return Err(format!(
+                        "cannot drop or rename columns of view \"{}\"",
+                        view.name
+                    ));
+                }
+                if view
+                    .dependencies
+                    .iter()
+                    .any(|&d| d == old.id || self.depends_on(d, old.id))
+                {
+                    return Err(format!(
+                        "infinite recursion detected in view \"{}\"",
+                        view.name
+                    ));
+                }
+                view.id = old.id;
+            }
+            _ if self.relation_exists(&view.name) => {
+                return Err(format!("relation \"{}\" already exists", view.name));
+            }
+            _ => {}
+        }
+        self.transaction(bm, |catalog| {
+            if let Some(pos) = existing {
+                catalog.views[pos] = view;
+                return Ok(catalog.views[pos].id);
+            }
+            view.id = catalog.next_table_id;
+            catalog.next_table_id += 1;
+            catalog.views.push(view);
+            Ok(catalog.next_table_id - 1)
+        })
+    }
+
+    /// Removes a view.
+    /// Views depending on it are removed as well if `cascade` is set, otherwise they are an error.
+    pub fn drop_view(
+        &mut self,
+        bm: &mut BufferManager,
+        name: &str,
+        cascade: bool,
+    ) -> Result<(), String> {
+        let pos = match self.views.iter().position(|v| v.name == name) {
+            Some(pos) => pos,
+            None => return Err(format!("view \"{}\" does not exist", name)),
+        };
+        let mut views = self.dependent_views(self.views[pos].id);
+        if let (Some(view), false) = (views.first(), cascade) {
+            return Err(format!(
+                "cannot drop view {} because view {} depends on it",
+                name, self.views[*view].name
+            ));
+        }
+        views.push(pos);
+        self.transaction(bm, |catalog| {
+            catalog.remove_views(&views);
+            Ok(())
+        })
+    }
+
+    pub fn get_view(&self, name: &str) -> Option<&ViewMetadata> {
+        self.views.iter().find(|v| v.name == name)
+    }
+
+    /// Whether the relation with the given ID is a view that references `target`,
+    /// directly or through other views.
+    fn depends_on(&self, relation: usize, target: usize) -> bool {
+        self.views
+            .iter()
+            .filter(|v| v.id == relation)
+            .flat_map(|v| &v.dependencies)
+            .any(|&d| d == target || self.depends_on(d, target))
+    }
+
+    /// Returns the positions of the views that depend on the given table or view.
+    fn dependent_views(&self, relation: usize) -> Vec<usize> {
+        (0..self.views.len())
+            .filter(|&i| self.depends_on(self.views[i].id, relation))
+            .collect()
+    }
+
+    fn remove_views(&mut self, positions: &[usize]) {
+        let mut i = 0;
+        self.views.retain(|_| {
+            i += 1;
+            !positions.contains(&(i - 1))
+        });
+    }
+
+    /// Records a new sequence, returns its ID. The ID in `sequence` is ignored.
+    pub fn create_sequence(
+        &mut self,
+        bm: &mut BufferManager,
+        mut sequence: SequenceMetadata,
+    ) -> Result<usize, String> {
+        if self.relation_exists(&sequence.name) {
+            return Err(format!("relation \"{}\" already exists", sequence.name));
+        }
+        self.transaction(bm, |catalog| {
+            sequence.id = catalog.next_table_id;
+            catalog.next_table_id += 1;
+            catalog.sequences.push(sequence);
+            Ok(catalog.next_table_id - 1)
+        })
+    }
+
+    /// Removes a sequence.
+    /// Column defaults using it are removed as well if `cascade` is set, otherwise they are an error.
+    pub fn drop_sequence(
+        &mut self,
+        bm: &mut BufferManager,
+        name: &str,
+        cascade: bool,
+    ) -> Result<(), String> {
+        let pos = match self.sequences.iter().position(|s| s.name == name) {
+            Some(pos) => pos,
+            None => return Err(format!("sequence \"{}\" does not exist", name)),
+        };
+        let uses = |column: &Column| {
+            let default = column.default.as_ref();
+            default.is_some_and(|d| referenced_sequences(d).iter().any(|s| s == name))
+        };
+        let mut users = Vec::new();
+        for table in self.tables.values() {
+            if let Some(column) = table.schema.columns().iter().find(|c| uses(c)) {
+                users.push(table.id);
+                if !cascade {
+                    return Err(format!(
+                        "cannot drop sequence {} because default value for column {} of table {} depends on it",
+                        name, column.name, table.name
+                    ));
+                }
+            }
+        }
+        self.transaction(bm, |catalog| {
+            catalog.sequences.remove(pos);
+            for id in users {
+                let table = catalog.tables.get_mut(&id).unwrap();
+                for stored in table.live_columns_mut().filter(|s| uses(&s.column)) {
+                    stored.column.default = None;
+                    stored.column.identity = None;
+                }
+                table.update_schema(table.version)?;
+            }
+            Ok(())
+        })
+    }
+
+    pub fn get_sequence(&self, name: &str) -> Option<&SequenceMetadata> {
+        self.sequences.iter().find(|s| s.name == name)
+    }
+
+    /// Reserves the next values of a sequence, as many as it caches unless it runs out of values,
+    /// and returns the first and the last of them.
+    /// The reserved values never wrap around, a cycling sequence starts over with the next reservation.
+    /// Only the sequence's record is updated, in place, instead of writing a new catalog.
+    pub fn reserve_sequence_values(
+        &mut self,
+        bm: &mut BufferManager,
+        id: usize,
+    ) -> Result<(i64, i64), String> {
+        let pos = match self.sequences.iter().position(|s| s.id == id) {
+            Some(pos) => pos,
+            None => return Err(format!("sequence {} does not exist", id)),
+        };
+        let sequence = &self.sequences[pos];
+        let first = match sequence.last_value {
+            Some(last) => sequence.advance(last),
+            None => Some(sequence.start),
+        };
+        let first = match first {
+            Some(first) => first,
+            None if sequence.increment > 0 => {
+                return Err(format!(
+                    "nextval: reached maximum value of sequence \"{}\" ({})",
+                    sequence.name, sequence.max_value
+                ))
+            }
+            None => {
+                return Err(format!(
+                    "nextval: reached minimum value of sequence \"{}\" ({})",
+                    sequence.name, sequence.min_value
+                ))
+            }
+        };
+        let mut last = first;
+        for _ in 1..sequence.cache {
+            match last.checked_add(sequence.increment) {
+                Some(next) if (sequence.min_value..=sequence.max_value).contains(&next) => {
+                    last = next
+                }
+                _ => break,
+            }
+        }
+
+        let previous = self.sequences[pos].last_value.replace(last);
+        let updated = self.update_sequence_record(bm, pos);
+        if updated.is_err() {
+            self.sequences[pos].last_value = previous;
+        }
+        updated.map(|_| (first, last))
+    }
+
+    /// Overwrites the record of a sequence in `qdb_sequences`, which keeps its size,
+    /// and writes its page to disk.
+    fn update_sequence_record(&mut self, bm: &mut BufferManager, pos: usize) -> Result<(), String> {
+        let sequence = &self.sequences[pos];
+        let rid = match self.sequence_records.get(&sequence.id) {
+            Some(&rid) => rid,
+            None => return Err(format!("sequence \"{}\" is not stored", sequence.name)),
+        };
+        let new_rid = self
+            .system_tables
+            .sequences
+            .update(bm, rid, &sequence_record(sequence))?;
+        debug_assert_eq!(new_rid, rid);
+        bm.write_back(rid.page);
+        Ok(())
+    }
+
+    /// Replaces the statistics of a table.
+    pub fn set_statistics(
+        &mut self,
+        bm: &mut BufferManager,
+        table: usize,
+        statistics: TableStatistics,
+    ) -> Result<(), String> {
+        if !self.tables.contains_key(&table) {
+            return Err(format!("table {} does not exist", table));
+        }
+        self.transaction(bm, |catalog| {
+            catalog.statistics.insert(table, statistics);
+            Ok(())
+        })
+    }
+
+    /// Returns the statistics of a table, if it has been analyzed.
+    pub fn get_statistics(&self, table: usize) -> Option<&TableStatistics> {
+        self.statistics.get(&table)
+    }
+
+    /// Fails if views reference the table, their definitions refer to its columns by name.
+    fn check_dependent_views(&self, table: usize, name: &str) -> Result<(), String> {
+        match self.views.iter().find(|v| v.dependencies.contains(&table)) {
+            Some(view) => Err(format!(
+                "cannot alter table {} because view {} depends on it",
+                name, view.name
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Records a new index, returns its ID.
+    /// The index structure has to be created by the caller, the ID in `index` is ignored.
+    pub fn create_index(
+        &mut self,
+        bm: &mut BufferManager,
+        mut index: IndexMetadata,
+    ) -> Result<usize, String> {
+        if self.relation_exists(&index.name) {
+            return Err(format!("relation \"{}\" already exists", index.name));
+        }
+        let table = match self.tables.get(&index.table) {
+            Some(table) => table,
+            None => return Err(format!("table {} does not exist", index.table)),
+        };
+        if index.columns.is_empty() || index.columns.iter().any(|&c| c >= table.schema.len()) {
+            return Err(format!("invalid key columns for index \"{}\"", index.name));
+        }
+        self.transaction(bm, |catalog| {
+            index.id = catalog.next_index_id;
+            catalog.next_index_id += 1;
+            catalog.indexes.push(index);
+            Ok(catalog.next_index_id - 1)
+        })
+    }
+
+    /// Removes an index, returns its metadata so the caller can drop the index structure.
+    pub fn drop_index(
+        &mut self,
+        bm: &mut BufferManager,
+        name: &str,
+    ) -> Result<IndexMetadata, String> {
+        let pos = match self.indexes.iter().position(|i| i.name == name) {
+            Some(pos) => pos,
+            None => return Err(format!("index \"{}\" does not exist", name)),
+        };
+        if let Some(c) = self.unique_constraint_of(&self.indexes[pos]) {
+            return Err(format!(
+                "cannot drop index {} because constraint {} on table {} requires it",
+                name, c.name, self.tables[&c.table].name
+            ));
+        }
+        self.transaction(bm, |catalog| Ok(catalog.indexes.remove(pos)))
+    }
+
+    /// Converts a constraint definition for the given table.
+    /// Constraints without a name get a generated name that is not in use yet.
+    pub fn resolve_constraint(
+        &self,
+        table: usize,
+        def: &ConstraintDef,
+    ) -> Result<ConstraintMetadata, String> {
+        let metadata = match self.tables.get(&table) {
+            Some(t) => t,
+            None => return Err(format!("table {} does not exist", table)),
+        };
+        let schema = &metadata.schema;
+        let positions = |schema: &Schema, columns: &[ast::Ident]| {
+            columns
+                .iter()
+                .map(|c| {
+                    let name = ident_name(c);
+                    schema
+                        .index_of(&name)
+                        .ok_or_else(|| format!("column \"{}\" does not exist", name))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let column_names = |columns: &[usize]| {
+            columns
+                .iter()
+                .map(|&c| schema.columns()[c].name.clone())
+                .collect::<Vec<_>>()
+                .join("_")
+        };
+
+        let (name, kind, suffix) = match &def.constraint {
+            TableConstraint::Unique {
+                name,
+                columns,
+                is_primary,
+            } => {
+                let columns = positions(schema, columns)?;
+                if *is_primary {
+                    let existing = self
+                        .table_constraints(table)
+                        .find(|c| matches!(c.kind, ConstraintKind::Unique { primary: true, .. }));
+                    if existing.is_some() {
+                        return Err(format!(
+                            "multiple primary keys for table \"{}\" are not allowed",
+                            metadata.name
+                        ));
+                    }
+                }
+                let suffix = if *is_primary {
+                    "pkey".to_owned()
+                } else {
+                    format!("{}_key", column_names(&columns))
+                };
+                let kind = ConstraintKind::Unique {
+                    columns,
+                    primary: *is_primary,
+                };
+                (name, kind, suffix)
+            }
+            TableConstraint::Check { name, expr } => {
+                let mut columns = referenced_columns(expr);
+                for column in &columns {
+                    if schema.column(column).is_none() {
+                        return Err(format!("column \"{}\" does not exist", column));
+                    }
+                }
+                columns.dedup();
+                let suffix = match columns.as_slice() {
+                    [column] => format!("{}_check", column),
+                    _ => "check".to_owned(),
+                };
+                (name, ConstraintKind::Check(*expr.clone()), suffix)
+            }
+            TableConstraint::ForeignKey {
+                name,
+                columns,
+                foreign_table,
+                referred_columns,
+            } => {
+                let columns = positions(schema, columns)?;
+                let referenced = match foreign_table.0.as_slice() {
+                    [ident] => ident_name(ident),
+                    _ => return Err(format!("schemas are not supported: {}", foreign_table)),
+                };
+                let referenced = match self.get_table(&referenced) {
+                    Some(t) => t,
+                    None => return Err(format!("relation \"{}\" does not exist", referenced)),
+                };
+                let referenced_columns = if referred_columns.is_empty() {
+                    // the primary key of the referenced table
+                    match self
+                        .table_constraints(referenced.id)
+                        .find_map(|c| match &c.kind {
+                            ConstraintKind::Unique {
+                                columns,
+                                primary: true,
+                            } => Some(columns.clone()),
+                            _ => None,
+                        }) {
+                        Some(columns) => columns,
+                        None => {
+                            return Err(format!(
+                                "there is no primary key for referenced table \"{}\"",
+                                referenced.name
+                            ))
+                        }
+                    }
+                } else {
+                    positions(&referenced.schema, referred_columns)?
+                };
+                if columns.len() != referenced_columns.len() {
+                    return Err(
+                        "number of referencing and referenced columns for foreign key disagree"
+                            .to_owned(),
+                    );
+                }
+                let has_unique = self.table_constraints(referenced.id).any(|c| {
+                    matches!(&c.kind, ConstraintKind::Unique { columns, .. }
+                        if columns.len() == referenced_columns.len()
+                            && columns.iter().all(|c| referenced_columns.contains(c)))
+                });
+                if !has_unique {
+                    return Err(format!(
+                        "there is no unique constraint matching given keys for referenced table \"{}\"",
+                        referenced.name
+                    ));
+                }
+                for (&c, &r) in columns.iter().zip(&referenced_columns) {
+                    let (a, b) = (
+                        schema.columns()[c].data_type,
+                        referenced.schema.columns()[r].data_type,
+                    );
+                    if !a.is_comparable_key(b) {
+                        return Err(format!(
+                            "foreign key columns are of incompatible types: {} and {}",
+                            a, b
+                        ));
+                    }
+                }
+                let kind = ConstraintKind::ForeignKey {
+                    columns: columns.clone(),
+                    referenced_table: referenced.id,
+                    referenced_columns,
+                    on_delete: ForeignKeyAction::from_sql(def.on_delete.as_ref())?,
+                    on_update: ForeignKeyAction::from_sql(def.on_update.as_ref())?,
+                };
+                (name, kind, format!("{}_fkey", column_names(&columns)))
+            }
+        };
+
+        let name = match name {
+            Some(name) => {
+                let name = ident_name(name);
+                if self.table_constraints(table).any(|c| c.name == name) {
+                    return Err(format!(
+                        "constraint \"{}\" for relation \"{}\" already exists",
+                        name, metadata.name
+                    ));
+                }
+                name
+            }
+            None => {
+                let base = format!("{}_{}", metadata.name, suffix);
+                let mut name = base.clone();
+                let mut i = 0;
+                while self.relation_exists(&name) || self.constraints.iter().any(|c| c.name == name)
+                {
+                    i += 1;
+                    name = format!("{}{}", base, i);
+                }
+                name
+            }
+        };
+        Ok(ConstraintMetadata { name, table, kind })
+    }
+
+    /// Records a new constraint.
+    /// PRIMARY KEY and UNIQUE constraints need a unique index of the same name on their columns,
+    /// which the caller has to create first.
+    pub fn add_constraint(
+        &mut self,
+        bm: &mut BufferManager,
+        constraint: ConstraintMetadata,
+    ) -> Result<(), String> {
+        let table = match self.tables.get(&constraint.table) {
+            Some(table) => table,
+            None => return Err(format!("table {} does not exist", constraint.table)),
+        };
+        if self
+            .table_constraints(table.id)
+            .any(|c| c.name == constraint.name)
+        {
+            return Err(format!(
+                "constraint \"{}\" for relation \"{}\" already exists",
+                constraint.name, table.name
+            ));
+        }
+        if let ConstraintKind::Unique { columns, .. } = &constraint.kind {
+            let index = self.get_index(&constraint.name);
+            if !index.is_some_and(|i| i.unique && i.table == table.id && &i.columns == columns) {
+                return Err(format!(
+                    "constraint \"{}\" has no matching unique index",
+                    constraint.name
+                ));
+            }
+        }
+        self.transaction(bm, |catalog| {
+            catalog.constraints.push(constraint);
+            Ok(())
+        })
+    }
+
+    /// Removes a constraint of the given table.
+    /// Returns the index of a PRIMARY KEY or UNIQUE constraint, which is dropped as well,
+    /// the caller has to drop its structure.
+    pub fn drop_constraint(
+        &mut self,
+        bm: &mut BufferManager,
+        table: &str,
+        name: &str,
+    ) -> Result<Option<IndexMetadata>, String> {
+        let id = self.table_id(table)?;
+        let pos = match self
+            .constraints
+            .iter()
+            .position(|c| c.table == id && c.name == name)
+        {
+            Some(pos) => pos,
+            None => {
+                return Err(format!(
+                    "constraint \"{}\" of relation \"{}\" does not exist",
+                    name, table
+                ))
+            }
+        };
+        if let ConstraintKind::Unique { columns, .. } = &self.constraints[pos].kind {
+            let dependent = self.referencing_constraints(id).find(|c| match &c.kind {
+                ConstraintKind::ForeignKey {
+                    referenced_columns, ..
+                } => {
+                    referenced_columns.len() == columns.len()
+                        && referenced_columns.iter().all(|c| columns.contains(c))
+                }
+                _ => false,
+            });
+            if let Some(dependent) = dependent {
+                return Err(format!(
+                    "cannot drop constraint {} because constraint {} depends on it",
+                    name, dependent.name
+                ));
+            }
         }
+        self.transaction(bm, |catalog| {
+            let constraint = catalog.constraints.remove(pos);
+            if let ConstraintKind::Unique { .. } = constraint.kind {
+                let index = catalog.indexes.iter().position(|i| i.name == name).unwrap();
+                return Ok(Some(catalog.indexes.remove(index)));
+            }
+            Ok(None)
+        })
+    }
+
+    /// Returns the constraints of the table with the given ID.
+    pub fn table_constraints(&self, table: usize) -> impl Iterator<Item = &ConstraintMetadata> {
+        self.constraints.iter().filter(move |c| c.table == table)
     }
 
-    pub fn create_index(&self, name: &str, table: &str) {}
+    /// Returns the foreign keys referencing the table with the given ID.
+    pub fn referencing_constraints(
+        &self,
+        table: usize,
+    ) -> impl Iterator<Item = &ConstraintMetadata> {
+        self.constraints.iter().filter(move |c| {
+            matches!(c.kind, ConstraintKind::ForeignKey { referenced_table, .. } if referenced_table == table)
+        })
+    }
+
+    /// Returns the PRIMARY KEY or UNIQUE constraint enforced by the given index.
+    fn unique_constraint_of(&self, index: &IndexMetadata) -> Option<&ConstraintMetadata> {
+        self.table_constraints(index.table)
+            .find(|c| c.name == index.name && matches!(c.kind, ConstraintKind::Unique { .. }))
+    }
+
+    /// Whether a table, view, sequence or index of the given name exists.
+    pub fn relation_exists(&self, name: &str) -> bool {
+        self.table_names.contains_key(name)
+            || self.get_view(name).is_some()
+            || self.get_sequence(name).is_some()
+            || self.get_index(name).is_some()
+    }
+
+    pub fn get_index(&self, name: &str) -> Option<&IndexMetadata> {
+        self.indexes.iter().find(|i| i.name == name)
+    }
+
+    /// Returns the metadata of all indexes.
+    pub fn indexes(&self) -> &[IndexMetadata] {
+        &self.indexes
+    }
 
-    pub fn get_index(&self, name: &str, table: &str) {}
+    /// Returns the metadata of all indexes on the table with the given ID.
+    pub fn table_indexes(&self, table: usize) -> impl Iterator<Item = &IndexMetadata> {
+        self.indexes.iter().filter(move |i| i.table == table)
+    }
+
+    /// Finds an index that can answer equality lookups on the given columns,
+    /// i.e. one whose key columns are all among them.
+    /// Unique indexes are preferred, then indexes with more key columns.
+    pub fn find_equality_index(&self, table: usize, columns: &[usize]) -> Option<&IndexMetadata> {
+        self.table_indexes(table)
+            .filter(|i| i.columns.iter().all(|c| columns.contains(c)))
+            .max_by_key(|i| (i.unique, i.columns.len()))
+    }
+
+    /// Finds the unique index whose key columns are exactly the given ones, in any order.
+    pub fn find_unique_index(&self, table: usize, columns: &[usize]) -> Option<&IndexMetadata> {
+        self.table_indexes(table).find(|i| {
+            i.unique
+                && i.columns.len() == columns.len()
+                && i.columns.iter().all(|c| columns.contains(c))
+        })
+    }
+
+    /// Finds a B+-tree whose first key column is the given column, which can be used for range scans.
+    pub fn find_range_index(&self, table: usize, column: usize) -> Option<&IndexMetadata> {
+        self.table_indexes(table)
+            .find(|i| i.kind == IndexKind::BTree && i.columns[0] == column)
+    }
+
+    /// Returns the metadata of all indexes on the given table.
+    pub fn get_table_indices(&self, table: &str) -> Vec<IndexMetadata> {
+        match self.table_names.get(table) {
+            Some(&id) => self
+                .indexes
+                .iter()
+                .filter(|i| i.table == id)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn bootstrap(bm: &mut BufferManager) -> Result<Self, String> {
+        let header = new_page(bm)?;
+        if header != HEADER_PAGE {
+            return Err("database file is not empty".to_owned());
+        }
+        let system_tables = SystemTables {
+            tables: HeapFile::create(bm)?,
+            columns: HeapFile::create(bm)?,
+            indexes: HeapFile::create(bm)?,
+            constraints: HeapFile::create(bm)?,
+            views: HeapFile::create(bm)?,
+            sequences: HeapFile::create(bm)?,
+            statistics: HeapFile::create(bm)?,
+        };
+        let catalog = Self {
+            tables: HashMap::new(),
+            table_names: HashMap::new(),
+            indexes: Vec::new(),
+            constraints: Vec::new(),
+            views: Vec::new(),
+            sequences: Vec::new(),
+            sequence_records: HashMap::new(),
+            statistics: HashMap::new(),
+            next_table_id: 0,
+            next_index_id: 0,
+            system_tables,
+        };
+        catalog.write_header(bm)?;
+        bm.flush_all();
+        Ok(catalog)
+    }
+
+    /// Applies a change to the catalog and persists it.
+    /// If either fails, the catalog is left unchanged.
+    fn transaction<T, F>(&mut self, bm: &mut BufferManager, change: F) -> Result<T, String>
+    where
+        F: FnOnce(&mut Self) -> Result<T, String>,
+    {
+        let backup = self.clone();
+        let result = change(self).and_then(|v| self.commit(bm).map(|_| v));
+        if result.is_err() {
+            *self = backup;
+            return result;
+        }
+        // the change is saved, failing to free the old system tables only leaks their pages
+        if backup.system_tables.destroy(bm).is_ok() {
+            let _ = self.save_free_list(bm);
+        }
+        result
+    }
+
+    /// Writes the catalog to new system tables and switches the file header over to them.
+    /// The old system tables are left to the caller.
+    fn commit(&mut self, bm: &mut BufferManager) -> Result<(), String> {
+        let old = self.system_tables;
+        let (system_tables, sequence_records) = self.store(bm)?;
+        self.system_tables = system_tables;
+        // the new system tables have to be on disk before the header points to them
+        bm.flush_all();
+        if let Err(err) = self.write_header(bm) {
+            let new = std::mem::replace(&mut self.system_tables, old);
+            let _ = new.destroy(bm);
+            return Err(err);
+        }
+        self.sequence_records = sequence_records;
+        bm.flush_all();
+        Ok(())
+    }
+
+    /// Saves the list of free pages, so pages deleted outside of DDL changes are not leaked.
+    pub fn save_free_list(&self, bm: &mut BufferManager) -> Result<(), String> {
+        self.write_header(bm)?;
+        bm.flush_all();
+        Ok(())
+    }
+
+    /// Writes the catalog to new system tables, also returns where the sequences are stored.
+    fn store(
+        &self,
+        bm: &mut BufferManager,
+    ) -> Result<(SystemTables, HashMap<usize, RecordId>), String> {
+        let mut system_tables = SystemTables {
+            tables: HeapFile::create(bm)?,
+            columns: HeapFile::create(bm)?,
+            indexes: HeapFile::create(bm)?,
+            constraints: HeapFile::create(bm)?,
+            views: HeapFile::create(bm)?,
+            sequences: HeapFile::create(bm)?,
+            statistics: HeapFile::create(bm)?,
+        };
+        match self.store_into(bm, &mut system_tables) {
+            Ok(sequence_records) => Ok((system_tables, sequence_records)),
+            Err(err) => {
+                system_tables.destroy(bm)?;
+                Err(err)
+            }
+        }
+    }
+
+    fn store_into(
+        &self,
+        bm: &mut BufferManager,
+        st: &mut SystemTables,
+    ) -> Result<HashMap<usize, RecordId>, String> {
+        let mut ids: Vec<_> = self.tables.keys().collect();
+        ids.sort_unstable();
+        for id in ids {
+            let table = &self.tables[id];
+            let mut w = RecordWriter::new();
+            w.u64(table.id as u64)
+                .str(&table.name)
+                .u64(table.first_page as u64)
+                .u64(table.version as u64);
+            st.tables.insert(bm, &w.finish())?;
+
+            for (position, stored) in table.history.iter().enumerate() {
+                let column = &stored.column;
+                let (tag, len) = column.data_type.tag();
+                let default = column.default.as_ref().map(|e| e.to_string());
+                let missing = match &stored.missing {
+                    Value::Null => None,
+                    value => Some(
+                        TupleLayout::from_types(vec![column.data_type])
+                            .encode(std::slice::from_ref(value))?,
+                    ),
+                };
+                let mut w = RecordWriter::new();
+                w.u64(table.id as u64)
+                    .u64(position as u64)
+                    .str(&column.name)
+                    .u8(tag)
+                    .u64(len as u64)
+                    .u8(column.nullable as u8)
+                    .opt_str(default.as_deref())
+                    .u64(stored.added as u64)
+                    .u64(stored.dropped.map_or(NO_VERSION, |v| v as u64))
+                    .opt_bytes(missing.as_deref())
+                    .u8(Identity::tag(column.identity));
+                st.columns.insert(bm, &w.finish())?;
+            }
+        }
+
+        for index in &self.indexes {
+            let mut w = RecordWriter::new();
+            w.u64(index.id as u64)
+                .str(&index.name)
+                .u64(index.table as u64)
+                .columns(&index.columns)
+                .u8(index.kind as u8)
+                .u8(index.unique as u8)
+                .u64(index.root_page.map_or(NO_PAGE, |p| p as u64));
+            st.indexes.insert(bm, &w.finish())?;
+        }
+
+        for constraint in &self.constraints {
+            let mut w = RecordWriter::new();
+            w.str(&constraint.name).u64(constraint.table as u64);
+            match &constraint.kind {
+                ConstraintKind::Unique { columns, primary } => {
+                    w.u8(0).columns(columns).u8(*primary as u8);
+                }
+                ConstraintKind::Check(expr) => {
+                    w.u8(1).str(&expr.to_string());
+                }
+                ConstraintKind::ForeignKey {
+                    columns,
+                    referenced_table,
+                    referenced_columns,
+                    on_delete,
+                    on_update,
+                } => {
+                    w.u8(2)
+                        .columns(columns)
+                        .u64(*referenced_table as u64)
+                        .columns(referenced_columns)
+                        .u8(*on_delete as u8)
+                        .u8(*on_update as u8);
+                }
+            }
+            st.constraints.insert(bm, &w.finish())?;
+        }
+
+        for view in &self.views {
+            let mut w = RecordWriter::new();
+            w.u64(view.id as u64)
+                .str(&view.name)
+                .str(&view.query.to_string())
+                .u64(view.columns.len() as u64);
+            for column in &view.columns {
+                w.str(column);
+            }
+            w.columns(&view.dependencies);
+            st.views.insert(bm, &w.finish())?;
+        }
+
+        let mut sequence_records = HashMap::new();
+        for sequence in &self.sequences {
+            let rid = st.sequences.insert(bm, &sequence_record(sequence))?;
+            sequence_records.insert(sequence.id, rid);
+        }
 
-    pub fn get_table_indices(&self, table: &str) {}
+        let mut ids: Vec<_> = self.statistics.keys().collect();
+        ids.sort_unstable();
+        for &id in ids {
+            let statistics = &self.statistics[&id];
+            let mut w = RecordWriter::new();
+            w.u8(STATISTICS_TABLE)
+                .u64(id as u64)
+                .u64(statistics.row_count)
+                .u64(statistics.page_count);
+            st.statistics.insert(bm, &w.finish())?;
+            for column in &statistics.columns {
+                let mut w = RecordWriter::new();
+                w.u8(STATISTICS_COLUMN)
+                    .u64(id as u64)
+                    .str(&column.column)
+                    .u64(column.null_fraction.to_bits())
+                    .u64(column.distinct_count);
+                st.statistics.insert(bm, &w.finish())?;
+                for (position, (value, frequency)) in column.most_common.iter().enumerate() {
+                    let mut w = RecordWriter::new();
+                    w.u8(STATISTICS_MOST_COMMON)
+                        .u64(id as u64)
+                        .str(&column.column)
+                        .u64(position as u64)
+                        .str(&value.to_string())
+                        .u64(frequency.to_bits());
+                    st.statistics.insert(bm, &w.finish())?;
+                }
+                for (position, bound) in column.histogram.iter().enumerate() {
+                    let mut w = RecordWriter::new();
+                    w.u8(STATISTICS_HISTOGRAM)
+                        .u64(id as u64)
+                        .str(&column.column)
+                        .u64(position as u64)
+                        .str(&bound.to_string());
+                    st.statistics.insert(bm, &w.finish())?;
+                }
+            }
+        }
+        Ok(sequence_records)
+    }
+
+    /// Reads the contents of the system tables.
+    fn load(&mut self, bm: &mut BufferManager) -> Result<(), String> {
+        let mut columns: HashMap<usize, Vec<(usize, StoredColumn)>> = HashMap::new();
+        for (_, record) in self.system_tables.columns.scan(bm)? {
+            let mut r = RecordReader::new(&record);
+            let table = r.u64()? as usize;
+            let position = r.u64()? as usize;
+            let name = r.str()?;
+            let data_type = DataType::from_tag(r.u8()?, r.u64()? as u32)?;
+            let mut column = Column::new(&name, data_type);
+            column.nullable = r.u8()? != 0;
+            column.default = match r.opt_str()? {
+                Some(sql) => Some(parse_expr(&sql)?),
+                None => None,
+            };
+            let added = r.u64()? as u16;
+            let dropped = match r.u64()? {
+                NO_VERSION => None,
+                v => Some(v as u16),
+            };
+            let missing = match r.opt_bytes()? {
+                Some(data) => {
+                    let layout = TupleLayout::from_types(vec![data_type]);
+                    TupleRef::new(&layout, data).get(0).to_value()
+                }
+                None => Value::Null,
+            };
+            column.identity = Identity::from_tag(r.u8()?)?;
+            let mut stored = StoredColumn::new(column, added, missing);
+            stored.dropped = dropped;
+            columns.entry(table).or_default().push((position, stored));
+        }
+
+        for (_, record) in self.system_tables.tables.scan(bm)? {
+            let mut r = RecordReader::new(&record);
+            let id = r.u64()? as usize;
+            let name = r.str()?;
+            let first_page = r.u64()? as PageID;
+            let version = r.u64()? as u16;
+            let mut cols = columns.remove(&id).unwrap_or_default();
+            cols.sort_by_key(|(position, _)| *position);
+            let history = cols.into_iter().map(|(_, c)| c).collect();
+            self.table_names.insert(name.clone(), id);
+            self.tables.insert(
+                id,
+                TableMetadata::new(name, id, first_page, version, history)?,
+            );
+        }
+
+        for (_, record) in self.system_tables.indexes.scan(bm)? {
+            let mut r = RecordReader::new(&record);
+            let id = r.u64()? as usize;
+            let name = r.str()?;
+            let table = r.u64()? as usize;
+            let columns = r.columns()?;
+            let kind = match r.u8()? {
+                0 => IndexKind::BTree,
+                1 => IndexKind::Hash,
+                k => return Err(format!("corrupt catalog: unknown index kind {}", k)),
+            };
+            let unique = r.u8()? != 0;
+            let root_page = match r.u64()? {
+                NO_PAGE => None,
+                p => Some(p as PageID),
+            };
+            self.indexes.push(IndexMetadata {
+                id,
+                name,
+                table,
+                columns,
+                kind,
+                unique,
+                root_page,
+            });
+        }
+        self.indexes.sort_by_key(|i| i.id);
+
+        for (_, record) in self.system_tables.constraints.scan(bm)? {
+            let mut r = RecordReader::new(&record);
+            let name = r.str()?;
+            let table = r.u64()? as usize;
+            let kind = match r.u8()? {
+                0 => ConstraintKind::Unique {
+                    columns: r.columns()?,
+                    primary: r.u8()? != 0,
+                },
+                1 => ConstraintKind::Check(parse_expr(&r.str()?)?),
+                2 => ConstraintKind::ForeignKey {
+                    columns: r.columns()?,
+                    referenced_table: r.u64()? as usize,
+                    referenced_columns: r.columns()?,
+                    on_delete: ForeignKeyAction::from_tag(r.u8()?)?,
+                    on_update: ForeignKeyAction::from_tag(r.u8()?)?,
+                },
+                k => return Err(format!("corrupt catalog: unknown constraint kind {}", k)),
+            };
+            self.constraints
+                .push(ConstraintMetadata { name, table, kind });
+        }
+
+        for (_, record) in self.system_tables.views.scan(bm)? {
+            let mut r = RecordReader::new(&record);
+            let id = r.u64()? as usize;
+            let name = r.str()?;
+            let query = parse_query(&r.str()?)?;
+            let columns = (0..r.u64()?).map(|_| r.str()).collect::<Result<_, _>>()?;
+            let dependencies = r.columns()?;
+            self.views.push(ViewMetadata {
+                id,
+                name,
+                columns,
+                query,
+                dependencies,
+            });
+        }
+        self.views.sort_by_key(|v| v.id);
+
+        for (rid, record) in self.system_tables.sequences.scan(bm)? {
+            let mut r = RecordReader::new(&record);
+            let id = r.u64()? as usize;
+            self.sequence_records.insert(id, rid);
+            let name = r.str()?;
+            let data_type = DataType::from_tag(r.u8()?, 0)?;
+            let mut sequence = SequenceMetadata {
+                id,
+                name,
+                data_type,
+                increment: r.u64()? as i64,
+                min_value: r.u64()? as i64,
+                max_value: r.u64()? as i64,
+                start: r.u64()? as i64,
+                cache: r.u64()? as i64,
+                cycle: r.u8()? != 0,
+                last_value: None,
+                owner: None,
+            };
+            let has_last_value = r.u8()? != 0;
+            let last_value = r.u64()? as i64;
+            if has_last_value {
+                sequence.last_value = Some(last_value);
+            }
+            if r.u8()? != 0 {
+                sequence.owner = Some((r.u64()? as usize, r.str()?));
+            }
+            self.sequences.push(sequence);
+        }
+        self.sequences.sort_by_key(|s| s.id);
+
+        let mut values = Vec::new();
+        for (_, record) in self.system_tables.statistics.scan(bm)? {
+            let mut r = RecordReader::new(&record);
+            let kind = r.u8()?;
+            let id = r.u64()? as usize;
+            if kind == STATISTICS_TABLE {
+                let statistics = TableStatistics {
+                    row_count: r.u64()?,
+                    page_count: r.u64()?,
+                    columns: Vec::new(),
+                };
+                self.statistics.insert(id, statistics);
+                continue;
+            }
+            let column = r.str()?;
+            match kind {
+                STATISTICS_COLUMN => {
+                    let statistics = ColumnStatistics {
+                        column,
+                        null_fraction: f64::from_bits(r.u64()?),
+                        distinct_count: r.u64()?,
+                        most_common: Vec::new(),
+                        histogram: Vec::new(),
+                    };
+                    match self.statistics.get_mut(&id) {
+                        Some(table) => table.columns.push(statistics),
+                        None => {
+                            return Err(format!("corrupt catalog: no statistics of table {}", id))
+                        }
+                    }
+                }
+                STATISTICS_MOST_COMMON | STATISTICS_HISTOGRAM => {
+                    let position = r.u64()?;
+                    let text = r.str()?;
+                    let frequency = match kind {
+                        STATISTICS_MOST_COMMON => f64::from_bits(r.u64()?),
+                        _ => 0.0,
+                    };
+                    values.push((id, column, kind, position, text, frequency));
+                }
+                kind => return Err(format!("corrupt catalog: unknown statistics kind {}", kind)),
+            }
+        }
+        // values are stored as text and converted back to the column's type
+        values.sort_by_key(|(id, _, kind, position, _, _)| (*id, *kind, *position));
+        for (id, column, kind, _, text, frequency) in values {
+            let data_type = match self.tables.get(&id).and_then(|t| t.schema.column(&column)) {
+                Some(c) => c.data_type,
+                None => {
+                    return Err(format!(
+                        "corrupt catalog: no column \"{}\" in table {}",
+                        column, id
+                    ))
+                }
+            };
+            let value = Value::Text(text).cast(data_type)?;
+            let statistics = self.statistics.get_mut(&id).unwrap();
+            let column = match statistics.columns.iter_mut().find(|c| c.column == column) {
+                Some(c) => c,
+                None => {
+                    return Err(format!(
+                        "corrupt catalog: no statistics of column \"{}\"",
+                        column
+                    ))
+                }
+            };
+            match kind {
+                STATISTICS_MOST_COMMON => column.most_common.push((value, frequency)),
+                _ => column.histogram.push(value),
+            }
+        }
+        Ok(())
+    }
+
+    fn write_header(&self, bm: &mut BufferManager) -> Result<(), String> {
+        let free_list = bm.write_free_list()?;
+        let p = fetch_page(bm, HEADER_PAGE)?;
+        let mut header = p.write().unwrap();
+        write_u64(&mut header.data, HEADER_MAGIC, MAGIC);
+        let roots = [
+            (HEADER_TABLES, self.system_tables.tables),
+            (HEADER_COLUMNS, self.system_tables.columns),
+            (HEADER_INDEXES, self.system_tables.indexes),
+            (HEADER_CONSTRAINTS, self.system_tables.constraints),
+            (HEADER_VIEWS, self.system_tables.views),
+            (HEADER_SEQUENCES, self.system_tables.sequences),
+            (HEADER_STATISTICS, self.system_tables.statistics),
+        ];
+        for (offset, heap) in roots {
+            write_u64(&mut header.data, offset, heap.first_page() as u64);
+        }
+        write_u64(
+            &mut header.data,
+            HEADER_NEXT_TABLE_ID,
+            self.next_table_id as u64,
+        );
+        write_u64(
+            &mut header.data,
+            HEADER_NEXT_INDEX_ID,
+            self.next_index_id as u64,
+        );
+        write_u64(
+            &mut header.data,
+            HEADER_FREE_LIST,
+            free_list.map_or(NO_PAGE, |p| p as u64),
+        );
+        drop(header);
+        bm.unpin_page(HEADER_PAGE, true);
+        Ok(())
+    }
 }
 
-#[derive(Clone)]
-struct TableMetadata {
-    schema: Schema,
-    name: String,
-    id: usize,
+/// The record of a sequence in `qdb_sequences`.
+/// The last value always takes the same space, so reserving values can update the record in place.
+fn sequence_record(sequence: &SequenceMetadata) -> Vec<u8> {
+    let mut w = RecordWriter::new();
+    w.u64(sequence.id as u64)
+        .str(&sequence.name)
+        .u8(sequence.data_type.tag().0)
+        .u64(sequence.increment as u64)
+        .u64(sequence.min_value as u64)
+        .u64(sequence.max_value as u64)
+        .u64(sequence.start as u64)
+        .u64(sequence.cache as u64)
+        .u8(sequence.cycle as u8)
+        .u8(sequence.last_value.is_some() as u8)
+        .u64(sequence.last_value.unwrap_or(0) as u64);
+    match &sequence.owner {
+        Some((table, column)) => w.u8(1).u64(*table as u64).str(column),
+        None => w.u8(0),
+    };
+    w.finish()
 }
 
-#[derive(Clone)]
-struct Schema {}
+/// Heap files of the system tables.
+#[derive(Clone, Copy)]
+struct SystemTables {
+    tables: HeapFile,
+    columns: HeapFile,
+    indexes: HeapFile,
+    constraints: HeapFile,
+    views: HeapFile,
+    sequences: HeapFile,
+    statistics: HeapFile,
+}
+
+impl SystemTables {
+    fn destroy(self, bm: &mut BufferManager) -> Result<(), String> {
+        self.tables.destroy(bm)?;
+        self.columns.destroy(bm)?;
+        self.indexes.destroy(bm)?;
+        self.constraints.destroy(bm)?;
+        self.views.destroy(bm)?;
+        self.sequences.destroy(bm)?;
+        self.statistics.destroy(bm)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TableMetadata {
+    /// The current schema.
+    pub schema: Schema,
+    pub name: String,
+    pub id: usize,
+    /// First page of the table's heap file.
+    pub first_page: PageID,
+    /// Version of the current schema, incremented whenever a column is added or dropped.
+    pub version: u16,
+    /// All columns the table ever had, including dropped ones, in storage order.
+    history: Vec<StoredColumn>,
+    /// Format of the rows, rebuilt whenever the history changes.
+    format: Arc<RowFormat>,
+}
+
+impl TableMetadata {
+    fn new(
+        name: String,
+        id: usize,
+        first_page: PageID,
+        version: u16,
+        history: Vec<StoredColumn>,
+    ) -> Result<Self, String> {
+        let mut table = Self {
+            schema: Schema::default(),
+            name,
+            id,
+            first_page,
+            version,
+            format: Arc::new(RowFormat::new(version, &history)),
+            history,
+        };
+        table.update_schema(version)?;
+        Ok(table)
+    }
+
+    /// Returns the format of the table's rows, which can read rows of all schema versions.
+    pub fn row_format(&self) -> &Arc<RowFormat> {
+        &self.format
+    }
+
+    fn next_version(&self) -> Result<u16, String> {
+        match self.version.checked_add(1) {
+            Some(version) if version as u64 != NO_VERSION => Ok(version),
+            _ => Err(format!(
+                "too many schema changes of table \"{}\"",
+                self.name
+            )),
+        }
+    }
+
+    fn live_columns_mut(&mut self) -> impl Iterator<Item = &mut StoredColumn> {
+        self.history.iter_mut().filter(|c| c.dropped.is_none())
+    }
+
+    /// Switches to the given version, rebuilding the current schema from the column history.
+    fn update_schema(&mut self, version: u16) -> Result<(), String> {
+        self.version = version;
+        self.schema = Schema::new(
+            self.history
+                .iter()
+                .filter(|c| c.dropped.is_none())
+                .map(|c| c.column.clone())
+                .collect(),
+        )?;
+        self.format = Arc::new(RowFormat::new(version, &self.history));
+        Ok(())
+    }
+}
+
+/// A column as recorded in the catalog, with the range of schema versions that contain it.
+#[derive(Clone, Debug)]
+pub struct StoredColumn {
+    pub column: Column,
+    /// Schema version that added the column.
+    pub added: u16,
+    /// Schema version that dropped the column, if it was dropped.
+    pub dropped: Option<u16>,
+    /// Value of the column in rows written before it was added.
+    pub missing: Value,
+}
+
+impl StoredColumn {
+    fn new(column: Column, added: u16, missing: Value) -> Self {
+        Self {
+            column,
+            added,
+            dropped: None,
+            missing,
+        }
+    }
+
+    /// Whether rows written under the given schema version contain this column.
+    pub fn in_version(&self, version: u16) -> bool {
+        self.added <= version && self.dropped.is_none_or(|dropped| version < dropped)
+    }
+}
+
+/// The ordered list of columns of a table.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Schema {
+    columns: Vec<Column>,
+}
+
+impl Schema {
+    /// Creates a schema, failing if column names are not unique.
+    pub fn new(columns: Vec<Column>) -> Result<Self, String> {
+        for (i, c) in columns.iter().enumerate() {
+            if columns[..i].iter().any(|other| other.name == c.name) {
+                return Err(format!("column \"{}\" specified more than once", c.name));
+            }
+        }
+        Ok(Self { columns })
+    }
+
+    /// Converts the column definitions of a `CREATE TABLE` statement.
+    pub fn from_column_defs(defs: &[ColumnDef]) -> Result<Self, String> {
+        let columns = defs
+            .iter()
+            .map(Column::from_column_def)
+            .collect::<Result<_, _>>()?;
+        Self::new(columns)
+    }
+
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    /// Looks up a column by name.
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns.iter().find(|c| c.name == name)
+    }
+
+    /// Looks up the position of a column by name.
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c.name == name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub data_type: DataType,
+    pub nullable: bool,
+    /// Expression evaluated for the column if an insert does not provide a value.
+    pub default: Option<Expr>,
+    /// Whether the column is an identity column, its default takes values from a sequence.
+    pub identity: Option<Identity>,
+}
+
+impl Column {
+    pub fn new(name: &str, data_type: DataType) -> Self {
+        Self {
+            name: name.to_owned(),
+            data_type,
+            nullable: true,
+            default: None,
+            identity: None,
+        }
+    }
+
+    fn from_column_def(def: &ColumnDef) -> Result<Self, String> {
+        let mut column = Column::new(&ident_name(&def.name), DataType::from_sql(&def.data_type)?);
+        for option in &def.options {
+            match &option.option {
+                ColumnOption::Null => column.nullable = true,
+                ColumnOption::NotNull => column.nullable = false,
+                ColumnOption::Unique { is_primary: true } => column.nullable = false,
+                ColumnOption::Default(expr) => column.default = Some(expr.clone()),
+                _ => {}
+            }
+        }
+        Ok(column)
+    }
+}
+
+/// How an identity column (`GENERATED ... AS IDENTITY`) treats explicitly given values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Identity {
+    /// Only the default may be inserted or assigned.
+    Always,
+    /// Explicit values replace the default like for other columns.
+    ByDefault,
+}
+
+impl Identity {
+    fn tag(identity: Option<Self>) -> u8 {
+        match identity {
+            None => 0,
+            Some(Identity::Always) => 1,
+            Some(Identity::ByDefault) => 2,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Result<Option<Self>, String> {
+        match tag {
+            0 => Ok(None),
+            1 => Ok(Some(Identity::Always)),
+            2 => Ok(Some(Identity::ByDefault)),
+            t => Err(format!("corrupt catalog: unknown identity kind {}", t)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DataType {
+    /// 32-bit signed integer
+    Int,
+    /// 64-bit signed integer
+    BigInt,
+    /// 64-bit IEEE floating point number
+    Float,
+    Boolean,
+    /// String with a maximum length in characters
+    Varchar(u32),
+    /// String of unlimited length
+    Text,
+    /// Days since 1970-01-01
+    Date,
+    /// Microseconds since 1970-01-01 00:00:00
+    Timestamp,
+    /// Binary string
+    Bytea,
+}
+
+impl DataType {
+    /// Converts a data type from the SQL AST.
+    pub fn from_sql(data_type: &ast::DataType) -> Result<Self, String> {
+        match data_type {
+            ast::DataType::SmallInt | ast::DataType::Int => Ok(DataType::Int),
+            ast::DataType::BigInt => Ok(DataType::BigInt),
+            ast::DataType::Float(_) | ast::DataType::Real | ast::DataType::Double => {
+                Ok(DataType::Float)
+            }
+            ast::DataType::Boolean => Ok(DataType::Boolean),
+            ast::DataType::Varchar(Some(n)) if *n <= u32::MAX as u64 => {
+                Ok(DataType::Varchar(*n as u32))
+            }
+            ast::DataType::Varchar(None) | ast::DataType::Text | ast::DataType::String => {
+                Ok(DataType::Text)
+            }
+            ast::DataType::Date => Ok(DataType::Date),
+            ast::DataType::Timestamp => Ok(DataType::Timestamp),
+            ast::DataType::Bytea => Ok(DataType::Bytea),
+            other => Err(format!("unsupported data type: {}", other)),
+        }
+    }
+
+    /// Whether values of the two types have the same index keys, as required for foreign keys.
+    pub fn is_comparable_key(self, other: DataType) -> bool {
+        use DataType::*;
+        match (self, other) {
+            (Int, Int) | (Int, BigInt) | (BigInt, Int) | (BigInt, BigInt) => true,
+            (Varchar(_), Varchar(_)) | (Varchar(_), Text) | (Text, Varchar(_)) | (Text, Text) => {
+                true
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    /// Compact representation used in the system tables: a tag and the maximum length.
+    fn tag(&self) -> (u8, u32) {
+        match *self {
+            DataType::Int => (0, 0),
+            DataType::BigInt => (1, 0),
+            DataType::Float => (2, 0),
+            DataType::Boolean => (3, 0),
+            DataType::Varchar(n) => (4, n),
+            DataType::Text => (5, 0),
+            DataType::Date => (6, 0),
+            DataType::Timestamp => (7, 0),
+            DataType::Bytea => (8, 0),
+        }
+    }
+
+    fn from_tag(tag: u8, len: u32) -> Result<Self, String> {
+        match tag {
+            0 => Ok(DataType::Int),
+            1 => Ok(DataType::BigInt),
+            2 => Ok(DataType::Float),
+            3 => Ok(DataType::Boolean),
+            4 => Ok(DataType::Varchar(len)),
+            5 => Ok(DataType::Text),
+            6 => Ok(DataType::Date),
+            7 => Ok(DataType::Timestamp),
+            8 => Ok(DataType::Bytea),
+            t => Err(format!("corrupt catalog: unknown data type {}", t)),
+        }
+    }
+}
+
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataType::Int => write!(f, "INT"),
+            DataType::BigInt => write!(f, "BIGINT"),
+            DataType::Float => write!(f, "FLOAT"),
+            DataType::Boolean => write!(f, "BOOLEAN"),
+            DataType::Varchar(n) => write!(f, "VARCHAR({})", n),
+            DataType::Text => write!(f, "TEXT"),
+            DataType::Date => write!(f, "DATE"),
+            DataType::Timestamp => write!(f, "TIMESTAMP"),
+            DataType::Bytea => write!(f, "BYTEA"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexKind {
+    BTree,
+    Hash,
+}
+
+#[derive(Clone, Debug)]
+pub struct IndexMetadata {
+    pub id: usize,
+    pub name: String,
+    /// ID of the indexed table.
+    pub table: usize,
+    /// Positions of the key columns in the table's schema, most significant first.
+    pub columns: Vec<usize>,
+    pub kind: IndexKind,
+    pub unique: bool,
+    /// Page from which an on-disk index structure is opened.
+    pub root_page: Option<PageID>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConstraintMetadata {
+    /// Unique among the constraints of the table.
+    pub name: String,
+    /// ID of the constrained table.
+    pub table: usize,
+    pub kind: ConstraintKind,
+}
+
+#[derive(Clone, Debug)]
+pub enum ConstraintKind {
+    /// PRIMARY KEY or UNIQUE, enforced by the unique index of the same name.
+    /// The columns of a primary key are also NOT NULL.
+    Unique { columns: Vec<usize>, primary: bool },
+    /// A condition every row has to satisfy, i.e. evaluate to true or NULL.
+    Check(Expr),
+    /// The values of `columns` have to exist in `referenced_columns` of the referenced table,
+    /// unless any of them is NULL.
+    ForeignKey {
+        columns: Vec<usize>,
+        referenced_table: usize,
+        referenced_columns: Vec<usize>,
+        on_delete: ForeignKeyAction,
+        on_update: ForeignKeyAction,
+    },
+}
+
+/// A named query, expanded wherever it is referenced.
+#[derive(Clone, Debug)]
+pub struct ViewMetadata {
+    pub id: usize,
+    pub name: String,
+    /// Names of the view's columns, given in its definition or taken from the query's output.
+    pub columns: Vec<String>,
+    pub query: Query,
+    /// IDs of the tables and views referenced directly by the query.
+    pub dependencies: Vec<usize>,
+}
+
+/// A generator of unique integers, e.g. for SERIAL and identity columns.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SequenceMetadata {
+    pub id: usize,
+    pub name: String,
+    /// INT or BIGINT, which limits the values.
+    pub data_type: DataType,
+    pub increment: i64,
+    pub min_value: i64,
+    pub max_value: i64,
+    pub start: i64,
+    /// How many values are reserved in the catalog at once.
+    /// Reserved values that are not used before the database is closed are skipped.
+    pub cache: i64,
+    /// Whether the sequence wraps around instead of failing when it runs out of values.
+    pub cycle: bool,
+    /// The last reserved value, `None` if no value was reserved yet.
+    last_value: Option<i64>,
+    /// Table ID and name of the SERIAL or identity column the sequence is dropped with.
+    pub owner: Option<(usize, String)>,
+}
+
+impl SequenceMetadata {
+    /// Creates a sequence with the given options, using PostgreSQL's defaults for missing ones.
+    pub fn new(name: &str, options: &SequenceOptions) -> Result<Self, String> {
+        let data_type = options.data_type.unwrap_or(DataType::BigInt);
+        let (type_min, type_max) = match data_type {
+            DataType::Int => (i32::MIN as i64, i32::MAX as i64),
+            _ => (i64::MIN, i64::MAX),
+        };
+        let increment = options.increment.unwrap_or(1);
+        if increment == 0 {
+            return Err("INCREMENT must not be zero".to_owned());
+        }
+        let ascending = increment > 0;
+        let min_value = options
+            .min_value
+            .unwrap_or(if ascending { 1 } else { type_min });
+        let max_value = options
+            .max_value
+            .unwrap_or(if ascending { type_max } else { -1 });
+        for (option, value) in [("MINVALUE", min_value), ("MAXVALUE", max_value)] {
+            if !(type_min..=type_max).contains(&value) {
+                return Err(format!(
+                    "{} ({}) is out of range for sequence data type {}",
+                    option, value, data_type
+                ));
+            }
+        }
+        if min_value >= max_value {
+            return Err(format!(
+                "MINVALUE ({}) must be less than MAXVALUE ({})",
+                min_value, max_value
+            ));
+        }
+        let start = options
+            .start
+            .unwrap_or(if ascending { min_value } else { max_value });
+        if start < min_value {
+            return Err(format!(
+                "START value ({}) cannot be less than MINVALUE ({})",
+                start, min_value
+            ));
+        }
+        if start > max_value {
+            return Err(format!(
+                "START value ({}) cannot be greater than MAXVALUE ({})",
+                start, max_value
+            ));
+        }
+        let cache = options.cache.unwrap_or(DEFAULT_SEQUENCE_CACHE);
+        if cache < 1 {
+            return Err(format!("CACHE ({}) must be greater than zero", cache));
+        }
+        Ok(Self {
+            id: 0,
+            name: name.to_owned(),
+            data_type,
+            increment,
+            min_value,
+            max_value,
+            start,
+            cache,
+            cycle: options.cycle.unwrap_or(false),
+            last_value: None,
+            owner: None,
+        })
+    }
+
+    /// Returns the value following `value`, or `None` if the sequence has run out of values.
+    fn advance(&self, value: i64) -> Option<i64> {
+        match value.checked_add(self.increment) {
+            Some(next) if (self.min_value..=self.max_value).contains(&next) => Some(next),
+            _ if !self.cycle => None,
+            _ if self.increment > 0 => Some(self.min_value),
+            _ => Some(self.max_value),
+        }
+    }
+
+    /// Converts a value of the sequence to its data type.
+    pub fn value(&self, value: i64) -> Value {
+        match self.data_type {
+            DataType::Int => Value::Int(value as i32),
+            _ => Value::BigInt(value),
+        }
+    }
+}
+
+/// What happens to referencing rows when a referenced row is deleted or its key is updated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ForeignKeyAction {
+    /// The change is rejected.
+    Restrict,
+    /// Referencing rows are deleted or updated as well.
+    Cascade,
+}
+
+impl ForeignKeyAction {
+    /// NO ACTION, the default, is checked immediately like RESTRICT.
+    fn from_sql(action: Option<&ReferentialAction>) -> Result<Self, String> {
+        match action {
+            None | Some(ReferentialAction::NoAction) | Some(ReferentialAction::Restrict) => {
+                Ok(ForeignKeyAction::Restrict)
+            }
+            Some(ReferentialAction::Cascade) => Ok(ForeignKeyAction::Cascade),
+            Some(action) => Err(format!("ON DELETE/UPDATE {} is not supported", action)),
+        }
+    }
+
+    fn from_tag(tag: u8) -> Result<Self, String> {
+        match tag {
+            0 => Ok(ForeignKeyAction::Restrict),
+            1 => Ok(ForeignKeyAction::Cascade),
+            t => Err(format!("corrupt catalog: unknown foreign key action {}", t)),
+        }
+    }
+}
+
+/// A constraint as written in SQL.
+/// Column constraints are converted to table constraints, keeping the referential actions
+/// that only the column syntax of foreign keys can specify.
+#[derive(Clone, Debug)]
+pub struct ConstraintDef {
+    pub constraint: TableConstraint,
+    pub on_delete: Option<ReferentialAction>,
+    pub on_update: Option<ReferentialAction>,
+}
+
+impl ConstraintDef {
+    pub fn new(constraint: TableConstraint) -> Self {
+        Self {
+            constraint,
+            on_delete: None,
+            on_update: None,
+        }
+    }
+
+    /// Collects the column and table constraints of a `CREATE TABLE` statement (except NOT NULL),
+    /// primary keys and unique constraints first, so foreign keys can reference them.
+    pub fn from_create_table(columns: &[ColumnDef], constraints: &[TableConstraint]) -> Vec<Self> {
+        let mut defs: Vec<_> = columns
+            .iter()
+            .flat_map(|column| column.options.iter().map(move |o| (column, o)))
+            .filter_map(|(column, option)| {
+                let name = option.name.clone();
+                let columns = vec![column.name.clone()];
+                Some(match &option.option {
+                    ColumnOption::Unique { is_primary } => Self::new(TableConstraint::Unique {
+                        name,
+                        columns,
+                        is_primary: *is_primary,
+                    }),
+                    ColumnOption::Check(expr) => Self::new(TableConstraint::Check {
+                        name,
+                        expr: Box::new(expr.clone()),
+                    }),
+                    ColumnOption::ForeignKey {
+                        foreign_table,
+                        referred_columns,
+                        on_delete,
+                        on_update,
+                    } => Self {
+                        constraint: TableConstraint::ForeignKey {
+                            name,
+                            columns,
+                            foreign_table: foreign_table.clone(),
+                            referred_columns: referred_columns.clone(),
+                        },
+                        on_delete: on_delete.clone(),
+                        on_update: on_update.clone(),
+                    },
+                    _ => return None,
+                })
+            })
+            .chain(constraints.iter().cloned().map(Self::new))
+            .collect();
+        defs.sort_by_key(|d| match d.constraint {
+            TableConstraint::Unique { .. } => 0,
+            TableConstraint::Check { .. } => 1,
+            TableConstraint::ForeignKey { .. } => 2,
+        });
+        defs
+    }
+}
+
+/// Serializes the records of the system tables.
+struct RecordWriter {
+    buf: Vec<u8>,
+}
+
+impl RecordWriter {
+    fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    fn u8(&mut self, v: u8) -> &mut Self {
+        self.buf.push(v);
+        self
+    }
+
+    fn u64(&mut self, v: u64) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn str(&mut self, v: &str) -> &mut Self {
+        self.bytes(v.as_bytes())
+    }
+
+    fn bytes(&mut self, v: &[u8]) -> &mut Self {
+        self.u64(v.len() as u64);
+        self.buf.extend_from_slice(v);
+        self
+    }
+
+    fn columns(&mut self, v: &[usize]) -> &mut Self {
+        self.u64(v.len() as u64);
+        for &c in v {
+            self.u64(c as u64);
+        }
+        self
+    }
+
+    fn opt_str(&mut self, v: Option<&str>) -> &mut Self {
+        self.opt_bytes(v.map(str::as_bytes))
+    }
+
+    fn opt_bytes(&mut self, v: Option<&[u8]>) -> &mut Self {
+        match v {
+            Some(v) => self.u8(1).bytes(v),
+            None => self.u8(0),
+        }
+    }
+
+    fn finish(&self) -> Vec<u8> {
+        self.buf.clone()
+    }
+}
+
+struct RecordReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> RecordReader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.buf.len() < n {
+            return Err("corrupt catalog: record too short".to_owned());
+        }
+        let (v, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(v)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<String, String> {
+        String::from_utf8(self.bytes()?.to_vec())
+            .map_err(|_| "corrupt catalog: invalid UTF-8".to_owned())
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u64()? as usize;
+        self.take(len)
+    }
+
+    fn columns(&mut self) -> Result<Vec<usize>, String> {
+        let len = self.u64()? as usize;
+        (0..len).map(|_| self.u64().map(|c| c as usize)).collect()
+    }
+
+    fn opt_str(&mut self) -> Result<Option<String>, String> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => self.str().map(Some),
+        }
+    }
+
+    fn opt_bytes(&mut self) -> Result<Option<&'a [u8]>, String> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => self.bytes().map(Some),
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use sqlparser::ast::Statement;
+
+    use crate::disk_manager::TempFile;
+    use crate::sql::parse_sql_statement;
+
+    fn schema_of(sql: &str) -> Result<Schema, String> {
+        match parse_sql_statement(sql)?.remove(0) {
+            Statement::CreateTable { columns, .. } => Schema::from_column_defs(&columns),
+            other => panic!("not a CREATE TABLE statement: {}", other),
+        }
+    }
+
     #[test]
     fn it_works() {}
+
+    #[test]
+    fn schema_from_create_table() {
+        let schema = schema_of(
+            "CREATE TABLE employees (
+                id BIGINT PRIMARY KEY,
+                Name VARCHAR(100) NOT NULL,
+                \"Email\" TEXT NULL,
+                salary FLOAT DEFAULT 0.0,
+                active BOOLEAN,
+                hired DATE,
+                last_login TIMESTAMP,
+                photo BYTEA,
+                age INT
+            )",
+        )
+        .unwrap();
+
+        let types: Vec<_> = schema.columns().iter().map(|c| c.data_type).collect();
+        assert_eq!(
+            types,
+            vec![
+                DataType::BigInt,
+                DataType::Varchar(100),
+                DataType::Text,
+                DataType::Float,
+                DataType::Boolean,
+                DataType::Date,
+                DataType::Timestamp,
+                DataType::Bytea,
+                DataType::Int,
+            ]
+        );
+        assert_eq!(schema.index_of("name"), Some(1));
+        assert_eq!(schema.index_of("Email"), Some(2));
+        assert_eq!(schema.index_of("email"), None);
+        assert!(!schema.column("id").unwrap().nullable);
+        assert!(!schema.column("name").unwrap().nullable);
+        assert!(schema.column("Email").unwrap().nullable);
+        assert!(schema.column("salary").unwrap().default.is_some());
+        assert!(schema.column("age").unwrap().default.is_none());
+    }
+
+    fn buffer_manager(name: &str) -> (BufferManager, TempFile) {
+        let file = TempFile::new(&format!("catalog_{}", name));
+        (BufferManager::with_file(16, file.path()), file)
+    }
+
+    #[test]
+    fn persist_and_reload() {
+        let (mut bm, file) = buffer_manager("reload");
+        let mut catalog = Catalog::open(&mut bm).unwrap();
+        let users =
+            schema_of("CREATE TABLE users (id BIGINT PRIMARY KEY, name TEXT DEFAULT 'x' || 'y')")
+                .unwrap();
+        let orders = schema_of("CREATE TABLE orders (id INT, amount FLOAT)").unwrap();
+        assert_eq!(catalog.create_table(&mut bm, "users", &users), Ok(0));
+        assert_eq!(catalog.create_table(&mut bm, "orders", &orders), Ok(1));
+        assert!(catalog.create_table(&mut bm, "users", &orders).is_err());
+        let index = IndexMetadata {
+            id: 42,
+            name: "users_pkey".to_owned(),
+            table: 0,
+            columns: vec![0],
+            kind: IndexKind::Hash,
+            unique: true,
+            root_page: Some(123),
+        };
+        assert_eq!(catalog.create_index(&mut bm, index.clone()), Ok(0));
+        assert!(catalog.create_index(&mut bm, index.clone()).is_err());
+        let orders_index = IndexMetadata {
+            name: "orders_idx".to_owned(),
+            table: 1,
+            ..index
+        };
+        assert_eq!(catalog.create_index(&mut bm, orders_index), Ok(1));
+        catalog.drop_table(&mut bm, "orders", false).unwrap();
+        assert!(catalog.drop_table(&mut bm, "orders", false).is_err());
+        assert_eq!(catalog.create_table(&mut bm, "orders", &orders), Ok(2));
+        drop(bm);
+
+        let mut bm = BufferManager::open(16, file.path()).unwrap();
+        let catalog = Catalog::open(&mut bm).unwrap();
+        let table = catalog.get_table("users").unwrap();
+        assert_eq!(table.id, 0);
+        assert_eq!(table.schema, users);
+        assert_eq!(catalog.get_table("orders").unwrap().schema, orders);
+        assert_eq!(catalog.get_table_by_id(2).unwrap().name, "orders");
+        assert_eq!(catalog.next_table_id, 3);
+        let indexes = catalog.get_table_indices("users");
+        assert_eq!(indexes.len(), 1);
+        assert_eq!(indexes[0].name, "users_pkey");
+        assert_eq!(indexes[0].kind, IndexKind::Hash);
+        assert_eq!(indexes[0].root_page, Some(123));
+        // dropped together with the first orders table
+        assert!(catalog.get_index("orders_idx").is_none());
+    }
+
+    #[test]
+    fn index_lookup() {
+        let (mut bm, _file) = buffer_manager("index_lookup");
+        let mut catalog = Catalog::open(&mut bm).unwrap();
+        let schema = schema_of("CREATE TABLE t (a INT, b INT, c INT)").unwrap();
+        let t = catalog.create_table(&mut bm, "t", &schema).unwrap();
+        let index = |name: &str, columns: Vec<usize>, kind, unique| IndexMetadata {
+            id: 0,
+            name: name.to_owned(),
+            table: t,
+            columns,
+            kind,
+            unique,
+            root_page: None,
+        };
+        for i in [
+            index("t_a", vec![0], IndexKind::Hash, false),
+            index("t_ab", vec![0, 1], IndexKind::BTree, false),
+            index("t_b", vec![1], IndexKind::BTree, true),
+        ] {
+            catalog.create_index(&mut bm, i).unwrap();
+        }
+        assert!(catalog
+            .create_index(&mut bm, index("t_d", vec![3], IndexKind::BTree, false))
+            .is_err());
+        assert!(catalog
+            .create_index(&mut bm, index("t", vec![0], IndexKind::BTree, false))
+            .is_err());
+
+        let find = |catalog: &Catalog, columns: &[usize]| {
+            catalog
+                .find_equality_index(t, columns)
+                .map(|i| i.name.clone())
+        };
+        assert_eq!(find(&catalog, &[0]).unwrap(), "t_a");
+        assert_eq!(find(&catalog, &[0, 1]).unwrap(), "t_b");
+        assert_eq!(find(&catalog, &[0, 2]).unwrap(), "t_a");
+        assert_eq!(find(&catalog, &[2]), None);
+        assert_eq!(catalog.find_range_index(t, 0).unwrap().name, "t_ab");
+        assert!(catalog.find_range_index(t, 2).is_none());
+
+        assert_eq!(catalog.drop_index(&mut bm, "t_b").unwrap().name, "t_b");
+        assert!(catalog.drop_index(&mut bm, "t_b").is_err());
+        assert_eq!(find(&catalog, &[0, 1]).unwrap(), "t_ab");
+        assert_eq!(catalog.table_indexes(t).count(), 2);
+    }
+
+    #[test]
+    fn old_system_tables_are_recycled() {
+        let (mut bm, file) = buffer_manager("recycle");
+        let mut catalog = Catalog::open(&mut bm).unwrap();
+        let schema = schema_of("CREATE TABLE t (a INT)").unwrap();
+        for i in 0..20 {
+            let name = format!("t{}", i);
+            catalog.create_table(&mut bm, &name, &schema).unwrap();
+            catalog.drop_table(&mut bm, &name, false).unwrap();
+        }
+        assert!(bm.num_pages() < 18);
+
+        // the free pages survive a restart
+        let pages = bm.num_pages();
+        for _ in 0..5 {
+            let mut bm = BufferManager::open(16, file.path()).unwrap();
+            let mut catalog = Catalog::open(&mut bm).unwrap();
+            catalog.create_table(&mut bm, "t", &schema).unwrap();
+            catalog.drop_table(&mut bm, "t", false).unwrap();
+            assert_eq!(bm.num_pages(), pages);
+        }
+    }
+
+    #[test]
+    fn view_dependencies() {
+        let (mut bm, file) = buffer_manager("views");
+        let mut catalog = Catalog::open(&mut bm).unwrap();
+        let schema = schema_of("CREATE TABLE t (a INT, b INT)").unwrap();
+        let t = catalog.create_table(&mut bm, "t", &schema).unwrap();
+        let view = |name: &str, sql: &str, columns: &[&str], dependencies| ViewMetadata {
+            id: 0,
+            name: name.to_owned(),
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            query: parse_query(sql).unwrap(),
+            dependencies,
+        };
+        let v1 = catalog
+            .create_view(
+                &mut bm,
+                view("v1", "SELECT a FROM t", &["a"], vec![t]),
+                false,
+            )
+            .unwrap();
+        let v2 = view("v2", "SELECT a AS x FROM v1", &["x"], vec![v1]);
+        let v2 = catalog.create_view(&mut bm, v2, false).unwrap();
+        assert!(catalog
+            .create_view(&mut bm, view("t", "SELECT 1", &["x"], vec![]), false)
+            .is_err());
+        assert!(catalog
+            .create_view(&mut bm, view("v1", "SELECT 1", &["a"], vec![]), false)
+            .is_err());
+
+        // redefinitions must keep the columns and must not reference themselves
+        let replaced = view("v1", "SELECT b, a FROM t", &["b", "a"], vec![t]);
+        assert!(catalog.create_view(&mut bm, replaced, true).is_err());
+        let cycle = view("v1", "SELECT x FROM v2", &["a"], vec![v2]);
+        assert!(catalog.create_view(&mut bm, cycle, true).is_err());
+        let replaced = view("v1", "SELECT a, b FROM t", &["a", "b"], vec![t]);
+        assert_eq!(catalog.create_view(&mut bm, replaced, true), Ok(v1));
+
+        assert_eq!(
+            catalog.drop_table(&mut bm, "t", false).unwrap_err(),
+            "cannot drop table t because view v1 depends on it"
+        );
+        assert!(catalog.drop_view(&mut bm, "v1", false).is_err());
+        assert!(catalog.rename_table(&mut bm, "t", "t2").is_err());
+        assert!(catalog.drop_column(&mut bm, "t", "b", true).is_err());
+        assert!(catalog.rename_column(&mut bm, "t", "b", "c").is_err());
+        assert_eq!(
+            catalog.drop_table(&mut bm, "v1", false).unwrap_err(),
+            "\"v1\" is not a table"
+        );
+        drop(bm);
+
+        let mut bm = BufferManager::open(16, file.path()).unwrap();
+        let mut catalog = Catalog::open(&mut bm).unwrap();
+        let v1 = catalog.get_view("v1").unwrap().clone();
+        assert_eq!(v1.columns, vec!["a", "b"]);
+        assert_eq!(v1.query.to_string(), "SELECT a, b FROM t");
+        assert_eq!(catalog.get_view("v2").unwrap().dependencies, vec![v1.id]);
+        catalog.drop_view(&mut bm, "v2", false).unwrap();
+        catalog
+            .create_view(
+                &mut bm,
+                view("v3", "SELECT a FROM v1", &["a"], vec![v1.id]),
+                false,
+            )
+            .unwrap();
+        catalog.drop_table(&mut bm, "t", true).unwrap();
+        assert!(catalog.get_view("v1").is_none());
+        assert!(catalog.get_view("v3").is_none());
+    }
+
+    #[test]
+    fn sequences() {
+        let (mut bm, file) = buffer_manager("sequences");
+        let mut catalog = Catalog::open(&mut bm).unwrap();
+        let options = SequenceOptions {
+            data_type: Some(DataType::Int),
+            increment: Some(-3),
+            max_value: Some(10),
+            min_value: Some(1),
+            cache: Some(2),
+            ..SequenceOptions::default()
+        };
+        let sequence = SequenceMetadata::new("s", &options).unwrap();
+        assert_eq!(sequence.start, 10);
+        let id = catalog.create_sequence(&mut bm, sequence).unwrap();
+        assert!(catalog
+            .create_sequence(&mut bm, SequenceMetadata::new("s", &options).unwrap())
+            .is_err());
+        assert_eq!(catalog.reserve_sequence_values(&mut bm, id), Ok((10, 7)));
+        drop(bm);
+
+        let mut bm = BufferManager::open(16, file.path()).unwrap();
+        let mut catalog = Catalog::open(&mut bm).unwrap();
+        assert_eq!(catalog.get_sequence("s").unwrap().value(4), Value::Int(4));
+        // only the sequence's record is updated, the system tables stay where they are
+        let sequences = catalog.system_tables.sequences.first_page();
+        assert_eq!(catalog.reserve_sequence_values(&mut bm, id), Ok((4, 1)));
+        assert_eq!(catalog.system_tables.sequences.first_page(), sequences);
+        assert_eq!(
+            catalog.reserve_sequence_values(&mut bm, id).unwrap_err(),
+            "nextval: reached minimum value of sequence \"s\" (1)"
+        );
+
+        // a DDL change rewrites the system tables, later reservations update the new records
+        let options = SequenceOptions {
+            cache: Some(5),
+            ..SequenceOptions::default()
+        };
+        let sequence = SequenceMetadata::new("u", &options).unwrap();
+        let id = catalog.create_sequence(&mut bm, sequence).unwrap();
+        assert_ne!(catalog.system_tables.sequences.first_page(), sequences);
+        assert_eq!(catalog.reserve_sequence_values(&mut bm, id), Ok((1, 5)));
+        drop(bm);
+        let mut bm = BufferManager::open(16, file.path()).unwrap();
+        let mut catalog = Catalog::open(&mut bm).unwrap();
+        assert_eq!(catalog.reserve_sequence_values(&mut bm, id), Ok((6, 10)));
+
+        let invalid = |options: SequenceOptions| SequenceMetadata::new("s", &options).unwrap_err();
+        assert_eq!(
+            invalid(SequenceOptions {
+                increment: Some(0),
+                ..SequenceOptions::default()
+            }),
+            "INCREMENT must not be zero"
+        );
+        assert_eq!(
+            invalid(SequenceOptions {
+                min_value: Some(5),
+                max_value: Some(5),
+                ..SequenceOptions::default()
+            }),
+            "MINVALUE (5) must be less than MAXVALUE (5)"
+        );
+        assert_eq!(
+            invalid(SequenceOptions {
+                start: Some(0),
+                ..SequenceOptions::default()
+            }),
+            "START value (0) cannot be less than MINVALUE (1)"
+        );
+
+        // cycling sequences wrap around, owned sequences are dropped with their column
+        let schema = schema_of("CREATE TABLE t (a INT, b INT)").unwrap();
+        let t = catalog.create_table(&mut bm, "t", &schema).unwrap();
+        let mut cycle = SequenceMetadata::new(
+            "c",
+            &SequenceOptions {
+                max_value: Some(3),
+                cycle: Some(true),
+                ..SequenceOptions::default()
+            },
+        )
+        .unwrap();
+        cycle.owner = Some((t, "a".to_owned()));
+        let c = catalog.create_sequence(&mut bm, cycle).unwrap();
+        // the reserved values end before the sequence wraps around
+        assert_eq!(catalog.reserve_sequence_values(&mut bm, c), Ok((1, 3)));
+        assert_eq!(catalog.reserve_sequence_values(&mut bm, c), Ok((1, 3)));
+        catalog.rename_column(&mut bm, "t", "a", "x").unwrap();
+        assert_eq!(
+            catalog.get_sequence("c").unwrap().owner,
+            Some((t, "x".to_owned()))
+        );
+        catalog.drop_column(&mut bm, "t", "x", false).unwrap();
+        assert!(catalog.get_sequence("c").is_none());
+        catalog.drop_sequence(&mut bm, "s", false).unwrap();
+        assert!(!catalog.relation_exists("s"));
+    }
+
+    #[test]
+    fn invalid_schemas() {
+        assert!(schema_of("CREATE TABLE t (a INT, A BIGINT)").is_err());
+        assert!(schema_of("CREATE TABLE t (a DECIMAL(10, 2))").is_err());
+    }
 }
@@ -0,0 +1,2002 @@
+// Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
+
+use sqlparser::ast::{
+    self, AlterTableOperation, Assignment, ColumnOption, ColumnOptionDef, Expr, Ident, ObjectName,
+    ObjectType, Query, SetExpr, Statement,
+};
+
+use crate::binder::Binder;
+use crate::buffer_manager::BufferManager;
+use crate::catalog::{
+    Catalog, Column, ConstraintDef, ConstraintKind, ConstraintMetadata, DataType, ForeignKeyAction,
+    Identity, IndexKind, IndexMetadata, Schema, SequenceMetadata, TableMetadata, ViewMetadata,
+};
+use crate::executor::{build, collect};
+use crate::expression::{evaluate, referenced_sequences, NoRow, Row, TableRow};
+use crate::heap_file::HeapFile;
+use crate::index_manager::{with_undo, Index, IndexManager, IndexedRow};
+use crate::page::RecordId;
+use crate::sql::{ident_name, parse_expr, sequence_name, Command, IdentityColumn};
+use crate::statistics::StatisticsCollector;
+use crate::table_scan::TableScanner;
+use crate::tuple::Tuple;
+use crate::value::Value;
+
+/// Number of pages kept in memory by the buffer manager.
+const BUFFER_POOL_SIZE: usize = 256;
+
+/// An open database file, executing SQL statements against it.
+pub struct Database {
+    bm: BufferManager,
+    catalog: Catalog,
+    indexes: IndexManager,
+    /// Heap files of the tables that have been accessed, by table ID.
+    heaps: HashMap<usize, HeapFile>,
+    /// Sequences used in this session, by sequence ID.
+    sequences: HashMap<usize, SequenceState>,
+}
+
+/// The values of a sequence reserved by this session, and the last one it returned.
+#[derive(Default)]
+struct SequenceState {
+    /// The next reserved value and the last one, `None` once they are used up.
+    cached: Option<(i64, i64)>,
+    current: Option<i64>,
+}
+
+/// Evaluates `nextval` and `currval` for expressions over another row,
+/// they need the database to reserve values.
+struct SessionRow<'a, 'b> {
+    row: &'a dyn Row,
+    db: RefCell<&'b mut Database>,
+}
+
+impl Row for SessionRow<'_, '_> {
+    fn column(&self, name: &[Ident]) -> Result<Value, String> {
+        self.row.column(name)
+    }
+
+    fn call(&self, name: &str, args: &[Value]) -> Option<Result<Value, String>> {
+        if name != "nextval" && name != "currval" {
+            return self.row.call(name, args);
+        }
+        let sequence = match args {
+            [Value::Null] => return Some(Ok(Value::Null)),
+            [Value::Text(arg)] => sequence_name(arg),
+            _ => return Some(Err(format!("function {} takes a sequence name", name))),
+        };
+        let mut db = self.db.borrow_mut();
+        Some(match name {
+            "nextval" => db.nextval(&sequence),
+            _ => db.currval(&sequence),
+        })
+    }
+}
+
+/// The result of a query.
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Tuple>,
+}
+
+/// A row change made by a DML statement, undone if the statement fails.
+enum Change {
+    Insert {
+        table: usize,
+        rid: RecordId,
+        row: Tuple,
+    },
+    Update {
+        table: usize,
+        old_rid: RecordId,
+        rid: RecordId,
+        old_row: Tuple,
+        row: Tuple,
+    },
+    Delete {
+        table: usize,
+        rid: RecordId,
+        row: Tuple,
+    },
+}
+
+impl Change {
+    /// The record ID of the row after the change, if it still exists.
+    fn new_rid(&self) -> Option<RecordId> {
+        match self {
+            Change::Insert { rid, .. } | Change::Update { rid, .. } => Some(*rid),
+            Change::Delete { .. } => None,
+        }
+    }
+}
+
+/// The record IDs of the rows a statement has changed so far.
+#[derive(Default)]
+struct ChangedRows {
+    rids: HashSet<RecordId>,
+    /// Number of changes whose record IDs have been added.
+    seen: usize,
+}
+
+impl ChangedRows {
+    /// Checks whether one of the changes left a row under the given record ID.
+    fn contains(&mut self, changes: &[Change], rid: RecordId) -> bool {
+        self.rids
+            .extend(changes[self.seen..].iter().filter_map(Change::new_rid));
+        self.seen = changes.len();
+        self.rids.contains(&rid)
+    }
+}
+
+impl Database {
+    /// Opens the given database file, creating a new database if it does not exist.
+    pub fn open(path: &str) -> Result<Self, String> {
+        let mut bm = BufferManager::open(BUFFER_POOL_SIZE, path)
+            .map_err(|err| format!("failed to open {}: {}", path, err))?;
+        let catalog = Catalog::open(&mut bm)?;
+        let mut db = Self {
+            bm,
+            catalog,
+            indexes: IndexManager::new(),
+            heaps: HashMap::new(),
+            sequences: HashMap::new(),
+        };
+        for index in db.catalog.indexes().to_vec() {
+            db.indexes.open_index(&mut db.bm, &index)?;
+        }
+        Ok(db)
+    }
+
+    pub fn catalog(&self) -> &Catalog {
+        &self.catalog
+    }
+
+    pub fn buffer_manager(&mut self) -> &mut BufferManager {
+        &mut self.bm
+    }
+
+    /// Executes a single command, returns the command tag on success.
+    pub fn execute(&mut self, command: &Command) -> Result<String, String> {
+        match command {
+            Command::Statement(statement) => self.execute_statement(statement),
+            Command::CreateIndex { statement, kind } => self.create_index(statement, *kind),
+            Command::CreateTable {
+                statement,
+                identities,
+            } => self.create_table(statement, identities),
+            Command::CreateSequence {
+                name,
+                if_not_exists,
+                options,
+            } => {
+                let name = object_name(name)?;
+                if *if_not_exists && self.catalog.get_sequence(&name).is_some() {
+                    return Ok("CREATE SEQUENCE".to_owned());
+                }
+                let sequence = SequenceMetadata::new(&name, options)?;
+                self.catalog.create_sequence(&mut self.bm, sequence)?;
+                Ok("CREATE SEQUENCE".to_owned())
+            }
+            Command::DropSequence {
+                names,
+                if_exists,
+                cascade,
+            } => {
+                for name in names {
+                    let name = object_name(name)?;
+                    let id = match self.catalog.get_sequence(&name) {
+                        Some(sequence) => sequence.id,
+                        None if *if_exists => continue,
+                        None => return Err(format!("sequence \"{}\" does not exist", name)),
+                    };
+                    self.catalog.drop_sequence(&mut self.bm, &name, *cascade)?;
+                    self.sequences.remove(&id);
+                }
+                Ok("DROP SEQUENCE".to_owned())
+            }
+            Command::Analyze { tables } => {
+                let ids = if tables.is_empty() {
+                    self.catalog.table_ids()
+                } else {
+                    tables
+                        .iter()
+                        .map(|name| Ok(self.table_by_name(&object_name(name)?)?.id))
+                        .collect::<Result<_, String>>()?
+                };
+                for id in ids {
+                    self.analyze(id)?;
+                }
+                Ok("ANALYZE".to_owned())
+            }
+        }
+    }
+
+    /// Runs a query and returns all of its rows.
+    pub fn query(&mut self, query: &Query) -> Result<QueryResult, String> {
+        let plan = Binder::new(&self.catalog).bind_query(query)?;
+        let mut operator = build(&plan, &self.catalog);
+        let rows = collect(operator.as_mut(), self)?;
+        Ok(QueryResult {
+            columns: plan.fields().iter().map(|f| f.name.clone()).collect(),
+            rows,
+        })
+    }
+
+    fn execute_statement(&mut self, statement: &Statement) -> Result<String, String> {
+        match statement {
+            Statement::Query(query) => {
+                let result = self.query(query)?;
+                Ok(format!("SELECT {}", result.rows.len()))
+            }
+            Statement::CreateTable { .. } => self.create_table(statement, &[]),
+            Statement::Drop {
+                object_type: ObjectType::Table,
+                if_exists,
+                names,
+                cascade,
+                ..
+            } => {
+                for name in names {
+                    let name = object_name(name)?;
+                    if *if_exists && self.catalog.get_table(&name).is_none() {
+                        continue;
+                    }
+                    self.drop_table(&name, *cascade)?;
+                }
+                Ok("DROP TABLE".to_owned())
+            }
+            Statement::CreateView {
+                or_replace,
+                materialized,
+                name,
+                columns,
+                query,
+                with_options,
+            } => {
+                if *materialized || !with_options.is_empty() {
+                    return Err("materialized views and view options are not supported".to_owned());
+                }
+                self.create_view(&object_name(name)?, columns, query, *or_replace)?;
+                Ok("CREATE VIEW".to_owned())
+            }
+            Statement::Drop {
+                object_type: ObjectType::View,
+                if_exists,
+                names,
+                cascade,
+                ..
+            } => {
+                for name in names {
+                    let name = object_name(name)?;
+                    if *if_exists && self.catalog.get_view(&name).is_none() {
+                        continue;
+                    }
+                    self.catalog.drop_view(&mut self.bm, &name, *cascade)?;
+                }
+                Ok("DROP VIEW".to_owned())
+            }
+            Statement::AlterTable { name, operation } => {
+                self.alter_table(&object_name(name)?, operation)?;
+                Ok("ALTER TABLE".to_owned())
+            }
+            Statement::CreateIndex { .. } => self.create_index(statement, IndexKind::BTree),
+            Statement::Analyze { table_name, .. } => {
+                let id = self.table_by_name(&object_name(table_name)?)?.id;
+                self.analyze(id)?;
+                Ok("ANALYZE".to_owned())
+            }
+            Statement::Drop {
+                object_type: ObjectType::Index,
+                if_exists,
+                names,
+                ..
+            } => {
+                for name in names {
+                    let name = object_name(name)?;
+                    if *if_exists && self.catalog.get_index(&name).is_none() {
+                        continue;
+                    }
+                    let index = self.catalog.drop_index(&mut self.bm, &name)?;
+                    self.indexes.drop_index(&mut self.bm, &index)?;
+                }
+                Ok("DROP INDEX".to_owned())
+            }
+            Statement::Insert {
+                table_name,
+                columns,
+                source,
+                ..
+            } => {
+                let rows = match &source.body {
+                    SetExpr::Values(values) if source.order_by.is_empty() => &values.0,
+                    _ => return Err("only INSERT ... VALUES is supported".to_owned()),
+                };
+                let table = self.table_by_name(&object_name(table_name)?)?;
+                self.statement(|db, changes| {
+                    db.insert_values(&table, columns, rows, changes)
+                        .map(|n| format!("INSERT 0 {}", n))
+                })
+            }
+            Statement::Update {
+                table_name,
+                assignments,
+                selection,
+            } => {
+                let table = self.table_by_name(&object_name(table_name)?)?;
+                self.statement(|db, changes| {
+                    db.update_where(&table, assignments, selection.as_ref(), changes)
+                        .map(|n| format!("UPDATE {}", n))
+                })
+            }
+            Statement::Delete {
+                table_name,
+                selection,
+            } => {
+                let table = self.table_by_name(&object_name(table_name)?)?;
+                self.statement(|db, changes| {
+                    db.delete_where(&table, selection.as_ref(), changes)
+                        .map(|n| format!("DELETE {}", n))
+                })
+            }
+            _ => Err(format!("statement not supported: {}", statement)),
+        }
+    }
+
+    /// Creates a table with its constraints,
+    /// and the sequences of its SERIAL and identity columns owned by the table.
+    fn create_table(
+        &mut self,
+        statement: &Statement,
+        identities: &[IdentityColumn],
+    ) -> Result<String, String> {
+        let (name, columns, constraints, if_not_exists) = match statement {
+            Statement::CreateTable {
+                name,
+                columns,
+                constraints,
+                if_not_exists,
+                ..
+            } => (name, columns, constraints, *if_not_exists),
+            _ => unreachable!(),
+        };
+        let name = object_name(name)?;
+        if if_not_exists && self.catalog.get_table(&name).is_some() {
+            return Ok("CREATE TABLE".to_owned());
+        }
+
+        // SERIAL and identity columns default to the next value of a new sequence
+        let mut columns = columns.clone();
+        let mut sequences = Vec::new();
+        for def in &mut columns {
+            let column = ident_name(&def.name);
+            let identity = identities.iter().find(|i| i.column == column);
+            let serial = serial_type(&def.data_type);
+            if identity.is_none() && serial.is_none() {
+                continue;
+            }
+            if let Some(data_type) = serial {
+                def.data_type = data_type;
+            }
+            let has_default = def
+                .options
+                .iter()
+                .any(|o| matches!(o.option, ColumnOption::Default(_)));
+            if has_default {
+                return Err(format!(
+                    "multiple default values specified for column \"{}\" of table \"{}\"",
+                    column, name
+                ));
+            }
+            let data_type = DataType::from_sql(&def.data_type)?;
+            if !matches!(data_type, DataType::Int | DataType::BigInt) {
+                return Err("identity column type must be smallint, integer, or bigint".to_owned());
+            }
+            let mut options = identity.map(|i| i.options.clone()).unwrap_or_default();
+            options.data_type = options.data_type.or(Some(data_type));
+            let mut sequence_name = format!("{}_{}_seq", name, column);
+            let mut suffix = 0;
+            while self.catalog.relation_exists(&sequence_name)
+                || sequences
+                    .iter()
+                    .any(|(_, s): &(_, SequenceMetadata)| s.name == sequence_name)
+            {
+                suffix += 1;
+                sequence_name = format!("{}_{}_seq{}", name, column, suffix);
+            }
+            let sequence = SequenceMetadata::new(&sequence_name, &options)?;
+            let literal = if sequence_name == sequence_name.to_lowercase() {
+                sequence_name.clone()
+            } else {
+                format!("\"{}\"", sequence_name.replace('"', "\"\""))
+            };
+            let default = parse_expr(&format!("nextval('{}')", literal.replace('\'', "''")))?;
+            for option in [ColumnOption::NotNull, ColumnOption::Default(default)] {
+                def.options.push(ColumnOptionDef { name: None, option });
+            }
+            sequences.push((column, sequence));
+        }
+        let mut schema_columns = Schema::from_column_defs(&columns)?.columns().to_vec();
+        for column in &mut schema_columns {
+            column.identity = identities
+                .iter()
+                .find(|i| i.column == column.name)
+                .map(|i| match i.always {
+                    true => Identity::Always,
+                    false => Identity::ByDefault,
+                });
+        }
+        for identity in identities {
+            if !schema_columns.iter().any(|c| c.name == identity.column) {
+                return Err(format!("column \"{}\" does not exist", identity.column));
+            }
+        }
+        let schema = Schema::new(schema_columns)?;
+
+        let id = self.catalog.create_table(&mut self.bm, &name, &schema)?;
+        let mut created = Ok(());
+        for (column, mut sequence) in sequences {
+            sequence.owner = Some((id, column));
+            created = created.and_then(|_| {
+                self.catalog
+                    .create_sequence(&mut self.bm, sequence)
+                    .map(|_| ())
+            });
+        }
+        for def in ConstraintDef::from_create_table(&columns, constraints) {
+            created = created.and_then(|_| self.add_constraint(id, &def));
+        }
+        if let Err(err) = created {
+            self.drop_table(&name, false)?;
+            return Err(err);
+        }
+        Ok("CREATE TABLE".to_owned())
+    }
+
+    fn drop_table(&mut self, name: &str, cascade: bool) -> Result<(), String> {
+        let id = self.table_by_name(name)?.id;
+        let indexes = self.catalog.get_table_indices(name);
+        self.catalog.drop_table(&mut self.bm, name, cascade)?;
+        self.heaps.remove(&id);
+        for index in &indexes {
+            self.indexes.drop_index(&mut self.bm, index)?;
+        }
+        Ok(())
+    }
+
+    /// Records a view after binding its query, which checks that the query is valid
+    /// and finds the relations it depends on.
+    fn create_view(
+        &mut self,
+        name: &str,
+        columns: &[Ident],
+        query: &Query,
+        replace: bool,
+    ) -> Result<(), String> {
+        let mut binder = Binder::new(&self.catalog);
+        let plan = binder.bind_query(query)?;
+        let fields = plan.fields();
+        if columns.len() > fields.len() {
+            return Err("CREATE VIEW specifies more column names than columns".to_owned());
+        }
+        let names: Vec<_> = fields
+            .iter()
+            .enumerate()
+            .map(|(i, f)| columns.get(i).map_or_else(|| f.name.clone(), ident_name))
+            .collect();
+        for (i, name) in names.iter().enumerate() {
+            if names[..i].contains(name) {
+                return Err(format!("column \"{}\" specified more than once", name));
+            }
+        }
+        let view = ViewMetadata {
+            id: 0,
+            name: name.to_owned(),
+            columns: names,
+            query: query.clone(),
+            dependencies: binder.dependencies(),
+        };
+        self.catalog.create_view(&mut self.bm, view, replace)?;
+        Ok(())
+    }
+
+    fn alter_table(&mut self, table: &str, operation: &AlterTableOperation) -> Result<(), String> {
+        let table_id = self.table_by_name(table)?.id;
+        match operation {
+            AlterTableOperation::AddColumn { column_def } => {
+                let column = Schema::from_column_defs(std::slice::from_ref(column_def))?.columns()
+                    [0]
+                .clone();
+                // existing rows get the default, evaluated once
+                let missing = match &column.default {
+                    Some(default) if !referenced_sequences(default).is_empty() => {
+                        if !self.table_rows(table_id)?.is_empty() {
+                            return Err(
+                                "cannot add a column with a sequence default to a non-empty table"
+                                    .to_owned(),
+                            );
+                        }
+                        Value::Null
+                    }
+                    Some(default) => self.eval(default, &NoRow)?.cast(column.data_type)?,
+                    None => Value::Null,
+                };
+                if missing.is_null() && !column.nullable && !self.table_rows(table_id)?.is_empty() {
+                    return Err(format!(
+                        "column \"{}\" of relation \"{}\" contains null values",
+                        column.name, table
+                    ));
+                }
+                let name = column.name.clone();
+                self.catalog
+                    .add_column(&mut self.bm, table, column, missing)?;
+                let defs = ConstraintDef::from_create_table(std::slice::from_ref(column_def), &[]);
+                for def in defs {
+                    if let Err(err) = self.add_constraint(table_id, &def) {
+                        for index in self.catalog.drop_column(&mut self.bm, table, &name, true)? {
+                            self.indexes.drop_index(&mut self.bm, &index)?;
+                        }
+                        return Err(err);
+                    }
+                }
+                Ok(())
+            }
+            AlterTableOperation::DropColumn {
+                column_name,
+                if_exists,
+                cascade,
+            } => {
+                let column = ident_name(column_name);
+                let exists = self
+                    .catalog
+                    .get_table(table)
+                    .unwrap()
+                    .schema
+                    .column(&column);
+                if *if_exists && exists.is_none() {
+                    return Ok(());
+                }
+                let dropped = self
+                    .catalog
+                    .drop_column(&mut self.bm, table, &column, *cascade)?;
+                for index in dropped {
+                    self.indexes.drop_index(&mut self.bm, &index)?;
+                }
+                Ok(())
+            }
+            AlterTableOperation::RenameColumn {
+                old_column_name,
+                new_column_name,
+            } => self.catalog.rename_column(
+                &mut self.bm,
+                table,
+                &ident_name(old_column_name),
+                &ident_name(new_column_name),
+            ),
+            AlterTableOperation::RenameTable { table_name } => {
+                self.catalog
+                    .rename_table(&mut self.bm, table, &object_name(table_name)?)
+            }
+            AlterTableOperation::AddConstraint(constraint) => {
+                self.add_constraint(table_id, &ConstraintDef::new(constraint.clone()))
+            }
+            AlterTableOperation::DropConstraint { name } => {
+                let index = self
+                    .catalog
+                    .drop_constraint(&mut self.bm, table, &ident_name(name))?;
+                if let Some(index) = index {
+                    self.indexes.drop_index(&mut self.bm, &index)?;
+                }
+                Ok(())
+            }
+            operation => Err(format!("ALTER TABLE {} is not supported", operation)),
+        }
+    }
+
+    /// Adds a constraint to a table, after checking that the existing rows satisfy it.
+    fn add_constraint(&mut self, table: usize, def: &ConstraintDef) -> Result<(), String> {
+        let constraint = self.catalog.resolve_constraint(table, def)?;
+        let metadata = self.catalog.get_table_by_id(table).unwrap().clone();
+        match &constraint.kind {
+            ConstraintKind::Unique { columns, primary } => {
+                if *primary {
+                    for (_, row) in self.table_rows(table)? {
+                        self.check_primary_key(&metadata, columns, &row)?;
+                    }
+                }
+                let index = self.build_new_index(IndexMetadata {
+                    id: 0,
+                    name: constraint.name.clone(),
+                    table,
+                    columns: columns.clone(),
+                    kind: IndexKind::BTree,
+                    unique: true,
+                    root_page: None,
+                })?;
+                if let Err(err) = self.catalog.add_constraint(&mut self.bm, constraint) {
+                    self.catalog.drop_index(&mut self.bm, &index.name)?;
+                    self.indexes.drop_index(&mut self.bm, &index)?;
+                    return Err(err);
+                }
+                Ok(())
+            }
+            ConstraintKind::Check(_) | ConstraintKind::ForeignKey { .. } => {
+                for (_, row) in self.table_rows(table)? {
+                    self.check_constraint(&metadata, &constraint, &row)?;
+                }
+                self.catalog.add_constraint(&mut self.bm, constraint)
+            }
+        }
+    }
+
+    fn create_index(&mut self, statement: &Statement, kind: IndexKind) -> Result<String, String> {
+        let (name, table_name, columns, unique, if_not_exists) = match statement {
+            Statement::CreateIndex {
+                name,
+                table_name,
+                columns,
+                unique,
+                if_not_exists,
+            } => (name, table_name, columns, *unique, *if_not_exists),
+            _ => unreachable!(),
+        };
+        let name = object_name(name)?;
+        if if_not_exists && self.catalog.get_index(&name).is_some() {
+            return Ok("CREATE INDEX".to_owned());
+        }
+        let table = self.table_by_name(&object_name(table_name)?)?;
+        let mut key_columns = Vec::with_capacity(columns.len());
+        for column in columns {
+            let column_name = match &column.expr {
+                Expr::Identifier(ident) => ident_name(ident),
+                expr => return Err(format!("index expressions are not supported: {}", expr)),
+            };
+            match table.schema.index_of(&column_name) {
+                Some(c) => key_columns.push(c),
+                None => return Err(format!("column \"{}\" does not exist", column_name)),
+            }
+        }
+
+        self.build_new_index(IndexMetadata {
+            id: 0,
+            name,
+            table: table.id,
+            columns: key_columns,
+            kind,
+            unique,
+            root_page: None,
+        })?;
+        Ok("CREATE INDEX".to_owned())
+    }
+
+    /// Creates an index and adds the existing rows of the table to it.
+    /// The ID and root page in `index` are ignored, returns the complete metadata.
+    fn build_new_index(&mut self, mut index: IndexMetadata) -> Result<IndexMetadata, String> {
+        let structure = Index::create(&mut self.bm, index.kind)?;
+        index.root_page = Some(structure.root_page());
+        index.id = match self.catalog.create_index(&mut self.bm, index.clone()) {
+            Ok(id) => id,
+            Err(err) => {
+                structure.destroy(&mut self.bm)?;
+                return Err(err);
+            }
+        };
+        self.indexes.add_index(index.id, structure);
+
+        let built = self
+            .table_rows(index.table)
+            .and_then(|rows| self.indexes.build_index(&mut self.bm, &index, rows));
+        if let Err(err) = built {
+            self.catalog.drop_index(&mut self.bm, &index.name)?;
+            self.indexes.drop_index(&mut self.bm, &index)?;
+            return Err(err);
+        }
+        Ok(index)
+    }
+
+    /// Collects the statistics of a table and stores them in the catalog.
+    fn analyze(&mut self, table: usize) -> Result<(), String> {
+        let schema = self.table_by_id(table)?.schema;
+        let mut collector = StatisticsCollector::new(&schema);
+        self.scan_table(table, |_, row| collector.add(row))?;
+        let page_count = self.heap(table)?.pages(&mut self.bm)?.len() as u64;
+        self.catalog
+            .set_statistics(&mut self.bm, table, collector.finish(page_count))
+    }
+
+    /// Runs a DML statement, undoing all its changes if it fails.
+    fn statement<F>(&mut self, run: F) -> Result<String, String>
+    where
+        F: FnOnce(&mut Self, &mut Vec<Change>) -> Result<String, String>,
+    {
+        let mut changes = Vec::new();
+        match run(self, &mut changes) {
+            Err(err) => {
+                let undo = self.undo(changes);
+                Err(with_undo(err, undo))
+            }
+            result => result,
+        }
+    }
+
+    fn undo(&mut self, changes: Vec<Change>) -> Result<(), String> {
+        // rows may end up under new record IDs when they are restored
+        let mut moved: HashMap<RecordId, RecordId> = HashMap::new();
+        let current = |moved: &HashMap<_, _>, rid| *moved.get(&rid).unwrap_or(&rid);
+        for change in changes.into_iter().rev() {
+            match change {
+                Change::Insert { table, rid, row } => {
+                    let table = self.table_by_id(table)?;
+                    self.delete_row(&table, current(&moved, rid), &row)?;
+                }
+                Change::Update {
+                    table,
+                    old_rid,
+                    rid,
+                    old_row,
+                    row,
+                } => {
+                    let table = self.table_by_id(table)?;
+                    let restored = self.update_row(&table, current(&moved, rid), &row, &old_row)?;
+                    moved.insert(old_rid, restored);
+                }
+                Change::Delete { table, rid, row } => {
+                    let table = self.table_by_id(table)?;
+                    let restored = self.insert_row(&table, &row)?;
+                    moved.insert(rid, restored);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Inserts the rows of an `INSERT ... VALUES` statement, returns their number.
+    fn insert_values(
+        &mut self,
+        table: &TableMetadata,
+        columns: &[Ident],
+        rows: &[Vec<Expr>],
+        changes: &mut Vec<Change>,
+    ) -> Result<usize, String> {
+        let schema = &table.schema;
+        let targets = if columns.is_empty() {
+            (0..schema.len()).collect()
+        } else {
+            let mut targets = Vec::with_capacity(columns.len());
+            for column in columns {
+                let name = ident_name(column);
+                match schema.index_of(&name) {
+                    Some(c) if targets.contains(&c) => {
+                        return Err(format!("column \"{}\" specified more than once", name))
+                    }
+                    Some(c) => targets.push(c),
+                    None => {
+                        return Err(format!(
+                            "column \"{}\" of relation \"{}\" does not exist",
+                            name, table.name
+                        ))
+                    }
+                }
+            }
+            targets
+        };
+
+        for exprs in rows {
+            if exprs.len() > targets.len() {
+                return Err("INSERT has more expressions than target columns".to_owned());
+            }
+            let mut row: Tuple = vec![Value::Null; schema.len()];
+            let mut given = vec![false; schema.len()];
+            for (expr, &c) in exprs.iter().zip(&targets) {
+                if !is_default(expr) {
+                    let column = &schema.columns()[c];
+                    if column.identity == Some(Identity::Always) {
+                        return Err(format!(
+                            "cannot insert a non-DEFAULT value into column \"{}\"",
+                            column.name
+                        ));
+                    }
+                    row[c] = self.eval(expr, &NoRow)?;
+                    given[c] = true;
+                }
+            }
+            for (c, column) in schema.columns().iter().enumerate() {
+                if !given[c] {
+                    row[c] = self.default_value(column)?;
+                }
+                row[c] = row[c].cast(column.data_type)?;
+            }
+            self.insert(table, row, changes)?;
+        }
+        Ok(rows.len())
+    }
+
+    fn update_where(
+        &mut self,
+        table: &TableMetadata,
+        assignments: &[Assignment],
+        selection: Option<&Expr>,
+        changes: &mut Vec<Change>,
+    ) -> Result<usize, String> {
+        let mut targets = Vec::with_capacity(assignments.len());
+        for assignment in assignments {
+            let name = ident_name(&assignment.id);
+            match table.schema.index_of(&name) {
+                Some(c) => targets.push((c, &assignment.value)),
+                None => {
+                    return Err(format!(
+                        "column \"{}\" of relation \"{}\" does not exist",
+                        name, table.name
+                    ))
+                }
+            }
+        }
+
+        let mut count = 0;
+        let mut changed = ChangedRows::default();
+        for (rid, row) in self.select_rows(table, selection)? {
+            // skip rows that were already changed by this statement, e.g. by a cascade
+            if changed.contains(changes, rid) {
+                continue;
+            }
+            let row = match self.get_row(table, rid)? {
+                Some(current) if current == row => current,
+                _ => continue,
+            };
+            let mut new_row = row.clone();
+            for &(c, expr) in &targets {
+                let column = &table.schema.columns()[c];
+                let value = if is_default(expr) {
+                    self.default_value(column)?
+                } else if column.identity == Some(Identity::Always) {
+                    return Err(format!(
+                        "column \"{}\" can only be updated to DEFAULT",
+                        column.name
+                    ));
+                } else {
+                    self.eval(expr, &table_row(table, &row))?
+                };
+                new_row[c] = value.cast(column.data_type)?;
+            }
+            self.update(table, rid, row, new_row, changes)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn delete_where(
+        &mut self,
+        table: &TableMetadata,
+        selection: Option<&Expr>,
+        changes: &mut Vec<Change>,
+    ) -> Result<usize, String> {
+        let mut count = 0;
+        let mut changed = ChangedRows::default();
+        for (rid, row) in self.select_rows(table, selection)? {
+            if changed.contains(changes, rid) {
+                continue;
+            }
+            match self.get_row(table, rid)? {
+                Some(current) if current == row => {}
+                _ => continue,
+            }
+            self.delete(table, rid, row, changes)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Returns the rows of a table for which the condition is true.
+    fn select_rows(
+        &mut self,
+        table: &TableMetadata,
+        selection: Option<&Expr>,
+    ) -> Result<Vec<(RecordId, Tuple)>, String> {
+        let mut rows = self.table_rows(table.id)?;
+        if let Some(selection) = selection {
+            let mut selected = Vec::new();
+            for (rid, row) in rows {
+                if evaluate(selection, &table_row(table, &row))?.truth()? == Some(true) {
+                    selected.push((rid, row));
+                }
+            }
+            rows = selected;
+        }
+        Ok(rows)
+    }
+
+    /// Inserts a row after checking the table's constraints.
+    fn insert(
+        &mut self,
+        table: &TableMetadata,
+        row: Tuple,
+        changes: &mut Vec<Change>,
+    ) -> Result<(), String> {
+        check_not_null(table, &row)?;
+        for constraint in self.constraints(table.id) {
+            self.check_constraint(table, &constraint, &row)?;
+        }
+        let rid = self.insert_row(table, &row)?;
+        changes.push(Change::Insert {
+            table: table.id,
+            rid,
+            row,
+        });
+        Ok(())
+    }
+
+    /// Replaces a row after checking the table's constraints,
+    /// and applies the actions of foreign keys referencing changed keys.
+    fn update(
+        &mut self,
+        table: &TableMetadata,
+        rid: RecordId,
+        old_row: Tuple,
+        row: Tuple,
+        changes: &mut Vec<Change>,
+    ) -> Result<(), String> {
+        let changed = |columns: &[usize]| columns.iter().any(|&c| old_row[c] != row[c]);
+        check_not_null(table, &row)?;
+        for constraint in self.constraints(table.id) {
+            match &constraint.kind {
+                ConstraintKind::ForeignKey { columns, .. } if !changed(columns) => {}
+                _ => self.check_constraint(table, &constraint, &row)?,
+            }
+        }
+        let new_rid = self.update_row(table, rid, &old_row, &row)?;
+        changes.push(Change::Update {
+            table: table.id,
+            old_rid: rid,
+            rid: new_rid,
+            old_row: old_row.clone(),
+            row: row.clone(),
+        });
+
+        for constraint in self
+            .catalog
+            .referencing_constraints(table.id)
+            .cloned()
+            .collect::<Vec<_>>()
+        {
+            let (columns, referenced_columns, action) = match &constraint.kind {
+                ConstraintKind::ForeignKey {
+                    columns,
+                    referenced_columns,
+                    on_update,
+                    ..
+                } => (columns, referenced_columns, *on_update),
+                _ => unreachable!(),
+            };
+            if !changed(referenced_columns) {
+                continue;
+            }
+            let child = self.table_by_id(constraint.table)?;
+            let key: Vec<_> = referenced_columns
+                .iter()
+                .map(|&c| old_row[c].clone())
+                .collect();
+            for (child_rid, child_row) in self.find_rows(&child, columns, &key)? {
+                if action == ForeignKeyAction::Restrict {
+                    return Err(referenced_error(table, &constraint, &child));
+                }
+                let mut new_child_row = child_row.clone();
+                for (&c, &r) in columns.iter().zip(referenced_columns) {
+                    new_child_row[c] = row[r].cast(child.schema.columns()[c].data_type)?;
+                }
+                self.update(&child, child_rid, child_row, new_child_row, changes)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes a row and applies the actions of foreign keys referencing it.
+    fn delete(
+        &mut self,
+        table: &TableMetadata,
+        rid: RecordId,
+        row: Tuple,
+        changes: &mut Vec<Change>,
+    ) -> Result<(), String> {
+        self.delete_row(table, rid, &row)?;
+        changes.push(Change::Delete {
+            table: table.id,
+            rid,
+            row: row.clone(),
+        });
+
+        for constraint in self
+            .catalog
+            .referencing_constraints(table.id)
+            .cloned()
+            .collect::<Vec<_>>()
+        {
+            let (columns, referenced_columns, action) = match &constraint.kind {
+                ConstraintKind::ForeignKey {
+                    columns,
+                    referenced_columns,
+                    on_delete,
+                    ..
+                } => (columns, referenced_columns, *on_delete),
+                _ => unreachable!(),
+            };
+            let child = self.table_by_id(constraint.table)?;
+            let key: Vec<_> = referenced_columns.iter().map(|&c| row[c].clone()).collect();
+            for (child_rid, child_row) in self.find_rows(&child, columns, &key)? {
+                if action == ForeignKeyAction::Restrict {
+                    return Err(referenced_error(table, &constraint, &child));
+                }
+                // the row may already have been deleted through another path
+                if self.get_row(&child, child_rid)?.as_ref() == Some(&child_row) {
+                    self.delete(&child, child_rid, child_row, changes)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn constraints(&self, table: usize) -> Vec<ConstraintMetadata> {
+        self.catalog.table_constraints(table).cloned().collect()
+    }
+
+    /// Checks a constraint for a new or updated row.
+    /// PRIMARY KEY and UNIQUE constraints are checked by their indexes.
+    fn check_constraint(
+        &mut self,
+        table: &TableMetadata,
+        constraint: &ConstraintMetadata,
+        row: &[Value],
+    ) -> Result<(), String> {
+        match &constraint.kind {
+            ConstraintKind::Unique { columns, primary } => {
+                if *primary {
+                    self.check_primary_key(table, columns, row)?;
+                }
+            }
+            ConstraintKind::Check(expr) => {
+                if evaluate(expr, &table_row(table, row))?.truth()? == Some(false) {
+                    return Err(format!(
+                        "new row for relation \"{}\" violates check constraint \"{}\"",
+                        table.name, constraint.name
+                    ));
+                }
+            }
+            ConstraintKind::ForeignKey {
+                columns,
+                referenced_table,
+                referenced_columns,
+                ..
+            } => {
+                let key: Vec<_> = columns.iter().map(|&c| row[c].clone()).collect();
+                if key.iter().any(Value::is_null) {
+                    return Ok(());
+                }
+                let parent = self.table_by_id(*referenced_table)?;
+                if !self.key_exists(&parent, referenced_columns, &key)? {
+                    return Err(format!(
+                        "insert or update on table \"{}\" violates foreign key constraint \"{}\"",
+                        table.name, constraint.name
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_primary_key(
+        &self,
+        table: &TableMetadata,
+        columns: &[usize],
+        row: &[Value],
+    ) -> Result<(), String> {
+        match columns.iter().find(|&&c| row[c].is_null()) {
+            Some(&c) => Err(not_null_error(table, &table.schema.columns()[c].name)),
+            None => Ok(()),
+        }
+    }
+
+    /// Finds the rows whose `columns` are equal to `key`, using an index if possible.
+    fn find_rows(
+        &mut self,
+        table: &TableMetadata,
+        columns: &[usize],
+        key: &[Value],
+    ) -> Result<Vec<(RecordId, Tuple)>, String> {
+        if key.iter().any(Value::is_null) {
+            return Ok(Vec::new());
+        }
+        let matches = |row: &Tuple| columns.iter().zip(key).all(|(&c, v)| row[c] == *v);
+        let index = self.catalog.find_equality_index(table.id, columns).cloned();
+        let rows = match index {
+            Some(index) => {
+                let rids = self.index_rids(table, &index, columns, key)?;
+                let mut rows = Vec::with_capacity(rids.len());
+                for rid in rids {
+                    if let Some(row) = self.get_row(table, rid)? {
+                        rows.push((rid, row));
+                    }
+                }
+                rows
+            }
+            None => {
+                // only the rows with a matching key are decoded
+                let scanner = TableScanner::new(table).with_key(columns.to_vec(), key.to_vec());
+                let mut rows = Vec::new();
+                self.scan(scanner, |rid, row| rows.push((rid, row)))?;
+                return Ok(rows);
+            }
+        };
+        Ok(rows.into_iter().filter(|(_, row)| matches(row)).collect())
+    }
+
+    /// Checks whether a row whose `columns` are equal to `key` exists,
+    /// only looking at the unique index on them if there is one.
+    fn key_exists(
+        &mut self,
+        table: &TableMetadata,
+        columns: &[usize],
+        key: &[Value],
+    ) -> Result<bool, String> {
+        if key.iter().any(Value::is_null) {
+            return Ok(false);
+        }
+        match self.catalog.find_unique_index(table.id, columns).cloned() {
+            Some(index) => Ok(!self.index_rids(table, &index, columns, key)?.is_empty()),
+            None => Ok(!self.find_rows(table, columns, key)?.is_empty()),
+        }
+    }
+
+    /// Looks up the record IDs for `key` in an index whose key columns are among `columns`.
+    fn index_rids(
+        &mut self,
+        table: &TableMetadata,
+        index: &IndexMetadata,
+        columns: &[usize],
+        key: &[Value],
+    ) -> Result<Vec<RecordId>, String> {
+        let mut values = vec![Value::Null; table.schema.len()];
+        for (&c, v) in columns.iter().zip(key) {
+            values[c] = v.cast(table.schema.columns()[c].data_type)?;
+        }
+        match values.index_key(&index.columns) {
+            Some(k) => self.indexes.get_index(index).unwrap().get(&mut self.bm, &k),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Inserts a row into the heap and all indexes of the table.
+    fn insert_row(&mut self, table: &TableMetadata, row: &Tuple) -> Result<RecordId, String> {
+        let data = table.row_format().encode(row)?;
+        let indexes = self.catalog.get_table_indices(&table.name);
+        let mut heap = self.heap(table.id)?;
+        let rid = self
+            .indexes
+            .insert(&mut self.bm, &indexes, &mut heap, row, &data);
+        self.heaps.insert(table.id, heap);
+        rid
+    }
+
+    fn update_row(
+        &mut self,
+        table: &TableMetadata,
+        rid: RecordId,
+        old_row: &Tuple,
+        row: &Tuple,
+    ) -> Result<RecordId, String> {
+        let data = table.row_format().encode(row)?;
+        let indexes = self.catalog.get_table_indices(&table.name);
+        let mut heap = self.heap(table.id)?;
+        let new_rid =
+            self.indexes
+                .update(&mut self.bm, &indexes, &mut heap, rid, old_row, row, &data);
+        self.heaps.insert(table.id, heap);
+        new_rid
+    }
+
+    fn delete_row(
+        &mut self,
+        table: &TableMetadata,
+        rid: RecordId,
+        row: &Tuple,
+    ) -> Result<(), String> {
+        let indexes = self.catalog.get_table_indices(&table.name);
+        let heap = self.heap(table.id)?;
+        self.indexes.delete(&mut self.bm, &indexes, &heap, rid, row)
+    }
+
+    /// Reads the row with the given record ID, `None` if it does not exist.
+    pub fn get_row(
+        &mut self,
+        table: &TableMetadata,
+        rid: RecordId,
+    ) -> Result<Option<Tuple>, String> {
+        let heap = self.heap(table.id)?;
+        match heap.get(&mut self.bm, rid)? {
+            Some(data) => table.row_format().decode(&data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the rows of the index's table whose key equals `key`.
+    pub fn index_lookup(
+        &mut self,
+        index: &IndexMetadata,
+        key: &[u8],
+    ) -> Result<Vec<Tuple>, String> {
+        let rids = match self.indexes.get_index(index) {
+            Some(structure) => structure.get(&mut self.bm, key)?,
+            None => return Err(format!("index \"{}\" is not open", index.name)),
+        };
+        let table = self.table_by_id(index.table)?;
+        let mut rows = Vec::new();
+        for rid in rids {
+            rows.extend(self.get_row(&table, rid)?);
+        }
+        Ok(rows)
+    }
+
+    /// Returns the record IDs of the rows of the index's table in the order of the B+-tree's keys.
+    /// Rows with NULL in a key column are not in the index and left out.
+    pub fn index_order(&mut self, index: &IndexMetadata) -> Result<Vec<RecordId>, String> {
+        match self.indexes.get_index(index) {
+            Some(Index::BTree(tree)) => {
+                tree.range(&mut self.bm, Bound::Unbounded, Bound::Unbounded)
+            }
+            Some(Index::Hash(_)) => Err(format!("index \"{}\" is not ordered", index.name)),
+            None => Err(format!("index \"{}\" is not open", index.name)),
+        }
+    }
+
+    /// Reads all rows of the given table.
+    pub fn table_rows(&mut self, table: usize) -> Result<Vec<(RecordId, Tuple)>, String> {
+        let mut rows = Vec::new();
+        self.scan_table(table, |rid, row| rows.push((rid, row)))?;
+        Ok(rows)
+    }
+
+    /// Calls `f` for every row of a table, only one page is pinned at a time.
+    fn scan_table<F>(&mut self, table: usize, f: F) -> Result<(), String>
+    where
+        F: FnMut(RecordId, Tuple),
+    {
+        let scanner = TableScanner::new(&self.table_by_id(table)?);
+        self.scan(scanner, f)
+    }
+
+    /// Calls `f` for every row the scanner returns.
+    fn scan<F>(&mut self, mut scanner: TableScanner, mut f: F) -> Result<(), String>
+    where
+        F: FnMut(RecordId, Tuple),
+    {
+        loop {
+            match scanner.next(&mut self.bm) {
+                Ok(Some((rid, row))) => f(rid, row),
+                Ok(None) => return Ok(()),
+                Err(err) => {
+                    scanner.close(&mut self.bm);
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    fn heap(&mut self, table: usize) -> Result<HeapFile, String> {
+        if let Some(heap) = self.heaps.get(&table) {
+            return Ok(*heap);
+        }
+        let first_page = self.table_by_id(table)?.first_page;
+        let heap = HeapFile::open(&mut self.bm, first_page)?;
+        self.heaps.insert(table, heap);
+        Ok(heap)
+    }
+
+    /// Evaluates an expression that may use sequences.
+    pub fn eval(&mut self, expr: &Expr, row: &dyn Row) -> Result<Value, String> {
+        let row = SessionRow {
+            row,
+            db: RefCell::new(self),
+        };
+        evaluate(expr, &row)
+    }
+
+    fn default_value(&mut self, column: &Column) -> Result<Value, String> {
+        match &column.default {
+            Some(expr) => self.eval(expr, &NoRow),
+            None => Ok(Value::Null),
+        }
+    }
+
+    /// Returns the next value of a sequence,
+    /// reserving new values in the catalog when the ones reserved by this session are used up.
+    fn nextval(&mut self, name: &str) -> Result<Value, String> {
+        let sequence = self.sequence_by_name(name)?;
+        let state = self.sequences.entry(sequence.id).or_default();
+        let (value, last) = match state.cached {
+            Some(range) => range,
+            None => self
+                .catalog
+                .reserve_sequence_values(&mut self.bm, sequence.id)?,
+        };
+        // the reserved values do not wrap around, so the next one cannot overflow
+        state.cached = (value != last).then(|| (value + sequence.increment, last));
+        state.current = Some(value);
+        Ok(sequence.value(value))
+    }
+
+    /// Returns the value most recently returned by `nextval` for the sequence in this session.
+    fn currval(&self, name: &str) -> Result<Value, String> {
+        let sequence = self.sequence_by_name(name)?;
+        match self.sequences.get(&sequence.id).and_then(|s| s.current) {
+            Some(value) => Ok(sequence.value(value)),
+            None => Err(format!(
+                "currval of sequence \"{}\" is not yet defined in this session",
+                name
+            )),
+        }
+    }
+
+    fn sequence_by_name(&self, name: &str) -> Result<SequenceMetadata, String> {
+        match self.catalog.get_sequence(name) {
+            Some(sequence) => Ok(sequence.clone()),
+            None => Err(format!("relation \"{}\" does not exist", name)),
+        }
+    }
+
+    fn table_by_name(&self, name: &str) -> Result<TableMetadata, String> {
+        match self.catalog.get_table(name) {
+            Some(table) => Ok(table.clone()),
+            None if self.catalog.get_view(name).is_some() => {
+                Err(format!("\"{}\" is not a table", name))
+            }
+            None => Err(format!("relation \"{}\" does not exist", name)),
+        }
+    }
+
+    fn table_by_id(&self, id: usize) -> Result<TableMetadata, String> {
+        match self.catalog.get_table_by_id(id) {
+            Some(table) => Ok(table.clone()),
+            None => Err(format!("table {} does not exist", id)),
+        }
+    }
+
+    /// Writes all changes to disk.
+    pub fn close(&mut self) -> Result<(), String> {
+        self.catalog.save_free_list(&mut self.bm)
+    }
+}
+
+/// Returns the name of an unqualified object, schemas are not supported.
+pub fn object_name(name: &ObjectName) -> Result<String, String> {
+    match name.0.as_slice() {
+        [ident] => Ok(ident_name(ident)),
+        _ => Err(format!("schemas are not supported: {}", name)),
+    }
+}
+
+/// Whether an expression is the `DEFAULT` keyword of INSERT and UPDATE.
+fn is_default(expr: &Expr) -> bool {
+    matches!(expr, Expr::Identifier(ident)
+        if ident.quote_style.is_none() && ident.value.eq_ignore_ascii_case("default"))
+}
+
+fn table_row<'a>(table: &'a TableMetadata, row: &'a [Value]) -> TableRow<'a> {
+    TableRow {
+        table: &table.name,
+        schema: &table.schema,
+        values: row,
+    }
+}
+
+/// Returns the integer type of a SERIAL pseudo-type, which sqlparser parses as a custom type.
+fn serial_type(data_type: &ast::DataType) -> Option<ast::DataType> {
+    let name = match data_type {
+        ast::DataType::Custom(name) => name.to_string().to_lowercase(),
+        _ => return None,
+    };
+    match name.as_str() {
+        "smallserial" | "serial2" | "serial" | "serial4" => Some(ast::DataType::Int),
+        "bigserial" | "serial8" => Some(ast::DataType::BigInt),
+        _ => None,
+    }
+}
+
+fn check_not_null(table: &TableMetadata, row: &[Value]) -> Result<(), String> {
+    for (column, value) in table.schema.columns().iter().zip(row) {
+        if !column.nullable && value.is_null() {
+            return Err(not_null_error(table, &column.name));
+        }
+    }
+    Ok(())
+}
+
+fn not_null_error(table: &TableMetadata, column: &str) -> String {
+    format!(
+        "null value in column \"{}\" of relation \"{}\" violates not-null constraint",
+        column, table.name
+    )
+}
+
+fn referenced_error(
+    table: &TableMetadata,
+    constraint: &ConstraintMetadata,
+    child: &TableMetadata,
+) -> String {
+    format!(
+        "update or delete on table \"{}\" violates foreign key constraint \"{}\" on table \"{}\"",
+        table.name, constraint.name, child.name
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::disk_manager::TempFile;
+    use crate::index_manager::IndexedRow;
+    use crate::sql::parse_sql;
+
+    fn execute(db: &mut Database, sql: &str) -> Result<String, String> {
+        let commands = parse_sql(sql)?;
+        db.execute(&commands[0])
+    }
+
+    /// Stores a row without any checks.
+    fn insert(db: &mut Database, table: &str, row: Tuple) {
+        let table = db.catalog().get_table(table).unwrap().clone();
+        let mut heap = HeapFile::open(&mut db.bm, table.first_page).unwrap();
+        let data = table.row_format().encode(&row).unwrap();
+        heap.insert(&mut db.bm, &data).unwrap();
+    }
+
+    fn rows(db: &mut Database, table: &str) -> Vec<Tuple> {
+        let id = db.catalog().get_table(table).unwrap().id;
+        let mut rows: Vec<_> = db
+            .table_rows(id)
+            .unwrap()
+            .into_iter()
+            .map(|(_, row)| row)
+            .collect();
+        rows.sort();
+        rows
+    }
+
+    fn open(name: &str) -> (Database, TempFile) {
+        let file = TempFile::new(&format!("database_{}", name));
+        (Database::open(file.path()).unwrap(), file)
+    }
+
+    #[test]
+    fn create_drop_table() {
+        let (mut db, file) = open("create_drop");
+        execute(&mut db, "CREATE TABLE a (x INT)").unwrap();
+        execute(&mut db, "CREATE TABLE B (y TEXT NOT NULL DEFAULT 'none')").unwrap();
+        assert!(execute(&mut db, "CREATE TABLE a (z INT)").is_err());
+        execute(&mut db, "CREATE TABLE IF NOT EXISTS a (z INT)").unwrap();
+        execute(&mut db, "CREATE TABLE c (x INT)").unwrap();
+        execute(&mut db, "DROP TABLE c").unwrap();
+        assert!(execute(&mut db, "DROP TABLE c").is_err());
+        execute(&mut db, "DROP TABLE IF EXISTS c").unwrap();
+        db.close().unwrap();
+
+        let db = Database::open(file.path()).unwrap();
+        let a = db.catalog().get_table("a").unwrap();
+        assert_eq!(a.schema.index_of("x"), Some(0));
+        let b = db.catalog().get_table("b").unwrap();
+        assert_eq!(
+            b.schema
+                .column("y")
+                .unwrap()
+                .default
+                .as_ref()
+                .unwrap()
+                .to_string(),
+            "'none'"
+        );
+        assert!(db.catalog().get_table("c").is_none());
+    }
+
+    #[test]
+    fn create_drop_index() {
+        let (mut db, file) = open("index");
+        execute(&mut db, "CREATE TABLE t (a INT, b TEXT, c BOOLEAN)").unwrap();
+        execute(&mut db, "CREATE UNIQUE INDEX t_a ON t USING hash (a)").unwrap();
+        execute(&mut db, "CREATE INDEX t_bc ON t (b, c)").unwrap();
+        execute(&mut db, "CREATE UNIQUE INDEX t_b ON t (b)").unwrap();
+        execute(
+            &mut db,
+            "INSERT INTO t VALUES (1, 'x', true), (2, 'y', false)",
+        )
+        .unwrap();
+        assert!(execute(&mut db, "CREATE INDEX t_bc ON t (a)").is_err());
+        execute(&mut db, "CREATE INDEX IF NOT EXISTS t_bc ON t (a)").unwrap();
+        assert!(execute(&mut db, "CREATE INDEX t_d ON t (d)").is_err());
+        assert!(execute(&mut db, "CREATE INDEX t_e ON u (a)").is_err());
+        execute(&mut db, "CREATE INDEX t_c ON t (c)").unwrap();
+        execute(&mut db, "DROP INDEX t_c").unwrap();
+        assert!(execute(&mut db, "DROP INDEX t_c").is_err());
+        execute(&mut db, "DROP INDEX IF EXISTS t_c").unwrap();
+        db.close().unwrap();
+
+        let db = Database::open(file.path()).unwrap();
+        let t = db.catalog().get_table("t").unwrap().id;
+        let a = db.catalog().get_index("t_a").unwrap();
+        assert_eq!(
+            (a.kind, a.unique, a.columns.clone()),
+            (IndexKind::Hash, true, vec![0])
+        );
+        assert!(a.root_page.is_some());
+        assert!(db.indexes.get_index(a).is_some());
+        let bc = db.catalog().find_range_index(t, 1).unwrap();
+        assert_eq!(
+            (bc.kind, bc.unique, bc.columns.clone()),
+            (IndexKind::BTree, false, vec![1, 2])
+        );
+        assert!(bc.root_page.is_some());
+        assert!(db.catalog().get_index("t_c").is_none());
+
+        // the B+-tree is reopened from disk instead of being rebuilt
+        let mut db = db;
+        assert!(execute(&mut db, "INSERT INTO t VALUES (3, 'y', true)").is_err());
+        execute(&mut db, "INSERT INTO t VALUES (3, 'z', true)").unwrap();
+        execute(&mut db, "DROP TABLE t").unwrap();
+        assert!(db.catalog().indexes().is_empty());
+    }
+
+    #[test]
+    fn index_existing_rows() {
+        let (mut db, file) = open("index_rows");
+        execute(&mut db, "CREATE TABLE t (a INT, b TEXT)").unwrap();
+        for i in 0..100 {
+            insert(
+                &mut db,
+                "t",
+                vec![Value::Int(i % 50), Value::Text(format!("row {}", i))],
+            );
+        }
+
+        assert!(execute(&mut db, "CREATE UNIQUE INDEX t_a ON t (a)").is_err());
+        assert!(db.catalog().get_index("t_a").is_none());
+        execute(&mut db, "CREATE UNIQUE INDEX t_b ON t (b)").unwrap();
+        execute(&mut db, "CREATE INDEX t_a ON t USING hash (a)").unwrap();
+        db.close().unwrap();
+
+        let mut db = Database::open(file.path()).unwrap();
+        let key = |v: Value| vec![v].index_key(&[0]).unwrap();
+        for (name, value, expected) in [
+            ("t_a", Value::Int(7), 2),
+            ("t_b", Value::Text("row 7".to_owned()), 1),
+            ("t_b", Value::Text("row 100".to_owned()), 0),
+        ] {
+            let index = db.catalog().get_index(name).unwrap().clone();
+            let structure = db.indexes.get_index(&index).unwrap();
+            assert_eq!(
+                structure.get(&mut db.bm, &key(value)).unwrap().len(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn alter_table() {
+        let (mut db, file) = open("alter");
+        let text = |s: &str| Value::Text(s.to_owned());
+        execute(&mut db, "CREATE TABLE t (a INT, b TEXT)").unwrap();
+        execute(&mut db, "CREATE INDEX t_b ON t (b)").unwrap();
+        execute(&mut db, "CREATE INDEX t_a ON t (a)").unwrap();
+        insert(&mut db, "t", vec![Value::Int(1), text("one")]);
+
+        execute(&mut db, "ALTER TABLE t ADD COLUMN c BIGINT DEFAULT 2 * 21").unwrap();
+        insert(
+            &mut db,
+            "t",
+            vec![Value::Int(2), text("two"), Value::BigInt(7)],
+        );
+        assert!(execute(&mut db, "ALTER TABLE t ADD COLUMN c INT").is_err());
+        assert!(execute(&mut db, "ALTER TABLE t ADD COLUMN d INT NOT NULL").is_err());
+        assert!(execute(&mut db, "ALTER TABLE t ADD COLUMN d INT DEFAULT a").is_err());
+        assert_eq!(
+            rows(&mut db, "t"),
+            vec![
+                vec![Value::Int(1), text("one"), Value::BigInt(42)],
+                vec![Value::Int(2), text("two"), Value::BigInt(7)],
+            ]
+        );
+
+        execute(&mut db, "ALTER TABLE t DROP COLUMN b").unwrap();
+        assert!(execute(&mut db, "ALTER TABLE t DROP COLUMN b").is_err());
+        execute(&mut db, "ALTER TABLE t DROP COLUMN IF EXISTS b").unwrap();
+        assert!(db.catalog().get_index("t_b").is_none());
+        assert_eq!(db.catalog().get_index("t_a").unwrap().columns, vec![0]);
+        // a new column with the name of a dropped one
+        execute(&mut db, "ALTER TABLE t ADD b BOOLEAN DEFAULT true").unwrap();
+        execute(&mut db, "ALTER TABLE t RENAME COLUMN c TO d").unwrap();
+        assert!(execute(&mut db, "ALTER TABLE t RENAME COLUMN a TO d").is_err());
+        execute(&mut db, "ALTER TABLE t RENAME TO u").unwrap();
+        assert!(execute(&mut db, "ALTER TABLE t RENAME TO v").is_err());
+        db.close().unwrap();
+
+        let mut db = Database::open(file.path()).unwrap();
+        let u = db.catalog().get_table("u").unwrap();
+        assert_eq!(u.version, 3);
+        let names: Vec<_> = u.schema.columns().iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "d", "b"]);
+        insert(
+            &mut db,
+            "u",
+            vec![Value::Int(3), Value::Null, Value::Boolean(false)],
+        );
+        assert_eq!(
+            rows(&mut db, "u"),
+            vec![
+                vec![Value::Int(1), Value::BigInt(42), Value::Boolean(true)],
+                vec![Value::Int(2), Value::BigInt(7), Value::Boolean(true)],
+                vec![Value::Int(3), Value::Null, Value::Boolean(false)],
+            ]
+        );
+    }
+
+    #[test]
+    fn dml() {
+        let (mut db, _file) = open("dml");
+        let text = |s: &str| Value::Text(s.to_owned());
+        execute(
+            &mut db,
+            "CREATE TABLE t (a INT, b TEXT DEFAULT 'x', c FLOAT)",
+        )
+        .unwrap();
+        assert_eq!(
+            execute(
+                &mut db,
+                "INSERT INTO t VALUES (1, 'one', 1), (2, DEFAULT, 2.5)"
+            ),
+            Ok("INSERT 0 2".to_owned())
+        );
+        execute(&mut db, "INSERT INTO t (c, a) VALUES (3, 3)").unwrap();
+        assert!(execute(&mut db, "INSERT INTO t (a, a) VALUES (1, 1)").is_err());
+        assert!(execute(&mut db, "INSERT INTO t (d) VALUES (1)").is_err());
+        assert!(execute(&mut db, "INSERT INTO t VALUES (1, 'a', 1, 1)").is_err());
+        assert!(execute(&mut db, "INSERT INTO t VALUES ('a')").is_err());
+        assert_eq!(
+            execute(&mut db, "UPDATE t SET c = c * 2, b = upper(b) WHERE a >= 2"),
+            Ok("UPDATE 2".to_owned())
+        );
+        assert_eq!(
+            execute(&mut db, "DELETE FROM t WHERE b = 'ONE' OR a = 1"),
+            Ok("DELETE 1".to_owned())
+        );
+        assert_eq!(
+            rows(&mut db, "t"),
+            vec![
+                vec![Value::Int(2), text("X"), Value::Float(5.0)],
+                vec![Value::Int(3), text("X"), Value::Float(6.0)],
+            ]
+        );
+        assert_eq!(execute(&mut db, "DELETE FROM t"), Ok("DELETE 2".to_owned()));
+    }
+
+    #[test]
+    fn constraints() {
+        let (mut db, file) = open("constraints");
+        execute(
+            &mut db,
+            "CREATE TABLE p (id INT PRIMARY KEY, code TEXT UNIQUE, CHECK (id > 0))",
+        )
+        .unwrap();
+        execute(
+            &mut db,
+            "CREATE TABLE c (
+                id BIGINT,
+                p_id INT REFERENCES p ON DELETE CASCADE ON UPDATE CASCADE,
+                code TEXT NOT NULL CHECK (length(code) < 5),
+                PRIMARY KEY (id),
+                CONSTRAINT c_code_fkey FOREIGN KEY (code) REFERENCES p (code)
+            )",
+        )
+        .unwrap();
+        assert!(db.catalog().get_index("p_pkey").unwrap().unique);
+        assert!(db.catalog().get_index("p_code_key").is_some());
+        assert!(execute(&mut db, "DROP INDEX p_pkey").is_err());
+        assert!(execute(&mut db, "CREATE TABLE d (x INT REFERENCES p (code))").is_err());
+        assert!(execute(&mut db, "CREATE TABLE d (x INT REFERENCES c (p_id))").is_err());
+        assert!(db.catalog().get_table("d").is_none());
+
+        execute(&mut db, "INSERT INTO p VALUES (1, 'a'), (2, 'b'), (3, 'c')").unwrap();
+        for (sql, error) in [
+            (
+                "INSERT INTO p VALUES (1, 'd')",
+                "unique constraint \"p_pkey\"",
+            ),
+            ("INSERT INTO p VALUES (NULL, 'd')", "not-null"),
+            (
+                "INSERT INTO p VALUES (0, 'd')",
+                "check constraint \"p_id_check\"",
+            ),
+            ("INSERT INTO p VALUES (4, 'd'), (5, 'd')", "\"p_code_key\""),
+        ] {
+            let err = execute(&mut db, sql).unwrap_err();
+            assert!(err.contains(error), "{}: {}", sql, err);
+        }
+        // the failed statements left nothing behind
+        assert_eq!(rows(&mut db, "p").len(), 3);
+
+        execute(
+            &mut db,
+            "INSERT INTO c VALUES (10, 1, 'a'), (11, 1, 'b'), (12, NULL, 'c')",
+        )
+        .unwrap();
+        for (sql, error) in [
+            (
+                "INSERT INTO c VALUES (13, 4, 'a')",
+                "foreign key constraint \"c_p_id_fkey\"",
+            ),
+            (
+                "INSERT INTO c VALUES (13, 1, 'x')",
+                "foreign key constraint \"c_code_fkey\"",
+            ),
+            ("INSERT INTO c VALUES (13, 1, NULL)", "not-null"),
+            ("INSERT INTO c (id, p_id) VALUES (13, 1)", "not-null"),
+            (
+                "UPDATE c SET code = 'abcdef'",
+                "check constraint \"c_code_check\"",
+            ),
+            (
+                "DELETE FROM p WHERE id = 3",
+                "violates foreign key constraint \"c_code_fkey\"",
+            ),
+            ("UPDATE p SET code = 'z' WHERE id = 2", "on table \"c\""),
+        ] {
+            let err = execute(&mut db, sql).unwrap_err();
+            assert!(err.contains(error), "{}: {}", sql, err);
+        }
+
+        // cascading update of the referenced key
+        execute(&mut db, "UPDATE p SET id = 7 WHERE id = 1").unwrap();
+        assert_eq!(
+            rows(&mut db, "c")
+                .iter()
+                .map(|r| r[1].clone())
+                .collect::<Vec<_>>(),
+            vec![Value::Int(7), Value::Int(7), Value::Null]
+        );
+        assert!(execute(&mut db, "DROP TABLE p").is_err());
+        db.close().unwrap();
+
+        let mut db = Database::open(file.path()).unwrap();
+        assert!(execute(&mut db, "INSERT INTO c VALUES (10, 7, 'a')").is_err());
+        // cascading delete, the restricted rows of c_code_fkey are deleted by the cascade
+        assert_eq!(
+            execute(&mut db, "DELETE FROM p WHERE id = 7"),
+            Ok("DELETE 1".to_owned())
+        );
+        assert_eq!(rows(&mut db, "c").len(), 1);
+        assert!(execute(&mut db, "DELETE FROM p").is_err());
+        assert_eq!(rows(&mut db, "p").len(), 2);
+
+        execute(&mut db, "ALTER TABLE c DROP CONSTRAINT c_code_fkey").unwrap();
+        execute(&mut db, "DELETE FROM p").unwrap();
+        assert!(execute(
+            &mut db,
+            "ALTER TABLE c ADD CONSTRAINT c_code_fkey FOREIGN KEY (code) REFERENCES p (code)"
+        )
+        .is_err());
+        execute(
+            &mut db,
+            "ALTER TABLE c ADD CONSTRAINT c_id_check CHECK (id > 0)",
+        )
+        .unwrap();
+        assert!(execute(&mut db, "INSERT INTO c VALUES (-1, NULL, 'a')").is_err());
+        execute(&mut db, "ALTER TABLE c RENAME COLUMN id TO key").unwrap();
+        assert!(execute(&mut db, "INSERT INTO c VALUES (-1, NULL, 'a')").is_err());
+        execute(&mut db, "ALTER TABLE c DROP COLUMN key").unwrap();
+        execute(&mut db, "INSERT INTO c VALUES (NULL, 'a')").unwrap();
+        assert!(db.catalog().get_index("c_pkey").is_none());
+        execute(&mut db, "DROP TABLE p CASCADE").unwrap();
+        execute(&mut db, "INSERT INTO c VALUES (5, 'b')").unwrap();
+
+        // composite keys are looked up in the referenced unique index, in any column order
+        execute(&mut db, "CREATE TABLE q (a INT, b INT, PRIMARY KEY (a, b))").unwrap();
+        execute(
+            &mut db,
+            "CREATE TABLE r (x INT, y BIGINT, FOREIGN KEY (y, x) REFERENCES q (b, a))",
+        )
+        .unwrap();
+        execute(&mut db, "INSERT INTO q VALUES (1, 2)").unwrap();
+        execute(&mut db, "INSERT INTO r VALUES (1, 2)").unwrap();
+        assert!(execute(&mut db, "INSERT INTO r VALUES (2, 1)").is_err());
+    }
+
+    #[test]
+    fn views() {
+        let (mut db, file) = open("views");
+        execute(&mut db, "CREATE TABLE t (a INT, b TEXT)").unwrap();
+        execute(&mut db, "CREATE TABLE u (a INT, c INT)").unwrap();
+        assert_eq!(
+            execute(
+                &mut db,
+                "CREATE VIEW v (x) AS SELECT a, b FROM t WHERE a > 0"
+            ),
+            Ok("CREATE VIEW".to_owned())
+        );
+        execute(
+            &mut db,
+            "CREATE VIEW w AS SELECT * FROM v JOIN u ON x = u.a",
+        )
+        .unwrap();
+        assert!(execute(&mut db, "CREATE VIEW v AS SELECT 1").is_err());
+        assert!(execute(&mut db, "CREATE VIEW bad AS SELECT d FROM t").is_err());
+        assert!(execute(&mut db, "CREATE VIEW bad (x, y, z) AS SELECT * FROM t").is_err());
+        assert!(execute(&mut db, "CREATE VIEW bad AS SELECT a, a FROM t").is_err());
+        assert!(execute(&mut db, "CREATE VIEW bad AS SELECT * FROM w, v").is_err());
+        assert!(execute(&mut db, "INSERT INTO v VALUES (1, 'x')").is_err());
+        db.close().unwrap();
+
+        let mut db = Database::open(file.path()).unwrap();
+        let w = db.catalog().get_view("w").unwrap();
+        assert_eq!(w.columns, vec!["x", "b", "a", "c"]);
+        let (u, v) = (
+            db.catalog().get_table("u").unwrap().id,
+            db.catalog().get_view("v").unwrap().id,
+        );
+        assert_eq!(w.dependencies, vec![u, v]);
+        assert!(execute(&mut db, "DROP TABLE t").is_err());
+        assert!(execute(&mut db, "DROP VIEW v").is_err());
+        assert_eq!(execute(&mut db, "DROP VIEW w"), Ok("DROP VIEW".to_owned()));
+        execute(&mut db, "DROP VIEW IF EXISTS w").unwrap();
+        assert!(execute(&mut db, "DROP VIEW w").is_err());
+        execute(
+            &mut db,
+            "CREATE OR REPLACE VIEW v AS SELECT a AS x, b, a * 2 AS c FROM t",
+        )
+        .unwrap();
+        execute(&mut db, "DROP TABLE t CASCADE").unwrap();
+        assert!(db.catalog().get_view("v").is_none());
+    }
+
+    #[test]
+    fn sequences() {
+        let (mut db, file) = open("sequences");
+        execute(&mut db, "CREATE SEQUENCE s INCREMENT 10 CACHE 2").unwrap();
+        assert!(execute(&mut db, "CREATE SEQUENCE s").is_err());
+        execute(&mut db, "CREATE SEQUENCE IF NOT EXISTS s").unwrap();
+        execute(
+            &mut db,
+            "CREATE TABLE t (id SERIAL PRIMARY KEY, n BIGINT DEFAULT nextval('s'))",
+        )
+        .unwrap();
+        assert!(execute(&mut db, "CREATE TABLE u (id SERIAL DEFAULT 1)").is_err());
+        assert!(db.catalog().get_sequence("t_id_seq").is_some());
+
+        assert_eq!(
+            execute(&mut db, "INSERT INTO t VALUES (DEFAULT, currval('s'))").unwrap_err(),
+            "currval of sequence \"s\" is not yet defined in this session"
+        );
+        execute(
+            &mut db,
+            "INSERT INTO t (n) VALUES (DEFAULT), (nextval('S') + currval('s'))",
+        )
+        .unwrap();
+        execute(&mut db, "UPDATE t SET n = nextval('s') WHERE id = 1").unwrap();
+        // values used by failed statements are not given out again
+        assert!(execute(
+            &mut db,
+            "INSERT INTO t VALUES (nextval('t_id_seq'), NULL), (1, 0)"
+        )
+        .is_err());
+        execute(&mut db, "INSERT INTO t (n) VALUES (0)").unwrap();
+        let row = |id, n| vec![Value::Int(id), Value::BigInt(n)];
+        assert_eq!(rows(&mut db, "t"), vec![row(1, 21), row(2, 22), row(4, 0)]);
+        db.close().unwrap();
+
+        // reserved values that were not used are skipped after reopening
+        let mut db = Database::open(file.path()).unwrap();
+        execute(&mut db, "INSERT INTO t (n) VALUES (nextval('s'))").unwrap();
+        assert_eq!(rows(&mut db, "t")[3], row(33, 41));
+        assert!(execute(&mut db, "DROP SEQUENCE s").is_err());
+        execute(&mut db, "DROP SEQUENCE s CASCADE").unwrap();
+        assert!(db.catalog().get_table("t").unwrap().schema.columns()[1]
+            .default
+            .is_none());
+        assert!(execute(&mut db, "DROP SEQUENCE s").is_err());
+        execute(&mut db, "DROP SEQUENCE IF EXISTS s").unwrap();
+        assert!(execute(
+            &mut db,
+            "ALTER TABLE t ADD COLUMN x INT DEFAULT nextval('t_id_seq')"
+        )
+        .is_err());
+        execute(&mut db, "DROP TABLE t").unwrap();
+        assert!(db.catalog().get_sequence("t_id_seq").is_none());
+        // the session steps through reserved values, a cycling sequence wraps around
+        execute(&mut db, "CREATE SEQUENCE c MAXVALUE 3 CYCLE CACHE 2").unwrap();
+        let values: Vec<_> = (0..5).map(|_| db.nextval("c").unwrap()).collect();
+        assert_eq!(values, [1, 2, 3, 1, 2].map(Value::BigInt));
+    }
+
+    #[test]
+    fn identity_columns() {
+        let (mut db, _file) = open("identity");
+        execute(
+            &mut db,
+            "CREATE TABLE t (a INT GENERATED ALWAYS AS IDENTITY (START 5 INCREMENT 5), \
+             b BIGINT GENERATED BY DEFAULT AS IDENTITY, c TEXT)",
+        )
+        .unwrap();
+        assert!(execute(
+            &mut db,
+            "CREATE TABLE u (a TEXT GENERATED ALWAYS AS IDENTITY)"
+        )
+        .is_err());
+        execute(&mut db, "INSERT INTO t (c) VALUES ('x')").unwrap();
+        execute(&mut db, "INSERT INTO t (b, c) VALUES (7, 'y')").unwrap();
+        assert_eq!(
+            execute(&mut db, "INSERT INTO t VALUES (1, 1, 'z')").unwrap_err(),
+            "cannot insert a non-DEFAULT value into column \"a\""
+        );
+        assert_eq!(
+            execute(&mut db, "UPDATE t SET a = 1").unwrap_err(),
+            "column \"a\" can only be updated to DEFAULT"
+        );
+        execute(&mut db, "UPDATE t SET a = DEFAULT WHERE c = 'y'").unwrap();
+        assert!(execute(&mut db, "INSERT INTO t (b) VALUES (NULL)").is_err());
+        assert_eq!(
+            rows(&mut db, "t"),
+            vec![
+                vec![Value::Int(5), Value::BigInt(1), Value::Text("x".to_owned())],
+                vec![
+                    Value::Int(15),
+                    Value::BigInt(7),
+                    Value::Text("y".to_owned())
+                ],
+            ]
+        );
+        execute(&mut db, "ALTER TABLE t RENAME COLUMN a TO id").unwrap();
+        execute(&mut db, "ALTER TABLE t DROP COLUMN id").unwrap();
+        assert!(db.catalog().get_sequence("t_a_seq").is_none());
+        assert!(db.catalog().get_sequence("t_b_seq").is_some());
+    }
+
+    #[test]
+    fn analyze() {
+        let (mut db, file) = open("analyze");
+        execute(&mut db, "CREATE TABLE t (a INT, b TEXT, c FLOAT)").unwrap();
+        execute(&mut db, "CREATE TABLE u (a INT)").unwrap();
+        for i in 0..200 {
+            let b = if i % 2 == 0 {
+                "'even'".to_owned()
+            } else {
+                format!("'{}'", i)
+            };
+            let sql = format!("INSERT INTO t VALUES ({}, {}, NULL)", i, b);
+            execute(&mut db, &sql).unwrap();
+        }
+        execute(&mut db, "ANALYZE").unwrap();
+        execute(&mut db, "ALTER TABLE t RENAME COLUMN b TO x").unwrap();
+        execute(&mut db, "ALTER TABLE t DROP COLUMN c").unwrap();
+        assert!(execute(&mut db, "ANALYZE missing").is_err());
+        db.close().unwrap();
+
+        let mut db = Database::open(file.path()).unwrap();
+        let t = db.catalog().get_table("t").unwrap().id;
+        let u = db.catalog().get_table("u").unwrap().id;
+        let statistics = db.catalog().get_statistics(t).unwrap().clone();
+        assert_eq!(statistics.row_count, 200);
+        assert!(statistics.page_count >= 1);
+        assert!(statistics.column("c").is_none());
+        let x = statistics.column("x").unwrap();
+        assert_eq!(x.distinct_count, 101);
+        assert_eq!(x.most_common, vec![(Value::Text("even".to_owned()), 0.5)]);
+        assert_eq!(x.histogram.first(), Some(&Value::Text("1".to_owned())));
+        let a = statistics.column("a").unwrap();
+        assert_eq!(a.histogram.len(), 101);
+        assert_eq!(a.histogram[100], Value::Int(199));
+        assert_eq!(db.catalog().get_statistics(u).unwrap().row_count, 0);
+
+        execute(&mut db, "DELETE FROM t WHERE a >= 100").unwrap();
+        execute(&mut db, "ANALYZE TABLE t").unwrap();
+        assert_eq!(db.catalog().get_statistics(t).unwrap().row_count, 100);
+        execute(&mut db, "DROP TABLE t").unwrap();
+        assert!(db.catalog().get_statistics(t).is_none());
+    }
+}
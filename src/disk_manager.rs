@@ -5,11 +5,18 @@ use std::convert::TryInto;
 use std::fs::{File, OpenOptions};
 use std::io::{self, Read, Seek, SeekFrom, Write};
 
-use crate::page::{PageID, PAGE_SIZE};
+use crate::page::{read_page_id, read_u64, write_u64, PageID, NO_PAGE, PAGE_SIZE};
+
+/// "QDBFREE1", marks the pages of the free list written by `write_free_list`.
+const FREE_MAGIC: u64 = 0x5144_4246_5245_4531;
 
 /// A trivial Disk Manager implementation that has all pages in a single large file.
 pub struct DiskManager {
     next_page_id: PageID,
+    /// Deallocated pages, reused before the file grows.
+    free_pages: Vec<PageID>,
+    /// Number of pages at the start of `free_pages` that are already linked on disk.
+    linked_pages: usize,
     filename: String,
     db_file: File,
 }
@@ -20,6 +27,8 @@ impl DiskManager {
     pub fn new(db_file_name: &str) -> Self {
         Self {
             next_page_id: 0,
+            free_pages: Vec::new(),
+            linked_pages: 0,
             filename: db_file_name.to_owned(),
             db_file: OpenOptions::new()
                 .read(true)
@@ -31,6 +40,24 @@ impl DiskManager {
         }
     }
 
+    /// Open an existing database file, creating it if it does not exist.
+    pub fn open(db_file_name: &str) -> io::Result<Self> {
+        let db_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(db_file_name)?;
+        let len = db_file.metadata()?.len() as usize;
+        Ok(Self {
+            next_page_id: len.div_ceil(PAGE_SIZE),
+            free_pages: Vec::new(),
+            linked_pages: 0,
+            filename: db_file_name.to_owned(),
+            db_file,
+        })
+    }
+
     /// Read the given page of the disk file into the memory buffer.
     pub fn read_page(&mut self, page: PageID, buf: &mut [u8; PAGE_SIZE]) -> io::Result<()> {
         let offset: u64 = (page * PAGE_SIZE).try_into().unwrap();
@@ -48,15 +75,94 @@ impl DiskManager {
         Ok(())
     }
 
-    ///
+    /// Returns the ID of an unused page, preferring previously deallocated ones.
     pub fn allocate_page(&mut self) -> PageID {
+        if let Some(id) = self.free_pages.pop() {
+            self.linked_pages = self.linked_pages.min(self.free_pages.len());
+            return id;
+        }
         let id = self.next_page_id;
         self.next_page_id += 1;
         return id;
     }
 
-    ///
-    pub fn deallocate_page(page: PageID) {}
+    /// Marks the given page as unused.
+    pub fn deallocate_page(&mut self, page: PageID) {
+        self.free_pages.push(page);
+    }
+
+    /// Writes the deallocated pages to disk as a list, each pointing to the page deallocated
+    /// before it, and returns its head. Only pages that are not linked yet are written.
+    pub fn write_free_list(&mut self) -> io::Result<Option<PageID>> {
+        let mut buf = [0; PAGE_SIZE];
+        for i in self.linked_pages..self.free_pages.len() {
+            let next = match i {
+                0 => NO_PAGE,
+                i => self.free_pages[i - 1] as u64,
+            };
+            write_u64(&mut buf, 0, FREE_MAGIC);
+            write_u64(&mut buf, 8, next);
+            self.write_page(self.free_pages[i], &buf)?;
+        }
+        self.linked_pages = self.free_pages.len();
+        Ok(self.free_pages.last().copied())
+    }
+
+    /// Reads the list written by `write_free_list`, replacing the deallocated pages.
+    /// The list ends early at a page that was reused since, the pages after it are leaked.
+    pub fn read_free_list(&mut self, head: Option<PageID>) -> io::Result<()> {
+        let mut pages = Vec::new();
+        let mut buf = [0; PAGE_SIZE];
+        let mut next = head;
+        while let Some(page) = next {
+            if page >= self.next_page_id || pages.contains(&page) {
+                break;
+            }
+            self.read_page(page, &mut buf)?;
+            if read_u64(&buf, 0) != FREE_MAGIC {
+                break;
+            }
+            pages.push(page);
+            next = read_page_id(&buf, 8);
+        }
+        pages.reverse();
+        self.linked_pages = pages.len();
+        self.free_pages = pages;
+        Ok(())
+    }
+
+    /// Number of pages in the file, including deallocated ones.
+    pub fn num_pages(&self) -> usize {
+        self.next_page_id
+    }
+}
+
+/// A temporary file with a name unique to the test and process,
+/// deleted when dropped.
+#[cfg(test)]
+pub struct TempFile {
+    path: std::path::PathBuf,
+}
+
+#[cfg(test)]
+impl TempFile {
+    pub fn new(name: &str) -> Self {
+        let file = format!("qdb_{}_{}.tmp", name, std::process::id());
+        let path = std::env::temp_dir().join(file);
+        let _ = std::fs::remove_file(&path);
+        Self { path }
+    }
+
+    pub fn path(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
 }
 
 #[cfg(test)]
@@ -80,4 +186,34 @@ mod tests {
             assert_eq!(buf1, buf2);
         }
     }
+
+    #[test]
+    fn free_list() {
+        let file = TempFile::new("disk_manager_free_list");
+        let path = file.path();
+        let mut dm = DiskManager::new(path);
+        for _ in 0..5 {
+            let page = dm.allocate_page();
+            dm.write_page(page, &[0; PAGE_SIZE]).unwrap();
+        }
+        dm.deallocate_page(1);
+        dm.deallocate_page(3);
+        assert_eq!(dm.write_free_list().unwrap(), Some(3));
+        // reusing a page unlinks it, only the new head has to be written
+        assert_eq!(dm.allocate_page(), 3);
+        dm.deallocate_page(4);
+        assert_eq!(dm.write_free_list().unwrap(), Some(4));
+
+        let mut dm = DiskManager::open(path).unwrap();
+        dm.read_free_list(Some(4)).unwrap();
+        assert_eq!(dm.allocate_page(), 4);
+        assert_eq!(dm.allocate_page(), 1);
+        assert_eq!(dm.allocate_page(), 5);
+
+        // a reused page ends the list
+        let mut dm = DiskManager::open(path).unwrap();
+        dm.write_page(4, &[0; PAGE_SIZE]).unwrap();
+        dm.read_free_list(Some(4)).unwrap();
+        assert_eq!(dm.allocate_page(), 5);
+    }
 }
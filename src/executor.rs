@@ -0,0 +1,1791 @@
+// Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::collections::HashSet;
+use std::vec::IntoIter;
+
+use sqlparser::ast::{BinaryOperator, Expr, Ident};
+
+use crate::aggregate::{HashAggregate, StreamAggregate};
+use crate::binder::{and, resolve, Field, JoinKind, Plan, SortKey};
+use crate::catalog::{Catalog, IndexMetadata, TableMetadata};
+use crate::database::Database;
+use crate::expression::{column_references, conjuncts, evaluate, referenced_sequences, NoRow, Row};
+use crate::external_sort::{sort_key, Sort, SortMergeJoin};
+use crate::hash_join::HashJoin;
+use crate::nested_loop_join::{IndexNestedLoopJoin, NestedLoopJoin};
+use crate::page::RecordId;
+use crate::table_scan::TableScanner;
+use crate::tuple::Tuple;
+use crate::value::Value;
+use crate::window::Window;
+
+/// A node of an executable query plan, producing its rows one at a time (the Volcano model).
+///
+/// `open` has to be called before `next`, and `close` once the rows are no longer needed.
+/// If `open` fails, the operator closes the inputs it opened before returning the error.
+/// `close` can be called in any state, also if the operator is not open.
+/// Operators take the database in every call instead of keeping a reference,
+/// so that all operators of a plan can read tables and evaluate expressions like `nextval`.
+pub trait Operator {
+    fn open(&mut self, db: &mut Database) -> Result<(), String>;
+
+    /// Returns the next row, or `None` once all rows have been produced.
+    fn next(&mut self, db: &mut Database) -> Result<Option<Tuple>, String>;
+
+    /// Releases the resources of the operator, it can be opened again afterwards.
+    fn close(&mut self, db: &mut Database) -> Result<(), String>;
+
+    /// The columns of the rows the operator produces.
+    fn schema(&self) -> &[Field];
+}
+
+/// Number of pages of rows an operator may keep in memory,
+/// before it works in blocks or spills to disk (like PostgreSQL's `work_mem`).
+pub const WORK_MEM_PAGES: usize = 256;
+
+/// Builds the operator tree executing a plan, using the catalog to choose join algorithms.
+pub fn build(plan: &Plan, catalog: &Catalog) -> Box<dyn Operator> {
+    if let Some(scan) = build_scan(plan) {
+        return Box::new(scan);
+    }
+    match plan {
+        Plan::Scan { table, fields } => Box::new(TableScan::new(*table, fields.clone())),
+        Plan::Values { rows, fields } => Box::new(Values::new(rows.clone(), fields.clone())),
+        Plan::Filter { input, predicate } => {
+            Box::new(Filter::new(build(input, catalog), predicate.clone()))
+        }
+        Plan::Project {
+            input,
+            exprs,
+            fields,
+        } => Box::new(Project::new(
+            build(input, catalog),
+            exprs.clone(),
+            fields.clone(),
+        )),
+        Plan::Join {
+            left,
+            right,
+            kind,
+            condition,
+            fields,
+        } => {
+            if let Some(join) = build_index_join(plan, catalog) {
+                return Box::new(join);
+            }
+            if let Some(join) = build_merge_join(plan, catalog) {
+                return Box::new(join);
+            }
+            if let Some(join) = build_hash_join(plan, catalog) {
+                return Box::new(join);
+            }
+            Box::new(NestedLoopJoin::new(
+                build(left, catalog),
+                build(right, catalog),
+                *kind,
+                condition.clone(),
+                fields.clone(),
+            ))
+        }
+        Plan::Alias { input, fields } => {
+            Box::new(Rename::new(build(input, catalog), fields.clone()))
+        }
+        Plan::Sort { input, keys } => Box::new(Sort::new(build(input, catalog), keys.clone())),
+        Plan::Limit {
+            input,
+            limit,
+            offset,
+        } => {
+            let input = match (&**input, limit) {
+                (Plan::Sort { input, keys }, Some(limit))
+                    if limit.saturating_add(*offset) <= MAX_TOP_N =>
+                {
+                    Box::new(TopN::new(
+                        build(input, catalog),
+                        keys.clone(),
+                        limit + offset,
+                    ))
+                }
+                _ => build(input, catalog),
+            };
+            let input = match offset {
+                0 => input,
+                offset => Box::new(Offset::new(input, *offset)),
+            };
+            match limit {
+                Some(limit) => Box::new(Limit::new(input, *limit)),
+                None => input,
+            }
+        }
+        Plan::Distinct { input } => Box::new(Distinct::new(build(input, catalog))),
+        Plan::Aggregate {
+            input,
+            group_by,
+            aggregates,
+            fields,
+        } => {
+            let (group_by, aggregates, fields) =
+                (group_by.clone(), aggregates.clone(), fields.clone());
+            if is_grouped(input, &group_by) {
+                Box::new(StreamAggregate::new(
+                    build(input, catalog),
+                    group_by,
+                    aggregates,
+                    fields,
+                ))
+            } else {
+                Box::new(HashAggregate::new(
+                    build(input, catalog),
+                    group_by,
+                    aggregates,
+                    fields,
+                ))
+            }
+        }
+        Plan::Window {
+            input,
+            partition_by,
+            order_by,
+            functions,
+            fields,
+        } => {
+            let keys: Vec<_> = window_keys(partition_by, order_by).collect();
+            let mut input = build(input, catalog);
+            if !keys.is_empty() {
+                input = Box::new(Sort::new(input, keys));
+            }
+            Box::new(Window::new(
+                input,
+                partition_by.clone(),
+                order_by.iter().map(|key| key.expr.clone()).collect(),
+                functions.clone(),
+                fields.clone(),
+            ))
+        }
+    }
+}
+
+/// Builds an index nested-loop join for an inner or left join with a table,
+/// if the condition compares all columns of one of the table's indexes for equality
+/// with expressions on the left input.
+fn build_index_join(plan: &Plan, catalog: &Catalog) -> Option<IndexNestedLoopJoin> {
+    let (left, right, kind, condition, fields) = match plan {
+        Plan::Join {
+            left,
+            right,
+            kind: kind @ (JoinKind::Inner | JoinKind::Left),
+            condition: Some(condition),
+            fields,
+        } => (left, right, *kind, condition, fields),
+        _ => return None,
+    };
+    let table = match **right {
+        Plan::Scan { table, .. } => catalog.get_table_by_id(table)?,
+        _ => return None,
+    };
+    // with more left rows than table pages, reading the table once is cheaper than the lookups
+    if let (Some(rows), Some(statistics)) = (
+        estimated_rows(left, catalog),
+        catalog.get_statistics(table.id),
+    ) {
+        if rows > statistics.page_count as f64 {
+            return None;
+        }
+    }
+    // pairs of table columns and the expressions they have to equal
+    let (keys, _) = equi_join_keys(condition, left.fields(), right.fields());
+    let equalities: Vec<_> = keys
+        .into_iter()
+        .filter_map(|(l, r)| Some((column_index(&r, right.fields())?, l)))
+        .collect();
+    let index = catalog
+        .get_table_indices(&table.name)
+        .into_iter()
+        .find(|index| {
+            index
+                .columns
+                .iter()
+                .all(|c| equalities.iter().any(|(column, _)| column == c))
+        })?;
+    let keys = index
+        .columns
+        .iter()
+        .map(|c| {
+            equalities
+                .iter()
+                .find(|(column, _)| column == c)
+                .unwrap()
+                .1
+                .clone()
+        })
+        .collect();
+    Some(IndexNestedLoopJoin::new(
+        build(left, catalog),
+        table,
+        index,
+        keys,
+        kind,
+        Some(condition.clone()),
+        fields.clone(),
+    ))
+}
+
+/// Estimates the number of rows of a plan from the statistics collected by `ANALYZE`,
+/// `None` if a table has not been analyzed or the plan is not a filtered scan.
+fn estimated_rows(plan: &Plan, catalog: &Catalog) -> Option<f64> {
+    match plan {
+        Plan::Scan { table, .. } => Some(catalog.get_statistics(*table)?.row_count as f64),
+        Plan::Filter { input, predicate } => {
+            let rows = estimated_rows(input, catalog)?;
+            Some(rows * selectivity(predicate, input, catalog))
+        }
+        Plan::Project { input, .. } | Plan::Alias { input, .. } => estimated_rows(input, catalog),
+        _ => None,
+    }
+}
+
+/// Estimates the fraction of rows of a scan for which the predicate is true,
+/// only comparisons of a column with a constant for equality are taken into account.
+fn selectivity(predicate: &Expr, input: &Plan, catalog: &Catalog) -> f64 {
+    let table = match input {
+        Plan::Scan { table, .. } => *table,
+        _ => return 1.0,
+    };
+    let (table, statistics) = match (
+        catalog.get_table_by_id(table),
+        catalog.get_statistics(table),
+    ) {
+        (Some(table), Some(statistics)) => (table, statistics),
+        _ => return 1.0,
+    };
+    let equality = |a: &Expr, b: &Expr| {
+        let c = column_index(a, input.fields())?;
+        let column = statistics.column(&table.schema.columns()[c].name)?;
+        let value = evaluate(b, &NoRow).ok()?;
+        if value.is_null() {
+            return Some(0.0);
+        }
+        if let Some((_, fraction)) = column.most_common.iter().find(|(v, _)| *v == value) {
+            return Some(*fraction);
+        }
+        // the other values are assumed to be equally common
+        let common: f64 = column.most_common.iter().map(|(_, f)| f).sum();
+        let others = column
+            .distinct_count
+            .saturating_sub(column.most_common.len() as u64);
+        Some((1.0 - column.null_fraction - common).max(0.0) / others.max(1) as f64)
+    };
+    conjuncts(predicate)
+        .into_iter()
+        .map(|conjunct| match conjunct {
+            Expr::BinaryOp {
+                left,
+                op: BinaryOperator::Eq,
+                right,
+            } => equality(left, right)
+                .or_else(|| equality(right, left))
+                .unwrap_or(1.0),
+            _ => 1.0,
+        })
+        .product()
+}
+
+/// Builds a hash join if the condition compares expressions on both inputs for equality.
+fn build_hash_join(plan: &Plan, catalog: &Catalog) -> Option<HashJoin> {
+    let (left, right, kind, condition, fields) = match plan {
+        Plan::Join {
+            left,
+            right,
+            kind,
+            condition: Some(condition),
+            fields,
+        } => (left, right, *kind, condition, fields),
+        _ => return None,
+    };
+    let (keys, residual) = equi_join_keys(condition, left.fields(), right.fields());
+    if keys.is_empty() {
+        return None;
+    }
+    let (left_keys, right_keys) = keys.into_iter().unzip();
+    let residual = residual.into_iter().cloned().reduce(and);
+    Some(HashJoin::new(
+        build(left, catalog),
+        build(right, catalog),
+        kind,
+        left_keys,
+        right_keys,
+        residual,
+        fields.clone(),
+    ))
+}
+
+/// Builds a sort-merge join if the condition compares expressions on both inputs for equality
+/// and one of the inputs is already sorted on its keys, the other input is sorted for the join.
+fn build_merge_join(plan: &Plan, catalog: &Catalog) -> Option<SortMergeJoin> {
+    let (left, right, kind, condition, fields) = match plan {
+        Plan::Join {
+            left,
+            right,
+            kind,
+            condition: Some(condition),
+            fields,
+        } => (left, right, *kind, condition, fields),
+        _ => return None,
+    };
+    let (keys, residual) = equi_join_keys(condition, left.fields(), right.fields());
+    if keys.is_empty() {
+        return None;
+    }
+    let sorted = sorted_keys(&keys, &ascending_columns(left), left.fields(), |(l, _)| l)
+        .or_else(|| sorted_keys(&keys, &ascending_columns(right), right.fields(), |(_, r)| r));
+    // an index leaves out the rows with NULL keys, which must not be padded by the join
+    let left_index = matches!(kind, JoinKind::Inner | JoinKind::Right | JoinKind::Semi)
+        .then(|| index_order(&keys, left, |(l, _)| l, catalog))
+        .flatten();
+    let right_index = matches!(
+        kind,
+        JoinKind::Inner | JoinKind::Left | JoinKind::Semi | JoinKind::Anti
+    )
+    .then(|| index_order(&keys, right, |(_, r)| r, catalog))
+    .flatten();
+    let (keys, left_index, right_index) = match (sorted, left_index, right_index) {
+        (Some(keys), _, _) => (keys, None, None),
+        (None, Some((l, left_keys)), Some((r, right_keys))) if left_keys == right_keys => {
+            (left_keys, Some(l), Some(r))
+        }
+        (None, Some((l, keys)), _) => (keys, Some(l), None),
+        (None, None, Some((r, keys))) => (keys, None, Some(r)),
+        (None, None, None) => return None,
+    };
+    let (left_keys, right_keys): (Vec<_>, Vec<_>) = keys.into_iter().unzip();
+    let residual = residual.into_iter().cloned().reduce(and);
+    Some(SortMergeJoin::new(
+        build_ordered(left, left_index, &left_keys, catalog),
+        build_ordered(right, right_index, &right_keys, catalog),
+        kind,
+        left_keys,
+        right_keys,
+        residual,
+        fields.clone(),
+    ))
+}
+
+/// Finds a B+-tree on the table scanned by one input of a join
+/// whose first key columns are the join keys of that side, with the keys in the order of the index.
+fn index_order<'a>(
+    keys: &[(Expr, Expr)],
+    input: &Plan,
+    side: fn(&(Expr, Expr)) -> &Expr,
+    catalog: &'a Catalog,
+) -> Option<(&'a IndexMetadata, Vec<(Expr, Expr)>)> {
+    let table = match input {
+        Plan::Scan { table, .. } => *table,
+        _ => return None,
+    };
+    keys.iter().find_map(|key| {
+        let index = catalog.find_range_index(table, column_index(side(key), input.fields())?)?;
+        Some((
+            index,
+            sorted_keys(keys, &index.columns, input.fields(), side)?,
+        ))
+    })
+}
+
+/// Builds an input of a merge join, reading it in the order of the index if there is one.
+fn build_ordered(
+    plan: &Plan,
+    index: Option<&IndexMetadata>,
+    keys: &[Expr],
+    catalog: &Catalog,
+) -> Box<dyn Operator> {
+    match index {
+        Some(index) => Box::new(IndexScan::new(index.clone(), plan.fields().to_vec())),
+        None => build_sorted(plan, keys, catalog),
+    }
+}
+
+/// Orders the join keys like the given columns of the input (which it is sorted on),
+/// `None` if the columns do not start with the keys of its side.
+fn sorted_keys(
+    keys: &[(Expr, Expr)],
+    columns: &[usize],
+    fields: &[Field],
+    side: fn(&(Expr, Expr)) -> &Expr,
+) -> Option<Vec<(Expr, Expr)>> {
+    let mut remaining = keys.to_vec();
+    let mut sorted = Vec::new();
+    for &c in columns.iter().take(keys.len()) {
+        let i = remaining
+            .iter()
+            .position(|key| column_index(side(key), fields) == Some(c))?;
+        sorted.push(remaining.remove(i));
+    }
+    if remaining.is_empty() {
+        Some(sorted)
+    } else {
+        None
+    }
+}
+
+/// Builds the operator for a plan whose rows are sorted on the keys,
+/// ascending with NULLs last, sorting them if the plan does not produce them in that order.
+fn build_sorted(plan: &Plan, keys: &[Expr], catalog: &Catalog) -> Box<dyn Operator> {
+    let sorted = ascending_columns(plan);
+    let input = build(plan, catalog);
+    if sorted.len() >= keys.len()
+        && keys
+            .iter()
+            .zip(sorted)
+            .all(|(key, c)| column_index(key, plan.fields()) == Some(c))
+    {
+        return input;
+    }
+    let keys = keys
+        .iter()
+        .map(|key| SortKey {
+            expr: key.clone(),
+            asc: true,
+            nulls_first: false,
+        })
+        .collect();
+    Box::new(Sort::new(input, keys))
+}
+
+/// The keys a window sorts its input on, its partitions ascending with NULLs last
+/// and then the window's order.
+fn window_keys<'a>(
+    partition_by: &'a [Expr],
+    order_by: &'a [SortKey],
+) -> impl Iterator<Item = SortKey> + 'a {
+    partition_by
+        .iter()
+        .map(|expr| SortKey {
+            expr: expr.clone(),
+            asc: true,
+            nulls_first: false,
+        })
+        .chain(order_by.iter().cloned())
+}
+
+/// The columns the rows of a plan are known to be sorted on, and for each of them
+/// whether it is sorted ascending with NULLs last, like merge joins need.
+fn sorted_columns(plan: &Plan) -> Vec<(usize, bool)> {
+    match plan {
+        Plan::Sort { input, keys } => keys
+            .iter()
+            .map_while(|key| {
+                let c = column_index(&key.expr, input.fields())?;
+                Some((c, key.asc && !key.nulls_first))
+            })
+            .collect(),
+        Plan::Window {
+            input,
+            partition_by,
+            order_by,
+            ..
+        } => window_keys(partition_by, order_by)
+            .map_while(|key| {
+                let c = column_index(&key.expr, input.fields())?;
+                Some((c, key.asc && !key.nulls_first))
+            })
+            .collect(),
+        Plan::Filter { input, .. }
+        | Plan::Limit { input, .. }
+        | Plan::Distinct { input }
+        | Plan::Alias { input, .. } => sorted_columns(input),
+        Plan::Project { input, exprs, .. } => sorted_columns(input)
+            .into_iter()
+            .map_while(|(c, ascending)| {
+                let c = exprs
+                    .iter()
+                    .position(|expr| column_index(expr, input.fields()) == Some(c))?;
+                Some((c, ascending))
+            })
+            .collect(),
+        Plan::Scan { .. } | Plan::Values { .. } | Plan::Join { .. } | Plan::Aggregate { .. } => {
+            Vec::new()
+        }
+    }
+}
+
+/// The columns the rows of a plan are sorted on ascending with NULLs last.
+fn ascending_columns(plan: &Plan) -> Vec<usize> {
+    sorted_columns(plan)
+        .into_iter()
+        .map_while(|(c, ascending)| ascending.then_some(c))
+        .collect()
+}
+
+/// Whether rows with equal values of the expressions are next to each other,
+/// because the plan is sorted on their columns in some order.
+fn is_grouped(plan: &Plan, exprs: &[Expr]) -> bool {
+    let sorted: Vec<_> = sorted_columns(plan).into_iter().map(|(c, _)| c).collect();
+    sorted.len() >= exprs.len()
+        && exprs
+            .iter()
+            .all(|expr| match column_index(expr, plan.fields()) {
+                Some(c) => sorted[..exprs.len()].contains(&c),
+                None => false,
+            })
+}
+
+/// Splits a join condition into pairs of expressions on the left and right input
+/// that have to be equal, and the other conjuncts.
+fn equi_join_keys<'a>(
+    condition: &'a Expr,
+    left: &[Field],
+    right: &[Field],
+) -> (Vec<(Expr, Expr)>, Vec<&'a Expr>) {
+    let fields = [left, right].concat();
+    // the input whose columns an expression uses, if it uses columns of only one of them
+    let side = |expr: &Expr| {
+        let columns = column_references(expr)
+            .iter()
+            .map(|name| resolve(&fields, name).ok())
+            .collect::<Option<Vec<_>>>()?;
+        if columns.is_empty() || !referenced_sequences(expr).is_empty() {
+            None
+        } else if columns.iter().all(|&c| c < left.len()) {
+            Some(JoinKind::Left)
+        } else if columns.iter().all(|&c| c >= left.len()) {
+            Some(JoinKind::Right)
+        } else {
+            None
+        }
+    };
+    let mut keys = Vec::new();
+    let mut residual = Vec::new();
+    for conjunct in conjuncts(condition) {
+        if let Expr::BinaryOp {
+            left: a,
+            op: BinaryOperator::Eq,
+            right: b,
+        } = conjunct
+        {
+            match (side(a), side(b)) {
+                (Some(JoinKind::Left), Some(JoinKind::Right)) => {
+                    keys.push(((**a).clone(), (**b).clone()));
+                    continue;
+                }
+                (Some(JoinKind::Right), Some(JoinKind::Left)) => {
+                    keys.push(((**b).clone(), (**a).clone()));
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        residual.push(conjunct);
+    }
+    (keys, residual)
+}
+
+/// Builds a table scan for a scan with a filter and projection of plain columns on top,
+/// `None` if the plan cannot be executed by a scan alone.
+fn build_scan(plan: &Plan) -> Option<TableScan> {
+    match plan {
+        Plan::Scan { table, fields } => Some(TableScan::new(*table, fields.clone())),
+        // predicates using sequences are evaluated in the session by the filter operator
+        Plan::Filter { input, predicate }
+            if matches!(**input, Plan::Scan { .. })
+                && referenced_sequences(predicate).is_empty() =>
+        {
+            build_scan(input).map(|scan| scan.with_predicate(predicate.clone()))
+        }
+        Plan::Project {
+            input,
+            exprs,
+            fields,
+        } => {
+            let scan = build_scan(input)?;
+            let columns = exprs
+                .iter()
+                .map(|expr| column_index(expr, scan.schema()))
+                .collect::<Option<Vec<_>>>()?;
+            Some(scan.with_projection(&columns, fields.clone()))
+        }
+        _ => None,
+    }
+}
+
+/// The field a plain column reference refers to, `None` for other expressions.
+fn column_index(expr: &Expr, fields: &[Field]) -> Option<usize> {
+    match expr {
+        Expr::Identifier(ident) => resolve(fields, std::slice::from_ref(ident)).ok(),
+        Expr::CompoundIdentifier(idents) => resolve(fields, idents).ok(),
+        _ => None,
+    }
+}
+
+/// Runs an operator to completion and returns all of its rows.
+/// The operator is closed even if opening it or producing a row fails.
+pub fn collect(operator: &mut dyn Operator, db: &mut Database) -> Result<Vec<Tuple>, String> {
+    let mut rows = Vec::new();
+    let result = operator.open(db).and_then(|_| loop {
+        match operator.next(db) {
+            Ok(Some(row)) => rows.push(row),
+            Ok(None) => break Ok(()),
+            Err(err) => break Err(err),
+        }
+    });
+    let closed = operator.close(db);
+    result.and(closed).map(|_| rows)
+}
+
+/// Closes the inputs of an operator if opening it failed, so that they keep no pages pinned.
+/// The error of opening is returned, it is more telling than one of closing.
+pub fn close_on_error(
+    result: Result<(), String>,
+    db: &mut Database,
+    inputs: &mut [&mut dyn Operator],
+) -> Result<(), String> {
+    if result.is_err() {
+        for input in inputs {
+            let _ = input.close(db);
+        }
+    }
+    result
+}
+
+/// A row produced by an operator, column references are resolved against its fields.
+pub struct PlanRow<'a> {
+    pub fields: &'a [Field],
+    pub values: &'a [Value],
+}
+
+impl Row for PlanRow<'_> {
+    fn column(&self, name: &[Ident]) -> Result<Value, String> {
+        resolve(self.fields, name).map(|i| self.values[i].clone())
+    }
+}
+
+/// Estimates the number of bytes a row takes up in memory.
+pub fn row_size(row: &[Value]) -> usize {
+    let data: usize = row
+        .iter()
+        .map(|value| match value {
+            Value::Text(s) => s.len(),
+            Value::Bytea(b) => b.len(),
+            _ => 0,
+        })
+        .sum();
+    std::mem::size_of::<Tuple>() + std::mem::size_of_val(row) + data
+}
+
+/// Whether a condition is true for a row, NULL counts as false.
+pub fn is_true(
+    db: &mut Database,
+    condition: &Expr,
+    fields: &[Field],
+    values: &[Value],
+) -> Result<bool, String> {
+    let row = PlanRow { fields, values };
+    Ok(db.eval(condition, &row)?.truth()? == Some(true))
+}
+
+/// Reads the rows of a table in the order of a B+-tree index on it.
+/// Rows with NULL in a key column are not in the index and not returned.
+pub struct IndexScan {
+    index: IndexMetadata,
+    fields: Vec<Field>,
+    table: Option<TableMetadata>,
+    rids: IntoIter<RecordId>,
+}
+
+impl IndexScan {
+    pub fn new(index: IndexMetadata, fields: Vec<Field>) -> Self {
+        IndexScan {
+            index,
+            fields,
+            table: None,
+            rids: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl Operator for IndexScan {
+    fn open(&mut self, db: &mut Database) -> Result<(), String> {
+        let table = match db.catalog().get_table_by_id(self.index.table) {
+            Some(table) => table.clone(),
+            None => return Err(format!("table {} does not exist", self.index.table)),
+        };
+        self.rids = db.index_order(&self.index)?.into_iter();
+        self.table = Some(table);
+        Ok(())
+    }
+
+    fn next(&mut self, db: &mut Database) -> Result<Option<Tuple>, String> {
+        let table = match &self.table {
+            Some(table) => table,
+            None => return Ok(None),
+        };
+        for rid in self.rids.by_ref() {
+            if let Some(row) = db.get_row(table, rid)? {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+
+    fn close(&mut self, _db: &mut Database) -> Result<(), String> {
+        self.table = None;
+        self.rids = Vec::new().into_iter();
+        Ok(())
+    }
+
+    fn schema(&self) -> &[Field] {
+        &self.fields
+    }
+}
+
+/// Reads the rows of a table, one page at a time.
+/// Filters and projections of plain columns can be pushed down into the scan,
+/// so that rows are only partially decoded.
+pub struct TableScan {
+    table: usize,
+    /// The columns of the table.
+    table_fields: Vec<Field>,
+    fields: Vec<Field>,
+    predicate: Option<Expr>,
+    projection: Vec<usize>,
+    scanner: Option<TableScanner>,
+}
+
+impl TableScan {
+    pub fn new(table: usize, fields: Vec<Field>) -> Self {
+        Self {
+            table,
+            table_fields: fields.clone(),
+            projection: (0..fields.len()).collect(),
+            fields,
+            predicate: None,
+            scanner: None,
+        }
+    }
+
+    /// Only returns the rows for which the predicate is true,
+    /// it refers to the columns of the table and must not use sequences.
+    pub fn with_predicate(mut self, predicate: Expr) -> Self {
+        self.predicate = Some(predicate);
+        self
+    }
+
+    /// Only returns the given columns of the current output.
+    pub fn with_projection(mut self, columns: &[usize], fields: Vec<Field>) -> Self {
+        self.projection = columns.iter().map(|&c| self.projection[c]).collect();
+        self.fields = fields;
+        self
+    }
+}
+
+impl Operator for TableScan {
+    fn open(&mut self, db: &mut Database) -> Result<(), String> {
+        let table = match db.catalog().get_table_by_id(self.table) {
+            Some(table) => table,
+            None => return Err(format!("table {} does not exist", self.table)),
+        };
+        let mut scanner = TableScanner::new(table).with_projection(self.projection.clone());
+        if let Some(predicate) = &self.predicate {
+            scanner = scanner.with_predicate(predicate.clone(), self.table_fields.clone())?;
+        }
+        self.scanner = Some(scanner);
+        Ok(())
+    }
+
+    fn next(&mut self, db: &mut Database) -> Result<Option<Tuple>, String> {
+        match &mut self.scanner {
+            Some(scanner) => Ok(scanner.next(db.buffer_manager())?.map(|(_, row)| row)),
+            None => Ok(None),
+        }
+    }
+
+    fn close(&mut self, db: &mut Database) -> Result<(), String> {
+        if let Some(mut scanner) = self.scanner.take() {
+            scanner.close(db.buffer_manager());
+        }
+        Ok(())
+    }
+
+    fn schema(&self) -> &[Field] {
+        &self.fields
+    }
+}
+
+/// Evaluates rows of constant expressions.
+pub struct Values {
+    rows: Vec<Vec<Expr>>,
+    fields: Vec<Field>,
+    position: usize,
+}
+
+impl Values {
+    pub fn new(rows: Vec<Vec<Expr>>, fields: Vec<Field>) -> Self {
+        Self {
+            rows,
+            fields,
+            position: 0,
+        }
+    }
+}
+
+impl Operator for Values {
+    fn open(&mut self, _db: &mut Database) -> Result<(), String> {
+        self.position = 0;
+        Ok(())
+    }
+
+    fn next(&mut self, db: &mut Database) -> Result<Option<Tuple>, String> {
+        let exprs = match self.rows.get(self.position) {
+            Some(exprs) => exprs,
+            None => return Ok(None),
+        };
+        self.position += 1;
+        let row = exprs
+            .iter()
+            .map(|expr| db.eval(expr, &NoRow))
+            .collect::<Result<_, _>>()?;
+        Ok(Some(row))
+    }
+
+    fn close(&mut self, _db: &mut Database) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn schema(&self) -> &[Field] {
+        &self.fields
+    }
+}
+
+/// Passes on the rows of its input for which the predicate is true.
+pub struct Filter {
+    input: Box<dyn Operator>,
+    predicate: Expr,
+}
+
+impl Filter {
+    pub fn new(input: Box<dyn Operator>, predicate: Expr) -> Self {
+        Self { input, predicate }
+    }
+}
+
+impl Operator for Filter {
+    fn open(&mut self, db: &mut Database) -> Result<(), String> {
+        self.input.open(db)
+    }
+
+    fn next(&mut self, db: &mut Database) -> Result<Option<Tuple>, String> {
+        while let Some(row) = self.input.next(db)? {
+            if is_true(db, &self.predicate, self.input.schema(), &row)? {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+
+    fn close(&mut self, db: &mut Database) -> Result<(), String> {
+        self.input.close(db)
+    }
+
+    fn schema(&self) -> &[Field] {
+        self.input.schema()
+    }
+}
+
+/// Evaluates expressions over the rows of its input.
+pub struct Project {
+    input: Box<dyn Operator>,
+    exprs: Vec<Expr>,
+    fields: Vec<Field>,
+}
+
+impl Project {
+    pub fn new(input: Box<dyn Operator>, exprs: Vec<Expr>, fields: Vec<Field>) -> Self {
+        Self {
+            input,
+            exprs,
+            fields,
+        }
+    }
+}
+
+impl Operator for Project {
+    fn open(&mut self, db: &mut Database) -> Result<(), String> {
+        self.input.open(db)
+    }
+
+    fn next(&mut self, db: &mut Database) -> Result<Option<Tuple>, String> {
+        let values = match self.input.next(db)? {
+            Some(values) => values,
+            None => return Ok(None),
+        };
+        let row = PlanRow {
+            fields: self.input.schema(),
+            values: &values,
+        };
+        let row = self
+            .exprs
+            .iter()
+            .map(|expr| db.eval(expr, &row))
+            .collect::<Result<_, _>>()?;
+        Ok(Some(row))
+    }
+
+    fn close(&mut self, db: &mut Database) -> Result<(), String> {
+        self.input.close(db)
+    }
+
+    fn schema(&self) -> &[Field] {
+        &self.fields
+    }
+}
+
+/// Gives the rows of its input new field names, dropping the columns without one.
+pub struct Rename {
+    input: Box<dyn Operator>,
+    fields: Vec<Field>,
+}
+
+impl Rename {
+    pub fn new(input: Box<dyn Operator>, fields: Vec<Field>) -> Self {
+        Self { input, fields }
+    }
+}
+
+impl Operator for Rename {
+    fn open(&mut self, db: &mut Database) -> Result<(), String> {
+        self.input.open(db)
+    }
+
+    fn next(&mut self, db: &mut Database) -> Result<Option<Tuple>, String> {
+        Ok(self.input.next(db)?.map(|mut row| {
+            row.truncate(self.fields.len());
+            row
+        }))
+    }
+
+    fn close(&mut self, db: &mut Database) -> Result<(), String> {
+        self.input.close(db)
+    }
+
+    fn schema(&self) -> &[Field] {
+        &self.fields
+    }
+}
+
+/// Passes on at most `limit` rows of its input, without pulling any further rows.
+pub struct Limit {
+    input: Box<dyn Operator>,
+    limit: usize,
+    returned: usize,
+}
+
+impl Limit {
+    pub fn new(input: Box<dyn Operator>, limit: usize) -> Self {
+        Self {
+            input,
+            limit,
+            returned: 0,
+        }
+    }
+}
+
+impl Operator for Limit {
+    fn open(&mut self, db: &mut Database) -> Result<(), String> {
+        self.returned = 0;
+        self.input.open(db)
+    }
+
+    fn next(&mut self, db: &mut Database) -> Result<Option<Tuple>, String> {
+        if self.returned >= self.limit {
+            return Ok(None);
+        }
+        let row = self.input.next(db)?;
+        if row.is_some() {
+            self.returned += 1;
+        }
+        Ok(row)
+    }
+
+    fn close(&mut self, db: &mut Database) -> Result<(), String> {
+        self.input.close(db)
+    }
+
+    fn schema(&self) -> &[Field] {
+        self.input.schema()
+    }
+}
+
+/// Skips the first `offset` rows of its input and passes on the following ones.
+pub struct Offset {
+    input: Box<dyn Operator>,
+    offset: usize,
+    skipped: bool,
+}
+
+impl Offset {
+    pub fn new(input: Box<dyn Operator>, offset: usize) -> Self {
+        Self {
+            input,
+            offset,
+            skipped: false,
+        }
+    }
+}
+
+impl Operator for Offset {
+    fn open(&mut self, db: &mut Database) -> Result<(), String> {
+        self.skipped = false;
+        self.input.open(db)
+    }
+
+    fn next(&mut self, db: &mut Database) -> Result<Option<Tuple>, String> {
+        if !self.skipped {
+            self.skipped = true;
+            for _ in 0..self.offset {
+                if self.input.next(db)?.is_none() {
+                    return Ok(None);
+                }
+            }
+        }
+        self.input.next(db)
+    }
+
+    fn close(&mut self, db: &mut Database) -> Result<(), String> {
+        self.input.close(db)
+    }
+
+    fn schema(&self) -> &[Field] {
+        self.input.schema()
+    }
+}
+
+/// Largest number of rows kept by a top-N sort, larger limits are sorted externally.
+pub const MAX_TOP_N: usize = 10_000;
+
+/// Returns the first `limit` rows of its input in the order of the sort keys,
+/// like a stable sort followed by a limit.
+///
+/// The rows are kept in a heap bounded to `limit` rows with the greatest of them on top,
+/// which is replaced by any smaller row of the input, so memory does not grow with the input.
+pub struct TopN {
+    input: Box<dyn Operator>,
+    keys: Vec<SortKey>,
+    limit: usize,
+    /// Rows with their encoded keys and their position in the input, which breaks ties.
+    heap: Vec<(Vec<u8>, usize, Tuple)>,
+    rows: IntoIter<(Vec<u8>, usize, Tuple)>,
+}
+
+impl TopN {
+    pub fn new(input: Box<dyn Operator>, keys: Vec<SortKey>, limit: usize) -> Self {
+        Self {
+            input,
+            keys,
+            limit,
+            heap: Vec::new(),
+            rows: Vec::new().into_iter(),
+        }
+    }
+
+    fn greater(&self, i: usize, j: usize) -> bool {
+        let (a, b) = (&self.heap[i], &self.heap[j]);
+        (&a.0, a.1) > (&b.0, b.1)
+    }
+
+    fn sift_up(&mut self, mut i: usize) {
+        while i > 0 && self.greater(i, (i - 1) / 2) {
+            self.heap.swap(i, (i - 1) / 2);
+            i = (i - 1) / 2;
+        }
+    }
+
+    fn sift_down(&mut self, mut i: usize) {
+        loop {
+            let mut largest = i;
+            for child in [2 * i + 1, 2 * i + 2] {
+                if child < self.heap.len() && self.greater(child, largest) {
+                    largest = child;
+                }
+            }
+            if largest == i {
+                return;
+            }
+            self.heap.swap(i, largest);
+            i = largest;
+        }
+    }
+
+    /// Keeps the first rows of the opened input in the heap and sorts them.
+    fn read_input(&mut self, db: &mut Database) -> Result<(), String> {
+        let mut position = 0;
+        while self.limit > 0 {
+            let row = match self.input.next(db)? {
+                Some(row) => row,
+                None => break,
+            };
+            let key = sort_key(db, &self.keys, self.input.schema(), &row)?;
+            self.heap.push((key, position, row));
+            position += 1;
+            if self.heap.len() <= self.limit {
+                let last = self.heap.len() - 1;
+                self.sift_up(last);
+            } else {
+                // the new row replaces the greatest one if it is smaller
+                let row = self.heap.pop().unwrap();
+                if row.0 < self.heap[0].0 {
+                    self.heap[0] = row;
+                    self.sift_down(0);
+                }
+            }
+        }
+        let mut rows = std::mem::take(&mut self.heap);
+        rows.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+        self.rows = rows.into_iter();
+        Ok(())
+    }
+}
+
+impl Operator for TopN {
+    fn open(&mut self, db: &mut Database) -> Result<(), String> {
+        self.heap.clear();
+        self.input.open(db)?;
+        let result = self.read_input(db);
+        close_on_error(result, db, &mut [self.input.as_mut()])
+    }
+
+    fn next(&mut self, _db: &mut Database) -> Result<Option<Tuple>, String> {
+        Ok(self.rows.next().map(|(_, _, row)| row))
+    }
+
+    fn close(&mut self, db: &mut Database) -> Result<(), String> {
+        self.heap.clear();
+        self.rows = Vec::new().into_iter();
+        self.input.close(db)
+    }
+
+    fn schema(&self) -> &[Field] {
+        self.input.schema()
+    }
+}
+
+/// Passes on the first of each group of equal rows of its input.
+pub struct Distinct {
+    input: Box<dyn Operator>,
+    seen: HashSet<Tuple>,
+}
+
+impl Distinct {
+    pub fn new(input: Box<dyn Operator>) -> Self {
+        Self {
+            input,
+            seen: HashSet::new(),
+        }
+    }
+}
+
+impl Operator for Distinct {
+    fn open(&mut self, db: &mut Database) -> Result<(), String> {
+        self.seen.clear();
+        self.input.open(db)
+    }
+
+    fn next(&mut self, db: &mut Database) -> Result<Option<Tuple>, String> {
+        while let Some(row) = self.input.next(db)? {
+            if !self.seen.contains(&row) {
+                self.seen.insert(row.clone());
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+
+    fn close(&mut self, db: &mut Database) -> Result<(), String> {
+        self.seen.clear();
+        self.input.close(db)
+    }
+
+    fn schema(&self) -> &[Field] {
+        self.input.schema()
+    }
+}
+
+/// A database in a temporary file with a name unique to the test and process,
+/// the file is deleted when the database is dropped.
+#[cfg(test)]
+pub struct TempDatabase {
+    db: Database,
+    _file: crate::disk_manager::TempFile,
+}
+
+#[cfg(test)]
+impl TempDatabase {
+    pub fn open(name: &str) -> Self {
+        let file = crate::disk_manager::TempFile::new(name);
+        let db = Database::open(file.path()).unwrap();
+        Self { db, _file: file }
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDatabase {
+    type Target = Database;
+
+    fn deref(&self) -> &Database {
+        &self.db
+    }
+}
+
+#[cfg(test)]
+impl std::ops::DerefMut for TempDatabase {
+    fn deref_mut(&mut self) -> &mut Database {
+        &mut self.db
+    }
+}
+
+/// Literal rows of nullable integers, their columns are named a, b, c, ... of the given table.
+#[cfg(test)]
+pub fn values<R: AsRef<[Option<i64>]>>(table: &str, rows: &[R]) -> Box<dyn Operator> {
+    use crate::sql::parse_expr;
+
+    let literal = |v: &Option<i64>| match v {
+        Some(v) => parse_expr(&v.to_string()).unwrap(),
+        None => parse_expr("NULL").unwrap(),
+    };
+    let width = rows.first().map_or(0, |row| row.as_ref().len());
+    let fields = (b'a'..)
+        .take(width)
+        .map(|name| Field::new(Some(table), &char::from(name).to_string()))
+        .collect();
+    let rows = rows
+        .iter()
+        .map(|row| row.as_ref().iter().map(literal).collect())
+        .collect();
+    Box::new(Values::new(rows, fields))
+}
+
+/// Checks a join operator of the `values` tables l and r for every join kind against
+/// a nested loop join on equal `keys` columns and the `residual` condition.
+/// `join` builds the operator from the kind, both inputs and the output fields,
+/// `counts` are the numbers of rows of the inner, left, right, full, semi and anti join.
+#[cfg(test)]
+pub fn check_join_kinds<L, R, O, F>(
+    db: &mut Database,
+    left: &[L],
+    right: &[R],
+    keys: &[&str],
+    residual: &str,
+    counts: [usize; 6],
+    join: F,
+) where
+    L: AsRef<[Option<i64>]>,
+    R: AsRef<[Option<i64>]>,
+    O: Operator,
+    F: Fn(JoinKind, Box<dyn Operator>, Box<dyn Operator>, Vec<Field>) -> O,
+{
+    let condition = keys
+        .iter()
+        .map(|key| format!("l.{0} = r.{0}", key))
+        .chain(std::iter::once(residual.to_owned()))
+        .collect::<Vec<_>>()
+        .join(" AND ");
+    let kinds = [
+        JoinKind::Inner,
+        JoinKind::Left,
+        JoinKind::Right,
+        JoinKind::Full,
+        JoinKind::Semi,
+        JoinKind::Anti,
+    ];
+    for (&kind, &count) in kinds.iter().zip(&counts) {
+        let (l, r) = (values("l", left), values("r", right));
+        let fields = match kind {
+            JoinKind::Semi | JoinKind::Anti => l.schema().to_vec(),
+            _ => [l.schema(), r.schema()].concat(),
+        };
+        let mut operator = join(kind, l, r, fields.clone());
+        let mut expected = NestedLoopJoin::new(
+            values("l", left),
+            values("r", right),
+            kind,
+            Some(crate::sql::parse_expr(&condition).unwrap()),
+            fields,
+        );
+        let mut rows = collect(&mut operator, db).unwrap();
+        let mut expected = collect(&mut expected, db).unwrap();
+        rows.sort();
+        expected.sort();
+        assert_eq!(rows, expected, "{:?}", kind);
+        assert_eq!(rows.len(), count, "{:?}", kind);
+        // the operator can be opened again
+        assert_eq!(
+            collect(&mut operator, db).unwrap().len(),
+            count,
+            "{:?}",
+            kind
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::binder::Binder;
+    use crate::sql::{parse_expr, parse_query, parse_sql};
+
+    fn open(name: &str) -> TempDatabase {
+        TempDatabase::open(&format!("executor_{}", name))
+    }
+
+    fn query(db: &mut Database, sql: &str) -> Vec<Vec<String>> {
+        let result = db.query(&parse_query(sql).unwrap()).unwrap();
+        result
+            .rows
+            .iter()
+            .map(|row| row.iter().map(|v| v.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn operators() {
+        let mut db = open("operators");
+        let exprs = |row: &[&str]| row.iter().map(|e| parse_expr(e).unwrap()).collect();
+        let rows = vec![
+            exprs(&["2", "'b'"]),
+            exprs(&["NULL", "'a'"]),
+            exprs(&["1", "'b'"]),
+        ];
+        let fields = vec![Field::new(None, "x"), Field::new(None, "y")];
+        let values = Box::new(Values::new(rows, fields));
+        let key = |expr: &str, asc, nulls_first| SortKey {
+            expr: parse_expr(expr).unwrap(),
+            asc,
+            nulls_first,
+        };
+        let sort = Sort::new(values, vec![key("y", true, false), key("x", false, true)]);
+        let mut limit = Limit::new(Box::new(Offset::new(Box::new(sort), 1)), 2);
+        let rows = collect(&mut limit, &mut db).unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                vec![Value::BigInt(2), Value::Text("b".to_owned())],
+                vec![Value::BigInt(1), Value::Text("b".to_owned())],
+            ]
+        );
+
+        let project = Project::new(
+            Box::new(limit),
+            vec![parse_expr("y").unwrap()],
+            vec![Field::new(None, "y")],
+        );
+        let mut distinct = Distinct::new(Box::new(project));
+        assert_eq!(collect(&mut distinct, &mut db).unwrap().len(), 1);
+        assert_eq!(collect(&mut distinct, &mut db).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn top_n() {
+        let mut db = open("top_n");
+        let rows = |n: i64| {
+            let rows = (0..n)
+                .map(|i| {
+                    let a = if i % 11 == 0 {
+                        "NULL".to_owned()
+                    } else {
+                        (i * 37 % 7).to_string()
+                    };
+                    vec![parse_expr(&a).unwrap(), parse_expr(&i.to_string()).unwrap()]
+                })
+                .collect();
+            let fields = vec![Field::new(None, "a"), Field::new(None, "b")];
+            Box::new(Values::new(rows, fields))
+        };
+        let keys = vec![SortKey {
+            expr: parse_expr("a").unwrap(),
+            asc: false,
+            nulls_first: false,
+        }];
+        for limit in [0, 1, 5, 50, 500] {
+            let mut top = TopN::new(rows(300), keys.clone(), limit);
+            let mut expected = Limit::new(Box::new(Sort::new(rows(300), keys.clone())), limit);
+            let rows = collect(&mut top, &mut db).unwrap();
+            assert_eq!(rows, collect(&mut expected, &mut db).unwrap());
+            assert_eq!(rows.len(), limit.min(300));
+        }
+
+        // rows after the limit are not pulled, so they are never evaluated
+        let exprs = |row: &[&str]| row.iter().map(|e| parse_expr(e).unwrap()).collect();
+        let values = || {
+            let rows = vec![exprs(&["1"]), exprs(&["2"]), exprs(&["1 / 0"])];
+            Box::new(Values::new(rows, vec![Field::new(None, "x")]))
+        };
+        let mut limit = Limit::new(Box::new(Offset::new(values(), 1)), 1);
+        assert_eq!(
+            collect(&mut limit, &mut db).unwrap(),
+            vec![vec![Value::BigInt(2)]]
+        );
+        assert!(collect(&mut Offset::new(values(), 1), &mut db).is_err());
+        assert!(collect(&mut Offset::new(values(), 5), &mut db).is_err());
+        assert!(collect(&mut Limit::new(values(), 0), &mut db)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn failed_queries_unpin_pages() {
+        let mut db = open("failed_queries");
+        let values: Vec<_> = (0..100).map(|i| format!("({})", i)).collect();
+        for sql in [
+            "CREATE TABLE t (a INT)".to_owned(),
+            format!("INSERT INTO t VALUES {}", values.join(", ")),
+        ] {
+            db.execute(&parse_sql(&sql).unwrap()[0]).unwrap();
+        }
+        assert_eq!(db.buffer_manager().pages_pinned(), 0);
+        // each query fails while opening an operator, after the scan below it pinned a page
+        for sql in [
+            "SELECT a FROM t ORDER BY 1 / (a - 50)",
+            "SELECT a FROM t ORDER BY 1 / (a - 50) LIMIT 3",
+            "SELECT sum(1 / (a - 50)) FROM t GROUP BY a % 7",
+            "SELECT * FROM t x JOIN t y ON x.a = 1 / (y.a - 50)",
+            "SELECT * FROM t x JOIN (SELECT a FROM t ORDER BY 1 / (a - 50)) y ON x.a = y.a",
+            "SELECT a / (a - 50) FROM t",
+        ] {
+            assert!(db.query(&parse_query(sql).unwrap()).is_err(), "{}", sql);
+            assert_eq!(db.buffer_manager().pages_pinned(), 0, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn estimates() {
+        let mut db = open("estimates");
+        let values: Vec<_> = (0..1000).map(|i| format!("({}, {})", i % 10, i)).collect();
+        for sql in [
+            "CREATE TABLE s (a INT, b INT)".to_owned(),
+            "CREATE TABLE t (a INT PRIMARY KEY)".to_owned(),
+            format!("INSERT INTO s VALUES {}", values.join(", ")),
+            "INSERT INTO t VALUES (1), (2), (3)".to_owned(),
+        ] {
+            db.execute(&parse_sql(&sql).unwrap()[0]).unwrap();
+        }
+        let plan = |db: &Database, sql: &str| {
+            let query = parse_query(sql).unwrap();
+            Binder::new(db.catalog()).bind_query(&query).unwrap()
+        };
+        // the join below the projection
+        let index_join = |db: &Database, sql: &str| match plan(db, sql) {
+            Plan::Project { input, .. } => build_index_join(&input, db.catalog()).is_some(),
+            other => panic!("not a projection: {:?}", other),
+        };
+        let join = "SELECT * FROM s JOIN t ON s.a = t.a";
+        let filtered =
+            "SELECT * FROM (SELECT * FROM s WHERE b = 7 AND a = 7) x JOIN t ON x.a = t.a";
+        assert!(index_join(&db, join));
+
+        db.execute(&parse_sql("ANALYZE").unwrap()[0]).unwrap();
+        let scan = plan(&db, "SELECT * FROM s WHERE a = 3 AND b = b");
+        let estimate = |plan: &Plan| match plan {
+            Plan::Project { input, .. } => estimated_rows(input, db.catalog()).unwrap(),
+            _ => panic!("not a projection: {:?}", plan),
+        };
+        assert!((estimate(&scan) - 100.0).abs() < 1e-6);
+        assert!(estimate(&plan(&db, "SELECT * FROM s WHERE b = 7 AND a = 7")) < 1.0);
+        assert_eq!(
+            estimate(&plan(&db, "SELECT * FROM s WHERE a IS NULL")),
+            1000.0
+        );
+        // many rows of s are joined by scanning t once, few rows are looked up in its index
+        assert!(!index_join(&db, join));
+        assert!(index_join(&db, filtered));
+        assert_eq!(query(&mut db, join).len(), 300);
+    }
+
+    #[test]
+    fn index_order() {
+        let mut db = open("index_order");
+        for sql in [
+            "CREATE TABLE t (a INT, b TEXT)",
+            "CREATE TABLE u (a INT, c INT)",
+            "INSERT INTO t VALUES (2, 'y'), (NULL, 'n'), (1, 'x'), (3, 'z')",
+            "INSERT INTO u VALUES (3, 30), (1, 10), (4, 40), (1, 11)",
+            "CREATE INDEX t_a ON t (a)",
+        ] {
+            db.execute(&parse_sql(sql).unwrap()[0]).unwrap();
+        }
+        let merge_join = |db: &Database, sql: &str| {
+            let query = parse_query(sql).unwrap();
+            match Binder::new(db.catalog()).bind_query(&query).unwrap() {
+                Plan::Project { input, .. } => build_merge_join(&input, db.catalog()).is_some(),
+                other => panic!("not a projection: {:?}", other),
+            }
+        };
+        // t is read in the order of its index, its row with a NULL key is never padded
+        let right = "SELECT t.a, t.b, u.c FROM t RIGHT JOIN u ON t.a = u.a";
+        assert!(merge_join(&db, right));
+        assert!(merge_join(&db, "SELECT * FROM u JOIN t ON u.a = t.a"));
+        assert!(!merge_join(&db, "SELECT * FROM t LEFT JOIN u ON t.a = u.a"));
+        assert!(!merge_join(&db, "SELECT * FROM t FULL JOIN u ON t.a = u.a"));
+        assert_eq!(
+            query(&mut db, right),
+            vec![
+                vec!["1", "x", "10"],
+                vec!["1", "x", "11"],
+                vec!["3", "z", "30"],
+                vec!["NULL", "NULL", "40"],
+            ]
+        );
+    }
+
+    #[test]
+    fn subqueries() {
+        let mut db = open("subqueries");
+        for sql in [
+            "CREATE TABLE t (a INT, b TEXT)",
+            "CREATE TABLE u (a INT, c INT)",
+            "INSERT INTO t VALUES (1, 'x'), (2, 'y'), (3, NULL), (NULL, 'z')",
+            "INSERT INTO u VALUES (1, 10), (1, 11), (3, 30)",
+        ] {
+            db.execute(&parse_sql(sql).unwrap()[0]).unwrap();
+        }
+        let cases = [
+            (
+                "SELECT b FROM t WHERE EXISTS (SELECT * FROM u WHERE u.a = t.a AND c > 10)",
+                vec!["x", "NULL"],
+            ),
+            (
+                "SELECT b FROM t WHERE NOT EXISTS (SELECT * FROM u WHERE u.a = t.a)",
+                vec!["y", "z"],
+            ),
+            (
+                "SELECT b FROM t WHERE a IN (SELECT a FROM u)",
+                vec!["x", "NULL"],
+            ),
+            (
+                "SELECT b FROM t WHERE a NOT IN (SELECT a FROM u WHERE c < 30)",
+                vec!["y", "NULL"],
+            ),
+            // the subquery has a NULL, so NOT IN is never true
+            (
+                "SELECT b FROM t WHERE a NOT IN (SELECT a FROM t WHERE b = 'z')",
+                vec![],
+            ),
+            // an empty subquery makes NOT IN true even for NULL
+            (
+                "SELECT b FROM t WHERE a NOT IN (SELECT a FROM u WHERE c > 100)",
+                vec!["x", "y", "NULL", "z"],
+            ),
+            (
+                "SELECT b FROM t WHERE a <> 1 AND a IN (SELECT max(a) FROM u GROUP BY c)",
+                vec!["NULL"],
+            ),
+        ];
+        for (sql, expected) in cases {
+            let mut rows: Vec<_> = query(&mut db, sql).into_iter().flatten().collect();
+            let mut expected: Vec<_> = expected.iter().map(|v| v.to_string()).collect();
+            rows.sort();
+            expected.sort();
+            assert_eq!(rows, expected, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn queries() {
+        let mut db = open("queries");
+        for sql in [
+            "CREATE TABLE t (a INT, b TEXT)",
+            "CREATE TABLE u (a INT, c INT)",
+            "INSERT INTO t VALUES (1, 'x'), (2, 'y'), (3, NULL)",
+            "INSERT INTO u VALUES (1, 10), (1, 11), (3, 30)",
+            "CREATE VIEW v AS SELECT a, b FROM t WHERE a > 1",
+        ] {
+            db.execute(&parse_sql(sql).unwrap()[0]).unwrap();
+        }
+        assert_eq!(
+            query(&mut db, "SELECT a * 2, upper(b) FROM t WHERE b IS NOT NULL"),
+            vec![vec!["2", "X"], vec!["4", "Y"]]
+        );
+        assert_eq!(
+            query(&mut db, "SELECT b FROM t ORDER BY b NULLS FIRST LIMIT 2"),
+            vec![vec!["NULL"], vec!["x"]]
+        );
+        assert_eq!(
+            query(&mut db, "SELECT c FROM u ORDER BY c DESC LIMIT 1 OFFSET 1"),
+            vec![vec!["11"]]
+        );
+        assert_eq!(
+            query(
+                &mut db,
+                "SELECT a, c FROM t JOIN u USING (a) ORDER BY c DESC"
+            ),
+            vec![vec!["3", "30"], vec!["1", "11"], vec!["1", "10"]]
+        );
+        assert_eq!(
+            query(
+                &mut db,
+                "SELECT t.a, u.c FROM t LEFT JOIN u ON t.a = u.a AND u.c > 10 ORDER BY 1, 2"
+            ),
+            vec![vec!["1", "11"], vec!["2", "NULL"], vec!["3", "30"]]
+        );
+        assert_eq!(
+            query(&mut db, "SELECT DISTINCT a FROM u ORDER BY a"),
+            vec![vec!["1"], vec!["3"]]
+        );
+        assert_eq!(
+            query(
+                &mut db,
+                "WITH w AS (SELECT a FROM v) SELECT count, a FROM w, (SELECT 1 AS count) c"
+            ),
+            vec![vec!["1", "2"], vec!["1", "3"]]
+        );
+        assert_eq!(
+            db.execute(&parse_sql("SELECT * FROM u OFFSET 1").unwrap()[0]),
+            Ok("SELECT 2".to_owned())
+        );
+        assert!(db.execute(&parse_sql("SELECT 1 / 0").unwrap()[0]).is_err());
+
+        assert_eq!(
+            query(
+                &mut db,
+                "SELECT t.a, u.c FROM t RIGHT JOIN u ON t.a = u.a AND u.c > 10 ORDER BY 2"
+            ),
+            vec![vec!["NULL", "10"], vec!["1", "11"], vec!["3", "30"]]
+        );
+        assert_eq!(
+            query(
+                &mut db,
+                "SELECT t.b, u.c FROM t FULL JOIN u ON u.a + 1 = t.a ORDER BY 1, 2"
+            ),
+            vec![
+                vec!["x", "NULL"],
+                vec!["y", "10"],
+                vec!["y", "11"],
+                vec!["NULL", "30"],
+                vec!["NULL", "NULL"],
+            ]
+        );
+
+        assert_eq!(
+            query(
+                &mut db,
+                "SELECT a, count(*), sum(c), avg(c), min(c), max(c) FROM u GROUP BY a ORDER BY a"
+            ),
+            vec![
+                vec!["1", "2", "21", "10.5", "10", "11"],
+                vec!["3", "1", "30", "30", "30", "30"],
+            ]
+        );
+        assert_eq!(
+            query(
+                &mut db,
+                "SELECT count(*), count(b), count(DISTINCT a % 2) FROM t"
+            ),
+            vec![vec!["3", "2", "2"]]
+        );
+        assert_eq!(
+            query(&mut db, "SELECT count(*), max(a) FROM t WHERE a > 5"),
+            vec![vec!["0", "NULL"]]
+        );
+        assert_eq!(
+            query(
+                &mut db,
+                "SELECT t.b, count(u.c) FROM t LEFT JOIN u ON t.a = u.a \
+                 GROUP BY t.b HAVING count(u.c) < 2 ORDER BY 1"
+            ),
+            vec![vec!["y", "0"], vec!["NULL", "1"]]
+        );
+        // the groups of a sorted input are aggregated one after another, in order
+        assert_eq!(
+            query(
+                &mut db,
+                "SELECT a, sum(c) FROM (SELECT a, c FROM u ORDER BY a DESC) s GROUP BY a"
+            ),
+            vec![vec!["3", "30"], vec!["1", "21"]]
+        );
+
+        // window functions over partitions of the joined rows, in the window's order
+        assert_eq!(
+            query(
+                &mut db,
+                "SELECT a, c, row_number() OVER (PARTITION BY a ORDER BY c DESC), \
+                 rank() OVER (ORDER BY a), lag(c) OVER (PARTITION BY a ORDER BY c DESC), \
+                 lead(c, 1, 0) OVER (ORDER BY c) FROM u ORDER BY c"
+            ),
+            vec![
+                vec!["1", "10", "2", "1", "11", "11"],
+                vec!["1", "11", "1", "1", "NULL", "30"],
+                vec!["3", "30", "1", "3", "NULL", "0"],
+            ]
+        );
+        assert_eq!(
+            query(
+                &mut db,
+                "SELECT c, sum(c) OVER (ORDER BY c ROWS BETWEEN 1 PRECEDING AND CURRENT ROW), \
+                 count(*) OVER (PARTITION BY a), max(c) OVER (ORDER BY a) FROM u ORDER BY c"
+            ),
+            vec![
+                vec!["10", "10", "2", "11"],
+                vec!["11", "21", "2", "11"],
+                vec!["30", "41", "1", "30"],
+            ]
+        );
+
+        // a sorted input is merged with the other one, producing rows in key order
+        assert_eq!(
+            query(
+                &mut db,
+                "SELECT x.a, u.c FROM (SELECT a, b FROM t ORDER BY a) x FULL JOIN u ON u.a = x.a"
+            ),
+            vec![
+                vec!["1", "10"],
+                vec!["1", "11"],
+                vec!["2", "NULL"],
+                vec!["3", "30"],
+            ]
+        );
+        assert_eq!(
+            query(
+                &mut db,
+                "SELECT * FROM (SELECT c, a FROM u ORDER BY a, c) x JOIN t ON x.a = t.a AND c > 10"
+            ),
+            vec![vec!["11", "1", "1", "x"], vec!["30", "3", "3", "NULL"]]
+        );
+
+        // the same joins probing an index of the right table
+        db.execute(&parse_sql("CREATE INDEX u_a ON u (a)").unwrap()[0])
+            .unwrap();
+        assert_eq!(
+            query(
+                &mut db,
+                "SELECT a, c FROM t JOIN u USING (a) ORDER BY c DESC"
+            ),
+            vec![vec!["3", "30"], vec!["1", "11"], vec!["1", "10"]]
+        );
+        assert_eq!(
+            query(
+                &mut db,
+                "SELECT t.a, u.c FROM t LEFT JOIN u ON t.a = u.a AND u.c > 10 ORDER BY 1, 2"
+            ),
+            vec![vec!["1", "11"], vec!["2", "NULL"], vec!["3", "30"]]
+        );
+
+        // filters and projections are pushed into the scan, pages are unpinned by close
+        let pages_free = db.buffer_manager().pages_free();
+        assert_eq!(
+            query(&mut db, "SELECT b, t.a FROM t WHERE a % 2 = 1 LIMIT 1"),
+            vec![vec!["x", "1"]]
+        );
+        assert_eq!(db.buffer_manager().pages_free(), pages_free);
+        db.execute(&parse_sql("CREATE SEQUENCE s").unwrap()[0])
+            .unwrap();
+        assert_eq!(
+            query(&mut db, "SELECT a FROM t WHERE a = nextval('s')"),
+            vec![vec!["1"], vec!["2"], vec!["3"]]
+        );
+        assert!(db
+            .execute(&parse_sql("SELECT d FROM t").unwrap()[0])
+            .is_err());
+        assert!(db
+            .execute(&parse_sql("SELECT a FROM t WHERE d").unwrap()[0])
+            .is_err());
+    }
+}
@@ -0,0 +1,532 @@
+// Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::cmp::Ordering;
+
+use sqlparser::ast::{self, BinaryOperator, Expr, FunctionArg, Ident, UnaryOperator};
+
+use crate::catalog::{DataType, Schema};
+use crate::sql::{ident_name, sequence_name};
+use crate::value::{Collation, Value};
+
+/// Provides the values of column references while evaluating an expression.
+pub trait Row {
+    /// Returns the value of a possibly qualified column.
+    fn column(&self, name: &[Ident]) -> Result<Value, String>;
+
+    /// Calls a function that is not built in, like `nextval` which needs the database.
+    /// Returns `None` if the function does not exist.
+    fn call(&self, _name: &str, _args: &[Value]) -> Option<Result<Value, String>> {
+        None
+    }
+}
+
+/// A row that has no columns, for evaluating constant expressions like defaults.
+pub struct NoRow;
+
+impl Row for NoRow {
+    fn column(&self, name: &[Ident]) -> Result<Value, String> {
+        Err(format!(
+            "cannot use column reference \"{}\" here",
+            display_name(name)
+        ))
+    }
+}
+
+/// A row of a single table, columns are referenced by name, optionally qualified with the table name.
+pub struct TableRow<'a> {
+    pub table: &'a str,
+    pub schema: &'a Schema,
+    pub values: &'a [Value],
+}
+
+impl Row for TableRow<'_> {
+    fn column(&self, name: &[Ident]) -> Result<Value, String> {
+        let column = match name {
+            [column] => column,
+            [table, column] if ident_name(table) == self.table => column,
+            _ => {
+                return Err(format!(
+                    "missing FROM-clause entry for \"{}\"",
+                    display_name(name)
+                ))
+            }
+        };
+        match self.schema.index_of(&ident_name(column)) {
+            Some(i) => Ok(self.values[i].clone()),
+            None => Err(format!("column \"{}\" does not exist", display_name(name))),
+        }
+    }
+}
+
+/// Evaluates an expression that does not reference any columns.
+pub fn evaluate_constant(expr: &Expr) -> Result<Value, String> {
+    evaluate(expr, &NoRow)
+}
+
+/// Evaluates a scalar expression for the given row.
+pub fn evaluate(expr: &Expr, row: &dyn Row) -> Result<Value, String> {
+    match expr {
+        Expr::Identifier(ident) => row.column(std::slice::from_ref(ident)),
+        Expr::CompoundIdentifier(idents) => row.column(idents),
+        Expr::Value(literal) => Value::from_literal(literal),
+        Expr::TypedString { data_type, value } => {
+            Value::Text(value.clone()).cast(DataType::from_sql(data_type)?)
+        }
+        Expr::Nested(expr) => evaluate(expr, row),
+        Expr::IsNull(expr) => Ok(Value::Boolean(evaluate(expr, row)?.is_null())),
+        Expr::IsNotNull(expr) => Ok(Value::Boolean(!evaluate(expr, row)?.is_null())),
+        Expr::Cast { expr, data_type } => evaluate(expr, row)?.cast(DataType::from_sql(data_type)?),
+        Expr::TryCast { expr, data_type } => {
+            let data_type = DataType::from_sql(data_type)?;
+            Ok(evaluate(expr, row)?.cast(data_type).unwrap_or(Value::Null))
+        }
+        Expr::UnaryOp { op, expr } => {
+            let value = evaluate(expr, row)?;
+            match op {
+                UnaryOperator::Plus => value.add(&Value::Int(0)),
+                UnaryOperator::Minus => value.neg(),
+                UnaryOperator::Not => value.not(),
+                op => Err(format!("unsupported operator: {}", op)),
+            }
+        }
+        Expr::BinaryOp { left, op, right } => match op {
+            // AND and OR only evaluate the right side if necessary
+            BinaryOperator::And => {
+                let left = evaluate(left, row)?;
+                if left.truth()? == Some(false) {
+                    return Ok(left);
+                }
+                left.and(&evaluate(right, row)?)
+            }
+            BinaryOperator::Or => {
+                let left = evaluate(left, row)?;
+                if left.truth()? == Some(true) {
+                    return Ok(left);
+                }
+                left.or(&evaluate(right, row)?)
+            }
+            op => binary_op(&evaluate(left, row)?, op, &evaluate(right, row)?),
+        },
+        Expr::Collate { expr, collation } => {
+            let collation = Collation::from_name(&display_name(&collation.0))?;
+            if collation != Collation::Binary {
+                return Err("COLLATE is only supported in comparisons".to_owned());
+            }
+            evaluate(expr, row)
+        }
+        Expr::Between {
+            expr,
+            negated,
+            low,
+            high,
+        } => {
+            let value = evaluate(expr, row)?;
+            let low = binary_op(&value, &BinaryOperator::GtEq, &evaluate(low, row)?)?;
+            let high = binary_op(&value, &BinaryOperator::LtEq, &evaluate(high, row)?)?;
+            let between = low.and(&high)?;
+            if *negated {
+                between.not()
+            } else {
+                Ok(between)
+            }
+        }
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => {
+            // x IN (a, b) is x = a OR x = b
+            let value = evaluate(expr, row)?;
+            let mut found = Value::Boolean(false);
+            for item in list {
+                let eq = binary_op(&value, &BinaryOperator::Eq, &evaluate(item, row)?)?;
+                found = found.or(&eq)?;
+                if found.truth()? == Some(true) {
+                    break;
+                }
+            }
+            if *negated {
+                found.not()
+            } else {
+                Ok(found)
+            }
+        }
+        Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => {
+            let operand = match operand {
+                Some(operand) => Some(evaluate(operand, row)?),
+                None => None,
+            };
+            for (condition, result) in conditions.iter().zip(results) {
+                let condition = evaluate(condition, row)?;
+                let matched = match &operand {
+                    Some(operand) => operand.sql_eq(&condition)?,
+                    None => condition.truth()?,
+                };
+                if matched == Some(true) {
+                    return evaluate(result, row);
+                }
+            }
+            match else_result {
+                Some(result) => evaluate(result, row),
+                None => Ok(Value::Null),
+            }
+        }
+        Expr::Function(function) => {
+            let name = function.name.to_string().to_lowercase();
+            let args = function
+                .args
+                .iter()
+                .map(|arg| match arg {
+                    FunctionArg::Unnamed(arg) => evaluate(arg, row),
+                    FunctionArg::Named { name, .. } => {
+                        Err(format!("named arguments are not supported: {}", name))
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            match row.call(&name, &args) {
+                Some(result) => result,
+                None => call_function(&name, &args),
+            }
+        }
+        expr => Err(format!("unsupported expression: {}", expr)),
+    }
+}
+
+/// Applies a binary operator other than AND and OR.
+pub fn binary_op(left: &Value, op: &BinaryOperator, right: &Value) -> Result<Value, String> {
+    let comparison = |matches: fn(Ordering) -> bool| -> Result<Value, String> {
+        Ok(match left.compare(right)? {
+            Some(ord) => Value::Boolean(matches(ord)),
+            None => Value::Null,
+        })
+    };
+    match op {
+        BinaryOperator::Plus => left.add(right),
+        BinaryOperator::Minus => left.sub(right),
+        BinaryOperator::Multiply => left.mul(right),
+        BinaryOperator::Divide => left.div(right),
+        BinaryOperator::Modulus => left.rem(right),
+        BinaryOperator::StringConcat => left.concat(right),
+        BinaryOperator::Eq => comparison(|o| o == Ordering::Equal),
+        BinaryOperator::NotEq => comparison(|o| o != Ordering::Equal),
+        BinaryOperator::Lt => comparison(|o| o == Ordering::Less),
+        BinaryOperator::LtEq => comparison(|o| o != Ordering::Greater),
+        BinaryOperator::Gt => comparison(|o| o == Ordering::Greater),
+        BinaryOperator::GtEq => comparison(|o| o != Ordering::Less),
+        BinaryOperator::And => left.and(right),
+        BinaryOperator::Or => left.or(right),
+        BinaryOperator::Like | BinaryOperator::NotLike => {
+            let matched = match (left, right) {
+                (Value::Null, _) | (_, Value::Null) => return Ok(Value::Null),
+                (Value::Text(s), Value::Text(pattern)) => like(s, pattern),
+                _ => {
+                    return Err(format!(
+                        "LIKE is only supported for strings: {:?} {}",
+                        left, right
+                    ))
+                }
+            };
+            Ok(Value::Boolean(matched == (*op == BinaryOperator::Like)))
+        }
+        op => Err(format!("unsupported operator: {}", op)),
+    }
+}
+
+fn call_function(name: &str, args: &[Value]) -> Result<Value, String> {
+    let arity = |n: usize| {
+        if args.len() == n {
+            Ok(())
+        } else {
+            Err(format!(
+                "function {} takes {} arguments, got {}",
+                name,
+                n,
+                args.len()
+            ))
+        }
+    };
+    match name {
+        "coalesce" => Ok(args
+            .iter()
+            .find(|v| !v.is_null())
+            .cloned()
+            .unwrap_or(Value::Null)),
+        "nullif" => {
+            arity(2)?;
+            match args[0].sql_eq(&args[1])? {
+                Some(true) => Ok(Value::Null),
+                _ => Ok(args[0].clone()),
+            }
+        }
+        "abs" => {
+            arity(1)?;
+            match args[0].compare(&Value::Int(0))? {
+                Some(Ordering::Less) => args[0].neg(),
+                _ => Ok(args[0].clone()),
+            }
+        }
+        "lower" | "upper" | "length" => {
+            arity(1)?;
+            let s = match &args[0] {
+                Value::Null => return Ok(Value::Null),
+                Value::Text(s) => s,
+                other => return Err(format!("function {}({:?}) does not exist", name, other)),
+            };
+            Ok(match name {
+                "lower" => Value::Text(s.to_lowercase()),
+                "upper" => Value::Text(s.to_uppercase()),
+                _ => Value::Int(s.chars().count() as i32),
+            })
+        }
+        _ => Err(format!("function {} does not exist", name)),
+    }
+}
+
+/// Matches a LIKE pattern, where `%` matches any string, `_` any character,
+/// and `\` escapes the next character.
+fn like(s: &str, pattern: &str) -> bool {
+    let s: Vec<char> = s.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    // positions in `s` at which the rest of the pattern may start
+    let mut starts = vec![false; s.len() + 1];
+    starts[0] = true;
+    let mut p = 0;
+    while p < pattern.len() {
+        let mut next = vec![false; s.len() + 1];
+        match pattern[p] {
+            '%' => {
+                if let Some(first) = starts.iter().position(|&b| b) {
+                    next[first..].iter_mut().for_each(|b| *b = true);
+                }
+            }
+            c => {
+                let literal = if c == '\\' && p + 1 < pattern.len() {
+                    p += 1;
+                    Some(pattern[p])
+                } else if c == '_' {
+                    None
+                } else {
+                    Some(c)
+                };
+                for i in 0..s.len() {
+                    next[i + 1] = starts[i] && literal.is_none_or(|l| s[i] == l);
+                }
+            }
+        }
+        starts = next;
+        p += 1;
+    }
+    starts[s.len()]
+}
+
+/// Returns the names of all columns referenced by an expression.
+pub fn referenced_columns(expr: &Expr) -> Vec<String> {
+    column_references(expr)
+        .iter()
+        .filter_map(|name| name.last().map(ident_name))
+        .collect()
+}
+
+/// Returns all column references of an expression, including their qualifiers.
+pub fn column_references(expr: &Expr) -> Vec<Vec<Ident>> {
+    let mut expr = expr.clone();
+    let mut references = Vec::new();
+    visit_columns(&mut expr, &mut |name| references.push(name.to_vec()));
+    references
+}
+
+/// Splits a condition into the expressions that are combined with `AND`.
+pub fn conjuncts(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => [conjuncts(left), conjuncts(right)].concat(),
+        Expr::Nested(expr) => conjuncts(expr),
+        expr => vec![expr],
+    }
+}
+
+/// Renames all references to a column.
+pub fn rename_column(expr: &mut Expr, column: &str, new_name: &str) {
+    visit_columns(expr, &mut |name| {
+        if let Some(c) = name.last_mut().filter(|c| ident_name(c) == column) {
+            *c = Ident::with_quote('"', new_name);
+        }
+    });
+}
+
+/// Returns the names of the sequences an expression uses with `nextval` or `currval`.
+pub fn referenced_sequences(expr: &Expr) -> Vec<String> {
+    let mut expr = expr.clone();
+    let mut sequences = Vec::new();
+    visit(&mut expr, &mut |e| {
+        if let Expr::Function(function) = e {
+            let name = function.name.to_string().to_lowercase();
+            if let ("nextval" | "currval", [FunctionArg::Unnamed(Expr::Value(arg))]) =
+                (name.as_str(), function.args.as_slice())
+            {
+                if let ast::Value::SingleQuotedString(arg) = arg {
+                    sequences.push(sequence_name(arg));
+                }
+            }
+        }
+    });
+    sequences
+}
+
+/// Replaces the subexpressions for which `f` returns a new expression, outermost first,
+/// `f` is then called on the subexpressions of the replacement.
+pub fn replace(expr: &mut Expr, f: &mut dyn FnMut(&Expr) -> Option<Expr>) {
+    visit(expr, &mut |e| {
+        if let Some(new) = f(e) {
+            *e = new;
+        }
+    });
+}
+
+/// Calls `f` on every column reference in the expressions that `evaluate` supports.
+fn visit_columns(expr: &mut Expr, f: &mut dyn FnMut(&mut [Ident])) {
+    visit(expr, &mut |e| match e {
+        Expr::Identifier(ident) => f(std::slice::from_mut(ident)),
+        Expr::CompoundIdentifier(idents) => f(idents),
+        _ => {}
+    });
+}
+
+/// Calls `f` on an expression and then on all its subexpressions that `evaluate` supports.
+fn visit(expr: &mut Expr, f: &mut dyn FnMut(&mut Expr)) {
+    f(expr);
+    match expr {
+        Expr::Nested(expr)
+        | Expr::IsNull(expr)
+        | Expr::IsNotNull(expr)
+        | Expr::Cast { expr, .. }
+        | Expr::TryCast { expr, .. }
+        | Expr::UnaryOp { expr, .. }
+        | Expr::Collate { expr, .. } => visit(expr, f),
+        Expr::BinaryOp { left, right, .. } => {
+            visit(left, f);
+            visit(right, f);
+        }
+        Expr::Between {
+            expr, low, high, ..
+        } => {
+            visit(expr, f);
+            visit(low, f);
+            visit(high, f);
+        }
+        Expr::InList { expr, list, .. } => {
+            visit(expr, f);
+            list.iter_mut().for_each(|e| visit(e, f));
+        }
+        Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => {
+            operand.iter_mut().for_each(|e| visit(e, f));
+            conditions.iter_mut().for_each(|e| visit(e, f));
+            results.iter_mut().for_each(|e| visit(e, f));
+            else_result.iter_mut().for_each(|e| visit(e, f));
+        }
+        Expr::Function(function) => {
+            for arg in &mut function.args {
+                match arg {
+                    FunctionArg::Unnamed(e) | FunctionArg::Named { arg: e, .. } => visit(e, f),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Formats a possibly qualified name for error messages.
+pub fn display_name(name: &[Ident]) -> String {
+    name.iter().map(ident_name).collect::<Vec<_>>().join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::catalog::Column;
+    use crate::sql::parse_expr;
+
+    fn eval(sql: &str) -> Result<Value, String> {
+        evaluate_constant(&parse_expr(sql).unwrap())
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(Value::Int(7)));
+        assert_eq!(eval("-(2 - 5)"), Ok(Value::Int(3)));
+        assert_eq!(eval("'a' || 'b'"), Ok(Value::Text("ab".to_owned())));
+        assert_eq!(eval("CAST('12' AS BIGINT)"), Ok(Value::BigInt(12)));
+        assert_eq!(eval("DATE '1970-01-11'"), Ok(Value::Date(10)));
+        assert_eq!(eval("NULL = NULL"), Ok(Value::Null));
+        assert_eq!(eval("NULL IS NULL"), Ok(Value::Boolean(true)));
+        assert_eq!(eval("false AND 1 / 0 = 1"), Ok(Value::Boolean(false)));
+        assert_eq!(eval("2 BETWEEN 1 AND 3"), Ok(Value::Boolean(true)));
+        assert_eq!(eval("2 IN (1, NULL)"), Ok(Value::Null));
+        assert_eq!(eval("2 NOT IN (1, 3)"), Ok(Value::Boolean(true)));
+        assert_eq!(
+            eval("CASE 2 WHEN 1 THEN 'one' WHEN 2 THEN 'two' END"),
+            Ok(Value::Text("two".to_owned()))
+        );
+        assert_eq!(eval("coalesce(NULL, 3, 4)"), Ok(Value::Int(3)));
+        assert_eq!(eval("upper('abc')"), Ok(Value::Text("ABC".to_owned())));
+        assert!(eval("1 / 0").is_err());
+        assert!(eval("x + 1").is_err());
+    }
+
+    #[test]
+    fn like_patterns() {
+        assert!(like("hello", "h%o"));
+        assert!(like("hello", "_ello"));
+        assert!(like("hello", "%"));
+        assert!(like("", "%%"));
+        assert!(!like("hello", "h_o"));
+        assert!(like("50%", "50\\%"));
+        assert!(!like("500", "50\\%"));
+        assert!(like("abcabc", "%abc"));
+        assert_eq!(eval("'abc' NOT LIKE 'a%'"), Ok(Value::Boolean(false)));
+    }
+
+    #[test]
+    fn column_references() {
+        let schema = Schema::new(vec![
+            Column::new("a", DataType::Int),
+            Column::new("b", DataType::Text),
+        ])
+        .unwrap();
+        let values = vec![Value::Int(3), Value::Null];
+        let row = TableRow {
+            table: "t",
+            schema: &schema,
+            values: &values,
+        };
+        let eval = |sql: &str| evaluate(&parse_expr(sql).unwrap(), &row);
+        assert_eq!(eval("a * 2 > 5"), Ok(Value::Boolean(true)));
+        assert_eq!(eval("t.b IS NULL"), Ok(Value::Boolean(true)));
+        assert!(eval("c").is_err());
+        assert!(eval("u.a").is_err());
+
+        let mut expr = parse_expr("a > 0 AND t.a < length(b)").unwrap();
+        assert_eq!(referenced_columns(&expr), vec!["a", "a", "b"]);
+        rename_column(&mut expr, "a", "X");
+        assert_eq!(expr.to_string(), "\"X\" > 0 AND t.\"X\" < length(b)");
+
+        let expr = parse_expr("a = 1 AND (b = 2 AND c) AND (d OR e)").unwrap();
+        let parts: Vec<_> = conjuncts(&expr).iter().map(|e| e.to_string()).collect();
+        assert_eq!(parts, vec!["a = 1", "b = 2", "c", "d OR e"]);
+    }
+}
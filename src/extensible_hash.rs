@@ -1,84 +1,322 @@
 // Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
 // Distributed under terms of the MIT license.
 
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::{mem, vec};
+
 /// Fill-Degree Constraint
 /// Hash table grows if a proprtion of buckets larger than this is filled.
 const FDC: f32 = 0.8;
 
+/// Hash table shrinks if a proportion of buckets smaller than this is filled.
+const MIN_FILL: f32 = 0.3;
+
+/// Capacity of a single bucket page, further items go to overflow pages.
 const ITEMS_PER_BUCKET: usize = 10;
 
-struct HashTable<V> {
-    buckets: Vec<Vec<(usize, V)>>,
+/// A fixed-size page of a bucket's chain.
+/// Items are stored together with their hash, so splitting never needs to rehash keys.
+struct BucketPage<K, V> {
+    items: Vec<(u64, K, V)>,
+    overflow: Option<usize>,
+}
+
+impl<K, V> BucketPage<K, V> {
+    fn new() -> Self {
+        Self {
+            items: Vec::with_capacity(ITEMS_PER_BUCKET),
+            overflow: None,
+        }
+    }
+}
+
+/// In-memory hash table based on Linear Hashing.
+/// The table grows and shrinks by one bucket at a time, so there are no expensive full rehashes.
+/// Every bucket is a chain of pages: its primary page plus any number of overflow pages.
+pub struct HashTable<K, V, S = RandomState> {
+    /// All bucket pages (primary and overflow), including unused pages on the free list.
+    pages: Vec<BucketPage<K, V>>,
+    /// Primary page of every bucket.
+    buckets: Vec<usize>,
+    free_pages: Vec<usize>,
     num_bits: usize,
     num_items: usize,
+    hash_builder: S,
+}
+
+impl<K: Hash + Eq, V> HashTable<K, V, RandomState> {
+    pub fn new() -> HashTable<K, V, RandomState> {
+        Self::with_hasher(RandomState::new())
+    }
+
+    pub fn with_capacity(capacity: usize) -> HashTable<K, V, RandomState> {
+        Self::with_capacity_and_hasher(capacity, RandomState::new())
+    }
 }
 
-impl<V> HashTable<V> {
-    pub fn new() -> HashTable<V> {
-        return HashTable {
-            buckets: vec![Vec::new()],
+impl<K, V, S> HashTable<K, V, S> {
+    pub fn with_hasher(hash_builder: S) -> HashTable<K, V, S> {
+        HashTable {
+            pages: vec![BucketPage::new()],
+            buckets: vec![0],
+            free_pages: Vec::new(),
             num_bits: 1,
             num_items: 0,
-        };
+            hash_builder,
+        }
     }
 
-    pub fn put(&mut self, k: usize, v: V) {
-        let b = self.bucket(k);
-        self.buckets[b].push((k, v));
-        self.num_items += 1;
-
-        if self.should_split() {
-            self.split();
+    /// Creates a table that can hold `capacity` items without splitting,
+    /// which avoids all split work when bulk-building, e.g. for a hash join.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> HashTable<K, V, S> {
+        let per_bucket = (ITEMS_PER_BUCKET as f32 * FDC) as usize;
+        let num_buckets = capacity.div_ceil(per_bucket).max(1);
+        let mut num_bits = 1;
+        while (1 << num_bits) < num_buckets {
+            num_bits += 1;
+        }
+        HashTable {
+            pages: (0..num_buckets).map(|_| BucketPage::new()).collect(),
+            buckets: (0..num_buckets).collect(),
+            free_pages: Vec::new(),
+            num_bits,
+            num_items: 0,
+            hash_builder,
         }
     }
 
-    pub fn get(&self, key: usize) -> Option<&V> {
-        let b = self.bucket(key);
-        for (k, v) in &self.buckets[b] {
-            if *k == key {
-                return Some(&v);
+    pub fn len(&self) -> usize {
+        self.num_items
+    }
+
+    /// Iterates over all key-value pairs in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.pages
+            .iter()
+            .flat_map(|p| p.items.iter().map(|(_, k, v)| (k, v)))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    /// Removes all items, keeping the hasher.
+    pub fn clear(&mut self) {
+        self.pages = vec![BucketPage::new()];
+        self.buckets = vec![0];
+        self.free_pages.clear();
+        self.num_bits = 1;
+        self.num_items = 0;
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> HashTable<K, V, S> {
+    /// Inserts the key-value pair, returning the previous value if the key was present.
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        match self.entry(k) {
+            Entry::Occupied(mut e) => Some(e.insert(v)),
+            Entry::Vacant(e) => {
+                e.insert(v);
+                None
             }
         }
-        return None;
     }
 
-    /// This is an O(n) operation because of the remove from Vec.
-    pub fn remove(&mut self, key: usize) {
-        let b = self.bucket(key);
-        for i in 0..self.buckets[b].len() {
-            if self.buckets[b][i].0 == key {
-                self.buckets[b].remove(i);
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (page, slot) = self.find(self.hash(key), key)?;
+        Some(&self.pages[page].items[slot].2)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (page, slot) = self.find(self.hash(key), key)?;
+        Some(&mut self.pages[page].items[slot].2)
+    }
+
+    #[cfg(test)]
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Removes the key from the table, returning its value if it was present.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hash(key);
+        let (page, slot) = self.find(hash, key)?;
+        Some(self.remove_at(hash, page, slot).1)
+    }
+
+    /// Gets the entry for the given key for in-place manipulation.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, S> {
+        let hash = self.hash(&key);
+        match self.find(hash, &key) {
+            Some((page, slot)) => Entry::Occupied(OccupiedEntry {
+                table: self,
+                page,
+                slot,
+            }),
+            None => Entry::Vacant(VacantEntry {
+                table: self,
+                key,
+                hash,
+            }),
+        }
+    }
+
+    /// Finds the page and slot of the given key.
+    fn find<Q>(&self, hash: u64, key: &Q) -> Option<(usize, usize)>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        let mut next = Some(self.buckets[self.bucket(hash)]);
+        while let Some(page) = next {
+            let items = &self.pages[page].items;
+            if let Some(slot) = items
+                .iter()
+                .position(|(h, k, _)| *h == hash && k.borrow() == key)
+            {
+                return Some((page, slot));
             }
-            break;
+            next = self.pages[page].overflow;
         }
+        None
+    }
+
+    /// Removes the item in the given slot, shrinking the table if it became too sparse.
+    /// Overflow pages that become empty are unlinked from their chain.
+    fn remove_at(&mut self, hash: u64, page: usize, slot: usize) -> (K, V) {
+        let (_, k, v) = self.pages[page].items.swap_remove(slot);
         self.num_items -= 1;
+
+        let primary = self.buckets[self.bucket(hash)];
+        if page != primary && self.pages[page].items.is_empty() {
+            let mut prev = primary;
+            while self.pages[prev].overflow != Some(page) {
+                prev = self.pages[prev].overflow.unwrap();
+            }
+            self.pages[prev].overflow = self.pages[page].overflow.take();
+            self.free_pages.push(page);
+        }
+
+        if self.should_shrink() {
+            self.shrink();
+        }
+        (k, v)
     }
 
-    /// This is an O(n^2) operation because of the remove from Vec.
+    /// Adds the item to the first page of the bucket's chain that has space left,
+    /// appending an overflow page if all are full.
+    /// Returns the page and slot the item was stored in.
+    fn push(&mut self, b: usize, item: (u64, K, V)) -> (usize, usize) {
+        let mut page = self.buckets[b];
+        while self.pages[page].items.len() == ITEMS_PER_BUCKET {
+            page = match self.pages[page].overflow {
+                Some(next) => next,
+                None => {
+                    let overflow = self.allocate_page();
+                    self.pages[page].overflow = Some(overflow);
+                    overflow
+                }
+            };
+        }
+        self.pages[page].items.push(item);
+        (page, self.pages[page].items.len() - 1)
+    }
+
+    /// Adds one bucket, splitting the items of the next bucket in line between it and the new one.
+    /// This takes a single pass over the split bucket and never triggers further splits.
     fn split(&mut self) {
         if self.buckets.len() == (1 << self.num_bits) {
             self.num_bits += 1;
         }
 
         let b_split = self.buckets.len() - (1 << (self.num_bits - 1));
-        self.buckets.push(Vec::new());
-        let mut i = 0;
-        while i != self.buckets[b_split].len() {
-            if self.bucket(self.buckets[b_split][i].0) != b_split {
-                let (k, v) = self.buckets[b_split].remove(i);
-                self.put(k, v);
-            } else {
-                i += 1;
+        let new_page = self.allocate_page();
+        self.buckets.push(new_page);
+
+        for item in self.take_chain(b_split) {
+            let b = self.bucket(item.0);
+            self.push(b, item);
+        }
+    }
+
+    /// Reverts the last split, merging the last bucket into its buddy.
+    fn shrink(&mut self) {
+        let last = self.buckets.len() - 1;
+        let b_merge = last - (1 << (self.num_bits - 1));
+        let items = self.take_chain(last);
+        let primary = self.buckets.pop().unwrap();
+        self.free_pages.push(primary);
+        if self.buckets.len() == (1 << (self.num_bits - 1)) && self.num_bits > 1 {
+            self.num_bits -= 1;
+        }
+
+        for item in items {
+            self.push(b_merge, item);
+        }
+    }
+
+    /// Removes all items of the bucket, putting its overflow pages on the free list.
+    fn take_chain(&mut self, b: usize) -> Vec<(u64, K, V)> {
+        let primary = self.buckets[b];
+        let mut items = mem::replace(
+            &mut self.pages[primary].items,
+            Vec::with_capacity(ITEMS_PER_BUCKET),
+        );
+        let mut next = self.pages[primary].overflow.take();
+        while let Some(page) = next {
+            items.append(&mut self.pages[page].items);
+            next = self.pages[page].overflow.take();
+            self.free_pages.push(page);
+        }
+        items
+    }
+
+    fn allocate_page(&mut self) -> usize {
+        match self.free_pages.pop() {
+            Some(page) => page,
+            None => {
+                self.pages.push(BucketPage::new());
+                self.pages.len() - 1
             }
         }
     }
 
     fn should_split(&self) -> bool {
-        return self.num_items as f32 / (self.buckets.len() * ITEMS_PER_BUCKET) as f32 > FDC;
+        self.fill_degree() > FDC
+    }
+
+    fn should_shrink(&self) -> bool {
+        self.buckets.len() > 1 && self.fill_degree() < MIN_FILL
+    }
+
+    fn fill_degree(&self) -> f32 {
+        self.num_items as f32 / (self.buckets.len() * ITEMS_PER_BUCKET) as f32
+    }
+
+    fn hash<Q: Hash + ?Sized>(&self, k: &Q) -> u64 {
+        self.hash_builder.hash_one(k)
     }
 
-    fn bucket(&self, k: usize) -> usize {
-        let h = k % (1 << self.num_bits);
+    fn bucket(&self, hash: u64) -> usize {
+        let h = (hash % (1 << self.num_bits)) as usize;
         if h >= self.buckets.len() {
             h - (1 << (self.num_bits - 1))
         } else {
@@ -87,69 +325,330 @@ impl<V> HashTable<V> {
     }
 }
 
+impl<K: Hash + Eq, V, S: BuildHasher + Default> Default for HashTable<K, V, S> {
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> IntoIterator for HashTable<K, V, S> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            pages: self.pages.into_iter(),
+            items: Vec::new().into_iter(),
+        }
+    }
+}
+
+/// Owning iterator over the items of a `HashTable`.
+pub struct IntoIter<K, V> {
+    pages: vec::IntoIter<BucketPage<K, V>>,
+    items: vec::IntoIter<(u64, K, V)>,
+}
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((_, k, v)) = self.items.next() {
+                return Some((k, v));
+            }
+            self.items = self.pages.next()?.items.into_iter();
+        }
+    }
+}
+
+/// A view into a single entry of a `HashTable`, see `HashTable::entry`.
+pub enum Entry<'a, K, V, S> {
+    Occupied(OccupiedEntry<'a, K, V, S>),
+    Vacant(VacantEntry<'a, K, V, S>),
+}
+
+pub struct OccupiedEntry<'a, K, V, S> {
+    table: &'a mut HashTable<K, V, S>,
+    page: usize,
+    slot: usize,
+}
+
+pub struct VacantEntry<'a, K, V, S> {
+    table: &'a mut HashTable<K, V, S>,
+    key: K,
+    hash: u64,
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> Entry<'a, K, V, S> {
+    #[cfg(test)]
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(default()),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+        if let Entry::Occupied(e) = &mut self {
+            f(e.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, K: Hash + Eq, V: Default, S: BuildHasher> Entry<'a, K, V, S> {
+    pub fn or_default(self) -> &'a mut V {
+        self.or_insert_with(V::default)
+    }
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> OccupiedEntry<'a, K, V, S> {
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.table.pages[self.page].items[self.slot].2
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.table.pages[self.page].items[self.slot].2
+    }
+
+    /// Replaces the value, returning the old one.
+    pub fn insert(&mut self, v: V) -> V {
+        mem::replace(self.get_mut(), v)
+    }
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> VacantEntry<'a, K, V, S> {
+    /// Inserts the value, growing the table first if necessary.
+    pub fn insert(self, v: V) -> &'a mut V {
+        let table = self.table;
+        table.num_items += 1;
+        if table.should_split() {
+            table.split();
+        }
+        let b = table.bucket(self.hash);
+        let (page, slot) = table.push(b, (self.hash, self.key, v));
+        &mut table.pages[page].items[slot].2
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use std::collections::HashMap;
+    use std::time::Instant;
+
     #[test]
     fn put_get() {
-        let mut ht = HashTable::<usize>::new();
-        ht.put(42, 100);
-        ht.put(43, 200);
-        ht.put(44, 300);
-        ht.put(45, 400);
-        assert_eq!(ht.get(42), Some(&100));
-        assert_eq!(ht.get(43), Some(&200));
-        assert_eq!(ht.get(44), Some(&300));
-        assert_eq!(ht.get(45), Some(&400));
-        assert_eq!(ht.get(46), None);
+        let mut ht = HashTable::<usize, usize>::new();
+        ht.insert(42, 100);
+        ht.insert(43, 200);
+        ht.insert(44, 300);
+        ht.insert(45, 400);
+        assert_eq!(ht.get(&42), Some(&100));
+        assert_eq!(ht.get(&43), Some(&200));
+        assert_eq!(ht.get(&44), Some(&300));
+        assert_eq!(ht.get(&45), Some(&400));
+        assert_eq!(ht.get(&46), None);
     }
 
     #[test]
     fn a_lot_of_items() {
-        let mut ht = HashTable::<usize>::new();
+        let mut ht = HashTable::<usize, usize>::new();
         for i in 0..1000 {
-            ht.put(i, 1000 + i);
+            ht.insert(i, 1000 + i);
         }
         for i in 0..1000 {
-            assert_eq!(ht.get(i), Some(&(1000 + i)));
+            assert_eq!(ht.get(&i), Some(&(1000 + i)));
         }
-        assert_eq!(ht.get(1000), None);
+        assert_eq!(ht.get(&1000), None);
 
         for i in 100..200 {
-            ht.remove(i);
+            ht.remove(&i);
         }
         for i in 0..1000 {
-            if i >= 100 && i < 200 {
-                assert_eq!(ht.get(i), None);
+            if (100..200).contains(&i) {
+                assert_eq!(ht.get(&i), None);
             } else {
-                assert_eq!(ht.get(i), Some(&(1000 + i)));
+                assert_eq!(ht.get(&i), Some(&(1000 + i)));
             }
         }
     }
 
     #[test]
     fn remove() {
-        let mut ht = HashTable::<usize>::new();
-        ht.put(42, 100);
-        ht.put(43, 200);
-        assert_eq!(ht.get(42), Some(&100));
-        assert_eq!(ht.get(43), Some(&200));
-        ht.remove(42);
-        assert_eq!(ht.get(42), None);
-        assert_eq!(ht.get(43), Some(&200));
+        let mut ht = HashTable::<usize, usize>::new();
+        ht.insert(42, 100);
+        ht.insert(43, 200);
+        assert_eq!(ht.get(&42), Some(&100));
+        assert_eq!(ht.get(&43), Some(&200));
+        assert_eq!(ht.remove(&42), Some(100));
+        assert_eq!(ht.remove(&42), None);
+        assert_eq!(ht.len(), 1);
+        assert_eq!(ht.get(&42), None);
+        assert_eq!(ht.get(&43), Some(&200));
     }
 
     #[test]
     fn split() {
-        let mut ht = HashTable::<usize>::new();
-        ht.put(42, 100);
-        ht.put(43, 200);
-        ht.put(44, 300);
-        ht.put(45, 400);
+        let mut ht = HashTable::<usize, usize>::new();
+        ht.insert(42, 100);
+        ht.insert(43, 200);
+        ht.insert(44, 300);
+        ht.insert(45, 400);
         ht.split();
         assert_eq!(ht.buckets.len(), 2);
-        assert_eq!(ht.buckets[0].len(), 2);
-        assert_eq!(ht.buckets[1].len(), 2);
+        let items = ht.pages[ht.buckets[0]].items.len() + ht.pages[ht.buckets[1]].items.len();
+        assert_eq!(items, 4);
+        for k in 42..46 {
+            assert!(ht.contains_key(&k));
+        }
+    }
+
+    #[test]
+    fn insert_replaces() {
+        let mut ht = HashTable::new();
+        assert_eq!(ht.insert("a".to_owned(), 1), None);
+        assert_eq!(ht.insert("a".to_owned(), 2), Some(1));
+        assert_eq!(ht.len(), 1);
+        assert_eq!(ht.get("a"), Some(&2));
+        *ht.get_mut("a").unwrap() += 1;
+        assert_eq!(ht.get("a"), Some(&3));
+    }
+
+    #[test]
+    fn entry() {
+        let mut ht = HashTable::new();
+        for w in "a b a c b a".split(' ') {
+            *ht.entry(w).or_insert(0) += 1;
+        }
+        assert_eq!(ht.get("a"), Some(&3));
+        assert_eq!(ht.get("b"), Some(&2));
+        assert_eq!(ht.get("c"), Some(&1));
+
+        ht.entry("c").and_modify(|n| *n = 10).or_default();
+        ht.entry("d").and_modify(|n| *n = 10).or_default();
+        assert_eq!(ht.get("c"), Some(&10));
+        assert_eq!(ht.get("d"), Some(&0));
+
+        assert_eq!(ht.remove("a"), Some(3));
+        assert_eq!(ht.get("a"), None);
+        assert_eq!(ht.len(), 3);
+    }
+
+    #[test]
+    fn shrink() {
+        let mut ht = HashTable::<usize, usize>::new();
+        for i in 0..10_000 {
+            ht.insert(i, i);
+        }
+        let max_buckets = ht.buckets.len();
+        for i in 0..9_990 {
+            assert_eq!(ht.remove(&i), Some(i));
+        }
+        assert!(ht.buckets.len() < max_buckets / 100);
+        let mut rest: Vec<_> = ht.into_iter().collect();
+        rest.sort_unstable();
+        assert_eq!(rest, (9_990..10_000).map(|i| (i, i)).collect::<Vec<_>>());
+    }
+
+    /// Simple xorshift generator, so random operations are reproducible.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+    }
+
+    #[test]
+    fn same_as_std_hashmap() {
+        for seed in 1..=10 {
+            let mut rng = Rng(seed);
+            let mut ht = HashTable::new();
+            let mut reference = HashMap::new();
+            for _ in 0..20_000 {
+                let k = rng.next() % 1000;
+                let v = rng.next();
+                match rng.next() % 5 {
+                    0 | 1 => assert_eq!(ht.insert(k, v), reference.insert(k, v)),
+                    2 => assert_eq!(ht.remove(&k), reference.remove(&k)),
+                    3 => assert_eq!(ht.get(&k), reference.get(&k)),
+                    _ => {
+                        let a = *ht.entry(k).and_modify(|x| *x ^= v).or_insert(v);
+                        let b = *reference.entry(k).and_modify(|x| *x ^= v).or_insert(v);
+                        assert_eq!(a, b);
+                    }
+                }
+                assert_eq!(ht.len(), reference.len());
+            }
+
+            let mut items: Vec<_> = ht.iter().map(|(&k, &v)| (k, v)).collect();
+            let mut expected: Vec<_> = reference.into_iter().collect();
+            items.sort_unstable();
+            expected.sort_unstable();
+            assert_eq!(items, expected);
+        }
+    }
+
+    #[test]
+    fn with_capacity() {
+        let mut ht = HashTable::with_capacity(1000);
+        let num_buckets = ht.buckets.len();
+        for i in 0..1000 {
+            ht.insert(i, i);
+        }
+        assert_eq!(ht.buckets.len(), num_buckets);
+        for i in 0..1000 {
+            assert_eq!(ht.get(&i), Some(&i));
+        }
+    }
+
+    /// Insertion should take amortized constant time, independent of the table size.
+    /// Run with `cargo test --release -- --ignored --nocapture bench_`.
+    #[test]
+    #[ignore]
+    fn bench_insert_millions() {
+        for &n in &[1_000_000, 2_000_000, 4_000_000, 8_000_000] {
+            let start = Instant::now();
+            let mut ht = HashTable::new();
+            for i in 0..n {
+                ht.insert(i, i);
+            }
+            let ours = start.elapsed();
+            assert_eq!(ht.len(), n);
+
+            let start = Instant::now();
+            let mut ht = HashTable::with_capacity(n);
+            for i in 0..n {
+                ht.insert(i, i);
+            }
+            let presized = start.elapsed();
+            assert_eq!(ht.len(), n);
+
+            let start = Instant::now();
+            let mut reference = HashMap::new();
+            for i in 0..n {
+                reference.insert(i, i);
+            }
+            let std = start.elapsed();
+
+            println!(
+                "{:>9} inserts: {:>6.1} ns/insert, presized {:>6.1} ns/insert, std HashMap {:>6.1} ns/insert",
+                n,
+                ours.as_nanos() as f64 / n as f64,
+                presized.as_nanos() as f64 / n as f64,
+                std.as_nanos() as f64 / n as f64,
+            );
+        }
     }
 }
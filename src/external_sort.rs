@@ -1,102 +1,532 @@
 // Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
 // Distributed under terms of the MIT license.
 
-use std::fs::File;
-use std::io::{Read, Write};
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::vec::IntoIter;
 
-const B: usize = 36;
-const M: usize = 8;
+use sqlparser::ast::Expr;
 
-/// Sorts a file of size B blocks on external storage, using 4*B IOs.
-pub fn sort_merge_join(path: &str) {
-    let mut f = File::open(path).unwrap();
-    let mut buffers = [[0u8; 4096]; M];
-    let runs = (B + M - 1) / M;
+use crate::binder::{Field, JoinKind, SortKey};
+use crate::database::Database;
+use crate::executor::{close_on_error, is_true, row_size, Operator, PlanRow, WORK_MEM_PAGES};
+use crate::hash_join::key;
+use crate::key_encoding::{KeyEncoder, KeyOrder};
+use crate::page::PAGE_SIZE;
+use crate::spill_file::{RowBuffer, SpillFile, SpillReader};
+use crate::tuple::Tuple;
+use crate::value::Value;
 
-    if runs > M - 1 {
-        panic!("file too large for Multi-Way Merge-Sort");
+/// Maximum number of runs merged at once, which keeps the number of open files small.
+const MAX_FAN_IN: usize = 64;
+
+/// Sorts the rows of its input by the sort keys, rows with equal keys keep the order of the input.
+///
+/// The rows are sorted in memory if they fit into the budget. Otherwise sorted runs of rows
+/// are written to spill files and merged, in several passes if there are more runs
+/// than can be merged at once.
+pub struct Sort {
+    input: Box<dyn Operator>,
+    keys: Vec<SortKey>,
+    /// Bytes of rows kept in memory.
+    memory: usize,
+    /// The sorted rows with their encoded keys, if they fit into memory.
+    rows: IntoIter<(Vec<u8>, Tuple)>,
+    /// The final merge of the runs, if the rows did not fit into memory.
+    merge: Option<Merge>,
+    /// Number of runs written, including those of intermediate merge passes.
+    runs: usize,
+}
+
+impl Sort {
+    pub fn new(input: Box<dyn Operator>, keys: Vec<SortKey>) -> Self {
+        Self {
+            input,
+            keys,
+            memory: WORK_MEM_PAGES * PAGE_SIZE,
+            rows: Vec::new().into_iter(),
+            merge: None,
+            runs: 0,
+        }
     }
 
-    sort_runs(&mut f, &mut buffers, runs);
+    /// Limits the rows held in memory to about the given number of pages,
+    /// which also bounds the number of runs merged at once.
+    #[cfg(test)]
+    pub fn with_buffer_pages(mut self, pages: usize) -> Self {
+        self.memory = pages.max(1) * PAGE_SIZE;
+        self
+    }
 
-    // TODO Merge and Join
-}
+    fn fan_in(&self) -> usize {
+        (self.memory / PAGE_SIZE)
+            .saturating_sub(1)
+            .clamp(2, MAX_FAN_IN)
+    }
 
-/// Sorts a file of size B blocks on external storage, using 4*B IOs.
-pub fn multiway_merge_sort(path: &str) {
-    let mut f = File::open(path).unwrap();
-    let mut buffers = [[0u8; 4096]; M];
-    let runs = (B + M - 1) / M;
-
-    if runs > M - 1 {
-        panic!("file too large for Multi-Way Merge-Sort");
-    }
-
-    sort_runs(&mut f, &mut buffers, runs);
-
-    // Merge sorted runs
-    let mut out = File::create("out.txt").unwrap();
-    let mut f_run = Vec::new();
-    for run in 0..runs {
-        f_run.push(File::open(format!("temp{}.txt", run)).unwrap());
-        f_run[run].read(&mut buffers[run]).unwrap();
-    }
-    let mut pos = [0usize; M - 1];
-    let mut done = [false; M - 1];
-    let mut done_with = 0;
-    let mut out_filled = 0;
-    loop {
-        // find min
-        let mut min_i = 0;
-        let mut min = 255;
-        for i in 0..runs {
-            if !done[i] && buffers[i][pos[i]] <= min {
-                min_i = i;
-                min = buffers[i][pos[i]];
-            }
+    /// Sorts the rows and writes them to a new run, leaving `rows` empty.
+    fn write_run(&mut self, rows: &mut Vec<(Vec<u8>, Tuple)>) -> Result<SpillFile, String> {
+        // the sort is stable, so rows with equal keys keep the order of the input
+        rows.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut run = SpillFile::create()?;
+        for (key, row) in rows.drain(..) {
+            run.write(&run_row(key, row))?;
         }
+        self.runs += 1;
+        Ok(run)
+    }
 
-        // read new block if necessary
-        pos[min_i] += 1;
-        if pos[min_i] == 4096 {
-            if f_run[min_i].read(&mut buffers[min_i]).unwrap() == 0 {
-                done[min_i] = true;
-                done_with += 1;
-                if done_with == runs {
+    /// Merges consecutive runs until there are few enough to be merged at once.
+    fn merge_runs(&mut self, mut runs: Vec<SpillFile>) -> Result<Vec<SpillFile>, String> {
+        let fan_in = self.fan_in();
+        while runs.len() > fan_in {
+            let mut merged = Vec::new();
+            let mut remaining = runs.into_iter();
+            loop {
+                let group: Vec<_> = remaining.by_ref().take(fan_in).collect();
+                if group.len() <= 1 {
+                    merged.extend(group);
                     break;
                 }
+                let mut merge = Merge::new(group)?;
+                let mut run = SpillFile::create()?;
+                while let Some((key, row)) = merge.next()? {
+                    run.write(&run_row(key, row))?;
+                }
+                self.runs += 1;
+                merged.push(run);
+            }
+            runs = merged;
+        }
+        Ok(runs)
+    }
+
+    /// Sorts the rows of the opened input, in memory or into runs that are merged by `next`.
+    fn read_input(&mut self, db: &mut Database) -> Result<(), String> {
+        let mut rows = Vec::new();
+        let mut size = 0;
+        let mut runs = Vec::new();
+        while let Some(row) = self.input.next(db)? {
+            let key = sort_key(db, &self.keys, self.input.schema(), &row)?;
+            size += key.len() + row_size(&row);
+            rows.push((key, row));
+            if size > self.memory {
+                runs.push(self.write_run(&mut rows)?);
+                size = 0;
             }
-            pos[min_i] = 0;
         }
+        if runs.is_empty() {
+            rows.sort_by(|(a, _), (b, _)| a.cmp(b));
+            self.rows = rows.into_iter();
+            return Ok(());
+        }
+        if !rows.is_empty() {
+            runs.push(self.write_run(&mut rows)?);
+        }
+        let runs = self.merge_runs(runs)?;
+        self.merge = Some(Merge::new(runs)?);
+        Ok(())
+    }
+}
+
+impl Operator for Sort {
+    fn open(&mut self, db: &mut Database) -> Result<(), String> {
+        self.rows = Vec::new().into_iter();
+        self.merge = None;
+        self.runs = 0;
+        self.input.open(db)?;
+        let result = self.read_input(db);
+        close_on_error(result, db, &mut [self.input.as_mut()])
+    }
 
-        // write to out buffer
-        buffers[M - 1][out_filled] = min;
-        out_filled += 1;
-        if out_filled == 4096 {
-            out.write(&buffers[M - 1]).unwrap();
-            out_filled = 0;
+    fn next(&mut self, _db: &mut Database) -> Result<Option<Tuple>, String> {
+        if let Some((_, row)) = self.rows.next() {
+            return Ok(Some(row));
         }
+        match &mut self.merge {
+            Some(merge) => Ok(merge.next()?.map(|(_, row)| row)),
+            None => Ok(None),
+        }
+    }
+
+    fn close(&mut self, db: &mut Database) -> Result<(), String> {
+        self.rows = Vec::new().into_iter();
+        self.merge = None;
+        self.input.close(db)
     }
 
-    if out_filled > 0 {
-        out.write(&buffers[M - 1][..out_filled + 1]).unwrap();
+    fn schema(&self) -> &[Field] {
+        self.input.schema()
+    }
+}
+
+/// Evaluates the sort keys on a row and encodes them into a memcomparable key,
+/// so that rows can be ordered by comparing their keys bytewise.
+pub fn sort_key(
+    db: &mut Database,
+    keys: &[SortKey],
+    fields: &[Field],
+    row: &[Value],
+) -> Result<Vec<u8>, String> {
+    let values = PlanRow {
+        fields,
+        values: row,
+    };
+    let mut enc = KeyEncoder::new();
+    for key in keys {
+        let order = if key.asc {
+            KeyOrder::ASC
+        } else {
+            KeyOrder::DESC
+        };
+        let order = KeyOrder {
+            nulls_first: key.nulls_first,
+            ..order
+        };
+        db.eval(&key.expr, &values)?
+            .encode_sort_key(&mut enc, order);
     }
-    out.flush().unwrap();
+    Ok(enc.finish())
 }
 
-fn sort_runs(f: &mut File, buffers: &mut [[u8; 4096]; M], runs: usize) {
-    for run in 0..runs {
-        for i in 0..M {
-            if f.read(&mut buffers[i]).unwrap() == 0 {
-                break;
+/// A row as it is written to a run, preceded by its key.
+fn run_row(key: Vec<u8>, row: Tuple) -> Tuple {
+    let mut values = Vec::with_capacity(row.len() + 1);
+    values.push(Value::Bytea(key));
+    values.extend(row);
+    values
+}
+
+/// Merges sorted runs, whose rows start with their keys, using a tree of losers.
+///
+/// Every inner node of the tree holds the run that lost the comparison of the runs' current rows
+/// in its subtree, so replacing the smallest row only takes one comparison per level.
+/// Rows with equal keys are returned in the order of the runs.
+struct Merge {
+    /// The runs, which are deleted when the merge is dropped.
+    runs: Vec<SpillFile>,
+    readers: Vec<SpillReader>,
+    /// The current key and row of each run, `None` once the run is exhausted.
+    heads: Vec<Option<(Vec<u8>, Tuple)>>,
+    /// The run with the smallest row at index 0, followed by the losers of the inner nodes.
+    tree: Vec<usize>,
+}
+
+impl Merge {
+    fn new(mut runs: Vec<SpillFile>) -> Result<Self, String> {
+        let readers = runs
+            .iter_mut()
+            .map(|run| run.read())
+            .collect::<Result<Vec<_>, _>>()?;
+        let k = runs.len();
+        let mut merge = Self {
+            runs,
+            readers,
+            heads: Vec::new(),
+            tree: vec![0; k],
+        };
+        merge.heads = (0..k).map(|i| merge.read(i)).collect::<Result<_, _>>()?;
+        // the runs are the leaves k..2k of an implicit binary tree
+        let mut winners = vec![0; 2 * k];
+        for (i, winner) in winners[k..].iter_mut().enumerate() {
+            *winner = i;
+        }
+        for node in (1..k).rev() {
+            let (a, b) = (winners[2 * node], winners[2 * node + 1]);
+            let (winner, loser) = if merge.less(a, b) { (a, b) } else { (b, a) };
+            winners[node] = winner;
+            merge.tree[node] = loser;
+        }
+        if k > 0 {
+            merge.tree[0] = winners[1];
+        }
+        Ok(merge)
+    }
+
+    /// Returns the smallest remaining row with its key.
+    fn next(&mut self) -> Result<Option<(Vec<u8>, Tuple)>, String> {
+        let k = self.runs.len();
+        if k == 0 {
+            return Ok(None);
+        }
+        let mut winner = self.tree[0];
+        let head = match self.heads[winner].take() {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+        self.heads[winner] = self.read(winner)?;
+        let mut node = (winner + k) / 2;
+        while node > 0 {
+            let loser = self.tree[node];
+            if self.less(loser, winner) {
+                self.tree[node] = winner;
+                winner = loser;
             }
+            node /= 2;
         }
+        self.tree[0] = winner;
+        Ok(Some(head))
+    }
 
-        let mut sorted = buffers.concat();
-        sorted.sort();
+    fn read(&mut self, run: usize) -> Result<Option<(Vec<u8>, Tuple)>, String> {
+        let mut row = match self.readers[run].next()? {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        match row.remove(0) {
+            Value::Bytea(key) => Ok(Some((key, row))),
+            _ => Err("sort run contains a row without key".to_owned()),
+        }
+    }
 
-        let mut out = File::create(format!("temp{}.txt", run)).unwrap();
-        out.write(&sorted).unwrap();
+    /// Whether the current row of run `a` comes before that of run `b`, exhausted runs come last.
+    fn less(&self, a: usize, b: usize) -> bool {
+        let ordering = match (&self.heads[a], &self.heads[b]) {
+            (Some((a, _)), Some((b, _))) => a.cmp(b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+        ordering.then(a.cmp(&b)) == Ordering::Less
+    }
+}
+
+/// Joins two inputs that are sorted on their keys (ascending, with NULLs last)
+/// by merging them, rows match if their keys are equal and the residual condition is true.
+///
+/// The right rows of a key are buffered as a group and joined with every left row of that key,
+/// large groups are spilled to disk. Keys containing NULL never match.
+pub struct SortMergeJoin {
+    left: Box<dyn Operator>,
+    right: Box<dyn Operator>,
+    kind: JoinKind,
+    left_keys: Vec<Expr>,
+    right_keys: Vec<Expr>,
+    condition: Option<Expr>,
+    /// The fields of both inputs, which the condition refers to.
+    condition_fields: Vec<Field>,
+    fields: Vec<Field>,
+    /// The next rows of both inputs with their keys, `None` once an input is exhausted.
+    left_row: Option<(Vec<Value>, Tuple)>,
+    right_row: Option<(Vec<Value>, Tuple)>,
+    /// The right rows with the key of the left rows being joined.
+    group: RowBuffer,
+    group_key: Option<Vec<Value>>,
+    /// Whether each row of the group has been joined, for right and full joins.
+    group_matched: Vec<bool>,
+    pending: VecDeque<Tuple>,
+}
+
+impl SortMergeJoin {
+    pub fn new(
+        left: Box<dyn Operator>,
+        right: Box<dyn Operator>,
+        kind: JoinKind,
+        left_keys: Vec<Expr>,
+        right_keys: Vec<Expr>,
+        condition: Option<Expr>,
+        fields: Vec<Field>,
+    ) -> Self {
+        Self {
+            condition_fields: [left.schema(), right.schema()].concat(),
+            left,
+            right,
+            kind,
+            left_keys,
+            right_keys,
+            condition,
+            fields,
+            left_row: None,
+            right_row: None,
+            group: RowBuffer::new(WORK_MEM_PAGES * PAGE_SIZE),
+            group_key: None,
+            group_matched: Vec::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Limits the rows of a group held in memory to about the given number of pages.
+    #[cfg(test)]
+    pub fn with_buffer_pages(mut self, pages: usize) -> Self {
+        self.group = RowBuffer::new(pages.max(1) * PAGE_SIZE);
+        self
+    }
+
+    fn next_left(&mut self, db: &mut Database) -> Result<(), String> {
+        self.left_row = match self.left.next(db)? {
+            Some(row) => Some((key(db, &self.left_keys, self.left.schema(), &row)?, row)),
+            None => None,
+        };
+        Ok(())
+    }
+
+    fn next_right(&mut self, db: &mut Database) -> Result<(), String> {
+        self.right_row = match self.right.next(db)? {
+            Some(row) => Some((key(db, &self.right_keys, self.right.schema(), &row)?, row)),
+            None => None,
+        };
+        Ok(())
+    }
+
+    fn unmatched_left(&mut self, row: Tuple) {
+        match self.kind {
+            JoinKind::Left | JoinKind::Full => {
+                let mut padded = row;
+                padded.resize(self.fields.len(), Value::Null);
+                self.pending.push_back(padded);
+            }
+            JoinKind::Anti => self.pending.push_back(row),
+            _ => {}
+        }
+    }
+
+    fn unmatched_right(&mut self, row: Tuple) {
+        if matches!(self.kind, JoinKind::Right | JoinKind::Full) {
+            let mut padded = vec![Value::Null; self.left.schema().len()];
+            padded.extend(row);
+            self.pending.push_back(padded);
+        }
+    }
+
+    /// Reads all right rows with the given key into the group.
+    fn read_group(&mut self, db: &mut Database, key: Vec<Value>) -> Result<(), String> {
+        self.group.clear();
+        self.group_matched.clear();
+        while let Some((_, row)) = self.right_row.take_if(|(k, _)| *k == key) {
+            self.group.push(row)?;
+            self.group_matched.push(false);
+            self.next_right(db)?;
+        }
+        self.group_key = Some(key);
+        Ok(())
+    }
+
+    /// Joins a left row with the rows of the group.
+    fn join_group(&mut self, db: &mut Database, row: Tuple) -> Result<(), String> {
+        let mut group = self.group.read()?;
+        let mut matched = false;
+        let mut i = 0;
+        while let Some(right) = group.next()? {
+            let mut joined = row.clone();
+            joined.extend(right);
+            let joins = match &self.condition {
+                Some(condition) => is_true(db, condition, &self.condition_fields, &joined)?,
+                None => true,
+            };
+            if joins {
+                matched = true;
+                self.group_matched[i] = true;
+                match self.kind {
+                    JoinKind::Semi | JoinKind::Anti => break,
+                    _ => self.pending.push_back(joined),
+                }
+            }
+            i += 1;
+        }
+        match self.kind {
+            JoinKind::Semi if matched => self.pending.push_back(row),
+            _ if !matched => self.unmatched_left(row),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Pads the rows of the group that were not joined and removes the group.
+    fn finish_group(&mut self) -> Result<(), String> {
+        if matches!(self.kind, JoinKind::Right | JoinKind::Full) {
+            let mut group = self.group.read()?;
+            let mut i = 0;
+            while let Some(row) = group.next()? {
+                if !self.group_matched[i] {
+                    let mut padded = vec![Value::Null; self.left.schema().len()];
+                    padded.extend(row);
+                    self.pending.push_back(padded);
+                }
+                i += 1;
+            }
+        }
+        self.group.clear();
+        self.group_matched.clear();
+        self.group_key = None;
+        Ok(())
+    }
+}
+
+impl Operator for SortMergeJoin {
+    fn open(&mut self, db: &mut Database) -> Result<(), String> {
+        self.group.clear();
+        self.group_matched.clear();
+        self.group_key = None;
+        self.pending.clear();
+        let result = self.left.open(db).and_then(|_| {
+            self.right.open(db)?;
+            self.next_left(db)?;
+            self.next_right(db)
+        });
+        close_on_error(result, db, &mut [self.left.as_mut(), self.right.as_mut()])
+    }
+
+    fn next(&mut self, db: &mut Database) -> Result<Option<Tuple>, String> {
+        loop {
+            if let Some(row) = self.pending.pop_front() {
+                return Ok(Some(row));
+            }
+            if let Some(group_key) = &self.group_key {
+                match self.left_row.take_if(|(key, _)| key == group_key) {
+                    Some((_, row)) => {
+                        self.join_group(db, row)?;
+                        self.next_left(db)?;
+                    }
+                    None => self.finish_group()?,
+                }
+                continue;
+            }
+            let has_null = |row: &Option<(Vec<Value>, Tuple)>| match row {
+                Some((key, _)) => key.iter().any(Value::is_null),
+                None => false,
+            };
+            // the side whose next row cannot match any row of the other side
+            let ordering = match (&self.left_row, &self.right_row) {
+                (None, None) => return Ok(None),
+                (Some(_), _) if has_null(&self.left_row) => Ordering::Less,
+                (_, Some(_)) if has_null(&self.right_row) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((left, _)), Some((right, _))) => left.cmp(right),
+            };
+            match ordering {
+                Ordering::Less => {
+                    let (_, row) = self.left_row.take().unwrap();
+                    self.unmatched_left(row);
+                    self.next_left(db)?;
+                }
+                Ordering::Greater => {
+                    let (_, row) = self.right_row.take().unwrap();
+                    self.unmatched_right(row);
+                    self.next_right(db)?;
+                }
+                Ordering::Equal => {
+                    let key = self.right_row.as_ref().unwrap().0.clone();
+                    self.read_group(db, key)?;
+                }
+            }
+        }
+    }
+
+    fn close(&mut self, db: &mut Database) -> Result<(), String> {
+        self.left_row = None;
+        self.right_row = None;
+        self.group.clear();
+        self.group_matched.clear();
+        self.group_key = None;
+        self.pending.clear();
+        let left = self.left.close(db);
+        let right = self.right.close(db);
+        left.and(right)
+    }
+
+    fn schema(&self) -> &[Field] {
+        &self.fields
     }
 }
 
@@ -104,8 +534,145 @@ fn sort_runs(f: &mut File, buffers: &mut [[u8; 4096]; M], runs: usize) {
 mod tests {
     use super::*;
 
+    use crate::binder::SortKey;
+    use crate::executor::{check_join_kinds, collect, values, TempDatabase};
+    use crate::sql::parse_expr;
+
+    /// Compares evaluated sort keys like the encoded keys should.
+    fn compare_keys(keys: &[SortKey], a: &[Value], b: &[Value]) -> Ordering {
+        for ((key, a), b) in keys.iter().zip(a).zip(b) {
+            let ordering = match (a.is_null(), b.is_null()) {
+                (true, true) => Ordering::Equal,
+                (true, false) if key.nulls_first => Ordering::Less,
+                (true, false) => Ordering::Greater,
+                (false, true) if key.nulls_first => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) if key.asc => a.cmp(b),
+                (false, false) => b.cmp(a),
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+
+    #[test]
+    fn sort() {
+        let mut db = TempDatabase::open("external_sort");
+        // a is unique, b has many duplicates and NULLs, so the order of a shows stability
+        let rows: Vec<_> = (0..3000)
+            .map(|i| {
+                [
+                    if i % 17 == 0 {
+                        None
+                    } else {
+                        Some(i * 7919 % 23)
+                    },
+                    Some(i),
+                ]
+            })
+            .collect();
+        let key = |expr: &str, asc, nulls_first| SortKey {
+            expr: parse_expr(expr).unwrap(),
+            asc,
+            nulls_first,
+        };
+        for keys in [
+            vec![key("t.a", true, false)],
+            vec![key("t.b", false, true)],
+            vec![key("t.a", true, true), key("t.b % 5", false, false)],
+        ] {
+            let mut expected: Vec<_> = rows
+                .iter()
+                .map(|&[a, b]| {
+                    let (a, b) = (a.map_or(Value::Null, Value::BigInt), b.unwrap());
+                    let key: Vec<_> = keys
+                        .iter()
+                        .map(|key| match key.expr.to_string().as_str() {
+                            "t.a" => a.clone(),
+                            "t.b" => Value::BigInt(b),
+                            _ => Value::BigInt(b % 5),
+                        })
+                        .collect();
+                    (key, vec![a, Value::BigInt(b)])
+                })
+                .collect();
+            expected.sort_by(|(a, _), (b, _)| compare_keys(&keys, a, b));
+            let expected: Vec<_> = expected.into_iter().map(|(_, row)| row).collect();
+
+            // fits into memory
+            let mut sort = Sort::new(values("t", &rows), keys.clone());
+            assert_eq!(collect(&mut sort, &mut db).unwrap(), expected);
+            assert_eq!(sort.runs, 0);
+
+            // two buffer pages only allow merging two runs at once
+            let mut sort = Sort::new(values("t", &rows), keys.clone()).with_buffer_pages(2);
+            sort.open(&mut db).unwrap();
+            let files: Vec<_> = sort
+                .merge
+                .as_ref()
+                .unwrap()
+                .runs
+                .iter()
+                .map(|run| run.path().to_path_buf())
+                .collect();
+            assert_eq!(files.len(), 2);
+            let mut sorted = Vec::new();
+            while let Some(row) = sort.next(&mut db).unwrap() {
+                sorted.push(row);
+            }
+            sort.close(&mut db).unwrap();
+            assert_eq!(sorted, expected);
+            assert!(sort.runs > 50, "{}", sort.runs);
+            assert!(files.iter().all(|file| !file.exists()));
+        }
+    }
+
     #[test]
-    fn mwms() {
-        multiway_merge_sort("1.txt");
+    fn merge_join() {
+        let mut db = TempDatabase::open("sort_merge_join");
+        // groups of 50 right rows do not fit into a single page
+        let left: Vec<_> = (0..100)
+            .map(|i| [if i % 9 == 0 { None } else { Some(i % 6) }, Some(i)])
+            .collect();
+        let right: Vec<_> = (0..200)
+            .map(|i| {
+                [
+                    if i % 50 == 7 { None } else { Some(i % 4 + 2) },
+                    Some(2 * i),
+                ]
+            })
+            .collect();
+        let sorted = |table: &str, rows| {
+            let key = SortKey {
+                expr: parse_expr(&format!("{}.a", table)).unwrap(),
+                asc: true,
+                nulls_first: false,
+            };
+            Box::new(Sort::new(rows, vec![key]))
+        };
+        let condition = "l.b < r.b";
+        let counts = [2576, 2576 + 40, 2576 + 7, 2576 + 40 + 7, 60, 40];
+        check_join_kinds(
+            &mut db,
+            &left,
+            &right,
+            &["a"],
+            condition,
+            counts,
+            |kind, l, r, fields| {
+                SortMergeJoin::new(
+                    sorted("l", l),
+                    sorted("r", r),
+                    kind,
+                    vec![parse_expr("l.a").unwrap()],
+                    vec![parse_expr("r.a").unwrap()],
+                    Some(parse_expr(condition).unwrap()),
+                    fields,
+                )
+                .with_buffer_pages(1)
+            },
+        );
     }
 }
@@ -0,0 +1,563 @@
+// Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::convert::TryInto;
+
+use crate::buffer_manager::{fetch_page, new_page, BufferManager};
+use crate::page::{read_page_id, read_u64, write_u64, PageID, RecordId, NO_PAGE, PAGE_SIZE};
+
+/// Fill-Degree Constraint
+/// Index grows by one bucket if the buckets' primary pages are filled more than this on average.
+const FDC: f32 = 0.8;
+
+/// "QDBHASH1"
+const MAGIC: u64 = 0x5144_4248_4153_4831;
+
+// Layout of the meta page.
+const META_MAGIC: usize = 0;
+const META_NUM_BITS: usize = 8;
+const META_NUM_BUCKETS: usize = 16;
+const META_NUM_ITEMS: usize = 24;
+const META_USED_BYTES: usize = 32;
+const META_DIRECTORY: usize = 40;
+const META_FREE_LIST: usize = 48;
+
+/// Directory pages start with the ID of the next directory page, followed by bucket page IDs.
+const DIR_ENTRIES_PER_PAGE: usize = (PAGE_SIZE - 8) / 8;
+
+/// Bucket pages start with the ID of the overflow page and the number of entries.
+/// Every entry consists of key length, key, page ID and slot of the record.
+const BUCKET_HEADER: usize = 8 + 2;
+const ENTRY_OVERHEAD: usize = 2 + 8 + 2;
+
+/// Largest key that can be stored in the index.
+pub const MAX_KEY_SIZE: usize = PAGE_SIZE - BUCKET_HEADER - ENTRY_OVERHEAD;
+
+/// Disk-resident Linear Hashing index, mapping byte-string keys to record IDs.
+/// Every bucket is a chain of pages: a primary page plus overflow pages.
+/// The directory of primary pages and the split state live in the meta page and directory pages,
+/// so the index can be reopened given only the ID of its meta page.
+/// Duplicate keys are allowed, uniqueness has to be enforced by the caller.
+pub struct HashIndex {
+    meta_page: PageID,
+    num_bits: usize,
+    num_items: usize,
+    used_bytes: usize,
+    buckets: Vec<PageID>,
+    dir_pages: Vec<PageID>,
+    free_list: Option<PageID>,
+}
+
+impl HashIndex {
+    /// Creates a new, empty index.
+    pub fn create(bm: &mut BufferManager) -> Result<Self, String> {
+        let meta_page = new_page(bm)?;
+        let mut index = Self {
+            meta_page,
+            num_bits: 1,
+            num_items: 0,
+            used_bytes: 0,
+            buckets: Vec::new(),
+            dir_pages: Vec::new(),
+            free_list: None,
+        };
+        let bucket = index.allocate_page(bm)?;
+        write_bucket(bm, bucket, &Bucket::default())?;
+        index.push_bucket(bm, bucket)?;
+        index.write_meta(bm)?;
+        Ok(index)
+    }
+
+    /// Opens an existing index, loading its directory from disk.
+    pub fn open(bm: &mut BufferManager, meta_page: PageID) -> Result<Self, String> {
+        let page = fetch_page(bm, meta_page)?;
+        let meta = page.read().unwrap().data;
+        bm.unpin_page(meta_page, false);
+
+        if read_u64(&meta, META_MAGIC) != MAGIC {
+            return Err(format!("page {} is not a hash index", meta_page));
+        }
+        let num_buckets = read_u64(&meta, META_NUM_BUCKETS) as usize;
+        let mut index = Self {
+            meta_page,
+            num_bits: read_u64(&meta, META_NUM_BITS) as usize,
+            num_items: read_u64(&meta, META_NUM_ITEMS) as usize,
+            used_bytes: read_u64(&meta, META_USED_BYTES) as usize,
+            buckets: Vec::with_capacity(num_buckets),
+            dir_pages: Vec::new(),
+            free_list: read_page_id(&meta, META_FREE_LIST),
+        };
+
+        let mut next_dir = read_page_id(&meta, META_DIRECTORY);
+        while let Some(dir) = next_dir {
+            let page = fetch_page(bm, dir)?;
+            let data = page.read().unwrap().data;
+            bm.unpin_page(dir, false);
+
+            let n = (num_buckets - index.buckets.len()).min(DIR_ENTRIES_PER_PAGE);
+            for i in 0..n {
+                index.buckets.push(read_u64(&data, 8 + 8 * i) as PageID);
+            }
+            index.dir_pages.push(dir);
+            next_dir = read_page_id(&data, 0);
+        }
+
+        if index.buckets.len() != num_buckets {
+            return Err(format!(
+                "hash index directory at page {} is truncated",
+                meta_page
+            ));
+        }
+        Ok(index)
+    }
+
+    /// The page ID needed to reopen this index.
+    pub fn meta_page(&self) -> PageID {
+        self.meta_page
+    }
+
+    /// Number of entries in the index.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.num_items
+    }
+
+    /// Deletes all pages of the index.
+    pub fn destroy(self, bm: &mut BufferManager) -> Result<(), String> {
+        let mut pages = vec![self.meta_page];
+        pages.extend(&self.dir_pages);
+        for &bucket in &self.buckets {
+            let mut next = Some(bucket);
+            while let Some(page) = next {
+                pages.push(page);
+                next = read_bucket(bm, page)?.overflow;
+            }
+        }
+        let mut next = self.free_list;
+        while let Some(page) = next {
+            pages.push(page);
+            next = read_bucket(bm, page)?.overflow;
+        }
+        for page in pages {
+            bm.delete_page(page);
+        }
+        Ok(())
+    }
+
+    /// Adds an entry for the given key, which may already be present.
+    pub fn insert(
+        &mut self,
+        bm: &mut BufferManager,
+        key: &[u8],
+        rid: RecordId,
+    ) -> Result<(), String> {
+        if key.len() > MAX_KEY_SIZE {
+            return Err(format!(
+                "hash index key of {} bytes exceeds maximum of {} bytes",
+                key.len(),
+                MAX_KEY_SIZE
+            ));
+        }
+
+        let mut page = self.buckets[self.bucket(hash_key(key))];
+        loop {
+            let mut bucket = read_bucket(bm, page)?;
+            if bucket.size() + entry_size(key) <= PAGE_SIZE {
+                bucket.entries.push((key.to_vec(), rid));
+                write_bucket(bm, page, &bucket)?;
+                break;
+            }
+            match bucket.overflow {
+                Some(next) => page = next,
+                None => {
+                    let overflow = self.allocate_page(bm)?;
+                    let new_bucket = Bucket {
+                        overflow: None,
+                        entries: vec![(key.to_vec(), rid)],
+                    };
+                    write_bucket(bm, overflow, &new_bucket)?;
+                    bucket.overflow = Some(overflow);
+                    write_bucket(bm, page, &bucket)?;
+                    break;
+                }
+            }
+        }
+        self.num_items += 1;
+        self.used_bytes += entry_size(key);
+
+        if self.should_split() {
+            self.split(bm)?;
+        }
+        self.write_meta(bm)
+    }
+
+    /// Returns the record IDs of all entries with the given key.
+    pub fn get(&self, bm: &mut BufferManager, key: &[u8]) -> Result<Vec<RecordId>, String> {
+        let mut rids = Vec::new();
+        let mut next = Some(self.buckets[self.bucket(hash_key(key))]);
+        while let Some(page) = next {
+            let bucket = read_bucket(bm, page)?;
+            rids.extend(
+                bucket
+                    .entries
+                    .iter()
+                    .filter(|(k, _)| k == key)
+                    .map(|(_, r)| *r),
+            );
+            next = bucket.overflow;
+        }
+        Ok(rids)
+    }
+
+    /// Removes the entry with the given key and record ID.
+    /// Returns whether such an entry existed.
+    pub fn remove(
+        &mut self,
+        bm: &mut BufferManager,
+        key: &[u8],
+        rid: RecordId,
+    ) -> Result<bool, String> {
+        let mut next = Some(self.buckets[self.bucket(hash_key(key))]);
+        while let Some(page) = next {
+            let mut bucket = read_bucket(bm, page)?;
+            if let Some(i) = bucket
+                .entries
+                .iter()
+                .position(|(k, r)| k == key && *r == rid)
+            {
+                bucket.entries.swap_remove(i);
+                write_bucket(bm, page, &bucket)?;
+                self.num_items -= 1;
+                self.used_bytes -= entry_size(key);
+                self.write_meta(bm)?;
+                return Ok(true);
+            }
+            next = bucket.overflow;
+        }
+        Ok(false)
+    }
+
+    /// Adds one bucket, splitting the entries of the next bucket in line between it and the new one.
+    /// The bucket is read and rewritten in a single pass, surplus overflow pages are recycled.
+    fn split(&mut self, bm: &mut BufferManager) -> Result<(), String> {
+        if self.buckets.len() == (1 << self.num_bits) {
+            self.num_bits += 1;
+        }
+
+        let b_split = self.buckets.len() - (1 << (self.num_bits - 1));
+        let new_page = self.allocate_page(bm)?;
+        self.push_bucket(bm, new_page)?;
+
+        let mut chain = Vec::new();
+        let mut entries = Vec::new();
+        let mut next = Some(self.buckets[b_split]);
+        while let Some(page) = next {
+            let bucket = read_bucket(bm, page)?;
+            chain.push(page);
+            entries.extend(bucket.entries);
+            next = bucket.overflow;
+        }
+
+        let (stay, moved): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .partition(|(k, _)| self.bucket(hash_key(k)) == b_split);
+        self.write_chain(bm, chain, stay)?;
+        self.write_chain(bm, vec![new_page], moved)
+    }
+
+    /// Packs the entries into the given chain of pages, allocating more pages if necessary.
+    /// Pages that are no longer needed are put on the free list.
+    fn write_chain(
+        &mut self,
+        bm: &mut BufferManager,
+        mut pages: Vec<PageID>,
+        entries: Vec<(Vec<u8>, RecordId)>,
+    ) -> Result<(), String> {
+        pages.reverse();
+        let mut page = pages.pop().unwrap();
+        let mut bucket = Bucket::default();
+        for (key, rid) in entries {
+            if bucket.size() + entry_size(&key) > PAGE_SIZE {
+                let next = match pages.pop() {
+                    Some(p) => p,
+                    None => self.allocate_page(bm)?,
+                };
+                bucket.overflow = Some(next);
+                write_bucket(bm, page, &bucket)?;
+                page = next;
+                bucket = Bucket::default();
+            }
+            bucket.entries.push((key, rid));
+        }
+        write_bucket(bm, page, &bucket)?;
+
+        for page in pages {
+            let free = Bucket {
+                overflow: self.free_list,
+                entries: Vec::new(),
+            };
+            write_bucket(bm, page, &free)?;
+            self.free_list = Some(page);
+        }
+        Ok(())
+    }
+
+    fn should_split(&self) -> bool {
+        let capacity = self.buckets.len() * (PAGE_SIZE - BUCKET_HEADER);
+        self.used_bytes as f32 / capacity as f32 > FDC
+    }
+
+    fn bucket(&self, h: u64) -> usize {
+        let h = (h % (1 << self.num_bits)) as usize;
+        if h >= self.buckets.len() {
+            h - (1 << (self.num_bits - 1))
+        } else {
+            h
+        }
+    }
+
+    /// Takes a page from the free list, or allocates a new one.
+    fn allocate_page(&mut self, bm: &mut BufferManager) -> Result<PageID, String> {
+        match self.free_list {
+            Some(page) => {
+                self.free_list = read_bucket(bm, page)?.overflow;
+                Ok(page)
+            }
+            None => new_page(bm),
+        }
+    }
+
+    /// Appends a bucket to the directory, growing the directory by a page if necessary.
+    fn push_bucket(&mut self, bm: &mut BufferManager, bucket: PageID) -> Result<(), String> {
+        let slot = self.buckets.len() % DIR_ENTRIES_PER_PAGE;
+        if slot == 0 {
+            let dir = self.allocate_page(bm)?;
+            let page = fetch_page(bm, dir)?;
+            write_u64(&mut page.write().unwrap().data, 0, NO_PAGE);
+            bm.unpin_page(dir, true);
+            if let Some(&prev) = self.dir_pages.last() {
+                let page = fetch_page(bm, prev)?;
+                write_u64(&mut page.write().unwrap().data, 0, dir as u64);
+                bm.unpin_page(prev, true);
+            }
+            self.dir_pages.push(dir);
+        }
+
+        let dir = *self.dir_pages.last().unwrap();
+        let page = fetch_page(bm, dir)?;
+        write_u64(&mut page.write().unwrap().data, 8 + 8 * slot, bucket as u64);
+        bm.unpin_page(dir, true);
+        self.buckets.push(bucket);
+        Ok(())
+    }
+
+    fn write_meta(&self, bm: &mut BufferManager) -> Result<(), String> {
+        let page = fetch_page(bm, self.meta_page)?;
+        {
+            let data = &mut page.write().unwrap().data;
+            write_u64(data, META_MAGIC, MAGIC);
+            write_u64(data, META_NUM_BITS, self.num_bits as u64);
+            write_u64(data, META_NUM_BUCKETS, self.buckets.len() as u64);
+            write_u64(data, META_NUM_ITEMS, self.num_items as u64);
+            write_u64(data, META_USED_BYTES, self.used_bytes as u64);
+            write_u64(data, META_DIRECTORY, self.dir_pages[0] as u64);
+            write_u64(
+                data,
+                META_FREE_LIST,
+                self.free_list.map_or(NO_PAGE, |p| p as u64),
+            );
+        }
+        bm.unpin_page(self.meta_page, true);
+        Ok(())
+    }
+}
+
+/// 64-bit FNV-1a followed by the MurmurHash3 finalizer, which mixes the low bits used for addressing.
+/// This needs to be stable across builds, since bucket assignments are persisted.
+pub fn hash_key(key: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in key {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x0100_0000_01b3);
+    }
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^= h >> 33;
+    h
+}
+
+/// In-memory representation of a single page of a bucket chain.
+#[derive(Default)]
+struct Bucket {
+    overflow: Option<PageID>,
+    entries: Vec<(Vec<u8>, RecordId)>,
+}
+
+impl Bucket {
+    fn read(data: &[u8; PAGE_SIZE]) -> Self {
+        let n = u16::from_le_bytes(data[8..10].try_into().unwrap()) as usize;
+        let mut entries = Vec::with_capacity(n);
+        let mut pos = BUCKET_HEADER;
+        for _ in 0..n {
+            let len = u16::from_le_bytes(data[pos..pos + 2].try_into().unwrap()) as usize;
+            pos += 2;
+            let key = data[pos..pos + len].to_vec();
+            pos += len;
+            let page = read_u64(data, pos) as PageID;
+            let slot = u16::from_le_bytes(data[pos + 8..pos + 10].try_into().unwrap());
+            pos += 10;
+            entries.push((key, RecordId { page, slot }));
+        }
+        Self {
+            overflow: read_page_id(data, 0),
+            entries,
+        }
+    }
+
+    fn write(&self, data: &mut [u8; PAGE_SIZE]) {
+        write_u64(data, 0, self.overflow.map_or(NO_PAGE, |p| p as u64));
+        data[8..10].copy_from_slice(&(self.entries.len() as u16).to_le_bytes());
+        let mut pos = BUCKET_HEADER;
+        for (key, rid) in &self.entries {
+            data[pos..pos + 2].copy_from_slice(&(key.len() as u16).to_le_bytes());
+            pos += 2;
+            data[pos..pos + key.len()].copy_from_slice(key);
+            pos += key.len();
+            write_u64(data, pos, rid.page as u64);
+            data[pos + 8..pos + 10].copy_from_slice(&rid.slot.to_le_bytes());
+            pos += 10;
+        }
+    }
+
+    /// Number of bytes this bucket occupies on its page.
+    fn size(&self) -> usize {
+        BUCKET_HEADER
+            + self
+                .entries
+                .iter()
+                .map(|(k, _)| entry_size(k))
+                .sum::<usize>()
+    }
+}
+
+fn entry_size(key: &[u8]) -> usize {
+    ENTRY_OVERHEAD + key.len()
+}
+
+fn read_bucket(bm: &mut BufferManager, page: PageID) -> Result<Bucket, String> {
+    let p = fetch_page(bm, page)?;
+    let bucket = Bucket::read(&p.read().unwrap().data);
+    bm.unpin_page(page, false);
+    Ok(bucket)
+}
+
+fn write_bucket(bm: &mut BufferManager, page: PageID, bucket: &Bucket) -> Result<(), String> {
+    let p = fetch_page(bm, page)?;
+    bucket.write(&mut p.write().unwrap().data);
+    bm.unpin_page(page, true);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::disk_manager::TempFile;
+
+    fn buffer_manager(name: &str) -> (TempFile, BufferManager) {
+        let file = TempFile::new(&format!("hash_index_{}", name));
+        let bm = BufferManager::with_file(16, file.path());
+        (file, bm)
+    }
+
+    fn rid(i: usize) -> RecordId {
+        RecordId {
+            page: i / 100,
+            slot: (i % 100) as u16,
+        }
+    }
+
+    #[test]
+    fn insert_get() {
+        let (_file, mut bm) = buffer_manager("insert_get");
+        let mut index = HashIndex::create(&mut bm).unwrap();
+        index.insert(&mut bm, b"alice", rid(1)).unwrap();
+        index.insert(&mut bm, b"bob", rid(2)).unwrap();
+        index.insert(&mut bm, b"alice", rid(3)).unwrap();
+        assert_eq!(index.get(&mut bm, b"alice").unwrap(), vec![rid(1), rid(3)]);
+        assert_eq!(index.get(&mut bm, b"bob").unwrap(), vec![rid(2)]);
+        assert_eq!(index.get(&mut bm, b"carol").unwrap(), vec![]);
+        assert_eq!(index.len(), 3);
+    }
+
+    #[test]
+    fn a_lot_of_items() {
+        let (_file, mut bm) = buffer_manager("a_lot_of_items");
+        let mut index = HashIndex::create(&mut bm).unwrap();
+        for i in 0..20_000 {
+            index
+                .insert(&mut bm, format!("key{}", i).as_bytes(), rid(i))
+                .unwrap();
+        }
+        assert!(index.buckets.len() > 100);
+        for i in 0..20_000 {
+            assert_eq!(
+                index.get(&mut bm, format!("key{}", i).as_bytes()).unwrap(),
+                vec![rid(i)]
+            );
+        }
+
+        for i in (0..20_000).step_by(2) {
+            assert!(index
+                .remove(&mut bm, format!("key{}", i).as_bytes(), rid(i))
+                .unwrap());
+        }
+        assert!(!index.remove(&mut bm, b"key0", rid(0)).unwrap());
+        assert_eq!(index.len(), 10_000);
+        for i in 0..20_000 {
+            let expected = if i % 2 == 0 { vec![] } else { vec![rid(i)] };
+            assert_eq!(
+                index.get(&mut bm, format!("key{}", i).as_bytes()).unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn overflow_chains() {
+        let (_file, mut bm) = buffer_manager("overflow_chains");
+        let mut index = HashIndex::create(&mut bm).unwrap();
+        let key = [7u8; 1000];
+        for i in 0..50 {
+            index.insert(&mut bm, &key, rid(i)).unwrap();
+        }
+        let rids = index.get(&mut bm, &key).unwrap();
+        assert_eq!(rids.len(), 50);
+        assert!(index
+            .insert(&mut bm, &[0u8; MAX_KEY_SIZE + 1], rid(0))
+            .is_err());
+    }
+
+    #[test]
+    fn reopen() {
+        let (_file, mut bm) = buffer_manager("reopen");
+        let mut index = HashIndex::create(&mut bm).unwrap();
+        for i in 0..5000 {
+            index
+                .insert(&mut bm, &(i as u64).to_be_bytes(), rid(i))
+                .unwrap();
+        }
+        let meta_page = index.meta_page();
+
+        let index = HashIndex::open(&mut bm, meta_page).unwrap();
+        assert_eq!(index.len(), 5000);
+        for i in 0..5000 {
+            assert_eq!(
+                index.get(&mut bm, &(i as u64).to_be_bytes()).unwrap(),
+                vec![rid(i)]
+            );
+        }
+    }
+}
@@ -1,99 +1,577 @@
 // Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
 // Distributed under terms of the MIT license.
 
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{Read, Write};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
 
-const B: usize = 36;
-const M: usize = 8;
+use sqlparser::ast::Expr;
 
-/// One-pass Hash Join for two relations, where one fits into main memory.
-pub fn simple_hash_join(path: &str) {
-    let hash_table: HashMap<&str> = HashMap::new();
+use crate::binder::{Field, JoinKind};
+use crate::database::Database;
+use crate::executor::{close_on_error, is_true, row_size, Operator, PlanRow, WORK_MEM_PAGES};
+use crate::extensible_hash;
+use crate::page::PAGE_SIZE;
+use crate::spill_file::{SpillFile, SpillReader};
+use crate::tuple::Tuple;
+use crate::value::Value;
+
+/// Maximum number of partitions the inputs are split into at once.
+const MAX_FANOUT: usize = 32;
+
+/// Partitions are split again at most this many times. The rows of a partition
+/// that is still too large after that mostly have the same key and are joined in memory anyway.
+pub const MAX_DEPTH: usize = 4;
+
+/// Hash join, the rows of the right (build) input are kept in a hash table
+/// and the rows of the left (probe) input look up their matches in it.
+///
+/// Rows match if their keys are equal and the residual condition is true for the joined row.
+/// Keys containing NULL never match, the rows are still padded by outer joins
+/// and returned by anti joins (like `NOT EXISTS`).
+///
+/// If the build input does not fit into memory, the join becomes a hybrid hash join:
+/// both inputs are partitioned by the hash of their keys, the first partition is joined
+/// in memory right away and the others are written to spill files and joined one after another.
+/// Partitions that still do not fit are partitioned again with a different hash.
+pub struct HashJoin {
+    left: Box<dyn Operator>,
+    right: Box<dyn Operator>,
+    kind: JoinKind,
+    /// Expressions on the left and right rows whose values have to be equal.
+    left_keys: Vec<Expr>,
+    right_keys: Vec<Expr>,
+    condition: Option<Expr>,
+    /// The fields of both inputs, which the condition refers to.
+    condition_fields: Vec<Field>,
+    fields: Vec<Field>,
+    /// Bytes of build rows kept in memory.
+    memory: usize,
+    /// The build rows that are joined in memory.
+    table: HashTable,
+    /// The partitions the inputs are split into, `None` for the one kept in memory.
+    /// Empty while all build rows fit into memory.
+    partitions: Vec<Option<Partition>>,
+    /// Spilled partitions that have not been joined yet.
+    spilled: Vec<Partition>,
+    /// The spilled partition being joined and the reader of its probe rows.
+    current: Option<(Partition, SpillReader)>,
+    /// Number of partitions joined in memory.
+    batches: usize,
+    pending: VecDeque<Tuple>,
+    phase: Phase,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Phase {
+    Probe,
+    /// Padding the build rows that were not joined, from the given position on.
+    Pad(usize),
+    Done,
+}
+
+/// Build rows with a hash table over their keys.
+#[derive(Default)]
+struct HashTable {
+    rows: Vec<(Vec<Value>, Tuple)>,
+    positions: extensible_hash::HashTable<Vec<Value>, Vec<usize>>,
+    /// Whether each row has been joined, for right and full joins.
+    matched: Vec<bool>,
+    /// Estimated bytes of memory used by the rows.
+    size: usize,
+}
+
+impl HashTable {
+    fn insert(&mut self, key: Vec<Value>, row: Tuple) {
+        self.size += row_size(&key) + row_size(&row);
+        // rows with NULL keys never match, they are only kept to be padded
+        if !key.iter().any(Value::is_null) {
+            self.positions
+                .entry(key.clone())
+                .or_default()
+                .push(self.rows.len());
+        }
+        self.rows.push((key, row));
+        self.matched.push(false);
+    }
+}
+
+/// Rows of both inputs with the same hash, written to spill files with their keys in front.
+struct Partition {
+    build: SpillFile,
+    probe: SpillFile,
+    /// How often the rows have been partitioned.
+    depth: usize,
+}
+
+impl Partition {
+    fn create(depth: usize) -> Result<Self, String> {
+        Ok(Self {
+            build: SpillFile::create()?,
+            probe: SpillFile::create()?,
+            depth,
+        })
+    }
+}
+
+/// The partition of a key, using a different hash function for each depth.
+pub fn partition_of(key: &[Value], depth: usize, partitions: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    depth.hash(&mut hasher);
+    key.hash(&mut hasher);
+    (hasher.finish() % partitions as u64) as usize
 }
 
-/// Grace Hash Join
-pub fn grace_hash_join(path: &str) {
-    let mut f = File::open(path).unwrap();
-    let mut buffers = [[0u8; 4096]; M];
-    let runs = (B + M - 1) / M;
+/// Number of partitions written at once with the given memory in bytes,
+/// each of them needs a page to buffer its rows.
+pub fn fanout(memory: usize) -> usize {
+    (memory / PAGE_SIZE).saturating_sub(1).clamp(2, MAX_FANOUT)
+}
+
+impl HashJoin {
+    pub fn new(
+        left: Box<dyn Operator>,
+        right: Box<dyn Operator>,
+        kind: JoinKind,
+        left_keys: Vec<Expr>,
+        right_keys: Vec<Expr>,
+        condition: Option<Expr>,
+        fields: Vec<Field>,
+    ) -> Self {
+        Self {
+            condition_fields: [left.schema(), right.schema()].concat(),
+            left,
+            right,
+            kind,
+            left_keys,
+            right_keys,
+            condition,
+            fields,
+            memory: WORK_MEM_PAGES * PAGE_SIZE,
+            table: HashTable::default(),
+            partitions: Vec::new(),
+            spilled: Vec::new(),
+            current: None,
+            batches: 0,
+            pending: VecDeque::new(),
+            phase: Phase::Done,
+        }
+    }
+
+    /// Limits the build rows held in memory to about the given number of pages.
+    #[cfg(test)]
+    pub fn with_buffer_pages(mut self, pages: usize) -> Self {
+        self.memory = pages.max(1) * PAGE_SIZE;
+        self
+    }
+
+    /// Reads the rows of the opened build input into the hash table and the partitions.
+    fn build(&mut self, db: &mut Database) -> Result<(), String> {
+        while let Some(row) = self.right.next(db)? {
+            let key = key(db, &self.right_keys, self.right.schema(), &row)?;
+            self.add_build_row(key, row)?;
+        }
+        Ok(())
+    }
 
-    if runs > M - 1 {
-        panic!("file too large for Multi-Way Merge-Sort");
+    fn add_build_row(&mut self, key: Vec<Value>, row: Tuple) -> Result<(), String> {
+        if !self.partitions.is_empty() {
+            let p = partition_of(&key, 0, self.partitions.len());
+            if let Some(partition) = &mut self.partitions[p] {
+                return partition.build.write(&[key, row].concat());
+            }
+        }
+        self.table.insert(key, row);
+        if self.table.size > self.memory {
+            self.spill_table()?;
+        }
+        Ok(())
+    }
+
+    /// Partitions the inputs because the build rows do not fit into memory,
+    /// or spills the partition kept in memory if it does not fit either.
+    fn spill_table(&mut self) -> Result<(), String> {
+        if self.partitions.is_empty() {
+            self.partitions.push(None);
+            for _ in 1..fanout(self.memory) {
+                self.partitions.push(Some(Partition::create(0)?));
+            }
+        } else {
+            self.partitions[0] = Some(Partition::create(0)?);
+        }
+        for (key, row) in std::mem::take(&mut self.table).rows {
+            let p = partition_of(&key, 0, self.partitions.len());
+            match &mut self.partitions[p] {
+                Some(partition) => partition.build.write(&[key, row].concat())?,
+                None => self.table.insert(key, row),
+            }
+        }
+        Ok(())
     }
 
-    // Sort runs
-    for run in 0..runs {
-        for i in 0..M {
-            if f.read(&mut buffers[i]).unwrap() == 0 {
-                break;
+    /// Loads the next spilled partition into memory, returns whether there was one.
+    fn next_partition(&mut self) -> Result<bool, String> {
+        self.table = HashTable::default();
+        self.current = None;
+        let keys = self.right_keys.len();
+        'partitions: while let Some(mut partition) = self.spilled.pop() {
+            // without rows on one side, only unmatched rows of the other side can be returned
+            let unmatched_left =
+                matches!(self.kind, JoinKind::Left | JoinKind::Full | JoinKind::Anti);
+            let unmatched_right = matches!(self.kind, JoinKind::Right | JoinKind::Full);
+            if (partition.build.is_empty() && !unmatched_left)
+                || (partition.probe.is_empty() && !unmatched_right)
+            {
+                continue;
             }
+            let mut build = partition.build.read()?;
+            while let Some(mut key) = build.next()? {
+                let row = key.split_off(keys);
+                self.table.insert(key, row);
+                if self.table.size > self.memory && partition.depth < MAX_DEPTH {
+                    self.repartition(partition, build)?;
+                    continue 'partitions;
+                }
+            }
+            let probe = partition.probe.read()?;
+            self.current = Some((partition, probe));
+            self.batches += 1;
+            return Ok(true);
         }
+        Ok(false)
+    }
 
-        let mut sorted = buffers.concat();
-        sorted.sort();
-
-        let mut out = File::create(format!("temp{}.txt", run)).unwrap();
-        out.write(&sorted).unwrap();
-    }
-
-    // Merge sorted runs
-    let mut out = File::create("out.txt").unwrap();
-    let mut f_run = Vec::new();
-    for run in 0..runs {
-        f_run.push(File::open(format!("temp{}.txt", run)).unwrap());
-        f_run[run].read(&mut buffers[run]).unwrap();
-    }
-    let mut pos = [0usize; M - 1];
-    let mut done = [false; M - 1];
-    let mut done_with = 0;
-    let mut out_filled = 0;
-    loop {
-        // find min
-        let mut min_i = 0;
-        let mut min = 255;
-        for i in 0..runs {
-            if !done[i] && buffers[i][pos[i]] <= min {
-                min_i = i;
-                min = buffers[i][pos[i]];
+    /// Splits a partition that does not fit into memory,
+    /// the build rows read so far are in the hash table and the others still in `build`.
+    fn repartition(
+        &mut self,
+        mut partition: Partition,
+        mut build: SpillReader,
+    ) -> Result<(), String> {
+        let depth = partition.depth + 1;
+        let fanout = fanout(self.memory);
+        let mut parts = (0..fanout)
+            .map(|_| Partition::create(depth))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut rows = std::mem::take(&mut self.table).rows.into_iter();
+        let mut next_build = || match rows.next() {
+            Some((key, row)) => Ok(Some([key, row].concat())),
+            None => build.next(),
+        };
+        while let Some(row) = next_build()? {
+            let p = partition_of(&row[..self.right_keys.len()], depth, fanout);
+            parts[p].build.write(&row)?;
+        }
+        let mut probe = partition.probe.read()?;
+        while let Some(row) = probe.next()? {
+            let p = partition_of(&row[..self.left_keys.len()], depth, fanout);
+            parts[p].probe.write(&row)?;
+        }
+        self.spilled.extend(parts);
+        Ok(())
+    }
+
+    /// Joins a probe row with its matching build rows.
+    fn probe(&mut self, db: &mut Database, key: Vec<Value>, row: Tuple) -> Result<(), String> {
+        let positions = &self.table.positions;
+        let candidates = match positions.get(&key) {
+            Some(candidates) => candidates.as_slice(),
+            None => &[],
+        };
+        let mut matched = false;
+        for &i in candidates {
+            let mut joined = row.clone();
+            joined.extend_from_slice(&self.table.rows[i].1);
+            let joins = match &self.condition {
+                Some(condition) => is_true(db, condition, &self.condition_fields, &joined)?,
+                None => true,
+            };
+            if joins {
+                matched = true;
+                self.table.matched[i] = true;
+                match self.kind {
+                    JoinKind::Semi | JoinKind::Anti => break,
+                    _ => self.pending.push_back(joined),
+                }
+            }
+        }
+        match self.kind {
+            JoinKind::Semi if matched => self.pending.push_back(row),
+            JoinKind::Anti if !matched => self.pending.push_back(row),
+            JoinKind::Left | JoinKind::Full if !matched => {
+                let mut padded = row;
+                padded.resize(self.fields.len(), Value::Null);
+                self.pending.push_back(padded);
             }
+            _ => {}
         }
+        Ok(())
+    }
 
-        // read new block if necessary
-        pos[min_i] += 1;
-        if pos[min_i] == 4096 {
-            if f_run[min_i].read(&mut buffers[min_i]).unwrap() == 0 {
-                done[min_i] = true;
-                done_with += 1;
-                if done_with == runs {
-                    break;
+    /// Returns the next probe row with its key, spilling the rows of the left input
+    /// that belong to a spilled partition. `None` once the probe rows are exhausted.
+    fn next_probe_row(&mut self, db: &mut Database) -> Result<Option<(Vec<Value>, Tuple)>, String> {
+        if let Some((_, probe)) = &mut self.current {
+            return Ok(probe.next()?.map(|mut key| {
+                let row = key.split_off(self.left_keys.len());
+                (key, row)
+            }));
+        }
+        while let Some(row) = self.left.next(db)? {
+            let key = key(db, &self.left_keys, self.left.schema(), &row)?;
+            // rows with NULL keys cannot match and are handled right away
+            if !self.partitions.is_empty() && !key.iter().any(Value::is_null) {
+                let p = partition_of(&key, 0, self.partitions.len());
+                if let Some(partition) = &mut self.partitions[p] {
+                    partition.probe.write(&[key, row].concat())?;
+                    continue;
                 }
             }
-            pos[min_i] = 0;
+            return Ok(Some((key, row)));
         }
+        self.spilled.extend(self.partitions.drain(..).flatten());
+        Ok(None)
+    }
+}
+
+/// Evaluates the key expressions for a row.
+pub fn key(
+    db: &mut Database,
+    exprs: &[Expr],
+    fields: &[Field],
+    values: &[Value],
+) -> Result<Vec<Value>, String> {
+    let row = PlanRow { fields, values };
+    exprs.iter().map(|expr| db.eval(expr, &row)).collect()
+}
+
+impl Operator for HashJoin {
+    fn open(&mut self, db: &mut Database) -> Result<(), String> {
+        self.table = HashTable::default();
+        self.partitions.clear();
+        self.spilled.clear();
+        self.current = None;
+        self.batches = 1;
+        self.pending.clear();
+        self.right.open(db)?;
+        let result = self.build(db).and_then(|_| self.right.close(db));
+        close_on_error(result, db, &mut [self.right.as_mut()])?;
+        self.phase = Phase::Probe;
+        self.left.open(db)
+    }
 
-        // write to out buffer
-        buffers[M - 1][out_filled] = min;
-        out_filled += 1;
-        if out_filled == 4096 {
-            out.write(&buffers[M - 1]).unwrap();
-            out_filled = 0;
+    fn next(&mut self, db: &mut Database) -> Result<Option<Tuple>, String> {
+        loop {
+            if let Some(row) = self.pending.pop_front() {
+                return Ok(Some(row));
+            }
+            match self.phase {
+                Phase::Probe => match self.next_probe_row(db)? {
+                    Some((key, row)) => self.probe(db, key, row)?,
+                    None => self.phase = Phase::Pad(0),
+                },
+                Phase::Pad(position) => {
+                    // right and full joins pad the build rows that were not joined
+                    if matches!(self.kind, JoinKind::Right | JoinKind::Full) {
+                        let unmatched = self.table.matched[position..].iter().position(|&m| !m);
+                        if let Some(i) = unmatched {
+                            let mut row = vec![Value::Null; self.left.schema().len()];
+                            row.extend_from_slice(&self.table.rows[position + i].1);
+                            self.phase = Phase::Pad(position + i + 1);
+                            return Ok(Some(row));
+                        }
+                    }
+                    self.phase = if self.next_partition()? {
+                        Phase::Probe
+                    } else {
+                        Phase::Done
+                    };
+                }
+                Phase::Done => return Ok(None),
+            }
         }
     }
 
-    if out_filled > 0 {
-        out.write(&buffers[M - 1][..out_filled + 1]).unwrap();
+    fn close(&mut self, db: &mut Database) -> Result<(), String> {
+        // dropping the partitions deletes their spill files
+        self.table = HashTable::default();
+        self.partitions.clear();
+        self.spilled.clear();
+        self.current = None;
+        self.pending.clear();
+        self.phase = Phase::Done;
+        let left = self.left.close(db);
+        let right = self.right.close(db);
+        left.and(right)
+    }
+
+    fn schema(&self) -> &[Field] {
+        &self.fields
     }
-    out.flush().unwrap();
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use crate::executor::{check_join_kinds, collect, values, TempDatabase};
+    use crate::sql::parse_expr;
+
+    fn keys(table: &str, columns: &[&str]) -> Vec<Expr> {
+        columns
+            .iter()
+            .map(|c| parse_expr(&format!("{}.{}", table, c)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn join_kinds() {
+        let mut db = TempDatabase::open("hash_join");
+        let left = [
+            [Some(1), Some(1), Some(10)],
+            [Some(1), Some(2), Some(20)],
+            [Some(2), Some(2), Some(30)],
+            [None, Some(1), Some(40)],
+            [Some(1), Some(1), Some(50)],
+        ];
+        let right = [
+            [Some(1), Some(1), Some(5)],
+            [Some(1), Some(1), Some(60)],
+            [Some(2), Some(2), Some(70)],
+            [Some(1), None, Some(80)],
+            [Some(3), Some(3), Some(90)],
+        ];
+        let condition = "l.c < r.c";
+        let counts = [3, 3 + 2, 3 + 3, 3 + 2 + 3, 3, 2];
+        check_join_kinds(
+            &mut db,
+            &left,
+            &right,
+            &["a", "b"],
+            condition,
+            counts,
+            |kind, l, r, fields| {
+                let (left_keys, right_keys) = (keys("l", &["a", "b"]), keys("r", &["a", "b"]));
+                let condition = Some(parse_expr(condition).unwrap());
+                HashJoin::new(l, r, kind, left_keys, right_keys, condition, fields)
+            },
+        );
+    }
+
     #[test]
-    fn mwms() {
-        multiway_merge_sort("1.txt");
+    fn partitioned() {
+        let mut db = TempDatabase::open("hybrid_hash_join");
+        let rows = |table, n: i64, key: &dyn Fn(i64) -> Option<i64>| {
+            let rows: Vec<_> = (0..n).map(|i| [key(i), Some(0), Some(i)]).collect();
+            values(table, &rows)
+        };
+        // many keys spread over the partitions, and a single key that cannot be split
+        let uniform = |i| if i % 100 == 0 { None } else { Some(i % 700) };
+        let skewed = |_| Some(7);
+        for (left_key, right_key, left_rows, right_rows) in [
+            (
+                &uniform as &dyn Fn(i64) -> Option<i64>,
+                &uniform as &dyn Fn(i64) -> Option<i64>,
+                2000,
+                3000,
+            ),
+            (&skewed, &skewed, 30, 2000),
+        ] {
+            for kind in [
+                JoinKind::Inner,
+                JoinKind::Left,
+                JoinKind::Right,
+                JoinKind::Full,
+                JoinKind::Semi,
+                JoinKind::Anti,
+            ] {
+                let join = |pages| {
+                    let (l, r) = (
+                        rows("l", left_rows, left_key),
+                        rows("r", right_rows, right_key),
+                    );
+                    let fields = match kind {
+                        JoinKind::Semi | JoinKind::Anti => l.schema().to_vec(),
+                        _ => [l.schema(), r.schema()].concat(),
+                    };
+                    let condition = parse_expr("l.c % 3 <> r.c % 3").unwrap();
+                    HashJoin::new(
+                        l,
+                        r,
+                        kind,
+                        keys("l", &["a"]),
+                        keys("r", &["a"]),
+                        Some(condition),
+                        fields,
+                    )
+                    .with_buffer_pages(pages)
+                };
+                let mut in_memory = join(10_000);
+                let mut expected = collect(&mut in_memory, &mut db).unwrap();
+                assert_eq!(in_memory.batches, 1);
+
+                let mut hybrid = join(4);
+                hybrid.open(&mut db).unwrap();
+                let mut rows = Vec::new();
+                let mut files: Vec<_> = hybrid
+                    .partitions
+                    .iter()
+                    .flatten()
+                    .map(|p| p.build.path().to_path_buf())
+                    .collect();
+                while let Some(row) = hybrid.next(&mut db).unwrap() {
+                    rows.push(row);
+                    let spilled = hybrid
+                        .spilled
+                        .iter()
+                        .chain(hybrid.current.iter().map(|c| &c.0));
+                    for partition in spilled {
+                        files.push(partition.build.path().to_path_buf());
+                        files.push(partition.probe.path().to_path_buf());
+                    }
+                }
+                assert!(hybrid.batches > 1);
+                hybrid.close(&mut db).unwrap();
+                assert!(!files.is_empty());
+                assert!(files.iter().all(|file| !file.exists()));
+
+                expected.sort();
+                rows.sort();
+                assert_eq!(rows, expected, "{:?}", kind);
+            }
+        }
+
+        // spill files are deleted if the join fails
+        let (l, r) = (rows("l", 1000, &uniform), rows("r", 1000, &uniform));
+        let fields = [l.schema(), r.schema()].concat();
+        let condition = parse_expr("l.c / (r.c - 999) = 0").unwrap();
+        let mut failing = HashJoin::new(
+            l,
+            r,
+            JoinKind::Inner,
+            keys("l", &["a"]),
+            keys("r", &["a"]),
+            Some(condition),
+            fields,
+        )
+        .with_buffer_pages(2);
+        failing.open(&mut db).unwrap();
+        let files: Vec<_> = failing
+            .partitions
+            .iter()
+            .flatten()
+            .map(|p| p.build.path().to_path_buf())
+            .collect();
+        assert!(!files.is_empty());
+        let result = loop {
+            match failing.next(&mut db) {
+                Ok(Some(_)) => {}
+                result => break result,
+            }
+        };
+        assert!(result.is_err());
+        assert!(files.iter().any(|file| file.exists()));
+        failing.close(&mut db).unwrap();
+        assert!(files.iter().all(|file| !file.exists()));
     }
 }
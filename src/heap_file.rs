@@ -0,0 +1,238 @@
+// Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use crate::buffer_manager::{fetch_page, new_page, BufferManager};
+use crate::page::{PageID, RecordId, MAX_TUPLE_SIZE};
+
+/// An unordered collection of tuples, stored in a chain of slotted heap pages.
+/// Only the first page is needed to reopen a heap file, it never changes.
+#[derive(Clone, Copy, Debug)]
+pub struct HeapFile {
+    first_page: PageID,
+    /// New tuples are appended here, free space in earlier pages is only reused by updates.
+    last_page: PageID,
+}
+
+impl HeapFile {
+    /// Creates a new, empty heap file.
+    pub fn create(bm: &mut BufferManager) -> Result<Self, String> {
+        let page = new_page(bm)?;
+        let p = fetch_page(bm, page)?;
+        p.write().unwrap().init_heap_page();
+        bm.unpin_page(page, true);
+        Ok(Self {
+            first_page: page,
+            last_page: page,
+        })
+    }
+
+    /// Opens the heap file starting at the given page.
+    pub fn open(bm: &mut BufferManager, first_page: PageID) -> Result<Self, String> {
+        let pages = Self::pages_from(bm, first_page)?;
+        Ok(Self {
+            first_page,
+            last_page: *pages.last().unwrap(),
+        })
+    }
+
+    pub fn first_page(&self) -> PageID {
+        self.first_page
+    }
+
+    /// IDs of all pages, in chain order.
+    pub fn pages(&self, bm: &mut BufferManager) -> Result<Vec<PageID>, String> {
+        Self::pages_from(bm, self.first_page)
+    }
+
+    /// Stores a new tuple, returns its record ID.
+    pub fn insert(&mut self, bm: &mut BufferManager, tuple: &[u8]) -> Result<RecordId, String> {
+        if tuple.len() > MAX_TUPLE_SIZE {
+            return Err(format!(
+                "tuple of {} bytes exceeds maximum of {} bytes",
+                tuple.len(),
+                MAX_TUPLE_SIZE
+            ));
+        }
+
+        let p = fetch_page(bm, self.last_page)?;
+        let slot = p.write().unwrap().add_tuple(tuple);
+        if let Some(slot) = slot {
+            bm.unpin_page(self.last_page, true);
+            return Ok(RecordId {
+                page: self.last_page,
+                slot,
+            });
+        }
+
+        let page = match new_page(bm) {
+            Ok(page) => page,
+            Err(err) => {
+                bm.unpin_page(self.last_page, false);
+                return Err(err);
+            }
+        };
+        p.write().unwrap().set_next_page(Some(page));
+        bm.unpin_page(self.last_page, true);
+        self.last_page = page;
+
+        let p = fetch_page(bm, page)?;
+        let mut guard = p.write().unwrap();
+        guard.init_heap_page();
+        let slot = guard.add_tuple(tuple).unwrap();
+        drop(guard);
+        bm.unpin_page(page, true);
+        Ok(RecordId { page, slot })
+    }
+
+    /// Returns a copy of the tuple with the given record ID, or `None` if it was deleted.
+    pub fn get(&self, bm: &mut BufferManager, rid: RecordId) -> Result<Option<Vec<u8>>, String> {
+        let p = fetch_page(bm, rid.page)?;
+        let tuple = p.read().unwrap().get_tuple(rid.slot).map(|t| t.to_vec());
+        bm.unpin_page(rid.page, false);
+        Ok(tuple)
+    }
+
+    /// Replaces the tuple with the given record ID.
+    /// The tuple stays in place if possible, otherwise it is moved and gets a new record ID.
+    pub fn update(
+        &mut self,
+        bm: &mut BufferManager,
+        rid: RecordId,
+        tuple: &[u8],
+    ) -> Result<RecordId, String> {
+        let p = fetch_page(bm, rid.page)?;
+        let mut guard = p.write().unwrap();
+        if guard.get_tuple(rid.slot).is_none() {
+            drop(guard);
+            bm.unpin_page(rid.page, false);
+            return Err(format!("tuple {:?} does not exist", rid));
+        }
+        let updated = guard.update_tuple(rid.slot, tuple);
+        drop(guard);
+        bm.unpin_page(rid.page, updated);
+        if updated {
+            return Ok(rid);
+        }
+
+        let new_rid = self.insert(bm, tuple)?;
+        self.delete(bm, rid)?;
+        Ok(new_rid)
+    }
+
+    /// Deletes the tuple with the given record ID, returns whether it existed.
+    pub fn delete(&self, bm: &mut BufferManager, rid: RecordId) -> Result<bool, String> {
+        let p = fetch_page(bm, rid.page)?;
+        let deleted = p.write().unwrap().delete_tuple(rid.slot);
+        bm.unpin_page(rid.page, deleted);
+        Ok(deleted)
+    }
+
+    /// Returns copies of all tuples together with their record IDs.
+    pub fn scan(&self, bm: &mut BufferManager) -> Result<Vec<(RecordId, Vec<u8>)>, String> {
+        let mut tuples = Vec::new();
+        let mut next = Some(self.first_page);
+        while let Some(page) = next {
+            let p = fetch_page(bm, page)?;
+            let guard = p.read().unwrap();
+            for slot in 0..guard.num_slots() {
+                if let Some(tuple) = guard.get_tuple(slot) {
+                    tuples.push((RecordId { page, slot }, tuple.to_vec()));
+                }
+            }
+            next = guard.next_page();
+            drop(guard);
+            bm.unpin_page(page, false);
+        }
+        Ok(tuples)
+    }
+
+    /// Deletes all pages of this heap file.
+    pub fn destroy(self, bm: &mut BufferManager) -> Result<(), String> {
+        for page in self.pages(bm)? {
+            bm.delete_page(page);
+        }
+        Ok(())
+    }
+
+    fn pages_from(bm: &mut BufferManager, first_page: PageID) -> Result<Vec<PageID>, String> {
+        let mut pages = Vec::new();
+        let mut next = Some(first_page);
+        while let Some(page) = next {
+            let p = fetch_page(bm, page)?;
+            next = p.read().unwrap().next_page();
+            bm.unpin_page(page, false);
+            pages.push(page);
+        }
+        Ok(pages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::disk_manager::TempFile;
+
+    fn buffer_manager(name: &str) -> (TempFile, BufferManager) {
+        let file = TempFile::new(&format!("heap_file_{}", name));
+        let bm = BufferManager::with_file(8, file.path());
+        (file, bm)
+    }
+
+    fn tuple(i: usize) -> Vec<u8> {
+        format!("tuple number {}", i).into_bytes()
+    }
+
+    #[test]
+    fn insert_get_scan() {
+        let (_file, mut bm) = buffer_manager("insert");
+        let mut heap = HeapFile::create(&mut bm).unwrap();
+        let rids: Vec<_> = (0..1000)
+            .map(|i| heap.insert(&mut bm, &tuple(i)).unwrap())
+            .collect();
+        assert!(heap.pages(&mut bm).unwrap().len() > 1);
+        for (i, &rid) in rids.iter().enumerate() {
+            assert_eq!(heap.get(&mut bm, rid).unwrap(), Some(tuple(i)));
+        }
+
+        let reopened = HeapFile::open(&mut bm, heap.first_page()).unwrap();
+        let all = reopened.scan(&mut bm).unwrap();
+        assert_eq!(all.len(), 1000);
+        assert!(all
+            .iter()
+            .enumerate()
+            .all(|(i, (rid, t))| *rid == rids[i] && *t == tuple(i)));
+
+        assert!(heap.insert(&mut bm, &[0; MAX_TUPLE_SIZE + 1]).is_err());
+        assert!(heap.insert(&mut bm, &[0; MAX_TUPLE_SIZE]).is_ok());
+    }
+
+    #[test]
+    fn update_delete() {
+        let (_file, mut bm) = buffer_manager("update");
+        let mut heap = HeapFile::create(&mut bm).unwrap();
+        let rids: Vec<_> = (0..200)
+            .map(|i| heap.insert(&mut bm, &tuple(i)).unwrap())
+            .collect();
+
+        assert!(heap.delete(&mut bm, rids[3]).unwrap());
+        assert!(!heap.delete(&mut bm, rids[3]).unwrap());
+        assert_eq!(heap.get(&mut bm, rids[3]).unwrap(), None);
+        assert!(heap.update(&mut bm, rids[3], b"x").is_err());
+
+        // fits in place
+        assert_eq!(heap.update(&mut bm, rids[0], b"short").unwrap(), rids[0]);
+        // has to move to another page
+        let big = vec![1u8; 2000];
+        let moved = heap.update(&mut bm, rids[1], &big).unwrap();
+        assert_ne!(moved, rids[1]);
+        assert_eq!(heap.get(&mut bm, moved).unwrap(), Some(big));
+        assert_eq!(heap.get(&mut bm, rids[1]).unwrap(), None);
+        assert_eq!(heap.scan(&mut bm).unwrap().len(), 199);
+
+        let pages = heap.pages(&mut bm).unwrap();
+        heap.destroy(&mut bm).unwrap();
+        let reused = HeapFile::create(&mut bm).unwrap();
+        assert!(pages.contains(&reused.first_page()));
+    }
+}
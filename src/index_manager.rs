@@ -0,0 +1,586 @@
+// Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::collections::HashMap;
+
+use crate::btree::{self, BTree};
+use crate::buffer_manager::BufferManager;
+use crate::catalog::{IndexKind, IndexMetadata};
+use crate::hash_index::{self, HashIndex};
+use crate::heap_file::HeapFile;
+use crate::page::{PageID, RecordId};
+
+/// A row of a table, from which index keys can be extracted.
+pub trait IndexedRow {
+    /// Encodes the given columns as a memcomparable key (see `key_encoding`).
+    /// Returns `None` if any of the columns is NULL, such keys are not indexed.
+    fn index_key(&self, columns: &[usize]) -> Option<Vec<u8>>;
+}
+
+/// An open index structure.
+pub enum Index {
+    BTree(BTree),
+    Hash(HashIndex),
+}
+
+impl Index {
+    /// Creates a new, empty index structure of the given kind.
+    pub fn create(bm: &mut BufferManager, kind: IndexKind) -> Result<Self, String> {
+        Ok(match kind {
+            IndexKind::BTree => Index::BTree(BTree::create(bm)?),
+            IndexKind::Hash => Index::Hash(HashIndex::create(bm)?),
+        })
+    }
+
+    /// The page the index structure can be reopened from.
+    pub fn root_page(&self) -> PageID {
+        match self {
+            Index::BTree(tree) => tree.meta_page(),
+            Index::Hash(hash) => hash.meta_page(),
+        }
+    }
+
+    /// Frees the pages of the index structure.
+    pub fn destroy(self, bm: &mut BufferManager) -> Result<(), String> {
+        match self {
+            Index::BTree(tree) => tree.destroy(bm),
+            Index::Hash(hash) => hash.destroy(bm),
+        }
+    }
+
+    /// Largest key the index structure can store.
+    fn max_key_size(&self) -> usize {
+        match self {
+            Index::BTree(_) => btree::MAX_KEY_SIZE,
+            Index::Hash(_) => hash_index::MAX_KEY_SIZE,
+        }
+    }
+
+    /// Returns the record IDs of all entries with the given key.
+    pub fn get(&self, bm: &mut BufferManager, key: &[u8]) -> Result<Vec<RecordId>, String> {
+        match self {
+            Index::BTree(tree) => tree.get(bm, key),
+            Index::Hash(hash) => hash.get(bm, key),
+        }
+    }
+
+    fn insert(&mut self, bm: &mut BufferManager, key: &[u8], rid: RecordId) -> Result<(), String> {
+        match self {
+            Index::BTree(tree) => tree.insert(bm, key, rid).map(|_| ()),
+            Index::Hash(hash) => hash.insert(bm, key, rid),
+        }
+    }
+
+    fn remove(
+        &mut self,
+        bm: &mut BufferManager,
+        key: &[u8],
+        rid: RecordId,
+    ) -> Result<bool, String> {
+        match self {
+            Index::BTree(tree) => tree.remove(bm, key, rid),
+            Index::Hash(hash) => hash.remove(bm, key, rid),
+        }
+    }
+}
+
+/// The Index Manager keeps all indexes of a table in sync with its heap.
+/// Every DML operation first checks all constraints, then changes the heap and only then
+/// touches the indexes, so a failing constraint never leaves a partial change behind.
+/// If an index cannot be updated, the entries changed so far and the heap change are undone.
+pub struct IndexManager {
+    indexes: HashMap<usize, Index>,
+}
+
+impl IndexManager {
+    pub fn new() -> Self {
+        Self {
+            indexes: HashMap::new(),
+        }
+    }
+
+    /// Creates the (empty) index structure for the given index.
+    #[cfg(test)]
+    pub fn create_index(
+        &mut self,
+        bm: &mut BufferManager,
+        index: &IndexMetadata,
+    ) -> Result<(), String> {
+        let structure = Index::create(bm, index.kind)?;
+        self.add_index(index.id, structure);
+        Ok(())
+    }
+
+    /// Opens the index structure of an existing index.
+    pub fn open_index(
+        &mut self,
+        bm: &mut BufferManager,
+        index: &IndexMetadata,
+    ) -> Result<(), String> {
+        let structure = match (index.kind, index.root_page) {
+            (IndexKind::BTree, Some(root)) => Index::BTree(BTree::open(bm, root)?),
+            (IndexKind::Hash, Some(root)) => Index::Hash(HashIndex::open(bm, root)?),
+            (_, None) => return Err(format!("index \"{}\" has no root page", index.name)),
+        };
+        self.add_index(index.id, structure);
+        Ok(())
+    }
+
+    /// Registers an already created index structure.
+    pub fn add_index(&mut self, id: usize, structure: Index) {
+        self.indexes.insert(id, structure);
+    }
+
+    /// Removes the index structure and frees its pages.
+    pub fn drop_index(
+        &mut self,
+        bm: &mut BufferManager,
+        index: &IndexMetadata,
+    ) -> Result<(), String> {
+        match self.indexes.remove(&index.id) {
+            Some(structure) => structure.destroy(bm),
+            None => Ok(()),
+        }
+    }
+
+    pub fn get_index(&self, index: &IndexMetadata) -> Option<&Index> {
+        self.indexes.get(&index.id)
+    }
+
+    /// Adds existing rows of the table to a newly created index.
+    /// Fails if the rows violate the index's UNIQUE constraint.
+    pub fn build_index<R, I>(
+        &mut self,
+        bm: &mut BufferManager,
+        index: &IndexMetadata,
+        rows: I,
+    ) -> Result<(), String>
+    where
+        R: IndexedRow,
+        I: IntoIterator<Item = (RecordId, R)>,
+    {
+        let indexes = std::slice::from_ref(index);
+        for (rid, row) in rows {
+            let keys = self.check(bm, indexes, &row, None)?;
+            self.move_entries(bm, indexes, &[None], rid, &keys, rid)?;
+        }
+        Ok(())
+    }
+
+    /// Inserts a new row into the heap and adds it to all the table's indexes.
+    /// Fails without touching the heap if this would violate a UNIQUE constraint.
+    pub fn insert<R: IndexedRow + ?Sized>(
+        &mut self,
+        bm: &mut BufferManager,
+        indexes: &[IndexMetadata],
+        heap: &mut HeapFile,
+        row: &R,
+        data: &[u8],
+    ) -> Result<RecordId, String> {
+        let keys = self.check(bm, indexes, row, None)?;
+        let rid = heap.insert(bm, data)?;
+        let none = vec![None; indexes.len()];
+        if let Err(err) = self.move_entries(bm, indexes, &none, rid, &keys, rid) {
+            return Err(with_undo(err, heap.delete(bm, rid).map(|_| ())));
+        }
+        Ok(rid)
+    }
+
+    /// Replaces the row stored under `rid` by `data` and returns the row's (possibly new) record ID.
+    /// Index entries are only touched for indexes whose key or record ID changed.
+    /// Fails without touching the heap if this would violate a UNIQUE constraint.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update<R: IndexedRow + ?Sized>(
+        &mut self,
+        bm: &mut BufferManager,
+        indexes: &[IndexMetadata],
+        heap: &mut HeapFile,
+        rid: RecordId,
+        old_row: &R,
+        new_row: &R,
+        data: &[u8],
+    ) -> Result<RecordId, String> {
+        let new_keys = self.check(bm, indexes, new_row, Some(rid))?;
+        let old_keys: Vec<_> = indexes
+            .iter()
+            .map(|i| old_row.index_key(&i.columns))
+            .collect();
+        let old_data = heap
+            .get(bm, rid)?
+            .ok_or_else(|| format!("tuple {:?} does not exist", rid))?;
+        let new_rid = heap.update(bm, rid, data)?;
+        if let Err(err) = self.move_entries(bm, indexes, &old_keys, rid, &new_keys, new_rid) {
+            // the entries point to the old record ID again, the restored row may get another one
+            let undo = heap.update(bm, new_rid, &old_data).and_then(|restored| {
+                self.move_entries(bm, indexes, &old_keys, rid, &old_keys, restored)
+            });
+            return Err(with_undo(err, undo));
+        }
+        Ok(new_rid)
+    }
+
+    /// Deletes the row stored under `rid` from the heap and removes it from all indexes.
+    pub fn delete<R: IndexedRow + ?Sized>(
+        &mut self,
+        bm: &mut BufferManager,
+        indexes: &[IndexMetadata],
+        heap: &HeapFile,
+        rid: RecordId,
+        row: &R,
+    ) -> Result<(), String> {
+        let keys: Vec<_> = indexes.iter().map(|i| row.index_key(&i.columns)).collect();
+        let none = vec![None; indexes.len()];
+        self.move_entries(bm, indexes, &keys, rid, &none, rid)?;
+        if let Err(err) = heap.delete(bm, rid) {
+            return Err(with_undo(
+                err,
+                self.move_entries(bm, indexes, &none, rid, &keys, rid),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Computes the row's key for every index and checks that they can be inserted.
+    /// The row currently stored under `rid`, if any, does not count as a duplicate.
+    fn check<R: IndexedRow + ?Sized>(
+        &mut self,
+        bm: &mut BufferManager,
+        indexes: &[IndexMetadata],
+        row: &R,
+        rid: Option<RecordId>,
+    ) -> Result<Vec<Option<Vec<u8>>>, String> {
+        let mut keys = Vec::with_capacity(indexes.len());
+        for index in indexes {
+            let key = row.index_key(&index.columns);
+            let structure = self.structure(index)?;
+            if let Some(k) = &key {
+                if k.len() > structure.max_key_size() {
+                    return Err(format!("index row too large for index \"{}\"", index.name));
+                }
+                if index.unique && structure.get(bm, k)?.iter().any(|&r| Some(r) != rid) {
+                    return Err(format!(
+                        "duplicate key value violates unique constraint \"{}\"",
+                        index.name
+                    ));
+                }
+            }
+            keys.push(key);
+        }
+        Ok(keys)
+    }
+
+    /// Replaces the row's entry with key `from[i]` and record ID `from_rid` in each index
+    /// by one with key `to[i]` and record ID `to_rid`, a missing key stands for no entry.
+    /// If this fails midway, the entries changed so far are changed back.
+    fn move_entries(
+        &mut self,
+        bm: &mut BufferManager,
+        indexes: &[IndexMetadata],
+        from: &[Option<Vec<u8>>],
+        from_rid: RecordId,
+        to: &[Option<Vec<u8>>],
+        to_rid: RecordId,
+    ) -> Result<(), String> {
+        for (i, index) in indexes.iter().enumerate() {
+            if let Err(err) = self.move_entry(bm, index, &from[i], from_rid, &to[i], to_rid) {
+                let mut undo = Ok(());
+                for j in (0..i).rev() {
+                    undo = undo.and(self.move_entry(
+                        bm,
+                        &indexes[j],
+                        &to[j],
+                        to_rid,
+                        &from[j],
+                        from_rid,
+                    ));
+                }
+                return Err(with_undo(err, undo));
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces an entry of one index, which is left unchanged if this fails.
+    fn move_entry(
+        &mut self,
+        bm: &mut BufferManager,
+        index: &IndexMetadata,
+        from: &Option<Vec<u8>>,
+        from_rid: RecordId,
+        to: &Option<Vec<u8>>,
+        to_rid: RecordId,
+    ) -> Result<(), String> {
+        if from == to && (from.is_none() || from_rid == to_rid) {
+            return Ok(());
+        }
+        let structure = self.structure(index)?;
+        if let Some(key) = to {
+            structure.insert(bm, key, to_rid)?;
+        }
+        if let Some(key) = from {
+            if let Err(err) = structure.remove(bm, key, from_rid) {
+                let undo = match to {
+                    Some(key) => structure.remove(bm, key, to_rid).map(|_| ()),
+                    None => Ok(()),
+                };
+                return Err(with_undo(err, undo));
+            }
+        }
+        Ok(())
+    }
+
+    fn structure(&mut self, index: &IndexMetadata) -> Result<&mut Index, String> {
+        self.indexes
+            .get_mut(&index.id)
+            .ok_or_else(|| format!("index \"{}\" is not open", index.name))
+    }
+}
+
+/// Returns the error of a failed change, mentioning the error of undoing it if that failed too.
+pub fn with_undo(err: String, undo: Result<(), String>) -> String {
+    match undo {
+        Ok(()) => err,
+        Err(undo) => format!("{} (undoing the change failed: {})", err, undo),
+    }
+}
+
+impl Default for IndexManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::disk_manager::TempFile;
+    use crate::key_encoding::{KeyEncoder, KeyOrder};
+
+    /// Rows of nullable integer columns.
+    impl IndexedRow for [Option<i64>] {
+        fn index_key(&self, columns: &[usize]) -> Option<Vec<u8>> {
+            let mut enc = KeyEncoder::new();
+            for &c in columns {
+                enc.i64(self[c]?, KeyOrder::ASC);
+            }
+            Some(enc.finish())
+        }
+    }
+
+    fn indexes() -> Vec<IndexMetadata> {
+        vec![
+            IndexMetadata {
+                id: 0,
+                name: "users_pkey".to_owned(),
+                table: 0,
+                columns: vec![0],
+                kind: IndexKind::Hash,
+                unique: true,
+                root_page: None,
+            },
+            IndexMetadata {
+                id: 1,
+                name: "users_age_idx".to_owned(),
+                table: 0,
+                columns: vec![1],
+                kind: IndexKind::BTree,
+                unique: false,
+                root_page: None,
+            },
+            IndexMetadata {
+                id: 2,
+                name: "users_email_key".to_owned(),
+                table: 0,
+                columns: vec![2],
+                kind: IndexKind::BTree,
+                unique: true,
+                root_page: None,
+            },
+        ]
+    }
+
+    fn setup(
+        name: &str,
+    ) -> (
+        TempFile,
+        BufferManager,
+        IndexManager,
+        Vec<IndexMetadata>,
+        HeapFile,
+    ) {
+        let file = TempFile::new(&format!("index_manager_{}", name));
+        let mut bm = BufferManager::with_file(16, file.path());
+        let mut im = IndexManager::new();
+        let indexes = indexes();
+        for index in &indexes {
+            im.create_index(&mut bm, index).unwrap();
+        }
+        let heap = HeapFile::create(&mut bm).unwrap();
+        (file, bm, im, indexes, heap)
+    }
+
+    /// Stores the row padded to `size` bytes.
+    fn data(row: &[Option<i64>], size: usize) -> Vec<u8> {
+        let mut data = format!("{:?}", row).into_bytes();
+        data.resize(size.max(data.len()), 0);
+        data
+    }
+
+    fn lookup(
+        im: &IndexManager,
+        bm: &mut BufferManager,
+        index: &IndexMetadata,
+        v: i64,
+    ) -> Vec<RecordId> {
+        let key = [Some(v)].index_key(&[0]).unwrap();
+        im.get_index(index).unwrap().get(bm, &key).unwrap()
+    }
+
+    #[test]
+    fn insert() {
+        let (_file, mut bm, mut im, indexes, mut heap) = setup("insert");
+        let mut rids = Vec::new();
+        for row in [
+            [Some(1), Some(30), Some(100)],
+            [Some(2), Some(30), None],
+            [Some(3), Some(40), None],
+        ]
+        .iter()
+        {
+            let r = im
+                .insert(&mut bm, &indexes, &mut heap, &row[..], &data(row, 0))
+                .unwrap();
+            assert_eq!(heap.get(&mut bm, r).unwrap(), Some(data(row, 0)));
+            rids.push(r);
+        }
+        assert_eq!(lookup(&im, &mut bm, &indexes[0], 2), vec![rids[1]]);
+        assert_eq!(
+            lookup(&im, &mut bm, &indexes[1], 30),
+            vec![rids[0], rids[1]]
+        );
+        assert_eq!(lookup(&im, &mut bm, &indexes[2], 100), vec![rids[0]]);
+
+        // duplicate primary key
+        let row = [Some(2), Some(50), None];
+        let err = im
+            .insert(&mut bm, &indexes, &mut heap, &row[..], &data(&row, 0))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "duplicate key value violates unique constraint \"users_pkey\""
+        );
+        assert_eq!(lookup(&im, &mut bm, &indexes[1], 50), vec![]);
+
+        // duplicate email, the NULLs of rows 1 and 2 did not conflict
+        let row = [Some(4), Some(50), Some(100)];
+        let err = im
+            .insert(&mut bm, &indexes, &mut heap, &row[..], &data(&row, 0))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "duplicate key value violates unique constraint \"users_email_key\""
+        );
+        assert_eq!(heap.scan(&mut bm).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn update_delete() {
+        let (_file, mut bm, mut im, indexes, mut heap) = setup("update_delete");
+        let row1 = [Some(1), Some(30), Some(100)];
+        let row2 = [Some(2), Some(40), Some(200)];
+        let rid1 = im
+            .insert(&mut bm, &indexes, &mut heap, &row1[..], &data(&row1, 0))
+            .unwrap();
+        let rid2 = im
+            .insert(&mut bm, &indexes, &mut heap, &row2[..], &data(&row2, 2000))
+            .unwrap();
+
+        // updating a row may keep its own unique key
+        let new_row1 = [Some(1), Some(35), Some(100)];
+        let r = im
+            .update(
+                &mut bm,
+                &indexes,
+                &mut heap,
+                rid1,
+                &row1[..],
+                &new_row1[..],
+                &data(&new_row1, 0),
+            )
+            .unwrap();
+        assert_eq!(r, rid1);
+        assert_eq!(lookup(&im, &mut bm, &indexes[1], 30), vec![]);
+        assert_eq!(lookup(&im, &mut bm, &indexes[1], 35), vec![rid1]);
+
+        // but not take another row's
+        let row = [Some(2), Some(35), Some(100)];
+        let err = im
+            .update(
+                &mut bm,
+                &indexes,
+                &mut heap,
+                rid1,
+                &new_row1[..],
+                &row[..],
+                &data(&row, 0),
+            )
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "duplicate key value violates unique constraint \"users_pkey\""
+        );
+        assert_eq!(heap.get(&mut bm, rid1).unwrap(), Some(data(&new_row1, 0)));
+
+        // moving the tuple to another page updates all indexes
+        let moved = im
+            .update(
+                &mut bm,
+                &indexes,
+                &mut heap,
+                rid1,
+                &new_row1[..],
+                &new_row1[..],
+                &data(&new_row1, 3000),
+            )
+            .unwrap();
+        assert_ne!(moved, rid1);
+        assert_eq!(lookup(&im, &mut bm, &indexes[0], 1), vec![moved]);
+        assert_eq!(lookup(&im, &mut bm, &indexes[1], 35), vec![moved]);
+
+        im.delete(&mut bm, &indexes, &heap, rid2, &row2[..])
+            .unwrap();
+        assert_eq!(heap.get(&mut bm, rid2).unwrap(), None);
+        assert_eq!(lookup(&im, &mut bm, &indexes[0], 2), vec![]);
+        assert_eq!(lookup(&im, &mut bm, &indexes[1], 40), vec![]);
+        let r = im
+            .insert(&mut bm, &indexes, &mut heap, &row2[..], &data(&row2, 0))
+            .unwrap();
+        assert_eq!(lookup(&im, &mut bm, &indexes[2], 200), vec![r]);
+
+        // updating a row that does not exist leaves the indexes alone
+        let row = [Some(5), Some(50), None];
+        let err = im
+            .update(
+                &mut bm,
+                &indexes,
+                &mut heap,
+                rid2,
+                &row2[..],
+                &row[..],
+                &data(&row, 0),
+            )
+            .unwrap_err();
+        assert_eq!(err, format!("tuple {:?} does not exist", rid2));
+        assert_eq!(lookup(&im, &mut bm, &indexes[0], 2), vec![r]);
+    }
+
+    #[test]
+    fn with_undo_error() {
+        assert_eq!(with_undo("failed".to_owned(), Ok(())), "failed");
+        assert_eq!(
+            with_undo("failed".to_owned(), Err("pinned".to_owned())),
+            "failed (undoing the change failed: pinned)"
+        );
+    }
+}
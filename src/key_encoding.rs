@@ -0,0 +1,241 @@
+// Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+/// Marker byte preceding every encoded column, so that NULL can appear in any position.
+const NULL_FIRST: u8 = 0x00;
+const NOT_NULL: u8 = 0x01;
+const NULL_LAST: u8 = 0x02;
+
+/// Byte strings are terminated by `0x00 0x01`, embedded zeros are escaped as `0x00 0xFF`.
+const ESCAPE: u8 = 0x00;
+const ESCAPED_ZERO: u8 = 0xFF;
+const TERMINATOR: u8 = 0x01;
+
+/// Sort order of a single key column.
+/// Defaults follow PostgreSQL: NULLs are larger than any value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyOrder {
+    pub descending: bool,
+    pub nulls_first: bool,
+}
+
+impl KeyOrder {
+    /// `ASC NULLS LAST`
+    pub const ASC: KeyOrder = KeyOrder {
+        descending: false,
+        nulls_first: false,
+    };
+
+    /// `DESC NULLS FIRST`
+    pub const DESC: KeyOrder = KeyOrder {
+        descending: true,
+        nulls_first: true,
+    };
+}
+
+impl Default for KeyOrder {
+    fn default() -> Self {
+        Self::ASC
+    }
+}
+
+/// Builds memcomparable keys, i.e. byte strings whose lexicographic order
+/// matches the order of the encoded (composite) key.
+/// Columns are appended left to right, the most significant column first.
+#[derive(Clone, Debug, Default)]
+pub struct KeyEncoder {
+    buf: Vec<u8>,
+}
+
+impl KeyEncoder {
+    pub fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    /// Appends a NULL column.
+    pub fn null(&mut self, order: KeyOrder) -> &mut Self {
+        let marker = if order.nulls_first {
+            NULL_FIRST
+        } else {
+            NULL_LAST
+        };
+        self.buf.push(marker);
+        self
+    }
+
+    pub fn bool(&mut self, v: bool, order: KeyOrder) -> &mut Self {
+        self.u8(v as u8, order)
+    }
+
+    pub fn u8(&mut self, v: u8, order: KeyOrder) -> &mut Self {
+        self.push_value(&[v], order)
+    }
+
+    /// Signed integers are stored big-endian with the sign bit flipped.
+    pub fn i64(&mut self, v: i64, order: KeyOrder) -> &mut Self {
+        let bytes = ((v as u64) ^ (1 << 63)).to_be_bytes();
+        self.push_value(&bytes, order)
+    }
+
+    /// IEEE floats are stored with the sign bit flipped for positive numbers
+    /// and all bits flipped for negative numbers.
+    /// -0.0 is equal to 0.0, NaN is larger than any other value (like in PostgreSQL).
+    pub fn f64(&mut self, v: f64, order: KeyOrder) -> &mut Self {
+        let v = if v == 0.0 {
+            0.0
+        } else if v.is_nan() {
+            f64::NAN
+        } else {
+            v
+        };
+        let bits = v.to_bits();
+        let bits = if bits >> 63 == 1 {
+            !bits
+        } else {
+            bits ^ (1 << 63)
+        };
+        self.push_value(&bits.to_be_bytes(), order)
+    }
+
+    /// Variable-length byte strings are escaped and terminated, so that no key is a prefix of another.
+    pub fn bytes(&mut self, v: &[u8], order: KeyOrder) -> &mut Self {
+        let mut enc = Vec::with_capacity(v.len() + 2);
+        for &b in v {
+            enc.push(b);
+            if b == ESCAPE {
+                enc.push(ESCAPED_ZERO);
+            }
+        }
+        enc.push(ESCAPE);
+        enc.push(TERMINATOR);
+        self.push_value(&enc, order)
+    }
+
+    /// Strings are compared bytewise, i.e. by code point for UTF-8.
+    pub fn str(&mut self, v: &str, order: KeyOrder) -> &mut Self {
+        self.bytes(v.as_bytes(), order)
+    }
+
+    /// Returns the finished key.
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+
+    fn push_value(&mut self, bytes: &[u8], order: KeyOrder) -> &mut Self {
+        self.buf.push(NOT_NULL);
+        if order.descending {
+            self.buf.extend(bytes.iter().map(|b| !b));
+        } else {
+            self.buf.extend_from_slice(bytes);
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(v: i64, order: KeyOrder) -> Vec<u8> {
+        let mut enc = KeyEncoder::new();
+        enc.i64(v, order);
+        enc.finish()
+    }
+
+    fn float(v: f64, order: KeyOrder) -> Vec<u8> {
+        let mut enc = KeyEncoder::new();
+        enc.f64(v, order);
+        enc.finish()
+    }
+
+    fn string(v: &str, order: KeyOrder) -> Vec<u8> {
+        let mut enc = KeyEncoder::new();
+        enc.str(v, order);
+        enc.finish()
+    }
+
+    fn null(order: KeyOrder) -> Vec<u8> {
+        let mut enc = KeyEncoder::new();
+        enc.null(order);
+        enc.finish()
+    }
+
+    fn assert_sorted(keys: &[Vec<u8>]) {
+        for w in keys.windows(2) {
+            assert!(w[0] < w[1], "{:?} !< {:?}", w[0], w[1]);
+        }
+    }
+
+    #[test]
+    fn integers() {
+        let values = [i64::MIN, -1000, -1, 0, 1, 42, 1000, i64::MAX];
+        let asc: Vec<_> = values.iter().map(|&v| int(v, KeyOrder::ASC)).collect();
+        assert_sorted(&asc);
+        let desc: Vec<_> = values
+            .iter()
+            .rev()
+            .map(|&v| int(v, KeyOrder::DESC))
+            .collect();
+        assert_sorted(&desc);
+    }
+
+    #[test]
+    fn floats() {
+        let values = [
+            f64::NEG_INFINITY,
+            -1e300,
+            -1.5,
+            -f64::MIN_POSITIVE,
+            0.0,
+            1e-300,
+            2.5,
+            f64::INFINITY,
+            f64::NAN,
+        ];
+        let asc: Vec<_> = values.iter().map(|&v| float(v, KeyOrder::ASC)).collect();
+        assert_sorted(&asc);
+        let desc: Vec<_> = values
+            .iter()
+            .rev()
+            .map(|&v| float(v, KeyOrder::DESC))
+            .collect();
+        assert_sorted(&desc);
+        assert_eq!(float(-0.0, KeyOrder::ASC), float(0.0, KeyOrder::ASC));
+    }
+
+    #[test]
+    fn strings() {
+        let values = ["", "\0", "\0\0", "\0a", "a", "a\0", "a\0b", "ab", "b"];
+        let asc: Vec<_> = values.iter().map(|v| string(v, KeyOrder::ASC)).collect();
+        assert_sorted(&asc);
+        let desc: Vec<_> = values
+            .iter()
+            .rev()
+            .map(|v| string(v, KeyOrder::DESC))
+            .collect();
+        assert_sorted(&desc);
+    }
+
+    #[test]
+    fn nulls() {
+        for &v in &[i64::MIN, 0, i64::MAX] {
+            assert!(null(KeyOrder::DESC) < int(v, KeyOrder::DESC));
+            assert!(null(KeyOrder::ASC) > int(v, KeyOrder::ASC));
+        }
+    }
+
+    #[test]
+    fn composite() {
+        // (name ASC, age DESC) with a variable-length first column
+        let rows = [("a", 30), ("a", 20), ("ab", 50), ("ab", 10), ("b", 99)];
+        let keys: Vec<_> = rows
+            .iter()
+            .map(|&(name, age)| {
+                let mut enc = KeyEncoder::new();
+                enc.str(name, KeyOrder::ASC).i64(age, KeyOrder::DESC);
+                enc.finish()
+            })
+            .collect();
+        assert_sorted(&keys);
+    }
+}
@@ -1,19 +1,34 @@
 // Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
 // Distributed under terms of the MIT license.
 
+mod aggregate;
+mod binder;
 mod btree;
 mod buffer_manager;
 mod catalog;
+mod database;
 mod disk_manager;
+mod executor;
+mod expression;
 mod extensible_hash;
 mod external_sort;
+mod hash_index;
+mod hash_join;
+mod heap_file;
+mod index_manager;
+mod key_encoding;
 mod lock_manager;
 mod nested_loop_join;
 mod page;
 mod relation;
 mod replacer;
+mod spill_file;
 mod sql;
+mod statistics;
 mod table_scan;
+mod tuple;
+mod value;
+mod window;
 
 use std::io::{self, Write};
 
@@ -21,7 +36,8 @@ use quicli::prelude::*;
 use sqlparser::ast::Statement;
 use structopt::StructOpt;
 
-use sql::parse_sql_statement;
+use database::{Database, QueryResult};
+use sql::{parse_sql, Command};
 
 #[derive(Debug, StructOpt)]
 struct CliArgs {
@@ -29,7 +45,7 @@ struct CliArgs {
     #[structopt(long = "count", short = "n", default_value = "3")]
     count: usize,
 
-    /// The file to read
+    /// The database file, created if it does not exist
     file: String,
 
     #[structopt(flatten)]
@@ -40,6 +56,14 @@ fn main() -> CliResult {
     let args = CliArgs::from_args();
     args.verbosity.setup_env_logger("qdb")?;
 
+    let mut db = match Database::open(&args.file) {
+        Ok(db) => db,
+        Err(err) => {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
     print_intro();
 
     loop {
@@ -49,34 +73,87 @@ fn main() -> CliResult {
         read_user_input(&mut buf)?;
 
         if buf.get(0..1) == Some("\\") {
-            perform_meta_command(&buf[1..]);
+            perform_meta_command(&mut db, &buf[1..]);
             continue;
         }
 
         match prepare_statement(&buf) {
-            Ok(statement) => execute_statement(statement),
+            Ok(statement) => execute_statement(&mut db, statement),
             Err(err) => println!("{}", err),
         }
     }
 }
 
-fn perform_meta_command(cmd: &str) {
+fn perform_meta_command(db: &mut Database, cmd: &str) {
     if cmd == "?" || cmd == "help" {
         print_help();
     } else if cmd == "q" || cmd == "quit" {
+        if let Err(err) = db.close() {
+            println!("{}", err);
+        }
         std::process::exit(0);
     } else {
         println!("Unrecognized command: {}", cmd);
     }
 }
 
-fn prepare_statement(sql: &str) -> Result<Vec<Statement>, String> {
-    return parse_sql_statement(sql);
+fn prepare_statement(sql: &str) -> Result<Vec<Command>, String> {
+    return parse_sql(sql);
+}
+
+fn execute_statement(db: &mut Database, commands: Vec<Command>) {
+    for command in commands {
+        let result = match &command {
+            Command::Statement(Statement::Query(query)) => db.query(query).map(print_rows),
+            command => db.execute(command).map(|tag| println!("{}", tag)),
+        };
+        if let Err(err) = result {
+            println!("ERROR: {}", err);
+            break;
+        }
+    }
 }
 
-fn execute_statement(statements: Vec<Statement>) {
-    for statement in statements {
-        println!("Executing: {:?}", statement);
+/// Prints the result of a query as a table, like psql.
+fn print_rows(result: QueryResult) {
+    let rows: Vec<Vec<String>> = result
+        .rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|v| {
+                    if v.is_null() {
+                        String::new()
+                    } else {
+                        v.to_string()
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    let mut widths: Vec<usize> = result.columns.iter().map(|c| c.chars().count()).collect();
+    for row in &rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+    let line = |values: &[String]| {
+        let cells: Vec<_> = values
+            .iter()
+            .zip(&widths)
+            .map(|(v, &w)| format!(" {:<w$} ", v, w = w))
+            .collect();
+        println!("{}", cells.join("|").trim_end());
+    };
+    line(&result.columns);
+    let separator: Vec<_> = widths.iter().map(|&w| "-".repeat(w + 2)).collect();
+    println!("{}", separator.join("+"));
+    for row in &rows {
+        line(row);
+    }
+    match rows.len() {
+        1 => println!("(1 row)"),
+        n => println!("({} rows)", n),
     }
 }
 
@@ -1,23 +1,608 @@
 // Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
 // Distributed under terms of the MIT license.
 
-use crate::buffer_manager::BufferManager;
-use crate::page::Page;
+use std::collections::VecDeque;
 
-pub fn join(mm: &mut BufferManager) {
-    let output_page = mm.new_page();
+use sqlparser::ast::Expr;
 
-    // TODO always use smaller relation as outer
+use crate::binder::{Field, JoinKind};
+use crate::catalog::{DataType, IndexMetadata, TableMetadata};
+use crate::database::Database;
+use crate::executor::{close_on_error, is_true, row_size, Operator, PlanRow, WORK_MEM_PAGES};
+use crate::key_encoding::{KeyEncoder, KeyOrder};
+use crate::page::PAGE_SIZE;
+use crate::tuple::Tuple;
+use crate::value::Value;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Phase {
+    /// Joining the rows of the scanned input with the current block.
+    Join,
+    /// Scanning once more to pad the rows that were not joined with any block.
+    PadScanned,
+    Done,
+}
+
+/// Joins two inputs by keeping blocks of rows of one of them in memory
+/// and scanning the other one once per block, any condition can be used.
+///
+/// If the right input fits into a single block it is kept in memory, otherwise the left one is,
+/// so that an input small enough to fit is only read once.
+/// Outer joins pad unmatched rows of the block input after each block,
+/// and those of the scanned input in a final scan unless there was only one block.
+/// Semi and anti joins return left rows of a block once the block has been joined.
+pub struct NestedLoopJoin {
+    left: Box<dyn Operator>,
+    right: Box<dyn Operator>,
+    kind: JoinKind,
+    condition: Option<Expr>,
+    /// The fields of both inputs, which the condition refers to.
+    condition_fields: Vec<Field>,
+    fields: Vec<Field>,
+    /// Bytes of rows per block.
+    block_size: usize,
+    /// The input kept in memory.
+    block_side: Side,
+    block: Vec<Tuple>,
+    /// Whether each row of the block has been joined.
+    block_matched: Vec<bool>,
+    blocks: usize,
+    /// Whether the block input is exhausted after the current block.
+    last_block: bool,
+    /// Position of the next row of the scanned input, and whether each was joined with any block.
+    position: usize,
+    scanned_matched: Vec<bool>,
+    /// Joined rows that have not been returned yet.
+    pending: VecDeque<Tuple>,
+    phase: Phase,
 }
 
-fn join_pages(p1: &Page, p2: &Page, left_attr: usize, right_attr: usize, out: &mut Page) {}
+impl NestedLoopJoin {
+    pub fn new(
+        left: Box<dyn Operator>,
+        right: Box<dyn Operator>,
+        kind: JoinKind,
+        condition: Option<Expr>,
+        fields: Vec<Field>,
+    ) -> Self {
+        Self {
+            condition_fields: [left.schema(), right.schema()].concat(),
+            left,
+            right,
+            kind,
+            condition,
+            fields,
+            block_size: WORK_MEM_PAGES * PAGE_SIZE,
+            block_side: Side::Right,
+            block: Vec::new(),
+            block_matched: Vec::new(),
+            blocks: 0,
+            last_block: false,
+            position: 0,
+            scanned_matched: Vec::new(),
+            pending: VecDeque::new(),
+            phase: Phase::Done,
+        }
+    }
+
+    /// Limits the rows held in memory to about the given number of pages.
+    #[cfg(test)]
+    pub fn with_buffer_pages(mut self, pages: usize) -> Self {
+        self.block_size = pages.max(1) * PAGE_SIZE;
+        self
+    }
+
+    fn input(&mut self, side: Side) -> &mut dyn Operator {
+        match side {
+            Side::Left => self.left.as_mut(),
+            Side::Right => self.right.as_mut(),
+        }
+    }
+
+    fn scanned_side(&self) -> Side {
+        match self.block_side {
+            Side::Left => Side::Right,
+            Side::Right => Side::Left,
+        }
+    }
+
+    /// Whether unmatched rows of the given input are returned padded with NULLs.
+    fn pads(&self, side: Side) -> bool {
+        match side {
+            Side::Left => matches!(self.kind, JoinKind::Left | JoinKind::Full),
+            Side::Right => matches!(self.kind, JoinKind::Right | JoinKind::Full),
+        }
+    }
+
+    /// Whether left rows are returned on their own, depending on whether they have a match.
+    fn filters(&self) -> bool {
+        matches!(self.kind, JoinKind::Semi | JoinKind::Anti)
+    }
 
-fn join_tuples() {}
+    /// Concatenates a row of each side, either of them is NULLs if missing.
+    fn joined(&self, side: Side, row: &[Value], other: Option<&[Value]>) -> Tuple {
+        let left_len = self.left.schema().len();
+        let (left, right) = match side {
+            Side::Left => (Some(row), other),
+            Side::Right => (other, Some(row)),
+        };
+        let mut joined = Vec::with_capacity(self.fields.len());
+        match left {
+            Some(left) => joined.extend_from_slice(left),
+            None => joined.resize(left_len, Value::Null),
+        }
+        match right {
+            Some(right) => joined.extend_from_slice(right),
+            None => joined.resize(self.condition_fields.len(), Value::Null),
+        }
+        joined
+    }
+
+    /// Reads the next block of rows from the block input.
+    fn read_block(&mut self, db: &mut Database) -> Result<(), String> {
+        self.block.clear();
+        let mut size = 0;
+        while size < self.block_size {
+            match self.input(self.block_side).next(db)? {
+                Some(row) => {
+                    size += row_size(&row);
+                    self.block.push(row);
+                }
+                None => {
+                    self.last_block = true;
+                    break;
+                }
+            }
+        }
+        self.block_matched = vec![false; self.block.len()];
+        self.blocks += 1;
+        Ok(())
+    }
+
+    /// Joins a row of the scanned input with the current block.
+    fn join_row(&mut self, db: &mut Database, row: Tuple) -> Result<(), String> {
+        let side = self.scanned_side();
+        let mut matched = false;
+        for i in 0..self.block.len() {
+            // left rows of semi and anti joins only need to be matched once
+            let left_matched = match side {
+                Side::Left => matched,
+                Side::Right => self.block_matched[i],
+            };
+            if self.filters() && left_matched {
+                continue;
+            }
+            let joined = self.joined(side, &row, Some(&self.block[i]));
+            let joins = match &self.condition {
+                Some(condition) => is_true(db, condition, &self.condition_fields, &joined)?,
+                None => true,
+            };
+            if joins {
+                matched = true;
+                self.block_matched[i] = true;
+                if !self.filters() {
+                    self.pending.push_back(joined);
+                }
+            }
+        }
+        // the right input is kept in memory in a single block if left rows are scanned
+        if side == Side::Left && self.filters() && matched == (self.kind == JoinKind::Semi) {
+            self.pending.push_back(row);
+        } else if self.pads(side) {
+            if self.blocks == 1 && self.last_block {
+                if !matched {
+                    self.pending.push_back(self.joined(side, &row, None));
+                }
+            } else {
+                if self.scanned_matched.len() <= self.position {
+                    self.scanned_matched.push(false);
+                }
+                self.scanned_matched[self.position] |= matched;
+            }
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    /// Pads the unmatched rows of the current block and moves on to the next one.
+    fn finish_block(&mut self, db: &mut Database) -> Result<(), String> {
+        if self.block_side == Side::Left && self.filters() {
+            let semi = self.kind == JoinKind::Semi;
+            let rows = self.block.drain(..).zip(&self.block_matched);
+            self.pending.extend(
+                rows.filter(|(_, &matched)| matched == semi)
+                    .map(|(row, _)| row),
+            );
+        } else if self.pads(self.block_side) {
+            for (row, _) in self
+                .block
+                .iter()
+                .zip(&self.block_matched)
+                .filter(|(_, &matched)| !matched)
+            {
+                self.pending
+                    .push_back(self.joined(self.block_side, row, None));
+            }
+        }
+        if !self.last_block {
+            self.read_block(db)?;
+        } else {
+            self.block.clear();
+        }
+        let pad_scanned = self.pads(self.scanned_side()) && self.blocks > 1;
+        self.phase = if !self.block.is_empty() {
+            Phase::Join
+        } else if pad_scanned {
+            Phase::PadScanned
+        } else {
+            Phase::Done
+        };
+        if self.phase != Phase::Done {
+            let scanned = self.input(self.scanned_side());
+            scanned.close(db)?;
+            scanned.open(db)?;
+            self.position = 0;
+        }
+        Ok(())
+    }
+
+    /// Opens the inputs and reads the first block of the one that is kept in memory.
+    fn open_inputs(&mut self, db: &mut Database) -> Result<(), String> {
+        self.block_side = Side::Right;
+        self.right.open(db)?;
+        self.read_block(db)?;
+        if self.last_block {
+            return self.left.open(db);
+        }
+        // the right input does not fit, its rows are read again once per block of the left one
+        self.right.close(db)?;
+        self.block_side = Side::Left;
+        self.blocks = 0;
+        self.left.open(db)?;
+        self.read_block(db)?;
+        self.right.open(db)
+    }
+}
+
+impl Operator for NestedLoopJoin {
+    fn open(&mut self, db: &mut Database) -> Result<(), String> {
+        self.blocks = 0;
+        self.last_block = false;
+        self.position = 0;
+        self.scanned_matched.clear();
+        self.pending.clear();
+        self.phase = Phase::Join;
+        let result = self.open_inputs(db);
+        close_on_error(result, db, &mut [self.left.as_mut(), self.right.as_mut()])
+    }
+
+    fn next(&mut self, db: &mut Database) -> Result<Option<Tuple>, String> {
+        loop {
+            if let Some(row) = self.pending.pop_front() {
+                return Ok(Some(row));
+            }
+            match self.phase {
+                Phase::Join => match self.input(self.scanned_side()).next(db)? {
+                    Some(row) => self.join_row(db, row)?,
+                    None => self.finish_block(db)?,
+                },
+                Phase::PadScanned => match self.input(self.scanned_side()).next(db)? {
+                    Some(row) => {
+                        if !self.scanned_matched.get(self.position).unwrap_or(&false) {
+                            let padded = self.joined(self.scanned_side(), &row, None);
+                            self.pending.push_back(padded);
+                        }
+                        self.position += 1;
+                    }
+                    None => self.phase = Phase::Done,
+                },
+                Phase::Done => return Ok(None),
+            }
+        }
+    }
+
+    fn close(&mut self, db: &mut Database) -> Result<(), String> {
+        self.block.clear();
+        self.block_matched.clear();
+        self.scanned_matched.clear();
+        self.pending.clear();
+        self.phase = Phase::Done;
+        let left = self.left.close(db);
+        let right = self.right.close(db);
+        left.and(right)
+    }
+
+    fn schema(&self) -> &[Field] {
+        &self.fields
+    }
+}
+
+/// Joins each row of the outer (left) input with the rows of a table
+/// that an index finds for the key computed from the outer row.
+///
+/// The key expressions are compared with the index columns for equality,
+/// the whole condition is checked again on the joined rows. Only inner and left joins are supported.
+pub struct IndexNestedLoopJoin {
+    outer: Box<dyn Operator>,
+    index: IndexMetadata,
+    /// The type of each index column, keys are cast to them before encoding.
+    key_types: Vec<DataType>,
+    /// Expressions on the outer row for each index column.
+    keys: Vec<Expr>,
+    kind: JoinKind,
+    condition: Option<Expr>,
+    fields: Vec<Field>,
+    pending: VecDeque<Tuple>,
+}
+
+impl IndexNestedLoopJoin {
+    pub fn new(
+        outer: Box<dyn Operator>,
+        table: &TableMetadata,
+        index: IndexMetadata,
+        keys: Vec<Expr>,
+        kind: JoinKind,
+        condition: Option<Expr>,
+        fields: Vec<Field>,
+    ) -> Self {
+        let key_types = index
+            .columns
+            .iter()
+            .map(|&c| table.schema.columns()[c].data_type)
+            .collect();
+        Self {
+            outer,
+            index,
+            key_types,
+            keys,
+            kind,
+            condition,
+            fields,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Encodes the index key for an outer row,
+    /// `None` if it cannot equal any key because a value is NULL or not of the column's type.
+    fn key(&self, db: &mut Database, row: &[Value]) -> Result<Option<Vec<u8>>, String> {
+        let mut enc = KeyEncoder::new();
+        for (expr, &data_type) in self.keys.iter().zip(&self.key_types) {
+            let row = PlanRow {
+                fields: self.outer.schema(),
+                values: row,
+            };
+            let value = db.eval(expr, &row)?;
+            if value.is_null() {
+                return Ok(None);
+            }
+            match value.cast(data_type) {
+                Ok(key) if key == value => key.encode_key(&mut enc, KeyOrder::ASC),
+                _ => return Ok(None),
+            }
+        }
+        Ok(Some(enc.finish()))
+    }
+}
+
+impl Operator for IndexNestedLoopJoin {
+    fn open(&mut self, db: &mut Database) -> Result<(), String> {
+        self.pending.clear();
+        self.outer.open(db)
+    }
+
+    fn next(&mut self, db: &mut Database) -> Result<Option<Tuple>, String> {
+        loop {
+            if let Some(row) = self.pending.pop_front() {
+                return Ok(Some(row));
+            }
+            let outer = match self.outer.next(db)? {
+                Some(row) => row,
+                None => return Ok(None),
+            };
+            let inner = match self.key(db, &outer)? {
+                Some(key) => db.index_lookup(&self.index, &key)?,
+                None => Vec::new(),
+            };
+            for inner in inner {
+                let mut joined = outer.clone();
+                joined.extend(inner);
+                let joins = match &self.condition {
+                    Some(condition) => is_true(db, condition, &self.fields, &joined)?,
+                    None => true,
+                };
+                if joins {
+                    self.pending.push_back(joined);
+                }
+            }
+            if self.pending.is_empty() && self.kind == JoinKind::Left {
+                let mut padded = outer;
+                padded.resize(self.fields.len(), Value::Null);
+                return Ok(Some(padded));
+            }
+        }
+    }
+
+    fn close(&mut self, db: &mut Database) -> Result<(), String> {
+        self.pending.clear();
+        self.outer.close(db)
+    }
+
+    fn schema(&self) -> &[Field] {
+        &self.fields
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use crate::executor::{collect, values, TempDatabase};
+    use crate::sql::{parse_expr, parse_sql};
+
+    #[test]
+    fn join_kinds() {
+        let mut db = TempDatabase::open("nested_loop_join");
+        let condition = parse_expr("l.a = r.a").unwrap();
+        let left = [
+            [Some(1), Some(10)],
+            [Some(2), Some(20)],
+            [Some(2), Some(21)],
+        ];
+        let right = [
+            [Some(2), Some(30)],
+            [Some(3), Some(40)],
+            [Some(2), Some(31)],
+        ];
+        let join = |kind| {
+            let (l, r) = (values("l", &left), values("r", &right));
+            let fields = [l.schema(), r.schema()].concat();
+            NestedLoopJoin::new(l, r, kind, Some(condition.clone()), fields)
+        };
+        let int = |v: &[i64]| v.iter().map(|&v| Value::BigInt(v)).collect::<Tuple>();
+        let null = |n| vec![Value::Null; n];
+        let sorted = |mut rows: Vec<Tuple>| {
+            rows.sort();
+            rows
+        };
+
+        let inner = collect(&mut join(JoinKind::Inner), &mut db).unwrap();
+        assert_eq!(
+            sorted(inner),
+            vec![
+                int(&[2, 20, 2, 30]),
+                int(&[2, 20, 2, 31]),
+                int(&[2, 21, 2, 30]),
+                int(&[2, 21, 2, 31]),
+            ]
+        );
+        let left = collect(&mut join(JoinKind::Left), &mut db).unwrap();
+        assert_eq!(left[0], [int(&[1, 10]), null(2)].concat());
+        assert_eq!(left.len(), 5);
+        let right = collect(&mut join(JoinKind::Right), &mut db).unwrap();
+        assert_eq!(right[4], [null(2), int(&[3, 40])].concat());
+        assert_eq!(right.len(), 5);
+        let full = collect(&mut join(JoinKind::Full), &mut db).unwrap();
+        assert_eq!(full.len(), 6);
+
+        // operators can be opened again after they were closed
+        let mut cross = join(JoinKind::Inner);
+        cross.condition = None;
+        assert_eq!(collect(&mut cross, &mut db).unwrap().len(), 9);
+        assert_eq!(collect(&mut cross, &mut db).unwrap().len(), 9);
+    }
+
+    #[test]
+    fn blocks() {
+        let mut db = TempDatabase::open("block_nested_loop_join");
+        let rows: Vec<_> = (0..1000).map(|i| [Some(i), Some(i * i)]).collect();
+        // a non-equality condition, the left input needs several blocks of one page
+        let condition = parse_expr("l.a BETWEEN r.a - 1 AND r.a + 1 AND r.b < 2500").unwrap();
+        for kind in [
+            JoinKind::Inner,
+            JoinKind::Left,
+            JoinKind::Right,
+            JoinKind::Full,
+            JoinKind::Semi,
+            JoinKind::Anti,
+        ] {
+            let (left, right) = (values("l", &rows), values("r", &rows));
+            let fields = match kind {
+                JoinKind::Semi | JoinKind::Anti => left.schema().to_vec(),
+                _ => [left.schema(), right.schema()].concat(),
+            };
+            let join = |pages| {
+                NestedLoopJoin::new(
+                    values("l", &rows),
+                    values("r", &rows),
+                    kind,
+                    Some(condition.clone()),
+                    fields.clone(),
+                )
+                .with_buffer_pages(pages)
+            };
+            let mut blocks = join(1);
+            let mut single = join(1000);
+            let mut expected = collect(&mut single, &mut db).unwrap();
+            let mut rows = collect(&mut blocks, &mut db).unwrap();
+            assert!(blocks.blocks > 1);
+            assert_eq!(single.blocks, 1);
+            expected.sort();
+            rows.sort();
+            assert_eq!(rows, expected);
+            // 50 right rows match 2 or 3 of the first 51 left rows each
+            let count = match kind {
+                JoinKind::Inner => 149,
+                JoinKind::Left => 149 + 1000 - 51,
+                JoinKind::Right => 149 + 1000 - 50,
+                JoinKind::Full => 149 + 2000 - 101,
+                JoinKind::Semi => 51,
+                JoinKind::Anti => 1000 - 51,
+            };
+            assert_eq!(rows.len(), count, "{:?}", kind);
+        }
+    }
+
     #[test]
-    fn it_works() {}
+    fn index_join() {
+        let mut db = TempDatabase::open("index_nested_loop_join");
+        for sql in [
+            "CREATE TABLE t (a INT, b TEXT)",
+            "CREATE INDEX t_a ON t USING HASH (a)",
+            "INSERT INTO t VALUES (1, 'x'), (2, 'y'), (2, 'z'), (NULL, 'n')",
+        ] {
+            db.execute(&parse_sql(sql).unwrap()[0]).unwrap();
+        }
+        let table = db.catalog().get_table("t").unwrap().clone();
+        let index = db.catalog().get_table_indices("t").pop().unwrap();
+        let rows = [
+            [Some(1), Some(10)],
+            [Some(2), Some(20)],
+            [Some(3), Some(30)],
+        ];
+        let join = |kind| {
+            let outer = values("l", &rows);
+            let fields = [
+                outer.schema(),
+                &[Field::new(Some("t"), "a"), Field::new(Some("t"), "b")],
+            ]
+            .concat();
+            IndexNestedLoopJoin::new(
+                outer,
+                &table,
+                index.clone(),
+                vec![parse_expr("l.a").unwrap()],
+                kind,
+                Some(parse_expr("l.a = t.a AND t.b <> 'z'").unwrap()),
+                fields,
+            )
+        };
+        let rows = collect(&mut join(JoinKind::Inner), &mut db).unwrap();
+        let text = |s: &str| Value::Text(s.to_owned());
+        assert_eq!(
+            rows,
+            vec![
+                vec![
+                    Value::BigInt(1),
+                    Value::BigInt(10),
+                    Value::Int(1),
+                    text("x")
+                ],
+                vec![
+                    Value::BigInt(2),
+                    Value::BigInt(20),
+                    Value::Int(2),
+                    text("y")
+                ],
+            ]
+        );
+        let rows = collect(&mut join(JoinKind::Left), &mut db).unwrap();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[2][2..], [Value::Null, Value::Null]);
+    }
 }
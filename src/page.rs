@@ -1,10 +1,35 @@
 // Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
 // Distributed under terms of the MIT license.
 
+use std::convert::TryInto;
+
 pub const PAGE_SIZE: usize = 4096;
 
 pub type PageID = usize;
 
+/// Marks the end of a page chain on disk.
+pub const NO_PAGE: u64 = u64::MAX;
+
+/// Heap pages start with the ID of the next page of the heap file, the number of slots
+/// and the offset of the tuple data, which grows from the end of the page towards the slot array.
+const HEAP_NEXT_PAGE: usize = 0;
+const HEAP_NUM_SLOTS: usize = 8;
+const HEAP_DATA_START: usize = 10;
+const HEAP_HEADER: usize = 12;
+
+/// Every slot stores offset and length of its tuple, an offset of 0 marks a free slot.
+const SLOT_SIZE: usize = 4;
+
+/// Largest tuple that fits into a heap page.
+pub const MAX_TUPLE_SIZE: usize = PAGE_SIZE - HEAP_HEADER - SLOT_SIZE;
+
+/// Identifies a tuple by the page it lives on and its slot within that page.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RecordId {
+    pub page: PageID,
+    pub slot: u16,
+}
+
 pub struct Page {
     pub id: PageID,
     pub dirty: bool,
@@ -36,9 +61,159 @@ impl Page {
         }
     }
 
-    pub fn add_tuple() {}
+    /// Formats this page as an empty heap page.
+    pub fn init_heap_page(&mut self) {
+        self.set_next_page(None);
+        write_u16(&mut self.data, HEAP_NUM_SLOTS, 0);
+        write_u16(&mut self.data, HEAP_DATA_START, PAGE_SIZE as u16);
+    }
 
-    pub fn write_to_disk(&self, file: &str) {}
+    /// The next page of the heap file this page belongs to.
+    pub fn next_page(&self) -> Option<PageID> {
+        read_page_id(&self.data, HEAP_NEXT_PAGE)
+    }
+
+    pub fn set_next_page(&mut self, next: Option<PageID>) {
+        write_u64(
+            &mut self.data,
+            HEAP_NEXT_PAGE,
+            next.map_or(NO_PAGE, |p| p as u64),
+        );
+    }
+
+    /// Number of slots, including free ones.
+    pub fn num_slots(&self) -> u16 {
+        read_u16(&self.data, HEAP_NUM_SLOTS)
+    }
+
+    /// Returns the tuple in the given slot, or `None` if the slot is free.
+    pub fn get_tuple(&self, slot: u16) -> Option<&[u8]> {
+        let (offset, len) = self.slot(slot)?;
+        Some(&self.data[offset..offset + len])
+    }
+
+    /// Stores the tuple in a free slot, returns `None` if it doesn't fit into this page.
+    pub fn add_tuple(&mut self, tuple: &[u8]) -> Option<u16> {
+        let num_slots = self.num_slots();
+        let slot = (0..num_slots)
+            .find(|&s| self.slot(s).is_none())
+            .unwrap_or(num_slots);
+        let new_slot = if slot == num_slots { SLOT_SIZE } else { 0 };
+        if tuple.len() + new_slot > self.free_space() {
+            return None;
+        }
+        if slot == num_slots {
+            write_u16(&mut self.data, HEAP_NUM_SLOTS, num_slots + 1);
+        }
+        self.place_tuple(slot, tuple);
+        Some(slot)
+    }
+
+    /// Replaces the tuple in the given slot, returns false if the new version doesn't fit into this page.
+    pub fn update_tuple(&mut self, slot: u16, tuple: &[u8]) -> bool {
+        let (offset, len) = match self.slot(slot) {
+            Some(s) => s,
+            None => return false,
+        };
+        if tuple.len() <= len {
+            self.data[offset..offset + tuple.len()].copy_from_slice(tuple);
+            self.set_slot(slot, offset, tuple.len());
+            return true;
+        }
+        if tuple.len() > self.free_space() + len {
+            return false;
+        }
+        self.set_slot(slot, 0, 0);
+        self.place_tuple(slot, tuple);
+        true
+    }
+
+    /// Frees the given slot, returns false if it was already free.
+    pub fn delete_tuple(&mut self, slot: u16) -> bool {
+        if self.slot(slot).is_none() {
+            return false;
+        }
+        self.set_slot(slot, 0, 0);
+        true
+    }
+
+    /// Number of bytes available for tuple data (and slots), after compaction.
+    pub fn free_space(&self) -> usize {
+        let used: usize = (0..self.num_slots())
+            .filter_map(|s| self.slot(s))
+            .map(|(_, len)| len)
+            .sum();
+        PAGE_SIZE - HEAP_HEADER - self.num_slots() as usize * SLOT_SIZE - used
+    }
+
+    /// Writes the tuple into the free slot, compacting the page if necessary.
+    /// The caller has to make sure that the tuple fits.
+    fn place_tuple(&mut self, slot: u16, tuple: &[u8]) {
+        let slots_end = HEAP_HEADER + self.num_slots() as usize * SLOT_SIZE;
+        if (read_u16(&self.data, HEAP_DATA_START) as usize) < slots_end + tuple.len() {
+            self.compact();
+        }
+        let offset = read_u16(&self.data, HEAP_DATA_START) as usize - tuple.len();
+        self.data[offset..offset + tuple.len()].copy_from_slice(tuple);
+        write_u16(&mut self.data, HEAP_DATA_START, offset as u16);
+        self.set_slot(slot, offset, tuple.len());
+    }
+
+    /// Moves all tuples to the end of the page, so that the free space is contiguous.
+    fn compact(&mut self) {
+        let mut tuples: Vec<_> = (0..self.num_slots())
+            .filter_map(|s| self.slot(s).map(|(offset, len)| (s, offset, len)))
+            .collect();
+        tuples.sort_by_key(|&(_, offset, _)| std::cmp::Reverse(offset));
+        let mut end = PAGE_SIZE;
+        for (s, offset, len) in tuples {
+            self.data.copy_within(offset..offset + len, end - len);
+            end -= len;
+            self.set_slot(s, end, len);
+        }
+        write_u16(&mut self.data, HEAP_DATA_START, end as u16);
+    }
+
+    fn slot(&self, slot: u16) -> Option<(usize, usize)> {
+        if slot >= self.num_slots() {
+            return None;
+        }
+        let pos = HEAP_HEADER + slot as usize * SLOT_SIZE;
+        match read_u16(&self.data, pos) as usize {
+            0 => None,
+            offset => Some((offset, read_u16(&self.data, pos + 2) as usize)),
+        }
+    }
+
+    fn set_slot(&mut self, slot: u16, offset: usize, len: usize) {
+        let pos = HEAP_HEADER + slot as usize * SLOT_SIZE;
+        write_u16(&mut self.data, pos, offset as u16);
+        write_u16(&mut self.data, pos + 2, len as u16);
+    }
+}
+
+pub fn read_u16(data: &[u8; PAGE_SIZE], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+pub fn write_u16(data: &mut [u8; PAGE_SIZE], offset: usize, v: u16) {
+    data[offset..offset + 2].copy_from_slice(&v.to_le_bytes());
+}
+
+pub fn read_u64(data: &[u8; PAGE_SIZE], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+pub fn write_u64(data: &mut [u8; PAGE_SIZE], offset: usize, v: u64) {
+    data[offset..offset + 8].copy_from_slice(&v.to_le_bytes());
+}
+
+/// Reads a page ID written as `u64`, where `NO_PAGE` stands for none.
+pub fn read_page_id(data: &[u8; PAGE_SIZE], offset: usize) -> Option<PageID> {
+    match read_u64(data, offset) {
+        NO_PAGE => None,
+        p => Some(p as PageID),
+    }
 }
 
 #[cfg(test)]
@@ -47,4 +222,58 @@ mod tests {
 
     #[test]
     fn it_works() {}
+
+    fn heap_page() -> Page {
+        let mut page = Page::new(1);
+        page.init_heap_page();
+        page
+    }
+
+    #[test]
+    fn heap_page_tuples() {
+        let mut page = heap_page();
+        assert_eq!(page.next_page(), None);
+        assert_eq!(page.add_tuple(b"hello"), Some(0));
+        assert_eq!(page.add_tuple(b""), Some(1));
+        assert_eq!(page.add_tuple(b"world"), Some(2));
+        assert_eq!(page.get_tuple(0), Some(&b"hello"[..]));
+        assert_eq!(page.get_tuple(1), Some(&b""[..]));
+        assert_eq!(page.get_tuple(3), None);
+
+        assert!(page.delete_tuple(0));
+        assert!(!page.delete_tuple(0));
+        assert_eq!(page.get_tuple(0), None);
+        assert_eq!(page.add_tuple(b"again"), Some(0));
+
+        assert!(page.update_tuple(2, b"w"));
+        assert!(page.update_tuple(2, b"a longer world"));
+        assert_eq!(page.get_tuple(2), Some(&b"a longer world"[..]));
+        assert_eq!(page.get_tuple(0), Some(&b"again"[..]));
+    }
+
+    #[test]
+    fn heap_page_full() {
+        let mut page = heap_page();
+        let tuple = [7u8; 100];
+        let mut n = 0;
+        while page.add_tuple(&tuple).is_some() {
+            n += 1;
+        }
+        assert_eq!(n, (PAGE_SIZE - HEAP_HEADER) / (100 + SLOT_SIZE));
+        assert!(page.free_space() < 100 + SLOT_SIZE);
+
+        // freed space can only be reused after compaction
+        for slot in (0..n as u16).step_by(2) {
+            assert!(page.delete_tuple(slot));
+        }
+        let big = [9u8; 250];
+        assert!(page.add_tuple(&big).is_some());
+        assert!(page.update_tuple(1, &big));
+        for slot in (1..n as u16).step_by(2) {
+            let expected: &[u8] = if slot == 1 { &big } else { &tuple };
+            assert_eq!(page.get_tuple(slot), Some(expected));
+        }
+        assert_eq!(page.add_tuple(&[0u8; MAX_TUPLE_SIZE]), None);
+        assert_eq!(heap_page().add_tuple(&[0u8; MAX_TUPLE_SIZE]), Some(0));
+    }
 }
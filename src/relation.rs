@@ -6,7 +6,6 @@ use std::io::{self, Read, Seek, SeekFrom};
 
 use crate::buffer_manager::BufferManager;
 use crate::page::{Page, PAGE_SIZE};
-use crate::table_scan::TableScanner;
 
 pub struct Relation<'a> {
     mm: &'a mut BufferManager,
@@ -21,10 +20,6 @@ impl<'a> Relation<'a> {
         };
     }
 
-    /*pub fn scan(&'a self) -> TableScanner<'a> {
-        return TableScanner::new(self, self.mm);
-    }*/
-
     /// Loads the page given by index from this relations disk file.
     pub fn get_page(&'a self, page: usize) -> io::Result<Page> {
         let mut file = File::open(&self.file).unwrap();
@@ -0,0 +1,380 @@
+// Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::vec::IntoIter;
+
+use crate::executor::row_size;
+use crate::tuple::Tuple;
+use crate::value::Value;
+
+/// Numbers the spill files of this process, so that their names are unique.
+static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+
+const NULL: u8 = 0;
+const INT: u8 = 1;
+const BIGINT: u8 = 2;
+const FLOAT: u8 = 3;
+const BOOLEAN: u8 = 4;
+const TEXT: u8 = 5;
+const DATE: u8 = 6;
+const TIMESTAMP: u8 = 7;
+const BYTEA: u8 = 8;
+
+/// A temporary file that operators write rows to when they do not fit into memory.
+///
+/// Rows are written one after another and can then be read back in the same order,
+/// any number of times. The file is deleted when the spill file is dropped,
+/// so it is also cleaned up if the query fails.
+pub struct SpillFile {
+    path: PathBuf,
+    writer: Option<BufWriter<File>>,
+    rows: u64,
+}
+
+impl SpillFile {
+    /// Creates a new, empty file in the system's temporary directory.
+    pub fn create() -> Result<Self, String> {
+        let name = format!(
+            "qdb_spill_{}_{}.tmp",
+            std::process::id(),
+            NEXT_FILE.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = std::env::temp_dir().join(name);
+        let file = File::create(&path).map_err(|e| io_error(&path, e))?;
+        Ok(Self {
+            path,
+            writer: Some(BufWriter::new(file)),
+            rows: 0,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows == 0
+    }
+
+    /// Appends a row, the file cannot be written to anymore once it has been read.
+    pub fn write(&mut self, row: &[Value]) -> Result<(), String> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&(row.len() as u32).to_le_bytes());
+        for value in row {
+            encode_value(value, &mut data);
+        }
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => return Err("spill file has already been read".to_owned()),
+        };
+        writer
+            .write_all(&data)
+            .map_err(|e| io_error(&self.path, e))?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Returns a reader for all rows written to the file.
+    pub fn read(&mut self) -> Result<SpillReader, String> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush().map_err(|e| io_error(&self.path, e))?;
+        }
+        let file = File::open(&self.path).map_err(|e| io_error(&self.path, e))?;
+        Ok(SpillReader {
+            reader: BufReader::new(file),
+            remaining: self.rows,
+        })
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        self.writer = None;
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Reads the rows of a spill file in the order they were written.
+pub struct SpillReader {
+    reader: BufReader<File>,
+    remaining: u64,
+}
+
+impl SpillReader {
+    pub fn next(&mut self) -> Result<Option<Tuple>, String> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        let len = u32::from_le_bytes(self.bytes::<4>()?) as usize;
+        (0..len)
+            .map(|_| self.value())
+            .collect::<Result<_, _>>()
+            .map(Some)
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut buf = [0; N];
+        self.reader
+            .read_exact(&mut buf)
+            .map_err(|e| format!("could not read spill file: {}", e))?;
+        Ok(buf)
+    }
+
+    fn vec(&mut self) -> Result<Vec<u8>, String> {
+        let len = u32::from_le_bytes(self.bytes::<4>()?) as usize;
+        let mut buf = vec![0; len];
+        self.reader
+            .read_exact(&mut buf)
+            .map_err(|e| format!("could not read spill file: {}", e))?;
+        Ok(buf)
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        let [tag] = self.bytes::<1>()?;
+        Ok(match tag {
+            NULL => Value::Null,
+            INT => Value::Int(i32::from_le_bytes(self.bytes()?)),
+            BIGINT => Value::BigInt(i64::from_le_bytes(self.bytes()?)),
+            FLOAT => Value::Float(f64::from_le_bytes(self.bytes()?)),
+            BOOLEAN => Value::Boolean(self.bytes::<1>()? != [0]),
+            TEXT => match String::from_utf8(self.vec()?) {
+                Ok(s) => Value::Text(s),
+                Err(_) => return Err("spill file contains invalid UTF-8".to_owned()),
+            },
+            DATE => Value::Date(i32::from_le_bytes(self.bytes()?)),
+            TIMESTAMP => Value::Timestamp(i64::from_le_bytes(self.bytes()?)),
+            BYTEA => Value::Bytea(self.vec()?),
+            tag => return Err(format!("spill file contains unknown value tag {}", tag)),
+        })
+    }
+}
+
+/// Rows kept in memory up to a budget, further rows are written to a spill file.
+pub struct RowBuffer {
+    /// Bytes of rows kept in memory.
+    memory: usize,
+    size: usize,
+    rows: Vec<Tuple>,
+    overflow: Option<SpillFile>,
+}
+
+impl RowBuffer {
+    pub fn new(memory: usize) -> Self {
+        Self {
+            memory,
+            size: 0,
+            rows: Vec::new(),
+            overflow: None,
+        }
+    }
+
+    pub fn push(&mut self, row: Tuple) -> Result<(), String> {
+        if let Some(file) = &mut self.overflow {
+            return file.write(&row);
+        }
+        self.size += row_size(&row);
+        self.rows.push(row);
+        if self.size > self.memory {
+            let mut file = SpillFile::create()?;
+            // the rows in memory stay there, only later ones go to the file
+            file.write(&self.rows.pop().unwrap())?;
+            self.overflow = Some(file);
+        }
+        Ok(())
+    }
+
+    /// Returns a reader for all rows, in the order they were pushed.
+    /// No more rows can be pushed once rows have been written to the spill file and read.
+    pub fn read(&mut self) -> Result<RowBufferReader<'_>, String> {
+        let overflow = match &mut self.overflow {
+            Some(file) => Some(file.read()?),
+            None => None,
+        };
+        Ok(RowBufferReader {
+            rows: &self.rows,
+            position: 0,
+            overflow,
+        })
+    }
+
+    /// Turns the buffer into an iterator over its rows, in the order they were pushed.
+    /// The spill file is deleted once the iterator is dropped.
+    pub fn into_rows(self) -> Result<RowBufferRows, String> {
+        let overflow = match self.overflow {
+            Some(mut file) => {
+                let reader = file.read()?;
+                Some((file, reader))
+            }
+            None => None,
+        };
+        Ok(RowBufferRows {
+            rows: self.rows.into_iter(),
+            overflow,
+        })
+    }
+
+    /// Removes all rows and deletes the spill file.
+    pub fn clear(&mut self) {
+        self.rows.clear();
+        self.size = 0;
+        self.overflow = None;
+    }
+}
+
+pub struct RowBufferReader<'a> {
+    rows: &'a [Tuple],
+    position: usize,
+    overflow: Option<SpillReader>,
+}
+
+impl RowBufferReader<'_> {
+    pub fn next(&mut self) -> Result<Option<Tuple>, String> {
+        if let Some(row) = self.rows.get(self.position) {
+            self.position += 1;
+            return Ok(Some(row.clone()));
+        }
+        match &mut self.overflow {
+            Some(reader) => reader.next(),
+            None => Ok(None),
+        }
+    }
+}
+
+pub struct RowBufferRows {
+    rows: IntoIter<Tuple>,
+    overflow: Option<(SpillFile, SpillReader)>,
+}
+
+impl RowBufferRows {
+    pub fn next(&mut self) -> Result<Option<Tuple>, String> {
+        if let Some(row) = self.rows.next() {
+            return Ok(Some(row));
+        }
+        match &mut self.overflow {
+            Some((_, reader)) => reader.next(),
+            None => Ok(None),
+        }
+    }
+}
+
+fn encode_value(value: &Value, data: &mut Vec<u8>) {
+    let mut bytes = |tag: u8, bytes: &[u8]| {
+        data.push(tag);
+        data.extend_from_slice(bytes);
+    };
+    match value {
+        Value::Null => bytes(NULL, &[]),
+        Value::Int(v) => bytes(INT, &v.to_le_bytes()),
+        Value::BigInt(v) => bytes(BIGINT, &v.to_le_bytes()),
+        Value::Float(v) => bytes(FLOAT, &v.to_le_bytes()),
+        Value::Boolean(v) => bytes(BOOLEAN, &[*v as u8]),
+        Value::Text(v) => {
+            let len: u32 = v.len().try_into().unwrap();
+            bytes(TEXT, &len.to_le_bytes());
+            data.extend_from_slice(v.as_bytes());
+        }
+        Value::Date(v) => bytes(DATE, &v.to_le_bytes()),
+        Value::Timestamp(v) => bytes(TIMESTAMP, &v.to_le_bytes()),
+        Value::Bytea(v) => {
+            let len: u32 = v.len().try_into().unwrap();
+            bytes(BYTEA, &len.to_le_bytes());
+            data.extend_from_slice(v);
+        }
+    }
+}
+
+fn io_error(path: &Path, err: io::Error) -> String {
+    format!(
+        "could not access spill file \"{}\": {}",
+        path.display(),
+        err
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_read() {
+        let rows = vec![
+            vec![
+                Value::Null,
+                Value::Int(-7),
+                Value::BigInt(1 << 40),
+                Value::Float(0.5),
+                Value::Boolean(true),
+            ],
+            vec![],
+            vec![
+                Value::Text("spilled ✓".to_owned()),
+                Value::Date(-3),
+                Value::Timestamp(42),
+                Value::Bytea(vec![0, 255]),
+            ],
+        ];
+        let mut file = SpillFile::create().unwrap();
+        let other = SpillFile::create().unwrap();
+        assert_ne!(file.path(), other.path());
+        for row in &rows {
+            file.write(row).unwrap();
+        }
+        assert!(!file.is_empty());
+
+        // rows can be read any number of times
+        for _ in 0..2 {
+            let mut reader = file.read().unwrap();
+            let mut read = Vec::new();
+            while let Some(row) = reader.next().unwrap() {
+                read.push(row);
+            }
+            assert_eq!(read, rows);
+        }
+        assert!(file.write(&[]).is_err());
+
+        let path = file.path().to_path_buf();
+        assert!(path.exists());
+        drop(file);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn row_buffer() {
+        let row = |i| vec![Value::BigInt(i), Value::Text("x".repeat(100))];
+        let mut buffer = RowBuffer::new(4096);
+        for i in 0..100 {
+            buffer.push(row(i)).unwrap();
+        }
+        assert!(buffer.rows.len() < 100);
+        let path = buffer.overflow.as_ref().unwrap().path().to_path_buf();
+        for _ in 0..2 {
+            let mut reader = buffer.read().unwrap();
+            for i in 0..100 {
+                assert_eq!(reader.next().unwrap(), Some(row(i)));
+            }
+            assert_eq!(reader.next().unwrap(), None);
+        }
+        buffer.clear();
+        assert!(buffer.rows.is_empty() && buffer.overflow.is_none());
+        assert!(!path.exists());
+
+        for i in 0..100 {
+            buffer.push(row(i)).unwrap();
+        }
+        let path = buffer.overflow.as_ref().unwrap().path().to_path_buf();
+        let mut rows = buffer.into_rows().unwrap();
+        for i in 0..100 {
+            assert_eq!(rows.next().unwrap(), Some(row(i)));
+        }
+        assert_eq!(rows.next().unwrap(), None);
+        drop(rows);
+        assert!(!path.exists());
+    }
+}
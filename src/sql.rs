@@ -1,9 +1,64 @@
 // Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
 // Distributed under terms of the MIT license.
 
-use sqlparser::ast::Statement;
+use sqlparser::ast::{AlterTableOperation, Expr, Ident, ObjectName, Query, Statement};
+use sqlparser::dialect::keywords::Keyword;
 use sqlparser::dialect::GenericDialect;
 use sqlparser::parser::Parser;
+use sqlparser::tokenizer::{Token, Tokenizer};
+
+use crate::catalog::{DataType, IndexKind};
+
+/// A statement to execute, either parsed by sqlparser or one of qdb's extensions.
+#[derive(Debug)]
+pub enum Command {
+    Statement(Statement),
+    /// `CREATE INDEX ... USING method (...)`, sqlparser does not support index methods.
+    CreateIndex {
+        statement: Statement,
+        kind: IndexKind,
+    },
+    /// `CREATE TABLE` with identity columns, sqlparser does not support `GENERATED ... AS IDENTITY`.
+    CreateTable {
+        statement: Statement,
+        identities: Vec<IdentityColumn>,
+    },
+    CreateSequence {
+        name: ObjectName,
+        if_not_exists: bool,
+        options: SequenceOptions,
+    },
+    DropSequence {
+        names: Vec<ObjectName>,
+        if_exists: bool,
+        cascade: bool,
+    },
+    /// `ANALYZE [table, ...]`, all tables if none are given.
+    Analyze {
+        tables: Vec<ObjectName>,
+    },
+}
+
+/// Options of a sequence, `None` if not given.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SequenceOptions {
+    pub data_type: Option<DataType>,
+    pub increment: Option<i64>,
+    pub min_value: Option<i64>,
+    pub max_value: Option<i64>,
+    pub start: Option<i64>,
+    pub cache: Option<i64>,
+    pub cycle: Option<bool>,
+}
+
+/// A column defined with `GENERATED {ALWAYS | BY DEFAULT} AS IDENTITY [(options)]`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IdentityColumn {
+    pub column: String,
+    /// Whether explicit values are rejected (`ALWAYS`) or only replace the default (`BY DEFAULT`).
+    pub always: bool,
+    pub options: SequenceOptions,
+}
 
 /*enum Statement {
     Select,
@@ -42,6 +97,377 @@ pub fn parse_sql_statement(sql: &str) -> Result<Vec<Statement>, String> {
     }*/
 }
 
+/// Parses SQL, falling back to qdb's extensions if sqlparser fails.
+pub fn parse_sql(sql: &str) -> Result<Vec<Command>, String> {
+    match parse_sql_statement(sql) {
+        Ok(statements) => Ok(statements.into_iter().map(Command::Statement).collect()),
+        Err(err) => {
+            let extension = parse_index_method(sql)
+                .or_else(|| parse_drop_constraint(sql))
+                .map(Ok)
+                .or_else(|| parse_sequence_statement(sql))
+                .or_else(|| parse_analyze(sql))
+                .or_else(|| parse_identity_columns(sql));
+            match extension {
+                Some(command) => Ok(vec![command?]),
+                None => Err(err),
+            }
+        }
+    }
+}
+
+/// Recognizes `CREATE [UNIQUE] INDEX ... ON table USING method (...)`.
+/// The method is removed and the rest is parsed by sqlparser.
+fn parse_index_method(sql: &str) -> Option<Command> {
+    let dialect = GenericDialect {};
+    let tokens = Tokenizer::new(&dialect, sql).tokenize().ok()?;
+    let significant: Vec<usize> = (0..tokens.len())
+        .filter(|&i| !matches!(tokens[i], Token::Whitespace(_)))
+        .collect();
+    let keyword = |i: usize| match significant.get(i).map(|&t| &tokens[t]) {
+        Some(Token::Word(w)) => w.keyword,
+        _ => Keyword::NoKeyword,
+    };
+
+    let mut pos = 1;
+    if keyword(0) != Keyword::CREATE {
+        return None;
+    }
+    if keyword(pos) == Keyword::UNIQUE {
+        pos += 1;
+    }
+    if keyword(pos) != Keyword::INDEX {
+        return None;
+    }
+    let using = (pos..significant.len()).find(|&i| keyword(i) == Keyword::USING)?;
+    let kind = match tokens.get(*significant.get(using + 1)?) {
+        Some(Token::Word(w)) if w.value.eq_ignore_ascii_case("btree") => IndexKind::BTree,
+        Some(Token::Word(w)) if w.value.eq_ignore_ascii_case("hash") => IndexKind::Hash,
+        _ => return None,
+    };
+
+    let (start, end) = (significant[using], significant[using + 1]);
+    let rest: String = tokens[..start]
+        .iter()
+        .chain(&tokens[end + 1..])
+        .map(|t| t.to_string())
+        .collect();
+    match parse_sql_statement(&rest).ok()?.as_slice() {
+        [statement @ Statement::CreateIndex { .. }] => Some(Command::CreateIndex {
+            statement: statement.clone(),
+            kind,
+        }),
+        _ => None,
+    }
+}
+
+/// Recognizes `ALTER TABLE table DROP CONSTRAINT name`, which sqlparser represents but does not parse.
+fn parse_drop_constraint(sql: &str) -> Option<Command> {
+    let dialect = GenericDialect {};
+    let tokens = Tokenizer::new(&dialect, sql).tokenize().ok()?;
+    let mut parser = Parser::new(tokens, &dialect);
+    if !parser.parse_keywords(&[Keyword::ALTER, Keyword::TABLE]) {
+        return None;
+    }
+    let table = parser.parse_object_name().ok()?;
+    if !parser.parse_keywords(&[Keyword::DROP, Keyword::CONSTRAINT]) {
+        return None;
+    }
+    let name = parser.parse_identifier().ok()?;
+    let _ = parser.consume_token(&Token::SemiColon);
+    if parser.peek_token() != Token::EOF {
+        return None;
+    }
+    Some(Command::Statement(Statement::AlterTable {
+        name: table,
+        operation: AlterTableOperation::DropConstraint { name },
+    }))
+}
+
+/// Recognizes `CREATE SEQUENCE` and `DROP SEQUENCE`.
+/// Errors are returned once the statement is recognized, the error of sqlparser would be misleading.
+fn parse_sequence_statement(sql: &str) -> Option<Result<Command, String>> {
+    let dialect = GenericDialect {};
+    let tokens = Tokenizer::new(&dialect, sql).tokenize().ok()?;
+    let mut parser = Parser::new(tokens, &dialect);
+    let create = if parse_words(&mut parser, &["CREATE", "SEQUENCE"]) {
+        true
+    } else if parse_words(&mut parser, &["DROP", "SEQUENCE"]) {
+        false
+    } else {
+        return None;
+    };
+    let command = if create {
+        parse_create_sequence(&mut parser)
+    } else {
+        parse_drop_sequence(&mut parser)
+    };
+    let _ = parser.consume_token(&Token::SemiColon);
+    Some(command.and_then(|command| match parser.peek_token() {
+        Token::EOF => Ok(command),
+        token => Err(format!("Parsing failed: unexpected {}", token)),
+    }))
+}
+
+/// Recognizes PostgreSQL's `ANALYZE [VERBOSE] [table, ...]`, sqlparser only supports Hive's syntax.
+fn parse_analyze(sql: &str) -> Option<Result<Command, String>> {
+    let dialect = GenericDialect {};
+    let tokens = Tokenizer::new(&dialect, sql).tokenize().ok()?;
+    let mut parser = Parser::new(tokens, &dialect);
+    if !parse_words(&mut parser, &["ANALYZE"]) {
+        return None;
+    }
+    parse_words(&mut parser, &["VERBOSE"]);
+    let _ = parser.consume_token(&Token::SemiColon);
+    let tables = match parser.peek_token() {
+        Token::EOF => Ok(Vec::new()),
+        _ => parser
+            .parse_comma_separated(Parser::parse_object_name)
+            .map_err(parse_error),
+    };
+    let _ = parser.consume_token(&Token::SemiColon);
+    Some(tables.and_then(|tables| match parser.peek_token() {
+        Token::EOF => Ok(Command::Analyze { tables }),
+        token => Err(format!("Parsing failed: unexpected {}", token)),
+    }))
+}
+
+fn parse_create_sequence(parser: &mut Parser) -> Result<Command, String> {
+    let if_not_exists = parse_words(parser, &["IF", "NOT", "EXISTS"]);
+    let name = parser.parse_object_name().map_err(parse_error)?;
+    let options = parse_sequence_options(parser)?;
+    Ok(Command::CreateSequence {
+        name,
+        if_not_exists,
+        options,
+    })
+}
+
+fn parse_drop_sequence(parser: &mut Parser) -> Result<Command, String> {
+    let if_exists = parse_words(parser, &["IF", "EXISTS"]);
+    let names = parser
+        .parse_comma_separated(Parser::parse_object_name)
+        .map_err(parse_error)?;
+    let cascade = parse_words(parser, &["CASCADE"]);
+    if !cascade {
+        parse_words(parser, &["RESTRICT"]);
+    }
+    Ok(Command::DropSequence {
+        names,
+        if_exists,
+        cascade,
+    })
+}
+
+/// Parses the options of `CREATE SEQUENCE` or an identity column, in any order.
+fn parse_sequence_options(parser: &mut Parser) -> Result<SequenceOptions, String> {
+    fn set<T>(option: &mut Option<T>, value: T) -> Result<(), String> {
+        match option.replace(value) {
+            Some(_) => Err("conflicting or redundant options".to_owned()),
+            None => Ok(()),
+        }
+    }
+
+    let mut options = SequenceOptions::default();
+    loop {
+        if parse_words(parser, &["AS"]) {
+            let data_type = parser.parse_data_type().map_err(parse_error)?;
+            match DataType::from_sql(&data_type)? {
+                t @ (DataType::Int | DataType::BigInt) => set(&mut options.data_type, t)?,
+                _ => return Err("sequence type must be smallint, integer, or bigint".to_owned()),
+            }
+        } else if parse_words(parser, &["INCREMENT"]) {
+            parse_words(parser, &["BY"]);
+            set(&mut options.increment, parse_integer(parser)?)?;
+        } else if parse_words(parser, &["MINVALUE"]) {
+            set(&mut options.min_value, parse_integer(parser)?)?;
+        } else if parse_words(parser, &["MAXVALUE"]) {
+            set(&mut options.max_value, parse_integer(parser)?)?;
+        } else if parse_words(parser, &["START"]) {
+            parse_words(parser, &["WITH"]);
+            set(&mut options.start, parse_integer(parser)?)?;
+        } else if parse_words(parser, &["CACHE"]) {
+            set(&mut options.cache, parse_integer(parser)?)?;
+        } else if parse_words(parser, &["CYCLE"]) {
+            set(&mut options.cycle, true)?;
+        } else if parse_words(parser, &["NO", "CYCLE"]) {
+            set(&mut options.cycle, false)?;
+        } else if !parse_words(parser, &["NO", "MINVALUE"])
+            && !parse_words(parser, &["NO", "MAXVALUE"])
+        {
+            return Ok(options);
+        }
+    }
+}
+
+/// Recognizes `CREATE TABLE` statements with identity columns.
+/// The identity specifications are removed and the rest is parsed by sqlparser.
+fn parse_identity_columns(sql: &str) -> Option<Result<Command, String>> {
+    let dialect = GenericDialect {};
+    let tokens = Tokenizer::new(&dialect, sql).tokenize().ok()?;
+    let significant: Vec<usize> = (0..tokens.len())
+        .filter(|&i| !matches!(tokens[i], Token::Whitespace(_)))
+        .collect();
+    let is_word = |i: usize, word: &str| match significant.get(i).map(|&t| &tokens[t]) {
+        Some(Token::Word(w)) => w.quote_style.is_none() && w.value.eq_ignore_ascii_case(word),
+        _ => false,
+    };
+    if !is_word(0, "CREATE") || !is_word(1, "TABLE") {
+        return None;
+    }
+
+    let mut identities = Vec::new();
+    let mut removed = Vec::new();
+    let mut depth = 0;
+    // the column being defined, the first word after the opening parenthesis or a comma
+    let mut column = None;
+    let mut i = 2;
+    while i < significant.len() {
+        match &tokens[significant[i]] {
+            Token::LParen => depth += 1,
+            Token::RParen => depth -= 1,
+            Token::Word(w) if depth == 1 && column.is_none() => {
+                column = Some(ident_name(&Ident {
+                    value: w.value.clone(),
+                    quote_style: w.quote_style,
+                }))
+            }
+            Token::Word(_) if depth == 1 && is_word(i, "GENERATED") => {
+                let start = i;
+                i += 1;
+                let always = if is_word(i, "ALWAYS") {
+                    i += 1;
+                    true
+                } else if is_word(i, "BY") && is_word(i + 1, "DEFAULT") {
+                    i += 2;
+                    false
+                } else {
+                    return None;
+                };
+                if !is_word(i, "AS") || !is_word(i + 1, "IDENTITY") {
+                    return None;
+                }
+                i += 1;
+                let mut options = SequenceOptions::default();
+                if matches!(
+                    significant.get(i + 1).map(|&t| &tokens[t]),
+                    Some(Token::LParen)
+                ) {
+                    let open = i + 1;
+                    let close = (open..significant.len())
+                        .find(|&j| tokens[significant[j]] == Token::RParen)?;
+                    let inner = tokens[significant[open] + 1..significant[close]].to_vec();
+                    let mut parser = Parser::new(inner, &dialect);
+                    options = match parse_sequence_options(&mut parser) {
+                        Ok(options) if parser.peek_token() == Token::EOF => options,
+                        Ok(_) => return Some(Err("invalid identity column options".to_owned())),
+                        Err(err) => return Some(Err(err)),
+                    };
+                    i = close;
+                }
+                identities.push(IdentityColumn {
+                    column: column.clone()?,
+                    always,
+                    options,
+                });
+                removed.push(significant[start]..=significant[i]);
+            }
+            Token::Comma if depth == 1 => column = None,
+            _ => {}
+        }
+        i += 1;
+    }
+    if identities.is_empty() {
+        return None;
+    }
+
+    let rest: String = (0..tokens.len())
+        .filter(|t| !removed.iter().any(|r| r.contains(t)))
+        .map(|t| tokens[t].to_string())
+        .collect();
+    match parse_sql_statement(&rest).ok()?.as_slice() {
+        [statement @ Statement::CreateTable { .. }] => Some(Ok(Command::CreateTable {
+            statement: statement.clone(),
+            identities,
+        })),
+        _ => None,
+    }
+}
+
+/// Consumes the given words if they come next, keywords and other words alike.
+fn parse_words(parser: &mut Parser, words: &[&str]) -> bool {
+    for (i, word) in words.iter().enumerate() {
+        match parser.peek_token() {
+            Token::Word(w) if w.quote_style.is_none() && w.value.eq_ignore_ascii_case(word) => {
+                parser.next_token();
+            }
+            _ => {
+                (0..i).for_each(|_| parser.prev_token());
+                return false;
+            }
+        }
+    }
+    true
+}
+
+fn parse_integer(parser: &mut Parser) -> Result<i64, String> {
+    let negative = parser.consume_token(&Token::Minus);
+    match parser.next_token() {
+        Token::Number(n, _) => {
+            let n = if negative { format!("-{}", n) } else { n };
+            n.parse().map_err(|_| format!("invalid integer: {}", n))
+        }
+        token => Err(format!(
+            "Parsing failed: expected an integer, found {}",
+            token
+        )),
+    }
+}
+
+fn parse_error(err: sqlparser::parser::ParserError) -> String {
+    format!("Parsing failed: {}", err)
+}
+
+/// Parses a single expression, e.g. a column default stored in the catalog.
+pub fn parse_expr(sql: &str) -> Result<Expr, String> {
+    let dialect = GenericDialect {};
+    let tokens = Tokenizer::new(&dialect, sql)
+        .tokenize()
+        .map_err(|err| format!("Parsing failed: {}", err.message))?;
+    Parser::new(tokens, &dialect)
+        .parse_expr()
+        .map_err(|err| format!("Parsing failed: {}", err))
+}
+
+/// Parses a single query, e.g. the definition of a view stored in the catalog.
+pub fn parse_query(sql: &str) -> Result<Query, String> {
+    let dialect = GenericDialect {};
+    let tokens = Tokenizer::new(&dialect, sql)
+        .tokenize()
+        .map_err(|err| format!("Parsing failed: {}", err.message))?;
+    Parser::new(tokens, &dialect)
+        .parse_query()
+        .map_err(|err| format!("Parsing failed: {}", err))
+}
+
+/// Returns the name an identifier refers to.
+/// Unquoted identifiers are case-insensitive and folded to lower case, like in PostgreSQL.
+pub fn ident_name(ident: &Ident) -> String {
+    match ident.quote_style {
+        Some(_) => ident.value.clone(),
+        None => ident.value.to_lowercase(),
+    }
+}
+
+/// Returns the name of the sequence the text argument of `nextval` or `currval` refers to.
+/// Like an identifier, it is folded to lower case unless it is double-quoted.
+pub fn sequence_name(arg: &str) -> String {
+    match arg.strip_prefix('"').and_then(|a| a.strip_suffix('"')) {
+        Some(quoted) => quoted.replace("\"\"", "\""),
+        None => arg.to_lowercase(),
+    }
+}
+
 /*fn parse_select_statement(sql: &str) -> Result<Statement, String> {
     let parts: Vec<&str> = sql.split(' ').collect();
     return Err(format!("Unknown SQL command"));
@@ -68,6 +494,135 @@ mod tests {
         assert!(parse_sql_statement("select id, name, salary from employees").is_ok());
     }
 
+    #[test]
+    fn drop_constraint() {
+        match parse_sql("ALTER TABLE t DROP CONSTRAINT \"T_pkey\";")
+            .unwrap()
+            .as_slice()
+        {
+            [Command::Statement(statement)] => assert_eq!(
+                statement.to_string(),
+                "ALTER TABLE t DROP CONSTRAINT \"T_pkey\""
+            ),
+            other => panic!("unexpected commands: {:?}", other),
+        }
+        assert!(parse_sql("ALTER TABLE t DROP CONSTRAINT a b").is_err());
+    }
+
+    #[test]
+    fn create_index_using() {
+        let commands = parse_sql("CREATE UNIQUE INDEX i ON t USING HASH (a, b);").unwrap();
+        match commands.as_slice() {
+            [Command::CreateIndex { statement, kind }] => {
+                assert_eq!(*kind, IndexKind::Hash);
+                assert_eq!(statement.to_string(), "CREATE UNIQUE INDEX i ON t(a,b)");
+            }
+            _ => panic!("unexpected commands: {:?}", commands),
+        }
+        assert!(matches!(
+            parse_sql("create index i on t using btree (a)").unwrap()[0],
+            Command::CreateIndex {
+                kind: IndexKind::BTree,
+                ..
+            }
+        ));
+        assert!(matches!(
+            parse_sql("CREATE INDEX i ON t (a)").unwrap()[0],
+            Command::Statement(_)
+        ));
+        assert!(parse_sql("CREATE INDEX i ON t USING gist (a)").is_err());
+        assert!(parse_sql("CREATE").is_err());
+        assert!(parse_sql("CREATE UNIQUE").is_err());
+    }
+
+    #[test]
+    fn sequences() {
+        match parse_sql(
+            "CREATE SEQUENCE IF NOT EXISTS s AS INT INCREMENT BY -2 NO MINVALUE START 10 CYCLE",
+        )
+        .unwrap()
+        .as_slice()
+        {
+            [Command::CreateSequence {
+                name,
+                if_not_exists: true,
+                options,
+            }] => {
+                assert_eq!(name.to_string(), "s");
+                assert_eq!(
+                    *options,
+                    SequenceOptions {
+                        data_type: Some(DataType::Int),
+                        increment: Some(-2),
+                        start: Some(10),
+                        cycle: Some(true),
+                        ..SequenceOptions::default()
+                    }
+                );
+            }
+            other => panic!("unexpected commands: {:?}", other),
+        }
+        assert!(parse_sql("CREATE SEQUENCE s START 1 START 2").is_err());
+        assert!(parse_sql("CREATE SEQUENCE s INCREMENT").is_err());
+        assert!(matches!(
+            parse_sql("DROP SEQUENCE IF EXISTS a, b CASCADE").unwrap().as_slice(),
+            [Command::DropSequence {
+                names,
+                if_exists: true,
+                cascade: true,
+            }] if names.len() == 2
+        ));
+        assert_eq!(sequence_name("S"), "s");
+        assert_eq!(sequence_name("\"S\""), "S");
+    }
+
+    #[test]
+    fn analyze() {
+        for (sql, tables) in [
+            ("ANALYZE;", 0),
+            ("analyze verbose a, b", 2),
+            ("ANALYZE t", 1),
+        ] {
+            match parse_sql(sql).unwrap().as_slice() {
+                [Command::Analyze { tables: names }] => assert_eq!(names.len(), tables),
+                other => panic!("unexpected commands: {:?}", other),
+            }
+        }
+        assert!(matches!(
+            parse_sql("ANALYZE TABLE t").unwrap()[0],
+            Command::Statement(Statement::Analyze { .. })
+        ));
+        assert!(parse_sql("ANALYZE t u").is_err());
+    }
+
+    #[test]
+    fn identity_columns() {
+        let sql = "CREATE TABLE t (id BIGINT GENERATED ALWAYS AS IDENTITY (START WITH 5), \
+                   b INT GENERATED BY DEFAULT AS IDENTITY, c TEXT)";
+        match parse_sql(sql).unwrap().as_slice() {
+            [Command::CreateTable {
+                statement,
+                identities,
+            }] => {
+                assert_eq!(
+                    statement.to_string(),
+                    "CREATE TABLE t (id BIGINT, b INT, c TEXT)"
+                );
+                assert_eq!(identities.len(), 2);
+                assert_eq!(identities[0].column, "id");
+                assert!(identities[0].always);
+                assert_eq!(identities[0].options.start, Some(5));
+                assert_eq!(identities[1].column, "b");
+                assert!(!identities[1].always);
+            }
+            other => panic!("unexpected commands: {:?}", other),
+        }
+        assert!(matches!(
+            parse_sql("CREATE TABLE t (id SERIAL)").unwrap()[0],
+            Command::Statement(_)
+        ));
+    }
+
     #[test]
     fn select_from_where() {
         assert!(parse_sql_statement("select id from employees where salary > 100000").is_ok());
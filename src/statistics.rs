@@ -0,0 +1,338 @@
+// Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use crate::catalog::Schema;
+use crate::tuple::Tuple;
+use crate::value::Value;
+
+/// Maximum number of most common values and histogram buckets per column,
+/// like PostgreSQL's `default_statistics_target`.
+pub const STATISTICS_TARGET: usize = 100;
+
+/// Number of rows sampled for most common values and histograms.
+const SAMPLE_SIZE: usize = 300 * STATISTICS_TARGET;
+
+/// Values wider than this many bytes are left out of most common values and histograms,
+/// so that the catalog stays small.
+const WIDTH_LIMIT: usize = 1024;
+
+/// Number of index bits of the HyperLogLog sketches, they use 2^12 registers for about 1.6% error.
+const HLL_PRECISION: u32 = 12;
+
+/// Statistics of a table collected by `ANALYZE`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableStatistics {
+    pub row_count: u64,
+    pub page_count: u64,
+    pub columns: Vec<ColumnStatistics>,
+}
+
+impl TableStatistics {
+    pub fn column(&self, name: &str) -> Option<&ColumnStatistics> {
+        self.columns.iter().find(|c| c.column == name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnStatistics {
+    pub column: String,
+    /// Fraction of rows in which the column is NULL.
+    pub null_fraction: f64,
+    /// Estimated number of distinct non-NULL values.
+    pub distinct_count: u64,
+    /// The most common values with the fraction of rows they appear in, most common first.
+    pub most_common: Vec<(Value, f64)>,
+    /// Ascending bounds of buckets that each hold about the same number of the other non-NULL values.
+    pub histogram: Vec<Value>,
+}
+
+/// Collects the statistics of a table from all of its rows in a single pass.
+/// Counts are exact, most common values and histograms are computed from a random sample.
+pub struct StatisticsCollector {
+    columns: Vec<String>,
+    rows: u64,
+    nulls: Vec<u64>,
+    sketches: Vec<HyperLogLog>,
+    sample: Vec<Tuple>,
+    random: XorShift,
+}
+
+impl StatisticsCollector {
+    pub fn new(schema: &Schema) -> Self {
+        Self {
+            columns: schema.columns().iter().map(|c| c.name.clone()).collect(),
+            rows: 0,
+            nulls: vec![0; schema.len()],
+            sketches: (0..schema.len()).map(|_| HyperLogLog::new()).collect(),
+            sample: Vec::new(),
+            random: XorShift(0x9e37_79b9_7f4a_7c15),
+        }
+    }
+
+    pub fn add(&mut self, row: Tuple) {
+        for (c, value) in row.iter().enumerate() {
+            match value {
+                Value::Null => self.nulls[c] += 1,
+                value => self.sketches[c].add(value),
+            }
+        }
+        self.rows += 1;
+        // reservoir sampling keeps every row seen so far with the same probability
+        if self.sample.len() < SAMPLE_SIZE {
+            self.sample.push(row);
+        } else {
+            let i = self.random.below(self.rows);
+            if i < SAMPLE_SIZE as u64 {
+                self.sample[i as usize] = row;
+            }
+        }
+    }
+
+    pub fn finish(self, page_count: u64) -> TableStatistics {
+        let complete = self.rows == self.sample.len() as u64;
+        let columns = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(c, name)| {
+                let values = self.sample.iter().map(|row| &row[c]);
+                let mut statistics = column_statistics(name, values, self.sample.len());
+                if self.rows > 0 {
+                    statistics.null_fraction = self.nulls[c] as f64 / self.rows as f64;
+                }
+                // counting the sample is exact if it is the whole table
+                if !complete {
+                    let non_null = self.rows - self.nulls[c];
+                    statistics.distinct_count =
+                        (self.sketches[c].estimate().round() as u64).min(non_null);
+                }
+                statistics
+            })
+            .collect();
+        TableStatistics {
+            row_count: self.rows,
+            page_count,
+            columns,
+        }
+    }
+}
+
+/// Computes the statistics of a column from a sample of its values.
+fn column_statistics<'a>(
+    name: &str,
+    values: impl Iterator<Item = &'a Value>,
+    sample_size: usize,
+) -> ColumnStatistics {
+    let mut counts: HashMap<&Value, usize> = HashMap::new();
+    let mut nulls = 0;
+    for value in values {
+        match value {
+            Value::Null => nulls += 1,
+            value => *counts.entry(value).or_default() += 1,
+        }
+    }
+    let distinct_count = counts.len() as u64;
+    let non_null = sample_size - nulls;
+    let mut counts: Vec<_> = counts
+        .into_iter()
+        .filter(|(value, _)| !is_wide(value))
+        .collect();
+    counts.sort_by(|(a, m), (b, n)| n.cmp(m).then_with(|| a.cmp(b)));
+
+    // values are common if they appear more often than the average value,
+    // unless all values fit the list and it describes the column completely
+    let common = if counts.len() <= STATISTICS_TARGET && distinct_count as usize == counts.len() {
+        counts.len()
+    } else {
+        let average = non_null as f64 / distinct_count as f64;
+        counts
+            .iter()
+            .take(STATISTICS_TARGET)
+            .take_while(|(_, n)| *n > 1 && *n as f64 > 1.25 * average)
+            .count()
+    };
+    let most_common = counts[..common]
+        .iter()
+        .map(|(value, n)| ((*value).clone(), *n as f64 / sample_size as f64))
+        .collect();
+
+    let mut rest: Vec<&Value> = counts[common..]
+        .iter()
+        .flat_map(|(value, n)| std::iter::repeat_n(*value, *n))
+        .collect();
+    rest.sort();
+    let histogram = if rest.len() < 2 {
+        Vec::new()
+    } else {
+        let buckets = STATISTICS_TARGET.min(rest.len() - 1);
+        (0..=buckets)
+            .map(|i| rest[i * (rest.len() - 1) / buckets].clone())
+            .collect()
+    };
+
+    ColumnStatistics {
+        column: name.to_owned(),
+        null_fraction: if sample_size == 0 {
+            0.0
+        } else {
+            nulls as f64 / sample_size as f64
+        },
+        distinct_count,
+        most_common,
+        histogram,
+    }
+}
+
+fn is_wide(value: &Value) -> bool {
+    match value {
+        Value::Text(s) => s.len() > WIDTH_LIMIT,
+        Value::Bytea(b) => 2 * b.len() > WIDTH_LIMIT,
+        _ => false,
+    }
+}
+
+/// Estimates the number of distinct values it has seen, using a fixed amount of memory.
+pub struct HyperLogLog {
+    /// The maximum rank of the hashes that fall into each register.
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        Self {
+            registers: vec![0; 1 << HLL_PRECISION],
+        }
+    }
+
+    pub fn add(&mut self, value: &Value) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let register = (hash >> (64 - HLL_PRECISION)) as usize;
+        // position of the first one bit in the remaining bits
+        let rank = ((hash << HLL_PRECISION).leading_zeros() + 1).min(64 - HLL_PRECISION + 1);
+        self.registers[register] = self.registers[register].max(rank as u8);
+    }
+
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        // linear counting is more accurate for small cardinalities
+        if estimate <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            estimate
+        }
+    }
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A fast pseudo-random number generator, seeded the same way every time
+/// so that statistics are reproducible.
+struct XorShift(u64);
+
+impl XorShift {
+    /// Returns a number in `0..n`.
+    fn below(&mut self, n: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::catalog::{Column, DataType};
+
+    #[test]
+    fn hyperloglog() {
+        let mut sketch = HyperLogLog::new();
+        assert_eq!(sketch.estimate(), 0.0);
+        for i in 0..100_000 {
+            sketch.add(&Value::BigInt(i % 50_000));
+        }
+        let estimate = sketch.estimate();
+        assert!((48_000.0..52_000.0).contains(&estimate), "{}", estimate);
+
+        let mut small = HyperLogLog::new();
+        for i in 0..100 {
+            small.add(&Value::Text(format!("v{}", i % 10)));
+        }
+        assert_eq!(small.estimate().round(), 10.0);
+    }
+
+    #[test]
+    fn collect() {
+        let schema = Schema::new(vec![
+            Column::new("a", DataType::Int),
+            Column::new("b", DataType::Text),
+        ])
+        .unwrap();
+        let mut collector = StatisticsCollector::new(&schema);
+        for i in 0..1000 {
+            // b is NULL in every fourth row and "x" in half of the others
+            let b = match i % 4 {
+                0 => Value::Null,
+                1 => Value::Text(format!("{}", i)),
+                _ => Value::Text("x".to_owned()),
+            };
+            collector.add(vec![Value::Int(i), b]);
+        }
+        let statistics = collector.finish(3);
+        assert_eq!((statistics.row_count, statistics.page_count), (1000, 3));
+
+        let a = statistics.column("a").unwrap();
+        assert_eq!(a.null_fraction, 0.0);
+        assert_eq!(a.distinct_count, 1000);
+        assert!(a.most_common.is_empty());
+        assert_eq!(a.histogram.len(), STATISTICS_TARGET + 1);
+        assert_eq!(a.histogram[0], Value::Int(0));
+        assert_eq!(a.histogram[1], Value::Int(9));
+        assert_eq!(a.histogram[STATISTICS_TARGET], Value::Int(999));
+
+        let b = statistics.column("b").unwrap();
+        assert_eq!(b.null_fraction, 0.25);
+        assert_eq!(b.distinct_count, 251);
+        assert_eq!(b.most_common, vec![(Value::Text("x".to_owned()), 0.5)]);
+        assert_eq!(b.histogram.len(), STATISTICS_TARGET + 1);
+    }
+
+    #[test]
+    fn sampled() {
+        let schema = Schema::new(vec![Column::new("a", DataType::BigInt)]).unwrap();
+        let mut collector = StatisticsCollector::new(&schema);
+        let rows = 2 * SAMPLE_SIZE as i64;
+        for i in 0..rows {
+            let value = if i % 10 == 0 {
+                Value::Null
+            } else {
+                Value::BigInt(i % 4999)
+            };
+            collector.add(vec![value]);
+        }
+        let a = &collector.finish(100).columns[0];
+        assert_eq!(a.null_fraction, 0.1);
+        assert!(
+            (4800..5200).contains(&a.distinct_count),
+            "{}",
+            a.distinct_count
+        );
+        assert!(a.most_common.len() <= STATISTICS_TARGET);
+        assert!(a.histogram.windows(2).all(|w| w[0] <= w[1]));
+    }
+}
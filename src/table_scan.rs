@@ -1,39 +1,164 @@
 // Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
 // Distributed under terms of the MIT license.
 
-use std::fs::File;
-use std::io::Read;
-use std::sync::Arc;
-
-use crate::buffer_manager::BufferManager;
-use crate::page::*;
-use crate::relation::Relation;
-
-pub struct TableScanner<'a> {
-    file: File,
-    rel: &'a Relation<'a>,
-    mm: &'a mut BufferManager,
+use std::sync::{Arc, RwLock};
+
+use sqlparser::ast::Expr;
+
+use crate::binder::{resolve, Field};
+use crate::buffer_manager::{fetch_page, BufferManager};
+use crate::catalog::TableMetadata;
+use crate::executor::PlanRow;
+use crate::expression::{column_references, evaluate};
+use crate::page::{Page, PageID, RecordId};
+use crate::tuple::{RowFormat, Tuple};
+use crate::value::Value;
+
+/// Iterates the rows of a table through the buffer manager, keeping only the current page pinned.
+/// Deleted slots are skipped.
+///
+/// Rows can be filtered by a predicate and reduced to some of their columns while decoding them,
+/// only the columns the predicate uses are decoded for rows it rejects.
+/// The scanner does not hold on to the buffer manager between calls,
+/// but `close` has to be called to unpin the current page if the scan is not run to its end.
+pub struct TableScanner {
+    format: Arc<RowFormat>,
+    width: usize,
+    next_page: Option<PageID>,
+    page: Option<(PageID, Arc<RwLock<Page>>)>,
+    next_slot: u16,
+    predicate: Option<Predicate>,
+    /// Columns and the values the returned rows must have in them.
+    key: Option<(Vec<usize>, Vec<Value>)>,
+    /// The columns of the returned rows.
+    projection: Vec<usize>,
 }
 
-impl<'a> TableScanner<'a> {
-    pub fn new(rel: &'a Relation, mm: &'a mut BufferManager) -> TableScanner<'a> {
-        let file = File::open(&rel.file).unwrap();
-        return TableScanner { file, rel, mm };
-    }
+struct Predicate {
+    expr: Expr,
+    /// Fields of all columns of the table, to resolve the predicate's column references.
+    fields: Vec<Field>,
+    /// The columns the predicate references.
+    columns: Vec<usize>,
 }
 
-impl<'a> Iterator for TableScanner<'a> {
-    type Item = Arc<Page>;
+impl TableScanner {
+    /// Scans all columns of all rows.
+    pub fn new(table: &TableMetadata) -> Self {
+        Self {
+            format: table.row_format().clone(),
+            width: table.schema.len(),
+            next_page: Some(table.first_page),
+            page: None,
+            next_slot: 0,
+            predicate: None,
+            key: None,
+            projection: (0..table.schema.len()).collect(),
+        }
+    }
+
+    /// Only returns rows for which the predicate is true.
+    /// `fields` are the table's columns, as the predicate refers to them.
+    pub fn with_predicate(mut self, expr: Expr, fields: Vec<Field>) -> Result<Self, String> {
+        let mut columns = column_references(&expr)
+            .iter()
+            .map(|name| resolve(&fields, name))
+            .collect::<Result<Vec<_>, _>>()?;
+        columns.sort_unstable();
+        columns.dedup();
+        self.predicate = Some(Predicate {
+            expr,
+            fields,
+            columns,
+        });
+        Ok(self)
+    }
+
+    /// Only returns rows whose `columns` are equal to `key`, checked without decoding the rows.
+    pub fn with_key(mut self, columns: Vec<usize>, key: Vec<Value>) -> Self {
+        self.key = Some((columns, key));
+        self
+    }
+
+    /// Only returns the given columns of each row, in the given order.
+    pub fn with_projection(mut self, columns: Vec<usize>) -> Self {
+        self.projection = columns;
+        self
+    }
+
+    /// Returns the next row and its record ID, or `None` once all rows have been returned.
+    pub fn next(&mut self, bm: &mut BufferManager) -> Result<Option<(RecordId, Tuple)>, String> {
+        loop {
+            let (page_id, page) = match &self.page {
+                Some(current) => current.clone(),
+                None => match self.next_page {
+                    Some(id) => {
+                        let page = fetch_page(bm, id)?;
+                        self.page = Some((id, page.clone()));
+                        self.next_slot = 0;
+                        (id, page)
+                    }
+                    None => return Ok(None),
+                },
+            };
+            let guard = page.read().unwrap();
+            while self.next_slot < guard.num_slots() {
+                let slot = self.next_slot;
+                self.next_slot += 1;
+                if let Some(data) = guard.get_tuple(slot) {
+                    if let Some(row) = self.row(data)? {
+                        return Ok(Some((
+                            RecordId {
+                                page: page_id,
+                                slot,
+                            },
+                            row,
+                        )));
+                    }
+                }
+            }
+            self.next_page = guard.next_page();
+            drop(guard);
+            self.page = None;
+            bm.unpin_page(page_id, false);
+        }
+    }
+
+    /// Unpins the current page, the scan cannot be continued afterwards.
+    pub fn close(&mut self, bm: &mut BufferManager) {
+        if let Some((page, _)) = self.page.take() {
+            bm.unpin_page(page, false);
+        }
+        self.next_page = None;
+    }
 
-    // TODO handle errors & deallocate page if read 0 bytes
-    fn next(&mut self) -> Option<Self::Item> {
-        /*let mut p = self.mm.allocate_empty_page().unwrap();
-        let n = self.file.read(&mut p.data).unwrap();
-        match n {
-            0 => None,
-            default => Some(p),
-        }*/
-        None
+    /// Decodes a stored row, `None` if the key or the predicate rejects it.
+    fn row(&self, data: &[u8]) -> Result<Option<Tuple>, String> {
+        if let Some((columns, key)) = &self.key {
+            let row = self.format.row(data)?;
+            if columns
+                .iter()
+                .zip(key)
+                .any(|(&c, v)| row.get(c).to_value() != *v)
+            {
+                return Ok(None);
+            }
+        }
+        if let Some(predicate) = &self.predicate {
+            let mut values = vec![Value::Null; self.width];
+            let decoded = self.format.decode_columns(data, &predicate.columns)?;
+            for (&c, value) in predicate.columns.iter().zip(decoded) {
+                values[c] = value;
+            }
+            let row = PlanRow {
+                fields: &predicate.fields,
+                values: &values,
+            };
+            if evaluate(&predicate.expr, &row)?.truth()? != Some(true) {
+                return Ok(None);
+            }
+        }
+        self.format.decode_columns(data, &self.projection).map(Some)
     }
 }
 
@@ -41,11 +166,78 @@ impl<'a> Iterator for TableScanner<'a> {
 mod tests {
     use super::*;
 
+    use crate::catalog::{Catalog, Schema};
+    use crate::disk_manager::TempFile;
+    use crate::heap_file::HeapFile;
+    use crate::sql::{parse_expr, parse_sql_statement};
+
     #[test]
-    fn test_scan() {
-        let mut mm = BufferManager::new(20);
-        let r = Relation::new(&mut mm, "movies.txt");
-        //let scanner = TableScanner::new(&r, &mut mm);
-        //for b in scanner {}
+    fn scan() {
+        let file = TempFile::new("table_scan");
+        let mut bm = BufferManager::open(4, file.path()).unwrap();
+        let mut catalog = Catalog::open(&mut bm).unwrap();
+        let schema =
+            match &parse_sql_statement("CREATE TABLE t (a INT, b TEXT, c BIGINT)").unwrap()[0] {
+                sqlparser::ast::Statement::CreateTable { columns, .. } => {
+                    Schema::from_column_defs(columns).unwrap()
+                }
+                _ => unreachable!(),
+            };
+        let id = catalog.create_table(&mut bm, "t", &schema).unwrap();
+        let table = catalog.get_table_by_id(id).unwrap().clone();
+        let format = table.row_format();
+        let mut heap = HeapFile::open(&mut bm, table.first_page).unwrap();
+        let mut rids = Vec::new();
+        for i in 0..2000 {
+            let row = vec![
+                Value::Int(i),
+                Value::Text(format!("row {}", i)),
+                Value::BigInt(i as i64 * 2),
+            ];
+            rids.push(heap.insert(&mut bm, &format.encode(&row).unwrap()).unwrap());
+        }
+        for rid in rids.iter().step_by(2) {
+            heap.delete(&mut bm, *rid).unwrap();
+        }
+        let pages_free = bm.pages_free();
+
+        let mut scanner = TableScanner::new(&table);
+        let mut count = 0;
+        while let Some((rid, row)) = scanner.next(&mut bm).unwrap() {
+            assert_eq!(rid, rids[2 * count + 1]);
+            assert_eq!(row[0], Value::Int(2 * count as i32 + 1));
+            count += 1;
+        }
+        assert_eq!(count, 1000);
+        assert_eq!(bm.pages_free(), pages_free);
+
+        let fields = vec![
+            Field::new(Some("t"), "a"),
+            Field::new(Some("t"), "b"),
+            Field::new(Some("t"), "c"),
+        ];
+        let mut scanner = TableScanner::new(&table)
+            .with_predicate(
+                parse_expr("t.a % 100 = 1 AND c > 1000").unwrap(),
+                fields.clone(),
+            )
+            .unwrap()
+            .with_projection(vec![2, 0]);
+        let (_, first) = scanner.next(&mut bm).unwrap().unwrap();
+        assert_eq!(first, vec![Value::BigInt(1002), Value::Int(501)]);
+        scanner.close(&mut bm);
+        assert_eq!(bm.pages_free(), pages_free);
+
+        let mut scanner =
+            TableScanner::new(&table).with_key(vec![1], vec![Value::Text("row 7".into())]);
+        let (rid, row) = scanner.next(&mut bm).unwrap().unwrap();
+        assert_eq!(rid, rids[7]);
+        assert_eq!(row[2], Value::BigInt(14));
+        assert!(scanner.next(&mut bm).unwrap().is_none());
+        assert_eq!(bm.pages_free(), pages_free);
+
+        assert!(TableScanner::new(&table)
+            .with_predicate(parse_expr("d = 1").unwrap(), fields)
+            .is_err());
     }
 }
@@ -0,0 +1,403 @@
+// Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::convert::TryInto;
+
+use crate::catalog::{DataType, StoredColumn};
+use crate::value::Value;
+
+/// A row of values, in schema order.
+pub type Tuple = Vec<Value>;
+
+/// Variable-length fields are located through their end offset, stored in the fixed-width part.
+const VAR_OFFSET_SIZE: usize = 2;
+
+/// Byte layout of the tuples of a schema:
+/// a null bitmap, followed by one fixed-width field per column and the variable-length data.
+/// Fixed-width columns are stored inline (zeroed if NULL),
+/// variable-length columns store the offset at which their data ends in the tuple.
+/// Their data starts where the previous variable-length column's data ends.
+#[derive(Clone, Debug)]
+pub struct TupleLayout {
+    types: Vec<DataType>,
+    /// Offset of every column's fixed-width field.
+    offsets: Vec<usize>,
+    /// Start of the variable-length data.
+    fixed_size: usize,
+}
+
+impl TupleLayout {
+    pub fn from_types(types: Vec<DataType>) -> Self {
+        let mut offsets = Vec::with_capacity(types.len());
+        let mut offset = types.len().div_ceil(8);
+        for t in &types {
+            offsets.push(offset);
+            offset += fixed_width(*t).unwrap_or(VAR_OFFSET_SIZE);
+        }
+        Self {
+            types,
+            offsets,
+            fixed_size: offset,
+        }
+    }
+
+    /// Serializes a row, which must have a value of the matching type (or NULL) for every column.
+    pub fn encode(&self, values: &[Value]) -> Result<Vec<u8>, String> {
+        if values.len() != self.types.len() {
+            return Err(format!(
+                "expected {} values, got {}",
+                self.types.len(),
+                values.len()
+            ));
+        }
+        let mut buf = vec![0; self.fixed_size];
+        for (i, (value, &t)) in values.iter().zip(&self.types).enumerate() {
+            if !value.has_type(t) {
+                return Err(format!(
+                    "value {:?} does not match column type {}",
+                    value, t
+                ));
+            }
+            let offset = self.offsets[i];
+            let field = &mut buf[offset..offset + fixed_width(t).unwrap_or(VAR_OFFSET_SIZE)];
+            match value {
+                Value::Null => {}
+                Value::Int(v) => field.copy_from_slice(&v.to_le_bytes()),
+                Value::BigInt(v) => field.copy_from_slice(&v.to_le_bytes()),
+                Value::Float(v) => field.copy_from_slice(&v.to_le_bytes()),
+                Value::Boolean(v) => field[0] = *v as u8,
+                Value::Date(v) => field.copy_from_slice(&v.to_le_bytes()),
+                Value::Timestamp(v) => field.copy_from_slice(&v.to_le_bytes()),
+                Value::Text(_) | Value::Bytea(_) => {}
+            }
+
+            if value.is_null() {
+                buf[i / 8] |= 1 << (i % 8);
+            }
+            if fixed_width(t).is_none() {
+                match value {
+                    Value::Text(v) => buf.extend_from_slice(v.as_bytes()),
+                    Value::Bytea(v) => buf.extend_from_slice(v),
+                    _ => {}
+                }
+                let end: u16 = buf
+                    .len()
+                    .try_into()
+                    .map_err(|_| "tuple too large".to_owned())?;
+                buf[offset..offset + VAR_OFFSET_SIZE].copy_from_slice(&end.to_le_bytes());
+            }
+        }
+        Ok(buf)
+    }
+}
+
+/// Size of the schema version stored in front of every row.
+const VERSION_SIZE: usize = 2;
+
+/// Format of the rows of a table, whose columns may have changed since rows were stored.
+/// Every row starts with the schema version it was written under, followed by a tuple of that
+/// version's layout. Rows are always written under the current version, and rows of older
+/// versions are converted when reading them: dropped columns are skipped and columns added
+/// later get the value that was recorded when they were added.
+#[derive(Clone, Debug)]
+pub struct RowFormat {
+    version: u16,
+    /// Layout of every schema version, together with the position of each current column in it.
+    versions: Vec<(TupleLayout, Vec<Option<usize>>)>,
+    /// For every current column, its value in rows of versions without it.
+    missing: Vec<Value>,
+}
+
+impl RowFormat {
+    /// Creates the format of the given schema version from the table's column history.
+    pub fn new(version: u16, history: &[StoredColumn]) -> Self {
+        let current: Vec<_> = history.iter().filter(|c| c.dropped.is_none()).collect();
+        let versions = (0..=version)
+            .map(|v| {
+                let columns: Vec<_> = history.iter().filter(|c| c.in_version(v)).collect();
+                let layout =
+                    TupleLayout::from_types(columns.iter().map(|c| c.column.data_type).collect());
+                let positions = current
+                    .iter()
+                    .map(|c| columns.iter().position(|other| std::ptr::eq(*c, *other)))
+                    .collect();
+                (layout, positions)
+            })
+            .collect();
+        Self {
+            version,
+            versions,
+            missing: current.iter().map(|c| c.missing.clone()).collect(),
+        }
+    }
+
+    /// Serializes a row of the current schema.
+    pub fn encode(&self, values: &[Value]) -> Result<Vec<u8>, String> {
+        let tuple = self.versions[self.version as usize].0.encode(values)?;
+        let mut buf = Vec::with_capacity(VERSION_SIZE + tuple.len());
+        buf.extend_from_slice(&self.version.to_le_bytes());
+        buf.extend_from_slice(&tuple);
+        Ok(buf)
+    }
+
+    /// Deserializes a row of any schema version into a row of the current schema.
+    pub fn decode(&self, data: &[u8]) -> Result<Tuple, String> {
+        let row = self.row(data)?;
+        Ok((0..self.missing.len())
+            .map(|c| row.get(c).to_value())
+            .collect())
+    }
+
+    /// Deserializes only the given columns of the current schema, in the given order.
+    pub fn decode_columns(&self, data: &[u8], columns: &[usize]) -> Result<Tuple, String> {
+        let row = self.row(data)?;
+        Ok(columns.iter().map(|&c| row.get(c).to_value()).collect())
+    }
+
+    /// Gives access to the current columns of a row of any schema version without decoding it.
+    pub fn row<'a>(&'a self, data: &'a [u8]) -> Result<RowRef<'a>, String> {
+        if data.len() < VERSION_SIZE {
+            return Err("corrupt row: missing schema version".to_owned());
+        }
+        let version = u16::from_le_bytes([data[0], data[1]]);
+        match self.versions.get(version as usize) {
+            Some((layout, positions)) => Ok(RowRef {
+                tuple: TupleRef::new(layout, &data[VERSION_SIZE..]),
+                positions,
+                missing: &self.missing,
+            }),
+            None => Err(format!("corrupt row: unknown schema version {}", version)),
+        }
+    }
+}
+
+/// A stored row seen through the current schema, see `RowFormat::row`.
+#[derive(Clone, Copy)]
+pub struct RowRef<'a> {
+    tuple: TupleRef<'a>,
+    /// Position of each current column in the tuple.
+    positions: &'a [Option<usize>],
+    missing: &'a [Value],
+}
+
+impl<'a> RowRef<'a> {
+    /// Returns the value of the given column of the current schema, borrowing variable-length data.
+    pub fn get(&self, column: usize) -> ValueRef<'a> {
+        match self.positions[column] {
+            Some(p) => self.tuple.get(p),
+            None => ValueRef::from(&self.missing[column]),
+        }
+    }
+}
+
+/// A serialized tuple, e.g. borrowed from a page, whose fields are accessed without copying.
+#[derive(Clone, Copy)]
+pub struct TupleRef<'a> {
+    layout: &'a TupleLayout,
+    data: &'a [u8],
+}
+
+impl<'a> TupleRef<'a> {
+    pub fn new(layout: &'a TupleLayout, data: &'a [u8]) -> Self {
+        Self { layout, data }
+    }
+
+    pub fn is_null(&self, column: usize) -> bool {
+        self.data[column / 8] & (1 << (column % 8)) != 0
+    }
+
+    /// Returns the value of the given column, borrowing variable-length data.
+    pub fn get(&self, column: usize) -> ValueRef<'a> {
+        if self.is_null(column) {
+            return ValueRef::Null;
+        }
+        let data = self.data;
+        let offset = self.layout.offsets[column];
+        match self.layout.types[column] {
+            DataType::Int => ValueRef::Int(i32::from_le_bytes(
+                data[offset..offset + 4].try_into().unwrap(),
+            )),
+            DataType::BigInt => ValueRef::BigInt(i64::from_le_bytes(
+                data[offset..offset + 8].try_into().unwrap(),
+            )),
+            DataType::Float => ValueRef::Float(f64::from_le_bytes(
+                data[offset..offset + 8].try_into().unwrap(),
+            )),
+            DataType::Boolean => ValueRef::Boolean(data[offset] != 0),
+            DataType::Date => ValueRef::Date(i32::from_le_bytes(
+                data[offset..offset + 4].try_into().unwrap(),
+            )),
+            DataType::Timestamp => ValueRef::Timestamp(i64::from_le_bytes(
+                data[offset..offset + 8].try_into().unwrap(),
+            )),
+            DataType::Varchar(_) | DataType::Text => {
+                // only valid UTF-8 is ever encoded
+                ValueRef::Text(std::str::from_utf8(self.var_field(column)).unwrap())
+            }
+            DataType::Bytea => ValueRef::Bytea(self.var_field(column)),
+        }
+    }
+
+    fn var_field(&self, column: usize) -> &'a [u8] {
+        let end = self.var_end(column);
+        let start = (0..column)
+            .rev()
+            .find(|&c| fixed_width(self.layout.types[c]).is_none())
+            .map_or(self.layout.fixed_size, |c| self.var_end(c));
+        &self.data[start..end]
+    }
+
+    fn var_end(&self, column: usize) -> usize {
+        let offset = self.layout.offsets[column];
+        u16::from_le_bytes(self.data[offset..offset + 2].try_into().unwrap()) as usize
+    }
+}
+
+/// A value borrowing its variable-length data, see `Value`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ValueRef<'a> {
+    Null,
+    Int(i32),
+    BigInt(i64),
+    Float(f64),
+    Boolean(bool),
+    Text(&'a str),
+    Date(i32),
+    Timestamp(i64),
+    Bytea(&'a [u8]),
+}
+
+impl<'a> From<&'a Value> for ValueRef<'a> {
+    fn from(value: &'a Value) -> Self {
+        match value {
+            Value::Null => ValueRef::Null,
+            Value::Int(v) => ValueRef::Int(*v),
+            Value::BigInt(v) => ValueRef::BigInt(*v),
+            Value::Float(v) => ValueRef::Float(*v),
+            Value::Boolean(v) => ValueRef::Boolean(*v),
+            Value::Text(v) => ValueRef::Text(v),
+            Value::Date(v) => ValueRef::Date(*v),
+            Value::Timestamp(v) => ValueRef::Timestamp(*v),
+            Value::Bytea(v) => ValueRef::Bytea(v),
+        }
+    }
+}
+
+impl<'a> ValueRef<'a> {
+    pub fn to_value(self) -> Value {
+        match self {
+            ValueRef::Null => Value::Null,
+            ValueRef::Int(v) => Value::Int(v),
+            ValueRef::BigInt(v) => Value::BigInt(v),
+            ValueRef::Float(v) => Value::Float(v),
+            ValueRef::Boolean(v) => Value::Boolean(v),
+            ValueRef::Text(v) => Value::Text(v.to_owned()),
+            ValueRef::Date(v) => Value::Date(v),
+            ValueRef::Timestamp(v) => Value::Timestamp(v),
+            ValueRef::Bytea(v) => Value::Bytea(v.to_vec()),
+        }
+    }
+}
+
+/// Width of the inline field of a fixed-width type, `None` for variable-length types.
+fn fixed_width(data_type: DataType) -> Option<usize> {
+    match data_type {
+        DataType::Boolean => Some(1),
+        DataType::Int | DataType::Date => Some(4),
+        DataType::BigInt | DataType::Float | DataType::Timestamp => Some(8),
+        DataType::Varchar(_) | DataType::Text | DataType::Bytea => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout() -> TupleLayout {
+        TupleLayout::from_types(vec![
+            DataType::BigInt,
+            DataType::Varchar(20),
+            DataType::Int,
+            DataType::Text,
+            DataType::Bytea,
+            DataType::Float,
+            DataType::Boolean,
+            DataType::Date,
+            DataType::Timestamp,
+        ])
+    }
+
+    #[test]
+    fn roundtrip() {
+        let layout = layout();
+        let rows = vec![
+            vec![
+                Value::BigInt(-1),
+                Value::Text("Ada".to_owned()),
+                Value::Int(36),
+                Value::Text(String::new()),
+                Value::Bytea(vec![0, 1, 2]),
+                Value::Float(0.5),
+                Value::Boolean(true),
+                Value::Date(-3650),
+                Value::Timestamp(1_600_000_000_000_000),
+            ],
+            vec![Value::Null; 9],
+            vec![
+                Value::BigInt(i64::MAX),
+                Value::Null,
+                Value::Int(i32::MIN),
+                Value::Text("ünïcødé".to_owned()),
+                Value::Null,
+                Value::Float(f64::NEG_INFINITY),
+                Value::Boolean(false),
+                Value::Null,
+                Value::Timestamp(0),
+            ],
+        ];
+        for row in rows {
+            let data = layout.encode(&row).unwrap();
+            let tuple = TupleRef::new(&layout, &data);
+            let decoded: Tuple = (0..row.len()).map(|i| tuple.get(i).to_value()).collect();
+            assert_eq!(decoded, row);
+        }
+    }
+
+    #[test]
+    fn borrowed_access() {
+        let layout = layout();
+        let row = vec![
+            Value::BigInt(7),
+            Value::Text("Grace".to_owned()),
+            Value::Null,
+            Value::Text("admiral".to_owned()),
+            Value::Bytea(vec![0xff; 100]),
+            Value::Null,
+            Value::Boolean(true),
+            Value::Date(1),
+            Value::Null,
+        ];
+        let data = layout.encode(&row).unwrap();
+        // null bitmap, 6 fixed-width fields and 3 offsets
+        assert_eq!(data.len(), 2 + 8 + 4 + 8 + 1 + 4 + 8 + 3 * 2 + 5 + 7 + 100);
+
+        let tuple = TupleRef::new(&layout, &data);
+        assert!(tuple.is_null(2));
+        assert_eq!(tuple.get(1), ValueRef::Text("Grace"));
+        assert_eq!(tuple.get(3), ValueRef::Text("admiral"));
+        assert_eq!(tuple.get(4), ValueRef::Bytea(&[0xff; 100]));
+        assert_eq!(tuple.get(5), ValueRef::Null);
+        if let ValueRef::Text(name) = tuple.get(1) {
+            assert_eq!(name.as_ptr(), data[layout.fixed_size..].as_ptr());
+        }
+    }
+
+    #[test]
+    fn type_mismatch() {
+        let layout = layout();
+        let mut row = vec![Value::Null; 9];
+        row[0] = Value::Int(1);
+        assert!(layout.encode(&row).is_err());
+        assert!(layout.encode(&row[1..]).is_err());
+    }
+}
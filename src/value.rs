@@ -0,0 +1,1145 @@
+// Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use sqlparser::ast;
+
+use crate::catalog::DataType;
+use crate::index_manager::IndexedRow;
+use crate::key_encoding::{KeyEncoder, KeyOrder};
+
+const MICROS_PER_DAY: i64 = 86_400_000_000;
+
+/// 2^63, the smallest float above `i64::MAX`.
+const I64_END: f64 = 9_223_372_036_854_775_808.0;
+
+/// A single SQL value.
+///
+/// SQL comparisons (`compare`, `sql_eq`) return `None` if either side is NULL.
+/// The `Eq`, `Hash` and `Ord` implementations instead follow `IS NOT DISTINCT FROM`:
+/// NULL equals NULL and sorts after all other values, like in GROUP BY, DISTINCT and ORDER BY.
+/// In both cases numbers of different types compare by numeric value, so `1 = 1.0`,
+/// and dates compare to timestamps as midnight of that day.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Null,
+    Int(i32),
+    BigInt(i64),
+    Float(f64),
+    Boolean(bool),
+    /// Value of a VARCHAR or TEXT column
+    Text(String),
+    /// Days since 1970-01-01
+    Date(i32),
+    /// Microseconds since 1970-01-01 00:00:00
+    Timestamp(i64),
+    Bytea(Vec<u8>),
+}
+
+impl Value {
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// The type of a non-NULL value. VARCHAR values are of type TEXT.
+    pub fn data_type(&self) -> Option<DataType> {
+        match self {
+            Value::Null => None,
+            Value::Int(_) => Some(DataType::Int),
+            Value::BigInt(_) => Some(DataType::BigInt),
+            Value::Float(_) => Some(DataType::Float),
+            Value::Boolean(_) => Some(DataType::Boolean),
+            Value::Text(_) => Some(DataType::Text),
+            Value::Date(_) => Some(DataType::Date),
+            Value::Timestamp(_) => Some(DataType::Timestamp),
+            Value::Bytea(_) => Some(DataType::Bytea),
+        }
+    }
+
+    /// Converts a literal from the SQL AST.
+    /// Integers get the smallest type they fit in, other numbers become FLOAT.
+    pub fn from_literal(literal: &ast::Value) -> Result<Self, String> {
+        match literal {
+            ast::Value::Number(n, _) => {
+                if let Ok(v) = n.parse::<i32>() {
+                    Ok(Value::Int(v))
+                } else if let Ok(v) = n.parse::<i64>() {
+                    Ok(Value::BigInt(v))
+                } else {
+                    n.parse::<f64>()
+                        .map(Value::Float)
+                        .map_err(|_| format!("invalid number: {}", n))
+                }
+            }
+            ast::Value::SingleQuotedString(s) => Ok(Value::Text(s.clone())),
+            ast::Value::Boolean(b) => Ok(Value::Boolean(*b)),
+            ast::Value::Null => Ok(Value::Null),
+            other => Err(format!("unsupported literal: {}", other)),
+        }
+    }
+
+    /// Interprets this value as the result of a condition, where NULL stands for unknown.
+    pub fn truth(&self) -> Result<Option<bool>, String> {
+        match self {
+            Value::Null => Ok(None),
+            Value::Boolean(b) => Ok(Some(*b)),
+            other => Err(format!(
+                "argument of type {} is not a boolean",
+                type_name(other)
+            )),
+        }
+    }
+
+    /// SQL comparison, `None` if either side is NULL.
+    /// Strings are compared bytewise, see `compare_collated`.
+    pub fn compare(&self, other: &Value) -> Result<Option<Ordering>, String> {
+        self.compare_collated(other, Collation::Binary)
+    }
+
+    /// SQL comparison using the given collation for strings.
+    pub fn compare_collated(
+        &self,
+        other: &Value,
+        collation: Collation,
+    ) -> Result<Option<Ordering>, String> {
+        if self.is_null() || other.is_null() {
+            return Ok(None);
+        }
+        if let (Value::Text(a), Value::Text(b)) = (self, other) {
+            return Ok(Some(collation.compare(a, b)));
+        }
+        match compare_non_null(self, other) {
+            Some(ord) => Ok(Some(ord)),
+            None => Err(format!(
+                "cannot compare {} with {}",
+                type_name(self),
+                type_name(other)
+            )),
+        }
+    }
+
+    /// SQL `=`, `None` if either side is NULL.
+    pub fn sql_eq(&self, other: &Value) -> Result<Option<bool>, String> {
+        Ok(self.compare(other)?.map(|o| o == Ordering::Equal))
+    }
+
+    /// Three-valued `AND`: false if either side is false, otherwise NULL if either side is NULL.
+    pub fn and(&self, other: &Value) -> Result<Value, String> {
+        Ok(match (self.truth()?, other.truth()?) {
+            (Some(false), _) | (_, Some(false)) => Value::Boolean(false),
+            (Some(true), Some(true)) => Value::Boolean(true),
+            _ => Value::Null,
+        })
+    }
+
+    /// Three-valued `OR`: true if either side is true, otherwise NULL if either side is NULL.
+    pub fn or(&self, other: &Value) -> Result<Value, String> {
+        Ok(match (self.truth()?, other.truth()?) {
+            (Some(true), _) | (_, Some(true)) => Value::Boolean(true),
+            (Some(false), Some(false)) => Value::Boolean(false),
+            _ => Value::Null,
+        })
+    }
+
+    pub fn not(&self) -> Result<Value, String> {
+        Ok(match self.truth()? {
+            Some(b) => Value::Boolean(!b),
+            None => Value::Null,
+        })
+    }
+
+    pub fn add(&self, other: &Value) -> Result<Value, String> {
+        match (self, other) {
+            (Value::Date(d), n) | (n, Value::Date(d)) if is_integer(n) => add_days(*d, as_i64(n)),
+            _ => arithmetic(
+                self,
+                other,
+                "+",
+                i32::checked_add,
+                i64::checked_add,
+                |a, b| a + b,
+            ),
+        }
+    }
+
+    pub fn sub(&self, other: &Value) -> Result<Value, String> {
+        match (self, other) {
+            (Value::Date(a), Value::Date(b)) => a
+                .checked_sub(*b)
+                .map(Value::Int)
+                .ok_or_else(|| "integer out of range".to_owned()),
+            (Value::Date(d), n) if is_integer(n) => match as_i64(n).checked_neg() {
+                Some(n) => add_days(*d, n),
+                None => Err("date out of range".to_owned()),
+            },
+            _ => arithmetic(
+                self,
+                other,
+                "-",
+                i32::checked_sub,
+                i64::checked_sub,
+                |a, b| a - b,
+            ),
+        }
+    }
+
+    pub fn mul(&self, other: &Value) -> Result<Value, String> {
+        arithmetic(
+            self,
+            other,
+            "*",
+            i32::checked_mul,
+            i64::checked_mul,
+            |a, b| a * b,
+        )
+    }
+
+    /// Division, truncating towards zero for integers.
+    pub fn div(&self, other: &Value) -> Result<Value, String> {
+        if is_zero(other) && is_numeric(self) {
+            return Err("division by zero".to_owned());
+        }
+        arithmetic(
+            self,
+            other,
+            "/",
+            i32::checked_div,
+            i64::checked_div,
+            |a, b| a / b,
+        )
+    }
+
+    /// Remainder of integer division, with the sign of the dividend.
+    pub fn rem(&self, other: &Value) -> Result<Value, String> {
+        if matches!(self, Value::Float(_)) || matches!(other, Value::Float(_)) {
+            return Err(format!(
+                "operator does not exist: {} % {}",
+                type_name(self),
+                type_name(other)
+            ));
+        }
+        if is_zero(other) && is_numeric(self) {
+            return Err("division by zero".to_owned());
+        }
+        arithmetic(
+            self,
+            other,
+            "%",
+            i32::checked_rem,
+            i64::checked_rem,
+            |a, b| a % b,
+        )
+    }
+
+    pub fn neg(&self) -> Result<Value, String> {
+        match self {
+            Value::Null => Ok(Value::Null),
+            Value::Int(v) => v
+                .checked_neg()
+                .map(Value::Int)
+                .ok_or_else(|| "integer out of range".to_owned()),
+            Value::BigInt(v) => v
+                .checked_neg()
+                .map(Value::BigInt)
+                .ok_or_else(|| "bigint out of range".to_owned()),
+            Value::Float(v) => Ok(Value::Float(-v)),
+            other => Err(format!("operator does not exist: - {}", type_name(other))),
+        }
+    }
+
+    /// String concatenation `||`, non-string operands are converted to TEXT.
+    pub fn concat(&self, other: &Value) -> Result<Value, String> {
+        if self.is_null() || other.is_null() {
+            return Ok(Value::Null);
+        }
+        if let (Value::Bytea(a), Value::Bytea(b)) = (self, other) {
+            return Ok(Value::Bytea([&a[..], &b[..]].concat()));
+        }
+        Ok(Value::Text(format!("{}{}", self, other)))
+    }
+
+    /// Converts this value to the given type, following PostgreSQL's casting rules.
+    /// Strings are parsed, floats are rounded to the nearest integer (ties to even),
+    /// and out-of-range values or strings longer than a VARCHAR's limit are errors.
+    pub fn cast(&self, to: DataType) -> Result<Value, String> {
+        let cannot_cast = || Err(format!("cannot cast type {} to {}", type_name(self), to));
+        let out_of_range = || Err(format!("{} out of range", to));
+        match (self, to) {
+            (Value::Null, _) => Ok(Value::Null),
+
+            (Value::Int(v), DataType::Int) => Ok(Value::Int(*v)),
+            (Value::BigInt(v), DataType::Int) => match i32::try_from(*v) {
+                Ok(v) => Ok(Value::Int(v)),
+                Err(_) => out_of_range(),
+            },
+            (Value::Float(v), DataType::Int) => {
+                let v = v.round_ties_even();
+                if v >= i32::MIN as f64 && v <= i32::MAX as f64 {
+                    Ok(Value::Int(v as i32))
+                } else {
+                    out_of_range()
+                }
+            }
+            (Value::Boolean(v), DataType::Int) => Ok(Value::Int(*v as i32)),
+
+            (Value::Int(v), DataType::BigInt) => Ok(Value::BigInt(*v as i64)),
+            (Value::BigInt(v), DataType::BigInt) => Ok(Value::BigInt(*v)),
+            (Value::Float(v), DataType::BigInt) => {
+                let v = v.round_ties_even();
+                // i64::MAX as f64 is 2^63, which is already out of range
+                if v >= i64::MIN as f64 && v < i64::MAX as f64 {
+                    Ok(Value::BigInt(v as i64))
+                } else {
+                    out_of_range()
+                }
+            }
+
+            (Value::Int(v), DataType::Float) => Ok(Value::Float(*v as f64)),
+            (Value::BigInt(v), DataType::Float) => Ok(Value::Float(*v as f64)),
+            (Value::Float(v), DataType::Float) => Ok(Value::Float(*v)),
+
+            (Value::Boolean(v), DataType::Boolean) => Ok(Value::Boolean(*v)),
+            (Value::Int(v), DataType::Boolean) => Ok(Value::Boolean(*v != 0)),
+
+            (Value::Date(v), DataType::Date) => Ok(Value::Date(*v)),
+            (Value::Timestamp(v), DataType::Date) => {
+                Ok(Value::Date(v.div_euclid(MICROS_PER_DAY) as i32))
+            }
+            (Value::Timestamp(v), DataType::Timestamp) => Ok(Value::Timestamp(*v)),
+            (Value::Date(v), DataType::Timestamp) => (*v as i64)
+                .checked_mul(MICROS_PER_DAY)
+                .map(Value::Timestamp)
+                .ok_or_else(|| "timestamp out of range".to_owned()),
+
+            (Value::Bytea(v), DataType::Bytea) => Ok(Value::Bytea(v.clone())),
+            (Value::Text(v), DataType::Bytea) => Ok(Value::Bytea(parse_bytea(v)?)),
+
+            (Value::Text(v), DataType::Varchar(n)) => {
+                if v.chars().count() > n as usize {
+                    return Err(format!("value too long for type character varying({})", n));
+                }
+                Ok(Value::Text(v.clone()))
+            }
+            (_, DataType::Varchar(_)) => self.cast(DataType::Text)?.cast(to),
+            (_, DataType::Text) => Ok(Value::Text(self.to_string())),
+            (Value::Text(v), _) => parse(v, to),
+
+            _ => cannot_cast(),
+        }
+    }
+
+    /// Whether this value can be stored in a column of the given type without conversion.
+    pub fn has_type(&self, data_type: DataType) -> bool {
+        matches!(
+            (self, data_type),
+            (Value::Null, _)
+                | (Value::Int(_), DataType::Int)
+                | (Value::BigInt(_), DataType::BigInt)
+                | (Value::Float(_), DataType::Float)
+                | (Value::Boolean(_), DataType::Boolean)
+                | (Value::Text(_), DataType::Varchar(_))
+                | (Value::Text(_), DataType::Text)
+                | (Value::Date(_), DataType::Date)
+                | (Value::Timestamp(_), DataType::Timestamp)
+                | (Value::Bytea(_), DataType::Bytea)
+        )
+    }
+
+    /// Appends this value to a memcomparable key.
+    /// All integer types share one encoding, so that keys of INT and BIGINT columns compare equal.
+    pub fn encode_key(&self, enc: &mut KeyEncoder, order: KeyOrder) {
+        match self {
+            Value::Null => enc.null(order),
+            Value::Int(v) => enc.i64(*v as i64, order),
+            Value::BigInt(v) => enc.i64(*v, order),
+            Value::Float(v) => enc.f64(*v, order),
+            Value::Boolean(v) => enc.bool(*v, order),
+            Value::Text(v) => enc.str(v, order),
+            Value::Date(v) => enc.i64(*v as i64, order),
+            Value::Timestamp(v) => enc.i64(*v, order),
+            Value::Bytea(v) => enc.bytes(v, order),
+        };
+    }
+
+    /// Appends this value to a memcomparable key that orders values like `Ord`,
+    /// also values of different types, so that sorts can compare their keys bytewise.
+    /// NULLs are placed according to the order instead of last.
+    pub fn encode_sort_key(&self, enc: &mut KeyEncoder, order: KeyOrder) {
+        if self.is_null() {
+            enc.null(order);
+            return;
+        }
+        enc.u8(type_rank(self), order);
+        match self {
+            Value::Null => {}
+            // integers are rounded to the nearest float, the remainder orders those rounded alike
+            Value::Int(_) | Value::BigInt(_) => {
+                let v = as_i64(self);
+                let f = v as f64;
+                enc.f64(f, order).i64((v as i128 - f as i128) as i64, order);
+            }
+            Value::Float(v) => {
+                enc.f64(*v, order).i64(0, order);
+            }
+            Value::Boolean(v) => {
+                enc.bool(*v, order);
+            }
+            Value::Text(v) => {
+                enc.str(v, order);
+            }
+            Value::Date(v) => {
+                enc.i64(*v as i64, order).i64(0, order);
+            }
+            Value::Timestamp(v) => {
+                let (days, micros) = split_timestamp(*v);
+                enc.i64(days, order).i64(micros, order);
+            }
+            Value::Bytea(v) => {
+                enc.bytes(v, order);
+            }
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Total order: values of incomparable types are ordered by type, NULL is largest.
+/// Among floats -0.0 equals 0.0 and NaN is larger than any other number.
+impl Ord for Value {
+    fn cmp(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Null, _) => Ordering::Greater,
+            (_, Value::Null) => Ordering::Less,
+            _ => compare_non_null(self, other)
+                .unwrap_or_else(|| type_rank(self).cmp(&type_rank(other))),
+        }
+    }
+}
+
+/// Consistent with `Eq`: equal numbers of different types have the same hash.
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Value::Null => state.write_u8(0),
+            Value::Int(v) => hash_number(*v as f64, Some(*v as i64), state),
+            Value::BigInt(v) => hash_number(*v as f64, Some(*v), state),
+            Value::Float(v) => hash_number(*v, None, state),
+            Value::Boolean(v) => {
+                state.write_u8(2);
+                v.hash(state);
+            }
+            Value::Text(v) => {
+                state.write_u8(3);
+                v.hash(state);
+            }
+            Value::Date(v) => {
+                state.write_u8(4);
+                (*v as i64, 0i64).hash(state);
+            }
+            Value::Timestamp(v) => {
+                state.write_u8(4);
+                split_timestamp(*v).hash(state);
+            }
+            Value::Bytea(v) => {
+                state.write_u8(5);
+                v.hash(state);
+            }
+        }
+    }
+}
+
+/// Formats values like PostgreSQL's text output.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "NULL"),
+            Value::Int(v) => write!(f, "{}", v),
+            Value::BigInt(v) => write!(f, "{}", v),
+            Value::Float(v) if v.is_nan() => write!(f, "NaN"),
+            Value::Float(v) if v.is_infinite() => {
+                write!(f, "{}Infinity", if *v < 0.0 { "-" } else { "" })
+            }
+            Value::Float(v) => write!(f, "{}", v),
+            Value::Boolean(v) => write!(f, "{}", v),
+            Value::Text(v) => write!(f, "{}", v),
+            Value::Date(v) => {
+                let (y, m, d) = civil_from_days(*v as i64);
+                write!(f, "{:04}-{:02}-{:02}", y, m, d)
+            }
+            Value::Timestamp(v) => {
+                let (y, m, d) = civil_from_days(v.div_euclid(MICROS_PER_DAY));
+                let micros = v.rem_euclid(MICROS_PER_DAY);
+                let secs = micros / 1_000_000;
+                write!(
+                    f,
+                    "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+                    y,
+                    m,
+                    d,
+                    secs / 3600,
+                    secs / 60 % 60,
+                    secs % 60
+                )?;
+                match micros % 1_000_000 {
+                    0 => Ok(()),
+                    frac => write!(f, ".{}", format!("{:06}", frac).trim_end_matches('0')),
+                }
+            }
+            Value::Bytea(v) => {
+                write!(f, "\\x")?;
+                v.iter().try_for_each(|b| write!(f, "{:02x}", b))
+            }
+        }
+    }
+}
+
+/// Rules for comparing strings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Collation {
+    /// Byte order of the UTF-8 encoding, i.e. code point order (`"C"` in PostgreSQL).
+    Binary,
+    /// Like `Binary`, after converting both strings to lower case.
+    CaseInsensitive,
+}
+
+impl Collation {
+    /// Looks up a collation by name, as used in `COLLATE "name"`.
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "c" | "posix" | "binary" | "default" => Ok(Collation::Binary),
+            "nocase" | "case_insensitive" => Ok(Collation::CaseInsensitive),
+            _ => Err(format!("collation \"{}\" does not exist", name)),
+        }
+    }
+
+    pub fn compare(self, a: &str, b: &str) -> Ordering {
+        match self {
+            Collation::Binary => a.cmp(b),
+            Collation::CaseInsensitive => a.to_lowercase().cmp(&b.to_lowercase()),
+        }
+    }
+}
+
+/// Compares non-NULL values, `None` if their types are not comparable.
+fn compare_non_null(a: &Value, b: &Value) -> Option<Ordering> {
+    use Value::*;
+    Some(match (a, b) {
+        (Int(_), Int(_)) | (Int(_), BigInt(_)) | (BigInt(_), Int(_)) | (BigInt(_), BigInt(_)) => {
+            as_i64(a).cmp(&as_i64(b))
+        }
+        (Float(x), Float(y)) => compare_floats(*x, *y),
+        (Int(_), Float(f)) | (BigInt(_), Float(f)) => compare_int_float(as_i64(a), *f),
+        (Float(f), Int(_)) | (Float(f), BigInt(_)) => compare_int_float(as_i64(b), *f).reverse(),
+        (Boolean(x), Boolean(y)) => x.cmp(y),
+        (Text(x), Text(y)) => x.cmp(y),
+        (Date(x), Date(y)) => x.cmp(y),
+        (Timestamp(x), Timestamp(y)) => x.cmp(y),
+        (Date(x), Timestamp(y)) => (*x as i64, 0).cmp(&split_timestamp(*y)),
+        (Timestamp(x), Date(y)) => split_timestamp(*x).cmp(&(*y as i64, 0)),
+        (Bytea(x), Bytea(y)) => x.cmp(y),
+        _ => return None,
+    })
+}
+
+/// Splits a timestamp into days and microseconds of the day,
+/// so it can be compared with a date that is out of range for timestamps.
+fn split_timestamp(micros: i64) -> (i64, i64) {
+    (
+        micros.div_euclid(MICROS_PER_DAY),
+        micros.rem_euclid(MICROS_PER_DAY),
+    )
+}
+
+/// -0.0 equals 0.0, NaN equals NaN and is larger than any other number.
+fn compare_floats(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.partial_cmp(&b).unwrap(),
+    }
+}
+
+/// Exact comparison, without rounding the integer to the nearest float.
+fn compare_int_float(i: i64, f: f64) -> Ordering {
+    if f.is_nan() || f >= I64_END {
+        return Ordering::Less;
+    }
+    if f < -I64_END {
+        return Ordering::Greater;
+    }
+    let t = f.trunc();
+    match i.cmp(&(t as i64)) {
+        Ordering::Equal => compare_floats(t, f),
+        ord => ord,
+    }
+}
+
+fn hash_number<H: Hasher>(f: f64, i: Option<i64>, state: &mut H) {
+    state.write_u8(1);
+    // integral floats hash like the equal integer
+    let i = i.or_else(|| {
+        if (-I64_END..I64_END).contains(&f) && f.trunc() == f {
+            Some(f as i64)
+        } else {
+            None
+        }
+    });
+    match i {
+        Some(i) => i.hash(state),
+        None if f.is_nan() => state.write_u64(u64::MAX),
+        None => f.to_bits().hash(state),
+    }
+}
+
+fn type_rank(v: &Value) -> u8 {
+    match v {
+        Value::Int(_) | Value::BigInt(_) | Value::Float(_) => 0,
+        Value::Boolean(_) => 1,
+        Value::Text(_) => 2,
+        Value::Date(_) | Value::Timestamp(_) => 3,
+        Value::Bytea(_) => 4,
+        Value::Null => 5,
+    }
+}
+
+fn type_name(v: &Value) -> String {
+    match v.data_type() {
+        Some(t) => t.to_string(),
+        None => "NULL".to_owned(),
+    }
+}
+
+fn is_integer(v: &Value) -> bool {
+    matches!(v, Value::Int(_) | Value::BigInt(_))
+}
+
+fn is_numeric(v: &Value) -> bool {
+    matches!(v, Value::Int(_) | Value::BigInt(_) | Value::Float(_))
+}
+
+fn is_zero(v: &Value) -> bool {
+    match v {
+        Value::Int(v) => *v == 0,
+        Value::BigInt(v) => *v == 0,
+        Value::Float(v) => *v == 0.0,
+        _ => false,
+    }
+}
+
+/// The value of an integer, callers must check `is_integer` first.
+fn as_i64(v: &Value) -> i64 {
+    match v {
+        Value::Int(v) => *v as i64,
+        Value::BigInt(v) => *v,
+        _ => unreachable!("not an integer: {:?}", v),
+    }
+}
+
+/// Applies a binary arithmetic operator, promoting INT to BIGINT to FLOAT as necessary.
+fn arithmetic(
+    a: &Value,
+    b: &Value,
+    op: &str,
+    int: fn(i32, i32) -> Option<i32>,
+    bigint: fn(i64, i64) -> Option<i64>,
+    float: fn(f64, f64) -> f64,
+) -> Result<Value, String> {
+    match (a, b) {
+        (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+        (Value::Int(x), Value::Int(y)) => int(*x, *y)
+            .map(Value::Int)
+            .ok_or_else(|| "integer out of range".to_owned()),
+        (Value::Float(_), _) | (_, Value::Float(_)) if is_numeric(a) && is_numeric(b) => {
+            let x = a.cast(DataType::Float)?;
+            let y = b.cast(DataType::Float)?;
+            match (x, y) {
+                (Value::Float(x), Value::Float(y)) => {
+                    let r = float(x, y);
+                    if r.is_infinite() && x.is_finite() && y.is_finite() {
+                        Err("value out of range: overflow".to_owned())
+                    } else {
+                        Ok(Value::Float(r))
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+        _ if is_integer(a) && is_integer(b) => bigint(as_i64(a), as_i64(b))
+            .map(Value::BigInt)
+            .ok_or_else(|| "bigint out of range".to_owned()),
+        _ => Err(format!(
+            "operator does not exist: {} {} {}",
+            type_name(a),
+            op,
+            type_name(b)
+        )),
+    }
+}
+
+/// Days since 1970-01-01 of the first and last dates PostgreSQL supports,
+/// 4714-11-24 BC (the first Julian day) and 5874897-12-31.
+const MIN_DATE: i64 = -2_440_588;
+const MAX_DATE: i64 = 2_145_042_905;
+
+/// The date the given number of days after 1970-01-01, if it is in PostgreSQL's range.
+fn checked_date(days: i64) -> Option<i32> {
+    if (MIN_DATE..=MAX_DATE).contains(&days) {
+        Some(days as i32)
+    } else {
+        None
+    }
+}
+
+fn add_days(date: i32, days: i64) -> Result<Value, String> {
+    (date as i64)
+        .checked_add(days)
+        .and_then(checked_date)
+        .map(Value::Date)
+        .ok_or_else(|| "date out of range".to_owned())
+}
+
+/// Parses the text representation of a value of the given type.
+fn parse(s: &str, to: DataType) -> Result<Value, String> {
+    let invalid = || Err(format!("invalid input syntax for type {}: \"{}\"", to, s));
+    let t = s.trim();
+    match to {
+        DataType::Int => match t.parse::<i64>() {
+            Ok(v) => Value::BigInt(v).cast(to),
+            Err(_) => invalid(),
+        },
+        DataType::BigInt => match t.parse::<i128>() {
+            Ok(v) => match i64::try_from(v) {
+                Ok(v) => Ok(Value::BigInt(v)),
+                Err(_) => Err(format!("{} out of range", to)),
+            },
+            Err(_) => invalid(),
+        },
+        DataType::Float => match t.to_lowercase().as_str() {
+            "nan" => Ok(Value::Float(f64::NAN)),
+            "infinity" | "+infinity" | "inf" => Ok(Value::Float(f64::INFINITY)),
+            "-infinity" | "-inf" => Ok(Value::Float(f64::NEG_INFINITY)),
+            lower => match lower.parse::<f64>() {
+                Ok(v) if v.is_finite() => Ok(Value::Float(v)),
+                Ok(_) => Err(format!("\"{}\" is out of range for type {}", s, to)),
+                Err(_) => invalid(),
+            },
+        },
+        DataType::Boolean => match t.to_lowercase().as_str() {
+            "t" | "true" | "y" | "yes" | "on" | "1" => Ok(Value::Boolean(true)),
+            "f" | "false" | "n" | "no" | "off" | "0" => Ok(Value::Boolean(false)),
+            _ => invalid(),
+        },
+        DataType::Date => match parse_date(t) {
+            Some(days) => Ok(Value::Date(days)),
+            None => invalid(),
+        },
+        DataType::Timestamp => match parse_timestamp(t) {
+            Some(micros) => Ok(Value::Timestamp(micros)),
+            None => invalid(),
+        },
+        DataType::Varchar(_) | DataType::Text => Value::Text(s.to_owned()).cast(to),
+        DataType::Bytea => parse_bytea(s).map(Value::Bytea),
+    }
+}
+
+/// `YYYY-MM-DD`, returns days since 1970-01-01.
+fn parse_date(s: &str) -> Option<i32> {
+    let mut parts = s.splitn(3, '-');
+    let y: i64 = parts.next()?.parse().ok()?;
+    let m: u32 = parts.next()?.parse().ok()?;
+    let d: u32 = parts.next()?.parse().ok()?;
+    let days_in_month = match m {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if y % 4 == 0 && (y % 100 != 0 || y % 400 == 0) => 29,
+        2 => 28,
+        _ => return None,
+    };
+    if d == 0 || d > days_in_month {
+        return None;
+    }
+    checked_date(days_from_civil(y, m, d))
+}
+
+/// `YYYY-MM-DD[( |T)HH:MM[:SS[.ffffff]]]`, returns microseconds since 1970-01-01 00:00:00.
+fn parse_timestamp(s: &str) -> Option<i64> {
+    let (date, time) = match s.find([' ', 'T']) {
+        Some(i) => (&s[..i], s[i + 1..].trim()),
+        None => (s, ""),
+    };
+    let days = parse_date(date)? as i64;
+    let mut micros = 0;
+    if !time.is_empty() {
+        let (hms, frac) = match time.find('.') {
+            Some(i) => (&time[..i], &time[i + 1..]),
+            None => (time, ""),
+        };
+        let parts: Vec<&str> = hms.split(':').collect();
+        if parts.len() < 2 || parts.len() > 3 || frac.len() > 6 {
+            return None;
+        }
+        let h: i64 = parts[0].parse().ok()?;
+        let m: i64 = parts[1].parse().ok()?;
+        let sec: i64 = parts.get(2).map_or(Some(0), |p| p.parse().ok())?;
+        if h > 23 || m > 59 || sec > 59 {
+            return None;
+        }
+        let frac: i64 = match frac {
+            "" => 0,
+            f => f.parse::<i64>().ok()? * 10i64.pow(6 - f.len() as u32),
+        };
+        micros = ((h * 60 + m) * 60 + sec) * 1_000_000 + frac;
+    }
+    days.checked_mul(MICROS_PER_DAY)?.checked_add(micros)
+}
+
+/// Accepts PostgreSQL's hex format `\x0123ab`, any other string is taken as raw bytes.
+fn parse_bytea(s: &str) -> Result<Vec<u8>, String> {
+    let hex = match s.strip_prefix("\\x") {
+        Some(hex) => hex,
+        None => return Ok(s.as_bytes().to_vec()),
+    };
+    if hex.len() % 2 != 0 {
+        return Err("invalid hexadecimal data: odd number of digits".to_owned());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| format!("invalid hexadecimal digit in \"{}\"", s))
+        })
+        .collect()
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+    // see http://howardhinnant.github.io/date_algorithms.html
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = (m as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + d as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + (m <= 2) as i64;
+    (y, m, d)
+}
+
+impl IndexedRow for [Value] {
+    fn index_key(&self, columns: &[usize]) -> Option<Vec<u8>> {
+        let mut enc = KeyEncoder::new();
+        for &c in columns {
+            if self[c].is_null() {
+                return None;
+            }
+            self[c].encode_key(&mut enc, KeyOrder::ASC);
+        }
+        Some(enc.finish())
+    }
+}
+
+impl IndexedRow for Vec<Value> {
+    fn index_key(&self, columns: &[usize]) -> Option<Vec<u8>> {
+        self.as_slice().index_key(columns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashSet;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_owned())
+    }
+
+    fn hash(v: &Value) -> u64 {
+        let mut h = DefaultHasher::new();
+        v.hash(&mut h);
+        h.finish()
+    }
+
+    #[test]
+    fn three_valued_logic() {
+        let (t, f, n) = (Value::Boolean(true), Value::Boolean(false), Value::Null);
+        assert_eq!(t.and(&n).unwrap(), Value::Null);
+        assert_eq!(f.and(&n).unwrap(), f);
+        assert_eq!(t.or(&n).unwrap(), t);
+        assert_eq!(f.or(&n).unwrap(), Value::Null);
+        assert_eq!(n.not().unwrap(), Value::Null);
+        assert!(Value::Int(1).and(&t).is_err());
+
+        assert_eq!(Value::Int(1).sql_eq(&Value::Null), Ok(None));
+        assert_eq!(Value::Null.sql_eq(&Value::Null), Ok(None));
+        assert_eq!(Value::Int(1).sql_eq(&Value::Float(1.0)), Ok(Some(true)));
+        assert!(Value::Int(1).compare(&text("1")).is_err());
+    }
+
+    #[test]
+    fn comparisons() {
+        let cmp = |a: Value, b: Value| a.compare(&b).unwrap().unwrap();
+        assert_eq!(
+            cmp(Value::BigInt(i64::MAX), Value::Float(9.2e18)),
+            Ordering::Greater
+        );
+        assert_eq!(
+            cmp(Value::BigInt(i64::MAX), Value::Float(9.3e18)),
+            Ordering::Less
+        );
+        assert_eq!(cmp(Value::Int(2), Value::Float(2.5)), Ordering::Less);
+        assert_eq!(cmp(Value::Float(-2.5), Value::Int(-2)), Ordering::Less);
+        assert_eq!(
+            cmp(Value::Float(f64::NAN), Value::BigInt(i64::MAX)),
+            Ordering::Greater
+        );
+        assert_eq!(cmp(Value::Float(-0.0), Value::Float(0.0)), Ordering::Equal);
+        assert_eq!(
+            cmp(Value::Date(1), Value::Timestamp(MICROS_PER_DAY)),
+            Ordering::Equal
+        );
+        assert_eq!(cmp(text("B"), text("a")), Ordering::Less);
+        assert_eq!(
+            text("B").compare_collated(&text("a"), Collation::CaseInsensitive),
+            Ok(Some(Ordering::Greater))
+        );
+        assert_eq!(
+            text("ABC").compare_collated(&text("abc"), Collation::CaseInsensitive),
+            Ok(Some(Ordering::Equal))
+        );
+
+        let mut values = vec![Value::Null, text("a"), Value::Float(1.5), Value::Int(1)];
+        values.sort();
+        assert_eq!(
+            values,
+            vec![Value::Int(1), Value::Float(1.5), text("a"), Value::Null]
+        );
+    }
+
+    #[test]
+    fn hash_consistent_with_eq() {
+        let equal = [
+            (Value::Int(42), Value::BigInt(42)),
+            (Value::Int(42), Value::Float(42.0)),
+            (Value::Float(0.0), Value::Float(-0.0)),
+            (Value::Float(f64::NAN), Value::Float(-f64::NAN)),
+            (Value::Date(3), Value::Timestamp(3 * MICROS_PER_DAY)),
+            (Value::Null, Value::Null),
+        ];
+        for (a, b) in &equal {
+            assert_eq!(a, b);
+            assert_eq!(hash(a), hash(b), "{:?} {:?}", a, b);
+        }
+        assert_ne!(Value::Int(1), Value::Float(1.5));
+        assert_ne!(Value::Int(1), Value::Boolean(true));
+
+        let set: HashSet<_> = vec![Value::Int(1), Value::BigInt(1), Value::Null, Value::Null]
+            .into_iter()
+            .collect();
+        assert_eq!(set.len(), 2);
+
+        // dates beyond the range of timestamps
+        let date = Value::Date(i32::MAX);
+        assert_ne!(hash(&date), hash(&Value::Timestamp(i64::MAX)));
+        assert!(date > Value::Timestamp(i64::MAX));
+        assert!(Value::Date(i32::MIN) < Value::Timestamp(i64::MIN));
+        assert_eq!(
+            date.cast(DataType::Timestamp).unwrap_err(),
+            "timestamp out of range"
+        );
+    }
+
+    #[test]
+    fn sort_keys_consistent_with_ord() {
+        let values = [
+            Value::Int(i32::MIN),
+            Value::Float(-1e300),
+            Value::BigInt(-3),
+            Value::Float(-2.5),
+            Value::Int(0),
+            Value::Float(-0.0),
+            Value::Float(0.5),
+            Value::BigInt((1 << 53) + 1),
+            Value::Float((1u64 << 53) as f64 + 2.0),
+            Value::BigInt(i64::MAX),
+            Value::Float(I64_END),
+            Value::Float(f64::INFINITY),
+            Value::Float(f64::NAN),
+            Value::Boolean(false),
+            Value::Boolean(true),
+            text(""),
+            text("a\0"),
+            text("ab"),
+            Value::Timestamp(-1),
+            Value::Date(0),
+            Value::Timestamp(0),
+            Value::Timestamp(MICROS_PER_DAY - 1),
+            Value::Date(1),
+            Value::Bytea(vec![]),
+            Value::Bytea(vec![0]),
+            Value::Null,
+        ];
+        let key = |v: &Value, order| {
+            let mut enc = KeyEncoder::new();
+            v.encode_sort_key(&mut enc, order);
+            enc.finish()
+        };
+        for a in &values {
+            for b in &values {
+                assert_eq!(
+                    key(a, KeyOrder::ASC).cmp(&key(b, KeyOrder::ASC)),
+                    a.cmp(b),
+                    "{:?} {:?}",
+                    a,
+                    b
+                );
+                if !a.is_null() && !b.is_null() {
+                    assert_eq!(
+                        key(a, KeyOrder::DESC).cmp(&key(b, KeyOrder::DESC)),
+                        b.cmp(a),
+                        "{:?} {:?}",
+                        a,
+                        b
+                    );
+                }
+            }
+        }
+        assert!(key(&Value::Null, KeyOrder::DESC) < key(&Value::Int(i32::MIN), KeyOrder::DESC));
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(Value::Int(2).add(&Value::Int(3)), Ok(Value::Int(5)));
+        assert_eq!(Value::Int(2).mul(&Value::BigInt(3)), Ok(Value::BigInt(6)));
+        assert_eq!(Value::Int(7).div(&Value::Int(2)), Ok(Value::Int(3)));
+        assert_eq!(Value::Int(-7).rem(&Value::Int(2)), Ok(Value::Int(-1)));
+        assert_eq!(
+            Value::Int(1).div(&Value::Float(4.0)),
+            Ok(Value::Float(0.25))
+        );
+        assert_eq!(Value::Int(1).add(&Value::Null), Ok(Value::Null));
+        assert_eq!(Value::Date(10).sub(&Value::Date(3)), Ok(Value::Int(7)));
+        assert_eq!(Value::Int(1).add(&Value::Date(3)), Ok(Value::Date(4)));
+        assert!(Value::Date(i32::MAX).sub(&Value::Date(-1)).is_err());
+
+        assert!(Value::Int(i32::MAX).add(&Value::Int(1)).is_err());
+        assert!(Value::Int(i32::MIN).div(&Value::Int(-1)).is_err());
+        assert!(Value::BigInt(i64::MIN).neg().is_err());
+        assert!(Value::BigInt(i64::MAX).mul(&Value::Int(2)).is_err());
+        assert!(Value::Float(1e308).mul(&Value::Float(10.0)).is_err());
+        assert!(Value::Int(1).div(&Value::Int(0)).is_err());
+        assert!(Value::Float(1.0).div(&Value::Float(0.0)).is_err());
+        assert!(Value::Float(1.0).rem(&Value::Int(1)).is_err());
+        assert!(text("a").add(&Value::Int(1)).is_err());
+        assert_eq!(text("a").concat(&Value::Int(1)), Ok(text("a1")));
+    }
+
+    #[test]
+    fn casts() {
+        assert_eq!(Value::Float(2.5).cast(DataType::Int), Ok(Value::Int(2)));
+        assert_eq!(
+            Value::Float(-3.5).cast(DataType::BigInt),
+            Ok(Value::BigInt(-4))
+        );
+        assert!(Value::BigInt(1 << 40).cast(DataType::Int).is_err());
+        assert!(Value::Float(9.3e18).cast(DataType::BigInt).is_err());
+        assert_eq!(text(" 42 ").cast(DataType::Int), Ok(Value::Int(42)));
+        assert!(text("4x").cast(DataType::Int).is_err());
+        assert_eq!(
+            text("-Infinity").cast(DataType::Float),
+            Ok(Value::Float(f64::NEG_INFINITY))
+        );
+        assert_eq!(
+            text("YES").cast(DataType::Boolean),
+            Ok(Value::Boolean(true))
+        );
+        assert_eq!(text("1970-01-02").cast(DataType::Date), Ok(Value::Date(1)));
+        assert!(text("2021-02-29").cast(DataType::Date).is_err());
+        assert_eq!(
+            text("2000-02-29 12:34:56.5")
+                .cast(DataType::Timestamp)
+                .unwrap()
+                .to_string(),
+            "2000-02-29 12:34:56.5"
+        );
+        assert_eq!(Value::Date(-1).to_string(), "1969-12-31");
+        assert_eq!(
+            Value::Timestamp(-1).cast(DataType::Date),
+            Ok(Value::Date(-1))
+        );
+        assert_eq!(
+            text("\\x00ff").cast(DataType::Bytea),
+            Ok(Value::Bytea(vec![0, 255]))
+        );
+        assert_eq!(
+            Value::Bytea(vec![0, 255]).cast(DataType::Text),
+            Ok(text("\\x00ff"))
+        );
+        assert_eq!(Value::Float(0.1).cast(DataType::Text), Ok(text("0.1")));
+        assert_eq!(text("héllo").cast(DataType::Varchar(5)), Ok(text("héllo")));
+        assert!(text("héllo!").cast(DataType::Varchar(5)).is_err());
+        assert!(Value::Int(12345).cast(DataType::Varchar(4)).is_err());
+        assert_eq!(Value::Null.cast(DataType::Date), Ok(Value::Null));
+        assert!(Value::Date(1).cast(DataType::Int).is_err());
+    }
+
+    #[test]
+    fn date_range() {
+        let last = text("5874897-12-31").cast(DataType::Date).unwrap();
+        assert_eq!(last, Value::Date(MAX_DATE as i32));
+        assert!(text("5874898-01-01").cast(DataType::Date).is_err());
+        assert!(last.add(&Value::Int(1)).is_err());
+        assert_eq!(
+            last.sub(&Value::Int(1)).unwrap().to_string(),
+            "5874897-12-30"
+        );
+
+        let first = Value::Date(0).sub(&Value::Int(2_440_588)).unwrap();
+        assert_eq!(first, Value::Date(MIN_DATE as i32));
+        assert!(first.sub(&Value::Int(1)).is_err());
+        assert!(first.add(&Value::BigInt(i64::MIN)).is_err());
+        assert_eq!(
+            last.sub(&first),
+            Ok(Value::Int((MAX_DATE - MIN_DATE) as i32))
+        );
+    }
+
+    #[test]
+    fn literals() {
+        let number = |n: &str| Value::from_literal(&ast::Value::Number(n.to_owned(), false));
+        assert_eq!(number("1"), Ok(Value::Int(1)));
+        assert_eq!(number("3000000000"), Ok(Value::BigInt(3_000_000_000)));
+        assert_eq!(number("1.5"), Ok(Value::Float(1.5)));
+        assert_eq!(number("1e400"), Ok(Value::Float(f64::INFINITY)));
+    }
+}
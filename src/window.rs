@@ -0,0 +1,704 @@
+// Copyright (C) 2021 Quentin Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::vec::IntoIter;
+
+use sqlparser::ast::{Expr, WindowFrameBound};
+
+use crate::binder::{AggregateFunction, Field, WindowCall, WindowFrame, WindowFunction};
+use crate::database::Database;
+use crate::executor::{Operator, PlanRow, WORK_MEM_PAGES};
+use crate::page::PAGE_SIZE;
+use crate::spill_file::{RowBuffer, RowBufferRows};
+use crate::tuple::Tuple;
+use crate::value::Value;
+
+/// Computes window functions over an input sorted on the partition expressions
+/// and then on the window's order, returning each row followed by the results of the functions.
+///
+/// The rows are processed one partition at a time. Only the order keys and the evaluated
+/// arguments of the partition's rows are kept in memory, the rows themselves are buffered
+/// up to the budget and spilled to a file beyond it.
+pub struct Window {
+    input: Box<dyn Operator>,
+    partition_by: Vec<Expr>,
+    order_by: Vec<Expr>,
+    functions: Vec<WindowCall>,
+    fields: Vec<Field>,
+    /// Bytes of rows kept in memory.
+    memory: usize,
+    /// The first row of the next partition, which has already been read from the input.
+    next_row: Option<(Vec<Value>, Entry, Tuple)>,
+    /// The remaining rows of the current partition and the results for them.
+    rows: Option<RowBufferRows>,
+    results: IntoIter<Vec<Value>>,
+    done: bool,
+}
+
+/// What the window functions need to know about a row of a partition.
+struct Entry {
+    order: Vec<Value>,
+    /// The evaluated arguments of each function.
+    args: Vec<Vec<Value>>,
+}
+
+impl Window {
+    pub fn new(
+        input: Box<dyn Operator>,
+        partition_by: Vec<Expr>,
+        order_by: Vec<Expr>,
+        functions: Vec<WindowCall>,
+        fields: Vec<Field>,
+    ) -> Self {
+        Self {
+            input,
+            partition_by,
+            order_by,
+            functions,
+            fields,
+            memory: WORK_MEM_PAGES * PAGE_SIZE,
+            next_row: None,
+            rows: None,
+            results: Vec::new().into_iter(),
+            done: false,
+        }
+    }
+
+    /// Limits the rows of a partition held in memory to about the given number of pages.
+    #[cfg(test)]
+    pub fn with_buffer_pages(mut self, pages: usize) -> Self {
+        self.memory = pages.max(1) * PAGE_SIZE;
+        self
+    }
+
+    /// Evaluates the partition key, order key and function arguments of an input row.
+    fn evaluate(
+        &self,
+        db: &mut Database,
+        row: Tuple,
+    ) -> Result<(Vec<Value>, Entry, Tuple), String> {
+        let values = PlanRow {
+            fields: self.input.schema(),
+            values: &row,
+        };
+        let mut eval = |exprs: &[Expr]| {
+            exprs
+                .iter()
+                .map(|expr| db.eval(expr, &values))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let partition = eval(&self.partition_by)?;
+        let order = eval(&self.order_by)?;
+        let args = self
+            .functions
+            .iter()
+            .map(|function| eval(&function.args))
+            .collect::<Result<_, _>>()?;
+        Ok((partition, Entry { order, args }, row))
+    }
+
+    /// Reads the next partition and computes the functions for it,
+    /// `false` once the input has no more rows.
+    fn read_partition(&mut self, db: &mut Database) -> Result<bool, String> {
+        let (partition, entry, row) = match self.next_row.take() {
+            Some(first) => first,
+            None => match self.input.next(db)? {
+                Some(row) => self.evaluate(db, row)?,
+                None => {
+                    self.done = true;
+                    return Ok(false);
+                }
+            },
+        };
+        let mut entries = vec![entry];
+        let mut buffer = RowBuffer::new(self.memory);
+        buffer.push(row)?;
+        loop {
+            let row = match self.input.next(db)? {
+                Some(row) => row,
+                None => {
+                    self.done = true;
+                    break;
+                }
+            };
+            let (key, entry, row) = self.evaluate(db, row)?;
+            if key != partition {
+                self.next_row = Some((key, entry, row));
+                break;
+            }
+            entries.push(entry);
+            buffer.push(row)?;
+        }
+        self.results = compute(&self.functions, &entries)?.into_iter();
+        self.rows = Some(buffer.into_rows()?);
+        Ok(true)
+    }
+}
+
+impl Operator for Window {
+    fn open(&mut self, db: &mut Database) -> Result<(), String> {
+        self.next_row = None;
+        self.rows = None;
+        self.results = Vec::new().into_iter();
+        self.done = false;
+        self.input.open(db)
+    }
+
+    fn next(&mut self, db: &mut Database) -> Result<Option<Tuple>, String> {
+        loop {
+            if let Some(rows) = &mut self.rows {
+                if let Some(mut row) = rows.next()? {
+                    row.extend(self.results.next().unwrap());
+                    return Ok(Some(row));
+                }
+                self.rows = None;
+            }
+            if (self.done && self.next_row.is_none()) || !self.read_partition(db)? {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn close(&mut self, db: &mut Database) -> Result<(), String> {
+        self.next_row = None;
+        self.rows = None;
+        self.results = Vec::new().into_iter();
+        self.input.close(db)
+    }
+
+    fn schema(&self) -> &[Field] {
+        &self.fields
+    }
+}
+
+/// The results of the functions for each row of a partition.
+fn compute(functions: &[WindowCall], entries: &[Entry]) -> Result<Vec<Vec<Value>>, String> {
+    let n = entries.len();
+    // for each row the first of its peers, the end of its peers and the number of its peer group
+    let mut first_peer = vec![0; n];
+    let mut peer_group = vec![0; n];
+    for i in 1..n {
+        if entries[i].order == entries[i - 1].order {
+            first_peer[i] = first_peer[i - 1];
+            peer_group[i] = peer_group[i - 1];
+        } else {
+            first_peer[i] = i;
+            peer_group[i] = peer_group[i - 1] + 1;
+        }
+    }
+    let mut peers_end = vec![n; n];
+    for i in (0..n.saturating_sub(1)).rev() {
+        if peer_group[i] == peer_group[i + 1] {
+            peers_end[i] = peers_end[i + 1];
+        } else {
+            peers_end[i] = i + 1;
+        }
+    }
+
+    let mut results = vec![Vec::with_capacity(functions.len()); n];
+    for (f, function) in functions.iter().enumerate() {
+        let arg = |i: usize, a: usize| entries[i].args[f].get(a);
+        match function.function {
+            WindowFunction::RowNumber => {
+                for (i, result) in results.iter_mut().enumerate() {
+                    result.push(Value::BigInt(i as i64 + 1));
+                }
+            }
+            WindowFunction::Rank => {
+                for (i, result) in results.iter_mut().enumerate() {
+                    result.push(Value::BigInt(first_peer[i] as i64 + 1));
+                }
+            }
+            WindowFunction::DenseRank => {
+                for (i, result) in results.iter_mut().enumerate() {
+                    result.push(Value::BigInt(peer_group[i] as i64 + 1));
+                }
+            }
+            WindowFunction::Lag | WindowFunction::Lead => {
+                for (i, result) in results.iter_mut().enumerate() {
+                    let offset = match arg(i, 1) {
+                        None => 1,
+                        Some(Value::Null) => {
+                            result.push(Value::Null);
+                            continue;
+                        }
+                        Some(Value::Int(v)) => *v as i64,
+                        Some(Value::BigInt(v)) => *v,
+                        Some(v) => {
+                            return Err(format!(
+                                "offset of {} must be an integer, not {}",
+                                if function.function == WindowFunction::Lag {
+                                    "lag"
+                                } else {
+                                    "lead"
+                                },
+                                v.data_type().unwrap()
+                            ))
+                        }
+                    };
+                    let offset = match function.function {
+                        WindowFunction::Lag => offset.checked_neg(),
+                        _ => Some(offset),
+                    };
+                    let target = offset
+                        .and_then(|offset| (i as i64).checked_add(offset))
+                        .filter(|&target| 0 <= target && target < n as i64);
+                    result.push(match target {
+                        Some(target) => arg(target as usize, 0).unwrap().clone(),
+                        None => arg(i, 2).cloned().unwrap_or(Value::Null),
+                    });
+                }
+            }
+            WindowFunction::Aggregate(aggregate) => {
+                let mut frame = SlidingAggregate::new(aggregate, function.args.is_empty());
+                for (i, result) in results.iter_mut().enumerate() {
+                    let (start, end) = bounds(&function.frame, i, first_peer[i], peers_end[i], n);
+                    while frame.end < end {
+                        frame.add(frame.end, arg(frame.end, 0))?;
+                        frame.end += 1;
+                    }
+                    while frame.start < start {
+                        frame.remove(frame.start, arg(frame.start, 0))?;
+                        frame.start += 1;
+                    }
+                    if frame.stale {
+                        frame.recompute((start..end).map(|j| arg(j, 0)))?;
+                    }
+                    result.push(frame.result());
+                }
+            }
+        }
+    }
+    Ok(results)
+}
+
+/// The rows `start..end` of the frame of row `i` of a partition of `n` rows,
+/// whose peers are the rows `first_peer..peers_end`.
+/// Neither bound decreases from one row to the next.
+fn bounds(
+    frame: &WindowFrame,
+    i: usize,
+    first_peer: usize,
+    peers_end: usize,
+    n: usize,
+) -> (usize, usize) {
+    let start = match frame.start {
+        WindowFrameBound::Preceding(None) => 0,
+        WindowFrameBound::Preceding(Some(k)) => i.saturating_sub(k as usize),
+        WindowFrameBound::CurrentRow if frame.rows => i,
+        WindowFrameBound::CurrentRow => first_peer,
+        WindowFrameBound::Following(k) => i.saturating_add(k.unwrap_or(0) as usize).min(n),
+    };
+    let end = match frame.end {
+        WindowFrameBound::Preceding(k) => (i + 1).saturating_sub(k.unwrap_or(0) as usize),
+        WindowFrameBound::CurrentRow if frame.rows => i + 1,
+        WindowFrameBound::CurrentRow => peers_end,
+        WindowFrameBound::Following(None) => n,
+        WindowFrameBound::Following(Some(k)) => {
+            i.saturating_add(k as usize).saturating_add(1).min(n)
+        }
+    };
+    // frames ending before they start are empty
+    (start, end.max(start))
+}
+
+/// An aggregate over the rows `start..end` of a partition, which can be moved forward
+/// by adding rows at its end and removing rows at its start.
+struct SlidingAggregate {
+    function: AggregateFunction,
+    /// Whether every row counts, like for `COUNT(*)`, instead of non-NULL arguments.
+    all_rows: bool,
+    start: usize,
+    end: usize,
+    count: i64,
+    sum: Value,
+    /// Whether rows were removed from a FLOAT sum, which then has to be computed again.
+    stale: bool,
+    /// Rows whose arguments may still become the minimum or maximum, for `MIN` and `MAX`.
+    /// Their arguments are ordered from the current minimum or maximum on.
+    candidates: VecDeque<(usize, Value)>,
+}
+
+impl SlidingAggregate {
+    fn new(function: AggregateFunction, all_rows: bool) -> Self {
+        Self {
+            function,
+            all_rows,
+            start: 0,
+            end: 0,
+            count: 0,
+            sum: Value::Null,
+            stale: false,
+            candidates: VecDeque::new(),
+        }
+    }
+
+    fn add(&mut self, i: usize, arg: Option<&Value>) -> Result<(), String> {
+        let arg = match arg {
+            None if self.all_rows => {
+                self.count += 1;
+                return Ok(());
+            }
+            Some(arg) if !arg.is_null() => arg,
+            _ => return Ok(()),
+        };
+        self.count += 1;
+        match self.function {
+            AggregateFunction::Count => {}
+            AggregateFunction::Sum | AggregateFunction::Avg => self.add_summand(arg)?,
+            AggregateFunction::Min | AggregateFunction::Max => {
+                let beaten = if self.function == AggregateFunction::Min {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                };
+                while let Some((_, last)) = self.candidates.back() {
+                    match arg.compare(last)? {
+                        Some(ordering) if ordering == beaten || ordering == Ordering::Equal => {
+                            self.candidates.pop_back();
+                        }
+                        _ => break,
+                    }
+                }
+                self.candidates.push_back((i, arg.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, i: usize, arg: Option<&Value>) -> Result<(), String> {
+        let arg = match arg {
+            None if self.all_rows => {
+                self.count -= 1;
+                return Ok(());
+            }
+            Some(arg) if !arg.is_null() => arg,
+            _ => return Ok(()),
+        };
+        self.count -= 1;
+        match self.function {
+            AggregateFunction::Count => {}
+            AggregateFunction::Sum | AggregateFunction::Avg => {
+                let arg = self.summand(arg)?;
+                if self.count == 0 {
+                    self.sum = Value::Null;
+                    self.stale = false;
+                } else if matches!(arg, Value::Float(_)) {
+                    // subtracting loses the precision of small summands next to large ones
+                    // and gives NaN for infinities, so the sum is computed again like in PostgreSQL
+                    self.stale = true;
+                } else {
+                    self.sum = self.sum.sub(&arg)?;
+                }
+            }
+            AggregateFunction::Min | AggregateFunction::Max => {
+                if self.candidates.front().is_some_and(|&(j, _)| j == i) {
+                    self.candidates.pop_front();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sums the arguments of the rows in the frame again, after rows were removed from a FLOAT sum.
+    fn recompute<'a>(
+        &mut self,
+        args: impl Iterator<Item = Option<&'a Value>>,
+    ) -> Result<(), String> {
+        self.sum = Value::Null;
+        self.stale = false;
+        for arg in args.flatten().filter(|arg| !arg.is_null()) {
+            self.add_summand(arg)?;
+        }
+        Ok(())
+    }
+
+    fn add_summand(&mut self, arg: &Value) -> Result<(), String> {
+        let arg = self.summand(arg)?;
+        self.sum = match self.sum {
+            Value::Null => arg,
+            _ => self.sum.add(&arg)?,
+        };
+        Ok(())
+    }
+
+    /// The argument as it is summed, integers are summed as BIGINT like in aggregates.
+    fn summand(&self, arg: &Value) -> Result<Value, String> {
+        match arg {
+            Value::Int(v) => Ok(Value::BigInt(*v as i64)),
+            Value::BigInt(_) | Value::Float(_) => Ok(arg.clone()),
+            _ => Err(format!(
+                "function {}({}) does not exist",
+                self.function,
+                arg.data_type().unwrap()
+            )),
+        }
+    }
+
+    fn result(&self) -> Value {
+        match self.function {
+            AggregateFunction::Count => Value::BigInt(self.count),
+            _ if self.count == 0 => Value::Null,
+            AggregateFunction::Sum => self.sum.clone(),
+            AggregateFunction::Avg => match self.sum {
+                Value::BigInt(sum) => Value::Float(sum as f64 / self.count as f64),
+                Value::Float(sum) => Value::Float(sum / self.count as f64),
+                _ => Value::Null,
+            },
+            AggregateFunction::Min | AggregateFunction::Max => self.candidates[0].1.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::binder::SortKey;
+    use crate::executor::{collect, TempDatabase, Values};
+    use crate::external_sort::Sort;
+    use crate::sql::parse_expr;
+
+    fn call(
+        function: WindowFunction,
+        args: &[&str],
+        rows: bool,
+        start: &str,
+        end: &str,
+    ) -> WindowCall {
+        let bound = |bound: &str| match bound {
+            "unbounded preceding" => WindowFrameBound::Preceding(None),
+            "current row" => WindowFrameBound::CurrentRow,
+            "unbounded following" => WindowFrameBound::Following(None),
+            _ => match bound.split_once(' ') {
+                Some((k, "preceding")) => WindowFrameBound::Preceding(Some(k.parse().unwrap())),
+                Some((k, "following")) => WindowFrameBound::Following(Some(k.parse().unwrap())),
+                _ => unreachable!(),
+            },
+        };
+        WindowCall {
+            function,
+            args: args.iter().map(|arg| parse_expr(arg).unwrap()).collect(),
+            frame: WindowFrame {
+                rows,
+                start: bound(start),
+                end: bound(end),
+            },
+        }
+    }
+
+    #[test]
+    fn window() {
+        let mut db = TempDatabase::open("window");
+        // a few large partitions, with NULLs and peers in the order
+        let rows: Vec<(i64, Option<i64>)> = (0..3000)
+            .map(|i| (i % 3, if i % 11 == 0 { None } else { Some(i % 50) }))
+            .collect();
+        let literal = |v: Option<i64>| parse_expr(&v.map_or("NULL".to_owned(), |v| v.to_string()));
+        let values = Values::new(
+            rows.iter()
+                .map(|&(p, x)| vec![literal(Some(p)).unwrap(), literal(x).unwrap()])
+                .collect(),
+            vec![Field::new(None, "p"), Field::new(None, "x")],
+        );
+        let key = |expr: &str| SortKey {
+            expr: parse_expr(expr).unwrap(),
+            asc: true,
+            nulls_first: false,
+        };
+        let sorted = Sort::new(Box::new(values), vec![key("p"), key("x")]);
+        let functions = vec![
+            call(
+                WindowFunction::RowNumber,
+                &[],
+                false,
+                "unbounded preceding",
+                "current row",
+            ),
+            call(
+                WindowFunction::Rank,
+                &[],
+                false,
+                "unbounded preceding",
+                "current row",
+            ),
+            call(
+                WindowFunction::DenseRank,
+                &[],
+                false,
+                "unbounded preceding",
+                "current row",
+            ),
+            call(
+                WindowFunction::Lag,
+                &["x"],
+                false,
+                "unbounded preceding",
+                "current row",
+            ),
+            call(
+                WindowFunction::Lead,
+                &["x", "2", "-1"],
+                false,
+                "unbounded preceding",
+                "current row",
+            ),
+            call(
+                WindowFunction::Aggregate(AggregateFunction::Sum),
+                &["x"],
+                true,
+                "2 preceding",
+                "current row",
+            ),
+            call(
+                WindowFunction::Aggregate(AggregateFunction::Max),
+                &["x * -1"],
+                true,
+                "1 preceding",
+                "3 following",
+            ),
+            call(
+                WindowFunction::Aggregate(AggregateFunction::Count),
+                &[],
+                false,
+                "unbounded preceding",
+                "current row",
+            ),
+            call(
+                WindowFunction::Aggregate(AggregateFunction::Avg),
+                &["x"],
+                true,
+                "3 following",
+                "5 following",
+            ),
+            // the end of the frame must not overflow
+            call(
+                WindowFunction::Aggregate(AggregateFunction::Count),
+                &["x"],
+                true,
+                "current row",
+                "18446744073709551615 following",
+            ),
+        ];
+        let fields: Vec<_> = ["p", "x", "a", "b", "c", "d", "e", "f", "g", "h", "i", "j"]
+            .iter()
+            .map(|name| Field::new(None, name))
+            .collect();
+        let mut window = Window::new(
+            Box::new(sorted),
+            vec![parse_expr("p").unwrap()],
+            vec![parse_expr("x").unwrap()],
+            functions,
+            fields,
+        )
+        .with_buffer_pages(1);
+
+        let mut expected = Vec::new();
+        for p in 0..3 {
+            let mut xs: Vec<Option<i64>> = rows
+                .iter()
+                .filter(|&&(q, _)| q == p)
+                .map(|&(_, x)| x)
+                .collect();
+            xs.sort_by_key(|x| (x.is_none(), *x));
+            let n = xs.len();
+            let optional = |v: Option<i64>| v.map_or(Value::Null, Value::BigInt);
+            for i in 0..n {
+                let first_peer = (0..=i).find(|&j| xs[j] == xs[i]).unwrap();
+                let last_peer = (i..n).rev().find(|&j| xs[j] == xs[i]).unwrap();
+                let dense_rank = 1 + (1..=i).filter(|&j| xs[j] != xs[j - 1]).count();
+                let frame = |start: usize, end: usize| -> Vec<i64> {
+                    xs[start.min(n)..end.min(n).max(start.min(n))]
+                        .iter()
+                        .flatten()
+                        .copied()
+                        .collect()
+                };
+                let sum = frame(i.saturating_sub(2), i + 1);
+                let max = frame(i.saturating_sub(1), i + 4).iter().map(|x| -x).max();
+                let avg = frame(i + 3, i + 6);
+                expected.push(vec![
+                    Value::BigInt(p),
+                    optional(xs[i]),
+                    Value::BigInt(i as i64 + 1),
+                    Value::BigInt(first_peer as i64 + 1),
+                    Value::BigInt(dense_rank as i64),
+                    if i == 0 {
+                        Value::Null
+                    } else {
+                        optional(xs[i - 1])
+                    },
+                    if i + 2 < n {
+                        optional(xs[i + 2])
+                    } else {
+                        Value::Int(-1)
+                    },
+                    if sum.is_empty() {
+                        Value::Null
+                    } else {
+                        Value::BigInt(sum.iter().sum())
+                    },
+                    optional(max),
+                    Value::BigInt(last_peer as i64 + 1),
+                    if avg.is_empty() {
+                        Value::Null
+                    } else {
+                        Value::Float(avg.iter().sum::<i64>() as f64 / avg.len() as f64)
+                    },
+                    Value::BigInt(frame(i, n).len() as i64),
+                ]);
+            }
+        }
+        for _ in 0..2 {
+            assert_eq!(collect(&mut window, &mut db).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn float_frames() {
+        let mut db = TempDatabase::open("window_float");
+        // removing rows from a FLOAT sum must not subtract them
+        for (first, expected) in [
+            ("CAST('1e20' AS FLOAT)", vec![1e20, 1.0, 2.0]),
+            ("CAST('Infinity' AS FLOAT)", vec![f64::INFINITY, 1.0, 2.0]),
+        ] {
+            let values = Values::new(
+                [first, "1.0", "2.0"]
+                    .iter()
+                    .map(|x| vec![parse_expr(x).unwrap()])
+                    .collect(),
+                vec![Field::new(None, "x")],
+            );
+            let functions = [AggregateFunction::Sum, AggregateFunction::Avg]
+                .iter()
+                .map(|&function| {
+                    call(
+                        WindowFunction::Aggregate(function),
+                        &["x"],
+                        true,
+                        "current row",
+                        "current row",
+                    )
+                })
+                .collect();
+            let mut window = Window::new(
+                Box::new(values),
+                Vec::new(),
+                Vec::new(),
+                functions,
+                ["x", "sum", "avg"]
+                    .iter()
+                    .map(|name| Field::new(None, name))
+                    .collect(),
+            );
+            let result = collect(&mut window, &mut db).unwrap();
+            let expected: Vec<Tuple> = expected
+                .into_iter()
+                .map(|x| vec![Value::Float(x); 3])
+                .collect();
+            assert_eq!(result, expected, "{}", first);
+        }
+    }
+}